@@ -3,14 +3,14 @@
 //! **NOTE**: Currently encoding from plaintext is unsupported by osu-db.
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
-use chrono::{DateTime, Utc};
 use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use osu_db::{
     collection::Collection,
     listing::{Beatmap, RankedStatus},
     replay::Action,
     score::BeatmapScores,
-    CollectionList, Listing, Replay, ScoreList,
+    BeatmapId, BeatmapsetId, CollectionList, Listing, Md5Hash, OsuTimestamp, Replay, ScoreList,
+    StoredHash,
 };
 use std::{
     cmp::{self, Reverse},
@@ -48,28 +48,28 @@ Note that encodings and filetypes are not exclusive (eg. `osu!.db?listing?bin` i
 /// other.
 #[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
 struct BmCorrectness<'a> {
-    folder_name: PreferPresent<'a>,
-    hash: PreferPresent<'a>,
-    file_name: PreferPresent<'a>,
-    beatmap_id: PreferPositive<i32>,
-    beatmapset_id: PreferPositive<i32>,
-    difficulty_name: PreferPresent<'a>,
+    folder_name: PreferPresent<&'a String>,
+    hash: PreferPresent<Md5Hash>,
+    file_name: PreferPresent<&'a String>,
+    beatmap_id: PreferPositive<BeatmapId>,
+    beatmapset_id: PreferPositive<BeatmapsetId>,
+    difficulty_name: PreferPresent<&'a String>,
     ranked_status: PreferRanked,
-    audio: PreferPresent<'a>,
-    last_online_check: PreferLarger<DateTime<Utc>>,
-    last_played: PreferLarger<Option<DateTime<Utc>>>,
+    audio: PreferPresent<&'a String>,
+    last_online_check: PreferLarger<OsuTimestamp>,
+    last_played: PreferLarger<Option<OsuTimestamp>>,
 }
 impl<'a> BmCorrectness<'a> {
     fn new(bm: &'a Beatmap) -> Self {
         Self {
-            folder_name: PreferPresent(&bm.folder_name),
-            hash: PreferPresent(&bm.hash),
-            file_name: PreferPresent(&bm.file_name),
+            folder_name: PreferPresent(bm.folder_name.as_ref()),
+            hash: PreferPresent(bm.hash.md5()),
+            file_name: PreferPresent(bm.file_name.as_ref()),
             beatmap_id: PreferPositive(bm.beatmap_id),
             beatmapset_id: PreferPositive(bm.beatmapset_id),
-            difficulty_name: PreferPresent(&bm.difficulty_name),
+            difficulty_name: PreferPresent(bm.difficulty_name.as_ref()),
             ranked_status: PreferRanked(bm.status),
-            audio: PreferPresent(&bm.audio),
+            audio: PreferPresent(bm.audio.as_ref()),
             last_online_check: PreferLarger(bm.last_online_check),
             last_played: PreferLarger(bm.last_played),
         }
@@ -149,8 +149,8 @@ impl<T> From<T> for PreferPositive<T> {
 }
 
 #[derive(Clone)]
-struct PreferPresent<'a>(&'a Option<String>);
-impl fmt::Debug for PreferPresent<'_> {
+struct PreferPresent<T>(Option<T>);
+impl<T> fmt::Debug for PreferPresent<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
@@ -164,15 +164,15 @@ impl fmt::Debug for PreferPresent<'_> {
     }
 }
 impl_ord_by_key! {
-    impl[] * for PreferPresent<'_> {
+    impl[T] * for PreferPresent<T> {
         fn key(p) {
             p.0.is_some() as u8
         }
     }
 }
-impl<'a> From<&'a Option<String>> for PreferPresent<'a> {
-    fn from(inner: &'a Option<String>) -> Self {
-        Self(inner)
+impl<'a, T> From<&'a Option<T>> for PreferPresent<&'a T> {
+    fn from(inner: &'a Option<T>) -> Self {
+        Self(inner.as_ref())
     }
 }
 
@@ -356,8 +356,8 @@ fn merge(merge_op: MergeOp, inputs: impl IntoIterator<Item = InMemory>) -> Resul
                             (&bm.title_ascii, &bm.difficulty_name, &bm.file_name)
                         );
                     }
-                    if let Some(hash) = &bm.hash {
-                        if let Some((meta, old_bm)) = by_hash.get_mut(&hash[..]) {
+                    if let Some(hash) = bm.hash.md5() {
+                        if let Some((meta, old_bm)) = by_hash.get_mut(&hash) {
                             let new = BmCorrectness::new(&bm);
                             let old = BmCorrectness::new(old_bm);
                             let replace = new > old;
@@ -382,7 +382,7 @@ fn merge(merge_op: MergeOp, inputs: impl IntoIterator<Item = InMemory>) -> Resul
                             *meta += merge_meta_add(i);
                         } else {
                             let meta = merge_meta_add(i);
-                            by_hash.insert(hash.clone(), (meta, bm));
+                            by_hash.insert(hash, (meta, bm));
                         }
                     } else {
                         println!(
@@ -408,7 +408,7 @@ fn merge(merge_op: MergeOp, inputs: impl IntoIterator<Item = InMemory>) -> Resul
         }
         FileType::Collections => {
             let (lists, mut out) = prepare!(Collections => |list| list.collections);
-            let mut by_name: HashMap<String, (i32, HashMap<String, i32>)> = HashMap::default();
+            let mut by_name: HashMap<String, (i32, HashMap<Md5Hash, i32>)> = HashMap::default();
             //Merge everything into hashmaps
             for (i, list) in lists.into_iter().enumerate() {
                 println!("processing collection listing {}", i);
@@ -422,7 +422,7 @@ fn merge(merge_op: MergeOp, inputs: impl IntoIterator<Item = InMemory>) -> Resul
                             by_name.entry(collection_name).or_default();
                         *collection_meta += merge_meta_add(i);
                         for (k, bm_hash) in collection.beatmap_hashes.into_iter().enumerate() {
-                            if let Some(bm_hash) = bm_hash {
+                            if let Some(bm_hash) = bm_hash.md5() {
                                 *out_collection.entry(bm_hash).or_default() += merge_meta_add(i);
                             } else {
                                 println!(
@@ -442,7 +442,9 @@ fn merge(merge_op: MergeOp, inputs: impl IntoIterator<Item = InMemory>) -> Resul
                     name: Some(name),
                     beatmap_hashes: collection
                         .into_iter()
-                        .filter_map(|(hash, meta)| merge_filter(merge_ctx, (meta, Some(hash))))
+                        .filter_map(|(hash, meta)| {
+                            merge_filter(merge_ctx, (meta, StoredHash::Md5(hash)))
+                        })
                         .collect(),
                 };
                 if !out_collection.beatmap_hashes.is_empty()
@@ -468,7 +470,7 @@ fn merge(merge_op: MergeOp, inputs: impl IntoIterator<Item = InMemory>) -> Resul
         FileType::Scores => {
             let (lists, mut out) = prepare!(Scores => |list| list.beatmaps);
             //Merge replays into a hashmap of hashsets
-            let mut bms_by_hash: HashMap<String, (i32, HashMap<ReplayWrapper, i32>)> =
+            let mut bms_by_hash: HashMap<Md5Hash, (i32, HashMap<ReplayWrapper, i32>)> =
                 HashMap::default();
             //Hashes all NaNs as equal and all zeroes as equal
             fn hash_f32<H: Hasher>(f: f32, h: &mut H) {
@@ -558,8 +560,8 @@ fn merge(merge_op: MergeOp, inputs: impl IntoIterator<Item = InMemory>) -> Resul
                 }
                 (
                     mode,
+                    beatmap_hash,
                     [
-                        s(beatmap_hash, false),
                         s(player_name, false),
                         s(life_graph, true),
                     ],
@@ -603,7 +605,7 @@ fn merge(merge_op: MergeOp, inputs: impl IntoIterator<Item = InMemory>) -> Resul
                         .sum::<usize>()
                 );
                 for (j, bm) in list.beatmaps.into_iter().enumerate() {
-                    if let Some(bm_hash) = bm.hash {
+                    if let Some(bm_hash) = bm.hash.md5() {
                         let (meta, bm_scores) = bms_by_hash.entry(bm_hash).or_default();
                         *meta += merge_meta_add(i);
                         for (_k, replay) in bm.scores.into_iter().enumerate() {
@@ -623,7 +625,7 @@ fn merge(merge_op: MergeOp, inputs: impl IntoIterator<Item = InMemory>) -> Resul
             for (hash, (replays_meta, replays)) in bms_by_hash {
                 let bm = {
                     let mut bm = BeatmapScores {
-                        hash: Some(hash),
+                        hash: hash.into(),
                         scores: replays
                             .into_iter()
                             .filter_map(|(wrapper, meta)| {
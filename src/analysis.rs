@@ -0,0 +1,272 @@
+//! Analysis helpers over replay data, such as extracting the cursor path of osu!standard replays.
+
+use crate::{prelude::*, replay::Replay};
+
+/// The width of the osu!standard playfield, in osu!pixels.
+const PLAYFIELD_WIDTH: f32 = 512.0;
+/// The height of the osu!standard playfield, in osu!pixels.
+const PLAYFIELD_HEIGHT: f32 = 384.0;
+
+/// A single point along the cursor path of a replay.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CursorPoint {
+    /// Milliseconds since the start of the song.
+    pub time: i64,
+    /// Horizontal cursor position, from `0` to `512` when within the playfield.
+    pub x: f32,
+    /// Vertical cursor position, from `0` to `384` when within the playfield.
+    pub y: f32,
+}
+
+/// Extract the cursor path of an osu!standard replay, as a polyline of timed points.
+///
+/// The leading header frames and the trailing seed frame are not included.
+/// Returns an empty path if the replay is not an osu!standard replay or if its actions are not
+/// available (see `Replay::replay_data`).
+pub fn cursor_path(replay: &Replay) -> Vec<CursorPoint> {
+    if replay.mode != Mode::Standard {
+        return Vec::new();
+    }
+    replay
+        .timed_actions()
+        .map(|(time, action)| CursorPoint {
+            time,
+            x: action.x,
+            y: action.y,
+        })
+        .collect()
+}
+
+impl Replay {
+    /// Iterate over the frames of the replay as `(time, x, y, buttons)` tuples, where `time` is
+    /// the absolute time in milliseconds and `buttons` is the raw button state of the frame.
+    ///
+    /// The leading header frames and the trailing seed frame are not included.
+    /// Note that `x` holds the pressed keys instead of a position in osu!mania replays.
+    fn frames(&self) -> impl Iterator<Item = (i64, f32, f32, u32)> + '_ {
+        self.timed_actions()
+            .map(|(time, action)| (time, action.x, action.y, action.z as u32))
+    }
+
+    /// Write the frames of the replay as CSV, with a `time,x,y,buttons` header line, for use as a
+    /// dataset.
+    ///
+    /// `time` is the absolute time in milliseconds and `buttons` is the raw button state.
+    /// The leading header frames and the trailing seed frame are not included.
+    pub fn export_frames_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "time,x,y,buttons")?;
+        for (time, x, y, buttons) in self.frames() {
+            writeln!(out, "{},{},{},{}", time, x, y, buttons)?;
+        }
+        Ok(())
+    }
+
+    /// Get the frames of the replay as an `(n_frames, 4)` array, with columns for absolute time
+    /// in milliseconds, x, y and raw button state.
+    ///
+    /// The leading header frames and the trailing seed frame are not included.
+    ///
+    /// Only available with the `ndarray` feature enabled.
+    #[cfg(feature = "ndarray")]
+    pub fn frames_array(&self) -> ndarray::Array2<f64> {
+        let data: Vec<f64> = self
+            .frames()
+            .flat_map(|(time, x, y, buttons)| [time as f64, x as f64, y as f64, buttons as f64])
+            .collect();
+        ndarray::Array2::from_shape_vec((data.len() / 4, 4), data)
+            .expect("frame data has 4 columns")
+    }
+}
+
+/// Smooth a cursor path using a centered moving average over `window` points.
+///
+/// Times are kept as-is, only positions are averaged.
+/// A window of `0` or `1` leaves the path unchanged.
+pub fn smooth(path: &[CursorPoint], window: usize) -> Vec<CursorPoint> {
+    let half = window / 2;
+    (0..path.len())
+        .map(|i| {
+            let lo = i.saturating_sub(half);
+            let hi = (i + half + 1).min(path.len());
+            let neighbours = &path[lo..hi];
+            let n = neighbours.len() as f32;
+            CursorPoint {
+                time: path[i].time,
+                x: neighbours.iter().map(|p| p.x).sum::<f32>() / n,
+                y: neighbours.iter().map(|p| p.y).sum::<f32>() / n,
+            }
+        })
+        .collect()
+}
+
+/// Resample a cursor path at a fixed interval of `step_ms` milliseconds, linearly interpolating
+/// the cursor position between the original points.
+///
+/// # Panics
+///
+/// Panics if `step_ms` is not positive.
+pub fn interpolate(path: &[CursorPoint], step_ms: i64) -> Vec<CursorPoint> {
+    assert!(step_ms > 0, "interpolation step must be positive");
+    let (first, last) = match (path.first(), path.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Vec::new(),
+    };
+    let mut out = Vec::new();
+    let mut seg = 0;
+    let mut time = first.time;
+    while time <= last.time {
+        while seg + 1 < path.len() - 1 && path[seg + 1].time <= time {
+            seg += 1;
+        }
+        let a = &path[seg];
+        let b = &path[(seg + 1).min(path.len() - 1)];
+        let t = if b.time > a.time {
+            (time - a.time) as f32 / (b.time - a.time) as f32
+        } else {
+            0.0
+        };
+        let t = t.clamp(0.0, 1.0);
+        out.push(CursorPoint {
+            time,
+            x: a.x + (b.x - a.x) * t,
+            y: a.y + (b.y - a.y) * t,
+        });
+        time += step_ms;
+    }
+    out
+}
+
+/// Write a cursor path as CSV, with a `time,x,y` header line.
+pub fn write_csv<W: Write>(path: &[CursorPoint], mut out: W) -> io::Result<()> {
+    writeln!(out, "time,x,y")?;
+    for point in path {
+        writeln!(out, "{},{},{}", point.time, point.x, point.y)?;
+    }
+    Ok(())
+}
+
+/// Write a cursor path as an SVG image of the osu!standard playfield, with the path drawn as a
+/// single polyline.
+pub fn write_svg<W: Write>(path: &[CursorPoint], mut out: W) -> io::Result<()> {
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}">"#,
+        PLAYFIELD_WIDTH, PLAYFIELD_HEIGHT
+    )?;
+    write!(
+        out,
+        r#"<polyline fill="none" stroke="black" stroke-width="1" points=""#
+    )?;
+    for (i, point) in path.iter().enumerate() {
+        if i != 0 {
+            out.write_all(b" ")?;
+        }
+        write!(out, "{},{}", point.x, point.y)?;
+    }
+    writeln!(out, r#""/>"#)?;
+    writeln!(out, "</svg>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::replay::{test::replay_with, Action};
+
+    fn point(time: i64, x: f32, y: f32) -> CursorPoint {
+        CursorPoint { time, x, y }
+    }
+
+    #[test]
+    fn export_frames() {
+        let action = |delta, x, z| Action {
+            delta,
+            x,
+            y: 2.0,
+            z,
+        };
+        let replay = replay_with(vec![
+            action(0, 256.0, 0.0),
+            action(-1, 256.0, 0.0),
+            action(10, 1.5, 0.0),
+            action(16, 3.0, 5.0),
+            action(-12345, 0.0, 1234.0),
+        ]);
+        let mut csv = Vec::new();
+        replay.export_frames_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "time,x,y,buttons\n9,1.5,2,0\n25,3,2,5\n"
+        );
+        #[cfg(feature = "ndarray")]
+        assert_eq!(
+            replay.frames_array(),
+            ndarray::arr2(&[[9.0, 1.5, 2.0, 0.0], [25.0, 3.0, 2.0, 5.0]])
+        );
+    }
+
+    #[test]
+    fn cursor_path() {
+        let action = |delta, x| Action {
+            delta,
+            x,
+            y: 2.0,
+            z: 0.0,
+        };
+        let mut replay = replay_with(vec![
+            action(0, 256.0),
+            action(-1, 256.0),
+            action(10, 1.5),
+            action(16, 3.0),
+            action(-12345, 0.0),
+        ]);
+        assert_eq!(
+            super::cursor_path(&replay),
+            vec![point(9, 1.5, 2.0), point(25, 3.0, 2.0)]
+        );
+        replay.mode = Mode::Mania;
+        assert_eq!(super::cursor_path(&replay), Vec::new());
+    }
+
+    #[test]
+    fn path_helpers() {
+        let path = vec![
+            point(0, 0.0, 0.0),
+            point(10, 30.0, 30.0),
+            point(30, 60.0, 0.0),
+        ];
+        assert_eq!(smooth(&path, 1), path);
+        assert_eq!(
+            smooth(&path, 3),
+            vec![
+                point(0, 15.0, 15.0),
+                point(10, 30.0, 10.0),
+                point(30, 45.0, 15.0)
+            ]
+        );
+
+        let resampled = interpolate(&path, 5);
+        let times: Vec<i64> = resampled.iter().map(|p| p.time).collect();
+        assert_eq!(times, vec![0, 5, 10, 15, 20, 25, 30]);
+        let xs: Vec<f32> = resampled.iter().map(|p| p.x).collect();
+        assert_eq!(xs, vec![0.0, 15.0, 30.0, 37.5, 45.0, 52.5, 60.0]);
+        assert_eq!(resampled[3].y, 22.5);
+        assert_eq!(interpolate(&[], 5), Vec::new());
+
+        let mut csv = Vec::new();
+        write_csv(&path, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "time,x,y\n0,0,0\n10,30,30\n30,60,0\n"
+        );
+        let mut svg = Vec::new();
+        write_svg(&path, &mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(
+            svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 512 384">"#)
+        );
+        assert!(svg.contains(r#"points="0,0 30,30 60,0"/>"#));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+}
@@ -0,0 +1,239 @@
+//! Construction of listing entries out of osu! API v2 objects.
+
+use crate::{
+    interchange::{get_hash, get_i32, get_string, get_uint, missing, out_of_range},
+    listing::{Beatmap, Grade, RankedStatus},
+    osu_file::{
+        default_folder_name, sanitize_file_name, DEFAULT_SLIDER_VELOCITY, DEFAULT_STACK_LENIENCY,
+    },
+    prelude::*,
+};
+use serde::de::Error as _;
+use serde_json::{Map, Value};
+
+impl Beatmap {
+    /// Build a listing entry out of a beatmap object from the osu! API v2, such as the ones
+    /// returned by the `/beatmaps/{id}` endpoint.
+    ///
+    /// Artist, title, creator, source and tags are taken from the nested `beatmapset` object, if
+    /// present.
+    /// Information that the API does not provide is filled in the way osu! does for freshly
+    /// imported maps: the beatmap is unplayed, there are no timing points and no audio file name,
+    /// only the no-mod star rating of the beatmap mode is known, and the file and folder names
+    /// follow the osu! naming conventions.
+    /// osu! fills in the missing information the next time it processes the beatmap.
+    ///
+    /// Only available with the `json` feature enabled.
+    pub fn from_api_v2_json(value: &Value) -> Result<Beatmap, Error> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| serde_json::Error::custom("expected a json object"))?;
+        let empty = Map::new();
+        let set = obj
+            .get("beatmapset")
+            .and_then(Value::as_object)
+            .unwrap_or(&empty);
+
+        let mode = match obj.get("mode").and_then(Value::as_str) {
+            Some("osu") => Mode::Standard,
+            Some("taiko") => Mode::Taiko,
+            Some("fruits") => Mode::CatchTheBeat,
+            Some("mania") => Mode::Mania,
+            _ => return Err(missing("mode").into()),
+        };
+        let status = match obj.get("status").and_then(Value::as_str) {
+            Some("graveyard") | Some("wip") | Some("pending") => RankedStatus::PendingWipGraveyard,
+            Some("ranked") => RankedStatus::Ranked,
+            Some("approved") => RankedStatus::Approved,
+            Some("qualified") => RankedStatus::Qualified,
+            Some("loved") => RankedStatus::Loved,
+            _ => return Err(missing("status").into()),
+        };
+        let beatmap_id = BeatmapId(get_i32(obj, "id")?);
+        let beatmapset_id = BeatmapsetId(get_i32(obj, "beatmapset_id")?);
+        let stars = vec![(ModSet::empty(), get_f64(obj, "difficulty_rating")?)];
+        let (mut std_ratings, mut taiko_ratings, mut ctb_ratings, mut mania_ratings) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        match mode {
+            Mode::Standard => std_ratings = stars,
+            Mode::Taiko => taiko_ratings = stars,
+            Mode::CatchTheBeat => ctb_ratings = stars,
+            Mode::Mania => mania_ratings = stars,
+        }
+        let last_modified = match obj.get("last_updated").and_then(Value::as_str) {
+            Some(date) => OsuTimestamp::parse_rfc3339(date).map_err(|_| missing("last_updated"))?,
+            None => OsuTimestamp::now(),
+        };
+
+        let artist = get_string(set, "artist")?;
+        let title = get_string(set, "title")?;
+        let creator = get_string(set, "creator")?;
+        let version = get_string(obj, "version")?;
+        let file_name = format!(
+            "{} - {} ({}) [{}].osu",
+            artist.as_deref().unwrap_or_default(),
+            title.as_deref().unwrap_or_default(),
+            creator.as_deref().unwrap_or_default(),
+            version.as_deref().unwrap_or_default(),
+        );
+        let folder_name = default_folder_name(
+            beatmapset_id,
+            artist.as_deref().unwrap_or_default(),
+            title.as_deref().unwrap_or_default(),
+        );
+
+        Ok(Beatmap {
+            artist_unicode: get_string(set, "artist_unicode")?,
+            artist_ascii: artist,
+            title_unicode: get_string(set, "title_unicode")?,
+            title_ascii: title,
+            creator,
+            difficulty_name: version,
+            audio: None,
+            hash: get_hash(obj, "checksum")?,
+            file_name: Some(sanitize_file_name(&file_name)),
+            status,
+            hitcircle_count: get_uint(obj, "count_circles")?,
+            slider_count: get_uint(obj, "count_sliders")?,
+            spinner_count: get_uint(obj, "count_spinners")?,
+            last_modified,
+            approach_rate: get_f64(obj, "ar")? as f32,
+            circle_size: get_f64(obj, "cs")? as f32,
+            hp_drain: get_f64(obj, "drain")? as f32,
+            overall_difficulty: get_f64(obj, "accuracy")? as f32,
+            slider_velocity: DEFAULT_SLIDER_VELOCITY,
+            std_ratings,
+            taiko_ratings,
+            ctb_ratings,
+            mania_ratings,
+            drain_time: get_uint(obj, "hit_length")?,
+            total_time: get_uint::<u32>(obj, "total_length")?
+                .checked_mul(1000)
+                .ok_or_else(|| out_of_range("total_length"))?,
+            //osu! stores -1 when there is no preview point
+            preview_time: u32::MAX,
+            timing_points: Vec::new(),
+            beatmap_id,
+            beatmapset_id,
+            thread_id: 0,
+            std_grade: Grade::Unplayed,
+            taiko_grade: Grade::Unplayed,
+            ctb_grade: Grade::Unplayed,
+            mania_grade: Grade::Unplayed,
+            local_beatmap_offset: 0,
+            stack_leniency: DEFAULT_STACK_LENIENCY,
+            mode,
+            song_source: get_string(set, "source")?,
+            tags: get_string(set, "tags")?,
+            online_offset: 0,
+            title_font: None,
+            last_played: None,
+            is_osz2: false,
+            folder_name: Some(folder_name),
+            last_online_check: OsuTimestamp::now(),
+            ignore_sounds: false,
+            ignore_skin: false,
+            disable_storyboard: false,
+            disable_video: false,
+            visual_override: false,
+            mysterious_short: None,
+            mysterious_last_modified: 0,
+            mania_scroll_speed: 0,
+        })
+    }
+}
+
+fn get_f64(obj: &Map<String, Value>, field: &str) -> Result<f64, serde_json::Error> {
+    obj.get(field)
+        .and_then(Value::as_f64)
+        .ok_or_else(|| missing(field))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn from_api_v2_json() {
+        let value = json!({
+            "beatmapset_id": 1011011,
+            "difficulty_rating": 6.42,
+            "id": 2116202,
+            "mode": "osu",
+            "status": "ranked",
+            "total_length": 215,
+            "user_id": 1,
+            "version": "Extra",
+            "accuracy": 9,
+            "ar": 9.4,
+            "bpm": 200,
+            "convert": false,
+            "count_circles": 600,
+            "count_sliders": 300,
+            "count_spinners": 1,
+            "cs": 4,
+            "drain": 5.5,
+            "hit_length": 210,
+            "is_scoreable": true,
+            "last_updated": "2019-08-05T14:45:22+00:00",
+            "mode_int": 0,
+            "checksum": "0123456789abcdef0123456789abcdef",
+            "max_combo": 1500,
+            "beatmapset": {
+                "artist": "Artist",
+                "artist_unicode": "アーティスト",
+                "creator": "Mapper",
+                "source": "",
+                "tags": "some tags",
+                "title": "Title: Subtitle",
+                "title_unicode": "タイトル",
+                "id": 1011011
+            }
+        });
+        let bm = Beatmap::from_api_v2_json(&value).unwrap();
+        assert_eq!(bm.beatmap_id, BeatmapId(2116202));
+        assert_eq!(bm.beatmapset_id, BeatmapsetId(1011011));
+        assert_eq!(bm.status, RankedStatus::Ranked);
+        assert_eq!(bm.std_ratings, vec![(ModSet::empty(), 6.42)]);
+        assert_eq!(bm.overall_difficulty, 9.0);
+        assert_eq!(bm.total_time, 215_000);
+        assert_eq!(bm.drain_time, 210);
+        assert_eq!(bm.artist_unicode.as_deref(), Some("アーティスト"));
+        assert_eq!(
+            bm.file_name.as_deref(),
+            Some("Artist - Title Subtitle (Mapper) [Extra].osu")
+        );
+        assert_eq!(
+            bm.folder_name.as_deref(),
+            Some("1011011 Artist - Title Subtitle")
+        );
+        //2019-08-05T14:45:22Z
+        assert_eq!(
+            bm.last_modified,
+            OsuTimestamp::from_unix_secs(1_565_016_322)
+        );
+
+        let mut huge = value.clone();
+        huge["id"] = json!(5_000_000_000_u64);
+        assert!(Beatmap::from_api_v2_json(&huge).is_err());
+        //Values that do not fit are rejected, including lengths that only overflow once converted
+        //from seconds to milliseconds
+        for (field, too_large) in [
+            ("total_length", json!(5_000_000)),
+            ("hit_length", json!(5_000_000_000_u64)),
+            ("count_circles", json!(70_000)),
+        ] {
+            let mut huge = value.clone();
+            huge[field] = too_large;
+            match Beatmap::from_api_v2_json(&huge) {
+                Err(Error::Json(err)) => assert!(err.to_string().contains(field), "{}", err),
+                other => panic!("expected a json error, got {:?}", other),
+            }
+        }
+
+        let mut partial = value;
+        partial.as_object_mut().unwrap().remove("ar");
+        assert!(Beatmap::from_api_v2_json(&partial).is_err());
+    }
+}
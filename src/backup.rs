@@ -0,0 +1,495 @@
+//! Backups of the osu! databases, snapshotting `osu!.db`, `scores.db` and `collection.db` together
+//! into a single zip archive that can be verified and restored later.
+//!
+//! Besides the database files themselves, kept byte-for-byte, the archive holds a `manifest.txt`
+//! with the version, entry count, size and MD5 hash of each database.
+//! Databases are parsed before being backed up, so that corrupt databases are never saved over a
+//! good backup, and again before being restored.
+//!
+//! Only available with the `backup` feature enabled.
+
+use crate::prelude::*;
+use crate::{
+    visit::{parse_listing_events, HeaderField, ListingVisitor},
+    CollectionList, Database, ScoreList,
+};
+use std::io::{Read, Seek};
+use zip::{result::ZipError, write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// The name of the manifest file within a backup archive.
+pub const MANIFEST_NAME: &str = "manifest.txt";
+
+/// The largest manifest that is read, far larger than the few lines of any real one.
+const MAX_MANIFEST_SIZE: u64 = 64 * 1024;
+
+/// The first line of every manifest, identifying the format.
+const MANIFEST_HEADER: &str = "osu-db backup 1";
+
+/// The contents of a backup, as described by its manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupManifest {
+    /// When the backup was taken.
+    pub created: OsuTimestamp,
+    /// One entry per database in the backup.
+    /// Databases that did not exist when the backup was taken are not listed.
+    pub files: Vec<BackupEntry>,
+}
+
+/// A single database within a backup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupEntry {
+    pub database: Database,
+    /// The version of the database.
+    pub version: u32,
+    /// The amount of beatmaps, scores or collections in the database.
+    pub entries: usize,
+    /// The size of the database file, in bytes.
+    pub size: u64,
+    /// The MD5 hash of the database file.
+    pub hash: Md5Hash,
+}
+
+/// A problem that makes a backup archive unusable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackupIssue {
+    /// The archive has no manifest.
+    MissingManifest,
+    /// The manifest could not be understood, at the given 1-based line.
+    InvalidManifest { line: usize },
+    /// A database listed in the manifest is not in the archive.
+    MissingFile(Database),
+    /// A database in the archive does not match the size or hash in the manifest.
+    ChecksumMismatch(Database),
+    /// A database in the archive does not match the version or entry count in the manifest.
+    ContentMismatch(Database),
+}
+impl fmt::Display for BackupIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupIssue::MissingManifest => write!(f, "missing {}", MANIFEST_NAME),
+            BackupIssue::InvalidManifest { line } => {
+                write!(f, "invalid {} at line {}", MANIFEST_NAME, line)
+            }
+            BackupIssue::MissingFile(db) => write!(f, "missing {}", db.file_name()),
+            BackupIssue::ChecksumMismatch(db) => {
+                write!(f, "checksum mismatch in {}", db.file_name())
+            }
+            BackupIssue::ContentMismatch(db) => {
+                write!(f, "{} does not match the manifest", db.file_name())
+            }
+        }
+    }
+}
+
+/// A consistent set of database files, along with their manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub manifest: BackupManifest,
+    files: Vec<(Database, Vec<u8>)>,
+}
+impl Snapshot {
+    /// Take a snapshot of the databases within the given osu! directory.
+    ///
+    /// Databases that do not exist are skipped, but databases that fail to parse are an error.
+    /// osu! rewrites its databases when closing, so it should not be running while the snapshot
+    /// is taken.
+    pub fn from_dir<P: AsRef<Path>>(osu_dir: P) -> Result<Snapshot, Error> {
+        let osu_dir = osu_dir.as_ref();
+        let mut files = Vec::new();
+        for &db in &Database::ALL {
+            match fs::read(osu_dir.join(db.file_name())) {
+                Ok(bytes) => files.push((db, bytes)),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Snapshot::from_files(files)
+    }
+
+    /// Take a snapshot out of the raw bytes of each database, such as databases kept in memory.
+    ///
+    /// Every database is parsed to fill in the manifest, which fails if any is corrupt.
+    /// If a database is given more than once, the last one is kept.
+    pub fn from_files<I>(files: I) -> Result<Snapshot, Error>
+    where
+        I: IntoIterator<Item = (Database, Vec<u8>)>,
+    {
+        let mut snapshot = Snapshot {
+            manifest: BackupManifest {
+                created: OsuTimestamp::now(),
+                files: Vec::new(),
+            },
+            files: Vec::new(),
+        };
+        for (db, bytes) in files {
+            let (version, entries) = inspect(db, &bytes)?;
+            let entry = BackupEntry {
+                database: db,
+                version,
+                entries,
+                size: bytes.len() as u64,
+                hash: Md5Hash::compute(&bytes),
+            };
+            snapshot.manifest.files.retain(|entry| entry.database != db);
+            snapshot.files.retain(|(other, _)| *other != db);
+            snapshot.manifest.files.push(entry);
+            snapshot.files.push((db, bytes));
+        }
+        Ok(snapshot)
+    }
+
+    /// Read and verify a backup archive.
+    ///
+    /// Every database is checked against the manifest, both by checksum and by parsing it, so
+    /// a successfully read snapshot can be safely restored.
+    /// Problems with the archive are reported as `Error::Backup`.
+    pub fn from_reader<R: Read + Seek>(archive: R) -> Result<Snapshot, Error> {
+        let mut archive = ZipArchive::new(archive)?;
+        let manifest = match read_entry(&mut archive, MANIFEST_NAME, MAX_MANIFEST_SIZE)? {
+            Some(raw) if raw.len() as u64 > MAX_MANIFEST_SIZE => {
+                return Err(BackupIssue::InvalidManifest { line: 1 }.into())
+            }
+            Some(raw) => parse_manifest(&raw)?,
+            None => return Err(BackupIssue::MissingManifest.into()),
+        };
+        let mut files = Vec::new();
+        for entry in &manifest.files {
+            let db = entry.database;
+            let bytes = read_entry(&mut archive, db.file_name(), entry.size)?
+                .ok_or(BackupIssue::MissingFile(db))?;
+            if bytes.len() as u64 != entry.size || Md5Hash::compute(&bytes) != entry.hash {
+                return Err(BackupIssue::ChecksumMismatch(db).into());
+            }
+            if inspect(db, &bytes)? != (entry.version, entry.entries) {
+                return Err(BackupIssue::ContentMismatch(db).into());
+            }
+            files.push((db, bytes));
+        }
+        Ok(Snapshot { manifest, files })
+    }
+
+    /// Read and verify a backup archive from a file.
+    ///
+    /// See `from_reader` for details.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Snapshot, Error> {
+        Snapshot::from_reader(File::open(path)?)
+    }
+
+    /// Write the snapshot as a backup archive.
+    pub fn to_writer<W: Write + Seek>(&self, out: W) -> Result<W, Error> {
+        let mut zip = ZipWriter::new(out);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file(MANIFEST_NAME, options)?;
+        write_manifest(&self.manifest, &mut zip)?;
+        for (db, bytes) in &self.files {
+            zip.start_file(db.file_name(), options)?;
+            zip.write_all(bytes)?;
+        }
+        Ok(zip.finish()?)
+    }
+
+    /// Write the snapshot as a backup archive into a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.to_writer(File::create(path)?)?;
+        Ok(())
+    }
+
+    /// The raw bytes of a database within the snapshot, if it was backed up.
+    pub fn file(&self, db: Database) -> Option<&[u8]> {
+        self.files
+            .iter()
+            .find(|(other, _)| *other == db)
+            .map(|(_, bytes)| &bytes[..])
+    }
+
+    /// Restore the databases in the snapshot into the given osu! directory, overwriting the
+    /// current ones.
+    ///
+    /// Every database is first written next to its destination, as `<name>.restore`, and only
+    /// once all of them are written are they renamed over the current ones.
+    /// The current databases are kept as `<name>.bak`, replacing any previous ones, so that if a
+    /// rename fails partway the databases restored so far are rolled back, and the osu!
+    /// directory is left as it was.
+    /// Databases that are not in the snapshot are left untouched.
+    /// osu! should not be running, or it will overwrite the restored databases when closing.
+    pub fn restore<P: AsRef<Path>>(&self, osu_dir: P) -> Result<(), Error> {
+        let osu_dir = osu_dir.as_ref();
+        let path =
+            |db: Database, suffix: &str| osu_dir.join(format!("{}{}", db.file_name(), suffix));
+        let remove_temps = || {
+            for (db, _) in &self.files {
+                let _ = fs::remove_file(path(*db, ".restore"));
+            }
+        };
+        for (db, bytes) in &self.files {
+            if let Err(err) = fs::write(path(*db, ".restore"), bytes) {
+                remove_temps();
+                return Err(err.into());
+            }
+        }
+        //The databases swapped in so far, and whether each replaced an existing one
+        let mut swapped = Vec::new();
+        for (db, _) in &self.files {
+            let db = *db;
+            match swap_in(&path(db, ".restore"), &path(db, ""), &path(db, ".bak")) {
+                Ok(replaced) => swapped.push((db, replaced)),
+                Err(err) => {
+                    //Best effort, as there is nothing more to do if rolling back fails too
+                    for &(db, replaced) in swapped.iter().rev() {
+                        let _ = if replaced {
+                            fs::rename(path(db, ".bak"), path(db, ""))
+                        } else {
+                            fs::remove_file(path(db, ""))
+                        };
+                    }
+                    remove_temps();
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Move a restored database into place, keeping the current one as a backup.
+///
+/// Returns whether there was a current database.
+fn swap_in(restored: &Path, current: &Path, backup: &Path) -> io::Result<bool> {
+    let replaced = match fs::rename(current, backup) {
+        Ok(()) => true,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+        Err(err) => return Err(err),
+    };
+    if let Err(err) = fs::rename(restored, current) {
+        if replaced {
+            let _ = fs::rename(backup, current);
+        }
+        return Err(err);
+    }
+    Ok(replaced)
+}
+
+/// Parse a database to find out its version and amount of entries.
+fn inspect(db: Database, bytes: &[u8]) -> Result<(u32, usize), Error> {
+    match db {
+        Database::Listing => {
+            struct Header(u32, usize);
+            impl ListingVisitor for Header {
+                fn header(&mut self, field: HeaderField) {
+                    match field {
+                        HeaderField::Version(version) => self.0 = version,
+                        HeaderField::BeatmapCount(count) => self.1 = count as usize,
+                        _ => {}
+                    }
+                }
+            }
+            let mut header = Header(0, 0);
+            parse_listing_events(bytes, &mut header)?;
+            Ok((header.0, header.1))
+        }
+        Database::Scores => {
+            let scores = ScoreList::from_bytes(bytes)?;
+            let count = scores.beatmaps.iter().map(|bm| bm.scores.len()).sum();
+            Ok((scores.version, count))
+        }
+        Database::Collections => {
+            let collections = CollectionList::from_bytes(bytes)?;
+            Ok((collections.version, collections.collections.len()))
+        }
+    }
+}
+
+/// Read a file out of the archive, reading at most one byte past `limit` so that a file larger
+/// than expected is detected without reading all of it.
+fn read_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+    limit: u64,
+) -> Result<Option<Vec<u8>>, Error> {
+    let entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut bytes = Vec::new();
+    entry
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut bytes)?;
+    Ok(Some(bytes))
+}
+
+fn write_manifest<W: Write>(manifest: &BackupManifest, out: &mut W) -> io::Result<()> {
+    writeln!(out, "{}", MANIFEST_HEADER)?;
+    writeln!(out, "created {}", manifest.created.ticks())?;
+    for entry in &manifest.files {
+        writeln!(
+            out,
+            "{} {} {} {} {}",
+            entry.database.file_name(),
+            entry.version,
+            entry.entries,
+            entry.size,
+            entry.hash
+        )?;
+    }
+    Ok(())
+}
+
+fn parse_manifest(raw: &[u8]) -> Result<BackupManifest, BackupIssue> {
+    let text = core::str::from_utf8(raw).map_err(|_| BackupIssue::InvalidManifest { line: 1 })?;
+    let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line));
+    let invalid = |line| BackupIssue::InvalidManifest { line };
+    match lines.next() {
+        Some((_, MANIFEST_HEADER)) => {}
+        _ => return Err(invalid(1)),
+    }
+    let created = match lines.next() {
+        Some((num, line)) => line
+            .strip_prefix("created ")
+            .and_then(|ticks| ticks.parse().ok())
+            .map(OsuTimestamp)
+            .ok_or_else(|| invalid(num))?,
+        None => return Err(invalid(2)),
+    };
+    let mut files = Vec::new();
+    for (num, line) in lines {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let entry = match fields[..] {
+            [name, version, entries, size, hash] => (|| {
+                Some(BackupEntry {
+                    database: *Database::ALL.iter().find(|db| db.file_name() == name)?,
+                    version: version.parse().ok()?,
+                    entries: entries.parse().ok()?,
+                    size: size.parse().ok()?,
+                    hash: hash.parse().ok()?,
+                })
+            })(),
+            _ => None,
+        };
+        files.push(entry.ok_or_else(|| invalid(num))?);
+    }
+    Ok(BackupManifest { created, files })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        collection::Collection, ids::test::hash, listing::test::beatmap, replay::test::replay_with,
+        score::BeatmapScores, Listing,
+    };
+    use std::io::Cursor;
+
+    fn databases() -> Vec<(Database, Vec<u8>)> {
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: Some("player".to_string()),
+            beatmaps: vec![beatmap(), beatmap()],
+            user_permissions: 1,
+        };
+        let scores = ScoreList {
+            version: 20211103,
+            beatmaps: vec![BeatmapScores {
+                hash: hash(1).into(),
+                scores: vec![replay_with(Vec::new()).into_score_entry(); 3],
+            }],
+        };
+        let collections = CollectionList {
+            version: 20211103,
+            collections: vec![Collection {
+                name: Some("favourites".to_string()),
+                beatmap_hashes: vec![hash(1).into()],
+            }],
+        };
+        let mut raw = (Vec::new(), Vec::new(), Vec::new());
+        listing.to_writer(&mut raw.0).unwrap();
+        scores.to_writer(&mut raw.1).unwrap();
+        collections.to_writer(&mut raw.2).unwrap();
+        vec![
+            (Database::Listing, raw.0),
+            (Database::Scores, raw.1),
+            (Database::Collections, raw.2),
+        ]
+    }
+
+    #[test]
+    fn snapshot() {
+        let snapshot = Snapshot::from_files(databases()).unwrap();
+        let counts: Vec<(Database, usize)> = snapshot
+            .manifest
+            .files
+            .iter()
+            .map(|entry| (entry.database, entry.entries))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                (Database::Listing, 2),
+                (Database::Scores, 3),
+                (Database::Collections, 1)
+            ]
+        );
+
+        let archive = snapshot.to_writer(Cursor::new(Vec::new())).unwrap();
+        let read = Snapshot::from_reader(Cursor::new(archive.get_ref())).unwrap();
+        assert_eq!(read, snapshot);
+        assert_eq!(read.file(Database::Scores), Some(&databases()[1].1[..]));
+
+        let mut tampered = snapshot.clone();
+        tampered.manifest.files[1].hash = hash(9);
+        let archive = tampered.to_writer(Cursor::new(Vec::new())).unwrap();
+        match Snapshot::from_reader(Cursor::new(archive.into_inner())) {
+            Err(Error::Backup(BackupIssue::ChecksumMismatch(Database::Scores))) => {}
+            other => panic!("expected checksum mismatch, got {:?}", other),
+        }
+
+        let mut corrupt = databases();
+        corrupt[0].1.truncate(100);
+        assert!(Snapshot::from_files(corrupt).is_err());
+    }
+
+    #[test]
+    fn restore() {
+        let dir = std::env::temp_dir().join(format!("osu-db-restore-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let file = |name: &str| fs::read(dir.join(name)).ok();
+        fs::write(dir.join("osu!.db"), b"old listing").unwrap();
+        fs::write(dir.join("scores.db"), b"old scores").unwrap();
+        let snapshot = Snapshot::from_files(databases()).unwrap();
+
+        //A backup that cannot be replaced makes the restore fail partway, and roll back
+        fs::create_dir(dir.join("scores.db.bak")).unwrap();
+        fs::write(dir.join("scores.db.bak").join("keep"), b"").unwrap();
+        assert!(snapshot.restore(&dir).is_err());
+        assert_eq!(file("osu!.db").as_deref(), Some(&b"old listing"[..]));
+        assert_eq!(file("scores.db").as_deref(), Some(&b"old scores"[..]));
+        assert_eq!(file("collection.db"), None);
+        assert_eq!(file("osu!.db.restore"), None);
+        assert_eq!(file("collection.db.restore"), None);
+
+        fs::remove_dir_all(dir.join("scores.db.bak")).unwrap();
+        snapshot.restore(&dir).unwrap();
+        for (db, bytes) in databases() {
+            assert_eq!(file(db.file_name()), Some(bytes));
+        }
+        assert_eq!(file("osu!.db.bak").as_deref(), Some(&b"old listing"[..]));
+        assert_eq!(file("scores.db.bak").as_deref(), Some(&b"old scores"[..]));
+        assert_eq!(file("collection.db.bak"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_entry() {
+        let snapshot = Snapshot::from_files(databases()).unwrap();
+        let mut lying = snapshot.clone();
+        lying.manifest.files[2].size -= 1;
+        let archive = lying.to_writer(Cursor::new(Vec::new())).unwrap();
+        match Snapshot::from_reader(Cursor::new(archive.into_inner())) {
+            Err(Error::Backup(BackupIssue::ChecksumMismatch(Database::Collections))) => {}
+            other => panic!("expected checksum mismatch, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Lists of beatmapset IDs, such as the ones consumed by mirror batch-downloaders to restore a
+//! song library on another machine.
+
+use crate::{listing::Beatmap, prelude::*, CollectionList, Listing};
+use std::collections::{BTreeSet, HashSet};
+
+/// A sorted list of unique beatmapset IDs.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BeatmapsetIds {
+    /// The beatmapset IDs, in ascending order and without duplicates.
+    pub ids: Vec<BeatmapsetId>,
+    /// Hashes of the beatmaps that were requested but could not be mapped to a beatmapset ID,
+    /// because they are not in the listing or are not submitted.
+    pub unresolved: Vec<Md5Hash>,
+}
+impl BeatmapsetIds {
+    fn collect<'a, I>(beatmaps: I) -> BeatmapsetIds
+    where
+        I: IntoIterator<Item = &'a Beatmap>,
+    {
+        let mut ids = BTreeSet::new();
+        let mut unresolved = Vec::new();
+        for bm in beatmaps {
+            if bm.beatmapset_id.is_submitted() {
+                ids.insert(bm.beatmapset_id);
+            } else if let Some(hash) = bm.hash.md5() {
+                unresolved.push(hash);
+            }
+        }
+        BeatmapsetIds {
+            ids: ids.into_iter().collect(),
+            unresolved,
+        }
+    }
+
+    /// Write the IDs as plain text, one per line.
+    pub fn write_text<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for id in &self.ids {
+            writeln!(out, "{}", id)?;
+        }
+        Ok(())
+    }
+
+    /// Write the IDs as a JSON array of numbers.
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        out.write_all(b"[")?;
+        for (i, id) in self.ids.iter().enumerate() {
+            if i != 0 {
+                out.write_all(b",")?;
+            }
+            write!(out, "{}", id)?;
+        }
+        out.write_all(b"]")?;
+        Ok(())
+    }
+}
+
+impl Listing {
+    /// Get the beatmapsets that are present in this listing but not in `other`.
+    ///
+    /// Typically `self` is the listing of an old installation and `other` the listing of a new
+    /// one, so the result is the list of beatmapsets that still have to be downloaded.
+    /// Beatmapsets are compared by ID, and beatmaps without an ID (unsubmitted maps) are
+    /// reported as unresolved.
+    pub fn missing_from(&self, other: &Listing) -> BeatmapsetIds {
+        let present: HashSet<BeatmapsetId> =
+            other.beatmaps.iter().map(|bm| bm.beatmapset_id).collect();
+        BeatmapsetIds::collect(
+            self.beatmaps.iter().filter(|bm| {
+                !bm.beatmapset_id.is_submitted() || !present.contains(&bm.beatmapset_id)
+            }),
+        )
+    }
+}
+
+impl CollectionList {
+    /// Get the beatmapsets needed for all beatmaps in all collections, looking up their hashes in
+    /// the given listing.
+    ///
+    /// Hashes that are not in the listing, or whose beatmap has no beatmapset ID, are reported as
+    /// unresolved.
+    pub fn required_beatmapsets(&self, listing: &Listing) -> BeatmapsetIds {
+        let index = listing.hash_index();
+        let mut ids = BTreeSet::new();
+        let mut unresolved = BTreeSet::new();
+        let hashes = self
+            .collections
+            .iter()
+            .flat_map(|c| &c.beatmap_hashes)
+            .filter_map(StoredHash::md5);
+        for hash in hashes {
+            match index.get(&hash).map(|idx| &listing.beatmaps[idx]) {
+                Some(bm) if bm.beatmapset_id.is_submitted() => {
+                    ids.insert(bm.beatmapset_id);
+                }
+                _ => {
+                    unresolved.insert(hash);
+                }
+            }
+        }
+        BeatmapsetIds {
+            ids: ids.into_iter().collect(),
+            unresolved: unresolved.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{collection::Collection, ids::test::hash, listing::test::beatmap};
+
+    fn with_set(n: u8, set: i32) -> Beatmap {
+        let mut bm = beatmap();
+        bm.hash = hash(n).into();
+        bm.beatmapset_id = BeatmapsetId(set);
+        bm
+    }
+
+    fn listing(beatmaps: Vec<Beatmap>) -> Listing {
+        Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps,
+            user_permissions: 0,
+        }
+    }
+
+    #[test]
+    fn missing_from() {
+        let old = listing(vec![
+            with_set(1, 30),
+            with_set(2, 10),
+            with_set(3, 10),
+            with_set(4, 20),
+            with_set(5, -1),
+        ]);
+        let new = listing(vec![with_set(6, 20)]);
+        let missing = old.missing_from(&new);
+        assert_eq!(missing.ids, vec![BeatmapsetId(10), BeatmapsetId(30)]);
+        assert_eq!(missing.unresolved, vec![hash(5)]);
+
+        let mut text = Vec::new();
+        missing.write_text(&mut text).unwrap();
+        assert_eq!(text, b"10\n30\n");
+        let mut json = Vec::new();
+        missing.write_json(&mut json).unwrap();
+        assert_eq!(json, b"[10,30]");
+    }
+
+    #[test]
+    fn required_beatmapsets() {
+        let local = listing(vec![with_set(1, 5), with_set(2, 3)]);
+        let collections = CollectionList {
+            version: 20211103,
+            collections: vec![Collection {
+                name: Some("pool".to_string()),
+                beatmap_hashes: vec![hash(1).into(), hash(9).into(), StoredHash::Missing],
+            }],
+        };
+        let required = collections.required_beatmapsets(&local);
+        assert_eq!(required.ids, vec![BeatmapsetId(5)]);
+        assert_eq!(required.unresolved, vec![hash(9)]);
+    }
+}
@@ -0,0 +1,356 @@
+//! Cross-referencing of the listing, score and collection databases, catching references that no
+//! longer line up, such as after deleting beatmaps or restoring a single database from a backup.
+//!
+//! Unlike the `validate` module, which checks each database on its own, this looks at all three
+//! together.
+
+use crate::{listing::Grade, prelude::*, CollectionList, Database, Listing, ScoreList};
+use alloc::collections::{BTreeMap, BTreeSet};
+
+/// A single inconsistency between databases.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// Scores are stored for a beatmap hash that is not present in the listing.
+    ScoresForMissingBeatmap { hash: Md5Hash, scores: usize },
+    /// A collection contains a beatmap hash that is not present in the listing.
+    CollectionForMissingBeatmap {
+        /// The index of the collection within the collection list.
+        collection: usize,
+        name: Option<String>,
+        hash: Md5Hash,
+    },
+    /// A beatmap has a grade in some mode, but there are no scores in that mode to back it.
+    GradeWithoutScore {
+        /// The index of the beatmap within the listing.
+        beatmap: usize,
+        mode: Mode,
+        grade: Grade,
+    },
+    /// The same beatmap hash appears more than once in the listing, or is split across more than
+    /// one group of scores.
+    DuplicateHash {
+        database: Database,
+        hash: Md5Hash,
+        count: usize,
+    },
+    /// The same beatmap hash appears more than once within a single collection.
+    DuplicateCollectionEntry {
+        /// The index of the collection within the collection list.
+        collection: usize,
+        hash: Md5Hash,
+        count: usize,
+    },
+}
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Finding::*;
+        match self {
+            ScoresForMissingBeatmap { hash, scores } => write!(
+                f,
+                "{} scores for beatmap {}, which is not in the listing",
+                scores, hash
+            ),
+            CollectionForMissingBeatmap {
+                collection,
+                name,
+                hash,
+            } => write!(
+                f,
+                "collection #{} ({}) contains beatmap {}, which is not in the listing",
+                collection,
+                name.as_deref().unwrap_or("unnamed"),
+                hash
+            ),
+            GradeWithoutScore {
+                beatmap,
+                mode,
+                grade,
+            } => write!(
+                f,
+                "beatmap #{} has {:?} grade {:?} but no {:?} scores",
+                beatmap, mode, grade, mode
+            ),
+            DuplicateHash {
+                database,
+                hash,
+                count,
+            } => write!(
+                f,
+                "beatmap {} appears {} times in {}",
+                hash,
+                count,
+                database.file_name()
+            ),
+            DuplicateCollectionEntry {
+                collection,
+                hash,
+                count,
+            } => write!(
+                f,
+                "beatmap {} appears {} times in collection #{}",
+                hash, count, collection
+            ),
+        }
+    }
+}
+
+/// Every inconsistency found between a listing, a score list and a collection list, as returned
+/// by `full_report`.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IntegrityReport {
+    /// Scores for missing beatmaps come first, followed by collections referencing missing
+    /// beatmaps, grades without scores and duplicate hashes, each in database order.
+    pub findings: Vec<Finding>,
+}
+impl IntegrityReport {
+    /// Whether the databases are consistent with each other.
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+impl fmt::Display for IntegrityReport {
+    /// Writes one finding per line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_clean() {
+            return f.write_str("no issues found");
+        }
+        for (idx, finding) in self.findings.iter().enumerate() {
+            if idx > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+/// Cross-reference the three osu! databases against each other.
+///
+/// Looks for scores and collection entries that point at beatmaps missing from the listing,
+/// beatmaps claiming a grade in a mode with no local scores, and beatmap hashes that appear more
+/// than once where they should be unique.
+/// Entries without a hash are skipped.
+pub fn full_report(
+    listing: &Listing,
+    scores: &ScoreList,
+    collections: &CollectionList,
+) -> IntegrityReport {
+    let local = listing.hash_index();
+    let mut findings = Vec::new();
+
+    for bm in &scores.beatmaps {
+        if let Some(hash) = bm.hash.md5() {
+            if !local.contains(&hash) {
+                findings.push(Finding::ScoresForMissingBeatmap {
+                    hash,
+                    scores: bm.scores.len(),
+                });
+            }
+        }
+    }
+
+    for (idx, collection) in collections.collections.iter().enumerate() {
+        let mut reported = BTreeSet::new();
+        for hash in collection.beatmap_hashes.iter().filter_map(StoredHash::md5) {
+            if !local.contains(&hash) && reported.insert(hash) {
+                findings.push(Finding::CollectionForMissingBeatmap {
+                    collection: idx,
+                    name: collection.name.clone(),
+                    hash,
+                });
+            }
+        }
+    }
+
+    let mut played = BTreeSet::new();
+    for bm in &scores.beatmaps {
+        for score in &bm.scores {
+            if let Some(hash) = score.beatmap_hash.md5().or_else(|| bm.hash.md5()) {
+                played.insert((hash, score.mode.raw()));
+            }
+        }
+    }
+    for (idx, bm) in listing.beatmaps.iter().enumerate() {
+        let hash = match bm.hash.md5() {
+            Some(hash) => hash,
+            None => continue,
+        };
+        for &mode in &[Mode::Standard, Mode::Taiko, Mode::CatchTheBeat, Mode::Mania] {
+            let grade = bm.grade(mode);
+            if grade != Grade::Unplayed && !played.contains(&(hash, mode.raw())) {
+                findings.push(Finding::GradeWithoutScore {
+                    beatmap: idx,
+                    mode,
+                    grade,
+                });
+            }
+        }
+    }
+
+    let duplicates = |hashes: &mut dyn Iterator<Item = Md5Hash>| {
+        let mut counts = BTreeMap::new();
+        let mut order = Vec::new();
+        for hash in hashes {
+            let count = counts.entry(hash).or_insert(0);
+            if *count == 1 {
+                order.push(hash);
+            }
+            *count += 1;
+        }
+        order
+            .into_iter()
+            .map(|hash| (hash, counts[&hash]))
+            .collect::<Vec<_>>()
+    };
+    for (database, hashes) in [
+        (
+            Database::Listing,
+            duplicates(&mut listing.beatmaps.iter().filter_map(|bm| bm.hash.md5())),
+        ),
+        (
+            Database::Scores,
+            duplicates(&mut scores.beatmaps.iter().filter_map(|bm| bm.hash.md5())),
+        ),
+    ] {
+        for (hash, count) in hashes {
+            findings.push(Finding::DuplicateHash {
+                database,
+                hash,
+                count,
+            });
+        }
+    }
+    for (idx, collection) in collections.collections.iter().enumerate() {
+        let mut hashes = collection.beatmap_hashes.iter().filter_map(StoredHash::md5);
+        for (hash, count) in duplicates(&mut hashes) {
+            findings.push(Finding::DuplicateCollectionEntry {
+                collection: idx,
+                hash,
+                count,
+            });
+        }
+    }
+
+    IntegrityReport { findings }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        collection::Collection, ids::test::hash, listing::test::beatmap, replay::test::replay_with,
+        score::BeatmapScores, Replay,
+    };
+
+    fn score(n: u8, mode: Mode) -> Replay {
+        let mut score = replay_with(Vec::new()).into_score_entry();
+        score.beatmap_hash = hash(n).into();
+        score.mode = mode;
+        score
+    }
+
+    #[test]
+    fn full_report() {
+        let mut beatmaps = Vec::new();
+        for n in [1, 2, 2] {
+            let mut bm = beatmap();
+            bm.hash = hash(n).into();
+            for &mode in &[Mode::Standard, Mode::Taiko, Mode::CatchTheBeat, Mode::Mania] {
+                *bm.grade_mut(mode) = Grade::Unplayed;
+            }
+            beatmaps.push(bm);
+        }
+        beatmaps[0].std_grade = Grade::A;
+        beatmaps[0].taiko_grade = Grade::S;
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps,
+            user_permissions: 0,
+        };
+        let group = |n, scores| BeatmapScores {
+            hash: hash(n).into(),
+            scores,
+        };
+        let scores = ScoreList {
+            version: 20211103,
+            beatmaps: vec![
+                group(1, vec![score(1, Mode::Standard)]),
+                group(3, vec![score(3, Mode::Standard), score(3, Mode::Mania)]),
+                group(1, Vec::new()),
+            ],
+        };
+        let collections = CollectionList {
+            version: 20211103,
+            collections: vec![Collection {
+                name: Some("favourites".to_string()),
+                beatmap_hashes: vec![
+                    hash(1).into(),
+                    hash(4).into(),
+                    hash(4).into(),
+                    StoredHash::Missing,
+                ],
+            }],
+        };
+
+        let report = super::full_report(&listing, &scores, &collections);
+        assert_eq!(
+            report.findings,
+            vec![
+                Finding::ScoresForMissingBeatmap {
+                    hash: hash(3),
+                    scores: 2
+                },
+                Finding::CollectionForMissingBeatmap {
+                    collection: 0,
+                    name: Some("favourites".to_string()),
+                    hash: hash(4)
+                },
+                Finding::GradeWithoutScore {
+                    beatmap: 0,
+                    mode: Mode::Taiko,
+                    grade: Grade::S
+                },
+                Finding::DuplicateHash {
+                    database: Database::Listing,
+                    hash: hash(2),
+                    count: 2
+                },
+                Finding::DuplicateHash {
+                    database: Database::Scores,
+                    hash: hash(1),
+                    count: 2
+                },
+                Finding::DuplicateCollectionEntry {
+                    collection: 0,
+                    hash: hash(4),
+                    count: 2
+                },
+            ]
+        );
+        assert_eq!(
+            report.to_string().lines().nth(2),
+            Some("beatmap #0 has Taiko grade S but no Taiko scores")
+        );
+
+        let listing = Listing {
+            beatmaps: vec![listing.beatmaps[1].clone()],
+            ..listing
+        };
+        let scores = ScoreList {
+            beatmaps: vec![group(2, vec![score(2, Mode::Taiko)])],
+            ..scores
+        };
+        let collections = CollectionList {
+            collections: Vec::new(),
+            ..collections
+        };
+        let report = super::full_report(&listing, &scores, &collections);
+        assert!(report.is_clean());
+        assert_eq!(report.to_string(), "no issues found");
+    }
+}
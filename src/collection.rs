@@ -1,8 +1,18 @@
 //! Parsing for the `collection.db` file, containing all user collections.
 
-use std::convert::identity;
+use alloc::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+};
+use core::convert::identity;
 
-use crate::prelude::*;
+use crate::{prelude::*, query::Filter, Listing};
+
+/// The newest `collection.db` version whose layout is known.
+///
+/// Newer versions are parsed assuming they share this layout.
+/// If parsing them fails, a `Warning::UnknownVersion` is reported.
+pub const LATEST_KNOWN_VERSION: u32 = 20211103;
 
 /// A structure representing the `collection.db` file.
 /// Contains a list of collections.
@@ -15,10 +25,27 @@ pub struct CollectionList {
 impl CollectionList {
     /// Read a collection list from its raw bytes.
     pub fn from_bytes(bytes: &[u8]) -> Result<CollectionList, Error> {
-        Ok(collections(bytes).map(|(_rem, collections)| collections)?)
+        CollectionList::from_bytes_with(bytes, ParseOptions::new())
+    }
+
+    /// Read a collection list from its raw bytes, with the given parsing options.
+    ///
+    /// Only the string fallback and warnings apply to collection lists.
+    pub fn from_bytes_with(bytes: &[u8], mut opts: ParseOptions) -> Result<CollectionList, Error> {
+        let (rem, version) = int(bytes)?;
+        let parsed = collections(rem, opts.string_fallback);
+        if parsed.is_err() {
+            opts.unknown_version(version, LATEST_KNOWN_VERSION);
+        }
+        let (_rem, collections) = parsed?;
+        Ok(CollectionList {
+            version,
+            collections,
+        })
     }
 
     /// Read a collection list from a `collection.db` file.
+    #[cfg(feature = "std")]
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<CollectionList, Error> {
         Self::from_bytes(&fs::read(path)?)
     }
@@ -29,9 +56,211 @@ impl CollectionList {
     }
 
     /// Similar to `to_writer` but writes the collection database to a file (ie. `collection.db`).
+    #[cfg(feature = "std")]
     pub fn to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
         self.to_writer(BufWriter::new(File::create(path)?))
     }
+
+    /// Write the collection list to an arbitrary writer, with the given save options.
+    pub fn to_writer_with<W: Write>(&self, mut out: W, opts: &SaveOptions) -> io::Result<()> {
+        let mut order: Vec<Cow<Collection>> = self.collections.iter().map(Cow::Borrowed).collect();
+        if opts.sort_by_name {
+            order.sort_by(|a, b| {
+                let key = |c: &Collection| c.name.as_ref().map(|name| name.to_lowercase());
+                key(a).cmp(&key(b)).then_with(|| a.name.cmp(&b.name))
+            });
+        }
+        if opts.sort_hashes {
+            for collection in order.iter_mut() {
+                collection.to_mut().beatmap_hashes.sort();
+            }
+        }
+        self.version.wr(&mut out)?;
+        (order.len() as u32).wr(&mut out)?;
+        for collection in &order {
+            collection.wr(&mut out)?;
+        }
+        Ok(())
+    }
+
+    /// Similar to `to_writer_with` but writes the collection database to a file (ie.
+    /// `collection.db`).
+    #[cfg(feature = "std")]
+    pub fn to_file_with<P: AsRef<Path>>(&self, path: P, opts: &SaveOptions) -> io::Result<()> {
+        self.to_writer_with(BufWriter::new(File::create(path)?), opts)
+    }
+
+    /// Generate a collection with the given name out of all beatmaps in the listing that match a
+    /// filter, which can be either a closure or a parsed `query::Query`.
+    ///
+    /// If a collection with the same name already exists its contents are replaced, so that
+    /// auto-generated collections can be regenerated in place.
+    /// Otherwise a new collection is added at the end.
+    /// Beatmaps without a hash are skipped, and duplicate hashes are only added once.
+    pub fn generate<F: Filter>(
+        &mut self,
+        name: &str,
+        listing: &Listing,
+        filter: F,
+    ) -> &mut Collection {
+        let mut seen = BTreeSet::new();
+        let beatmap_hashes = listing
+            .beatmaps
+            .iter()
+            .filter(|bm| filter.matches(bm))
+            .filter_map(|bm| bm.hash.md5())
+            .filter(|hash| seen.insert(*hash))
+            .map(StoredHash::Md5)
+            .collect();
+        let idx = match self.position(name) {
+            Some(idx) => idx,
+            None => {
+                self.collections.push(Collection {
+                    name: Some(name.to_string()),
+                    beatmap_hashes: Vec::new(),
+                });
+                self.collections.len() - 1
+            }
+        };
+        let collection = &mut self.collections[idx];
+        collection.beatmap_hashes = beatmap_hashes;
+        collection
+    }
+
+    /// Move the collection at index `from` so that it ends up at index `to`, shifting the
+    /// collections in between.
+    ///
+    /// The order of the collection list is the order osu! displays collections in.
+    /// Panics if either index is out of bounds.
+    pub fn move_collection(&mut self, from: usize, to: usize) {
+        let collection = self.collections.remove(from);
+        self.collections.insert(to, collection);
+    }
+
+    /// Sort the collections alphabetically by name, ignoring case.
+    ///
+    /// Collections without a name are placed first, and collections with equal names keep their
+    /// relative order.
+    pub fn sort_collections_by_name(&mut self) {
+        self.collections
+            .sort_by_cached_key(|c| c.name.as_ref().map(|name| name.to_lowercase()));
+    }
+
+    /// Get the index of the collection with the given name, if any.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.collections
+            .iter()
+            .position(|c| c.name.as_deref() == Some(name))
+    }
+
+    /// Rename the collection named `from` to `to`.
+    ///
+    /// Fails without changing anything if there is no collection named `from`, or if another
+    /// collection is already named `to`, as osu! cannot tell such collections apart.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), CollectionError> {
+        let idx = self
+            .position(from)
+            .ok_or_else(|| CollectionError::NotFound(from.to_string()))?;
+        if from != to && self.position(to).is_some() {
+            return Err(CollectionError::DuplicateName(to.to_string()));
+        }
+        self.collections[idx].name = Some(to.to_string());
+        Ok(())
+    }
+
+    /// Remove a beatmap hash from every collection.
+    ///
+    /// Returns the amount of entries removed.
+    pub fn remove_hash_everywhere(&mut self, hash: Md5Hash) -> usize {
+        let mut removed = 0;
+        for collection in &mut self.collections {
+            let before = collection.beatmap_hashes.len();
+            collection.beatmap_hashes.retain(|h| h.md5() != Some(hash));
+            removed += before - collection.beatmap_hashes.len();
+        }
+        removed
+    }
+
+    /// Replace a beatmap hash with another one in every collection, such as when a beatmap is
+    /// updated and its hash changes.
+    ///
+    /// Collections that already contain the new hash do not get a duplicate entry.
+    /// Returns the amount of entries replaced or removed.
+    pub fn replace_hash_everywhere(&mut self, old: Md5Hash, new: Md5Hash) -> usize {
+        let mut changed = 0;
+        for collection in &mut self.collections {
+            let contains = |hash| {
+                collection
+                    .beatmap_hashes
+                    .iter()
+                    .any(|h| h.md5() == Some(hash))
+            };
+            if !contains(old) {
+                continue;
+            }
+            let mut has_new = contains(new);
+            collection.beatmap_hashes.retain_mut(|h| {
+                if h.md5() != Some(old) {
+                    return true;
+                }
+                changed += 1;
+                if has_new {
+                    false
+                } else {
+                    *h = new.into();
+                    has_new = true;
+                    true
+                }
+            });
+        }
+        changed
+    }
+
+    /// Find, for each collection, the beatmaps that are not present in the given listing.
+    ///
+    /// Collections store nothing but hashes, so the beatmap IDs of missing beatmaps are unknown
+    /// at first. They can be filled in from an external source (eg. the osu! API or a cache of it)
+    /// through `MissingReport::resolve_ids`.
+    pub fn missing_hashes(&self, listing: &Listing) -> MissingReport {
+        let local = listing.hash_index();
+        let collections = self
+            .collections
+            .iter()
+            .enumerate()
+            .map(|(index, collection)| {
+                let hashes = || collection.beatmap_hashes.iter().filter_map(StoredHash::md5);
+                MissingInCollection {
+                    index,
+                    name: collection.name.clone(),
+                    total: hashes().count(),
+                    missing: hashes()
+                        .filter(|hash| !local.contains(hash))
+                        .map(|hash| MissingBeatmap {
+                            hash,
+                            beatmap_id: None,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        MissingReport { collections }
+    }
+}
+
+/// Options controlling how a collection list is written.
+///
+/// osu! shows collections sorted by name regardless of the order they are stored in, so sorting
+/// them on write only matters to make logically identical collection lists serialize to
+/// identical bytes.
+/// The collection list itself is left untouched.
+#[derive(Debug, Clone, Default)]
+pub struct SaveOptions {
+    /// Write the collections sorted by name, ignoring case, with unnamed collections first.
+    /// Names that only differ in case are ordered by their exact bytes.
+    pub sort_by_name: bool,
+    /// Write the beatmap hashes of each collection in ascending order, with missing hashes
+    /// first.
+    pub sort_hashes: bool,
 }
 
 /// A single collection.
@@ -40,24 +269,111 @@ impl CollectionList {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Collection {
     pub name: Option<String>,
-    pub beatmap_hashes: Vec<Option<String>>,
+    pub beatmap_hashes: Vec<StoredHash>,
 }
 
-fn collections(bytes: &[u8]) -> IResult<&[u8], CollectionList> {
-    let (rem, version) = int(bytes)?;
-    let (rem, collections) = length_count(map(int, identity), collection)(rem)?;
+impl Collection {
+    /// Move the beatmap entry at index `from` so that it ends up at index `to`, shifting the
+    /// entries in between.
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn move_entry(&mut self, from: usize, to: usize) {
+        let hash = self.beatmap_hashes.remove(from);
+        self.beatmap_hashes.insert(to, hash);
+    }
+}
 
-    let list = CollectionList {
-        version,
-        collections,
-    };
+/// An error in an operation on a collection list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollectionError {
+    /// There is no collection with the given name.
+    NotFound(String),
+    /// There is already a collection with the given name.
+    DuplicateName(String),
+}
+impl fmt::Display for CollectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CollectionError::NotFound(name) => write!(f, "no collection named {:?}", name),
+            CollectionError::DuplicateName(name) => {
+                write!(f, "a collection named {:?} already exists", name)
+            }
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for CollectionError {}
+
+/// The beatmaps of each collection that are missing from a listing, as returned by
+/// `CollectionList::missing_hashes`.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingReport {
+    /// One entry per collection, in the same order as the collection list.
+    pub collections: Vec<MissingInCollection>,
+}
+impl MissingReport {
+    /// The amount of distinct beatmaps missing across all collections.
+    pub fn total_missing(&self) -> usize {
+        self.collections
+            .iter()
+            .flat_map(|c| &c.missing)
+            .map(|missing| &missing.hash)
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+
+    /// The collections that have at least one missing beatmap.
+    pub fn incomplete(&self) -> impl Iterator<Item = &MissingInCollection> {
+        self.collections.iter().filter(|c| !c.missing.is_empty())
+    }
+
+    /// Fill in the beatmap IDs of missing beatmaps, by looking up their hashes in an external
+    /// source.
+    ///
+    /// The lookup is only called once per distinct hash.
+    pub fn resolve_ids<F>(&mut self, mut lookup: F)
+    where
+        F: FnMut(Md5Hash) -> Option<BeatmapId>,
+    {
+        let mut resolved = BTreeMap::new();
+        for missing in self.collections.iter_mut().flat_map(|c| &mut c.missing) {
+            missing.beatmap_id = *resolved
+                .entry(missing.hash)
+                .or_insert_with(|| lookup(missing.hash));
+        }
+    }
+}
+
+/// The beatmaps of a single collection that are missing from a listing.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingInCollection {
+    /// The index of the collection within the collection list.
+    pub index: usize,
+    pub name: Option<String>,
+    /// The amount of beatmaps in the collection.
+    pub total: usize,
+    pub missing: Vec<MissingBeatmap>,
+}
+
+/// A beatmap that is in a collection but not in the listing.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingBeatmap {
+    pub hash: Md5Hash,
+    /// The beatmap ID, if it was resolved through `MissingReport::resolve_ids`.
+    pub beatmap_id: Option<BeatmapId>,
+}
 
-    Ok((rem, list))
+/// Parse the collections that follow the version.
+fn collections(bytes: &[u8], strings: StringFallback) -> IResult<&[u8], Vec<Collection>> {
+    length_count(map(int, identity), |rem| collection(rem, strings))(bytes)
 }
 
-fn collection(bytes: &[u8]) -> IResult<&[u8], Collection> {
-    let (rem, name) = opt_string(bytes)?;
-    let (rem, beatmap_hashes) = length_count(map(int, identity), opt_string)(rem)?;
+fn collection(bytes: &[u8], strings: StringFallback) -> IResult<&[u8], Collection> {
+    let (rem, name) = opt_string_with(bytes, strings)?;
+    let (rem, beatmap_hashes) = length_count(map(int, identity), stored_hash)(rem)?;
 
     let collection = Collection {
         name,
@@ -76,3 +392,139 @@ writer!(Collection [this,out] {
     this.name.wr(out)?;
     PrefixedList(&this.beatmap_hashes).wr(out)?;
 });
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ids::test::hash, listing::test::beatmap};
+
+    #[test]
+    fn missing_hashes() {
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap()],
+            user_permissions: 0,
+        };
+        let local = beatmap().hash;
+        let list = CollectionList {
+            version: 20211103,
+            collections: vec![
+                Collection {
+                    name: Some("pool".to_string()),
+                    beatmap_hashes: vec![local.clone(), hash(1).into()],
+                },
+                Collection {
+                    name: Some("complete".to_string()),
+                    beatmap_hashes: vec![local],
+                },
+                Collection {
+                    name: Some("other pool".to_string()),
+                    beatmap_hashes: vec![hash(1).into(), hash(2).into()],
+                },
+            ],
+        };
+        let mut report = list.missing_hashes(&listing);
+        assert_eq!(report.total_missing(), 2);
+        assert_eq!(report.collections[0].total, 2);
+        assert_eq!(
+            report.incomplete().map(|c| c.index).collect::<Vec<_>>(),
+            vec![0, 2]
+        );
+
+        let mut lookups = 0;
+        report.resolve_ids(|h| {
+            lookups += 1;
+            if h == hash(1) {
+                Some(BeatmapId(7))
+            } else {
+                None
+            }
+        });
+        assert_eq!(lookups, 2);
+        assert_eq!(
+            report.collections[2].missing[0].beatmap_id,
+            Some(BeatmapId(7))
+        );
+        assert_eq!(report.collections[2].missing[1].beatmap_id, None);
+    }
+
+    #[test]
+    fn edit() {
+        let named = |name: &str, hashes: &[u8]| Collection {
+            name: Some(name.to_string()),
+            beatmap_hashes: hashes.iter().map(|&n| hash(n).into()).collect(),
+        };
+        let mut list = CollectionList {
+            version: 20211103,
+            collections: vec![named("b", &[1, 2]), named("C", &[2, 3]), named("a", &[1])],
+        };
+        let names = |list: &CollectionList| {
+            list.collections
+                .iter()
+                .map(|c| c.name.clone().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        list.sort_collections_by_name();
+        assert_eq!(names(&list), vec!["a", "b", "C"]);
+        list.move_collection(2, 0);
+        assert_eq!(names(&list), vec!["C", "a", "b"]);
+
+        assert_eq!(
+            list.rename("a", "b"),
+            Err(CollectionError::DuplicateName("b".to_string()))
+        );
+        assert_eq!(
+            list.rename("d", "e"),
+            Err(CollectionError::NotFound("d".to_string()))
+        );
+        assert_eq!(list.rename("a", "d"), Ok(()));
+        assert_eq!(names(&list), vec!["C", "d", "b"]);
+
+        assert_eq!(list.replace_hash_everywhere(hash(1), hash(3)), 2);
+        assert_eq!(list.collections[1], named("d", &[3]));
+        assert_eq!(list.collections[2], named("b", &[3, 2]));
+        list.collections[2].move_entry(0, 1);
+        assert_eq!(list.remove_hash_everywhere(hash(2)), 2);
+        assert_eq!(list.collections[0], named("C", &[3]));
+        assert_eq!(list.collections[2], named("b", &[3]));
+    }
+
+    #[test]
+    fn save_options() {
+        let named = |name: &str, hashes: &[u8]| Collection {
+            name: Some(name.to_string()),
+            beatmap_hashes: hashes.iter().map(|&n| hash(n).into()).collect(),
+        };
+        let mut list = CollectionList {
+            version: 20211103,
+            collections: vec![named("b", &[2, 1]), named("a", &[3, 1]), named("B", &[])],
+        };
+        let opts = SaveOptions {
+            sort_by_name: true,
+            sort_hashes: true,
+        };
+        let mut raw = Vec::new();
+        list.to_writer_with(&mut raw, &opts).unwrap();
+        assert_eq!(
+            CollectionList::from_bytes(&raw).unwrap().collections,
+            vec![named("a", &[1, 3]), named("B", &[]), named("b", &[1, 2])]
+        );
+
+        list.collections.reverse();
+        list.collections[0].beatmap_hashes.reverse();
+        let mut shuffled = Vec::new();
+        list.to_writer_with(&mut shuffled, &opts).unwrap();
+        assert_eq!(shuffled, raw);
+
+        let mut plain = Vec::new();
+        list.to_writer(&mut plain).unwrap();
+        let mut kept = Vec::new();
+        list.to_writer_with(&mut kept, &SaveOptions::default())
+            .unwrap();
+        assert_eq!(kept, plain);
+    }
+}
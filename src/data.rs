@@ -0,0 +1,332 @@
+//! Loading every database of an osu! install at once, with joined queries across them.
+//!
+//! Most applications just want to open an osu! folder and look up the scores and collections of
+//! a beatmap, without handling three file paths and matching up hashes by hand.
+
+use crate::{
+    check::{self, IntegrityReport},
+    collection::Collection,
+    ids::{HashIndex, Md5Map},
+    listing::Beatmap,
+    prelude::*,
+    score::Score,
+    CollectionList, Database, Listing, ScoreList,
+};
+
+/// The databases of an osu! install, indexed by beatmap hash so they can be queried together.
+///
+/// `presence.db` is not supported, so it is not loaded.
+/// The databases can only be read through this structure, so that the indexes never go stale.
+/// Use `into_parts` to edit them, and `from_parts` to query them again afterwards.
+#[derive(Debug, Clone)]
+pub struct OsuData {
+    listing: Listing,
+    scores: Option<ScoreList>,
+    collections: Option<CollectionList>,
+    beatmap_index: HashIndex,
+    score_index: HashIndex,
+    collection_index: Md5Map<Vec<usize>>,
+}
+impl OsuData {
+    /// Load the databases within the given osu! directory.
+    ///
+    /// `osu!.db` must exist, but `scores.db` and `collection.db` are skipped if missing, as osu!
+    /// only creates them once they have something to store.
+    /// Any database that fails to parse is an error.
+    pub fn load<P: AsRef<Path>>(osu_dir: P) -> Result<OsuData, Error> {
+        Self::load_with(osu_dir, ParseOptions::new())
+    }
+
+    /// Similar to `load`, but parses every database with the given parsing options.
+    ///
+    /// The options apply to each database as they would to its own `from_bytes_with`, and the
+    /// databases are parsed in turn: `osu!.db`, then `scores.db`, then `collection.db`.
+    /// Progress and spans are reported for both the listing and the scores, one after the other.
+    pub fn load_with<P: AsRef<Path>>(osu_dir: P, mut opts: ParseOptions) -> Result<OsuData, Error> {
+        let osu_dir = osu_dir.as_ref();
+        let listing = fs::read(osu_dir.join(Database::Listing.file_name()))?;
+        let listing = Listing::from_bytes_with(&listing, opts.reborrow())?;
+        let scores = read_optional(&osu_dir.join(Database::Scores.file_name()))?
+            .map(|bytes| ScoreList::from_bytes_with(&bytes, opts.reborrow()))
+            .transpose()?;
+        let collections = read_optional(&osu_dir.join(Database::Collections.file_name()))?
+            .map(|bytes| CollectionList::from_bytes_with(&bytes, opts.reborrow()))
+            .transpose()?;
+        Ok(OsuData::from_parts(listing, scores, collections))
+    }
+
+    /// Index databases that were already loaded.
+    pub fn from_parts(
+        listing: Listing,
+        scores: Option<ScoreList>,
+        collections: Option<CollectionList>,
+    ) -> OsuData {
+        let beatmap_index = listing.hash_index();
+        let score_index = match &scores {
+            Some(scores) => scores.hash_index(),
+            None => HashIndex::default(),
+        };
+        let mut collection_index = Md5Map::<Vec<usize>>::default();
+        for (idx, collection) in collections
+            .iter()
+            .flat_map(|list| &list.collections)
+            .enumerate()
+        {
+            for hash in collection.beatmap_hashes.iter().filter_map(StoredHash::md5) {
+                let containing = collection_index.entry(hash).or_default();
+                //A collection may list the same hash more than once
+                if containing.last() != Some(&idx) {
+                    containing.push(idx);
+                }
+            }
+        }
+        OsuData {
+            listing,
+            scores,
+            collections,
+            beatmap_index,
+            score_index,
+            collection_index,
+        }
+    }
+
+    /// Take the databases back out, dropping the indexes.
+    pub fn into_parts(self) -> (Listing, Option<ScoreList>, Option<CollectionList>) {
+        (self.listing, self.scores, self.collections)
+    }
+
+    /// The `osu!.db` listing.
+    pub fn listing(&self) -> &Listing {
+        &self.listing
+    }
+
+    /// The `scores.db` score database, if there was one.
+    pub fn scores(&self) -> Option<&ScoreList> {
+        self.scores.as_ref()
+    }
+
+    /// The `collection.db` collection list, if there was one.
+    pub fn collections(&self) -> Option<&CollectionList> {
+        self.collections.as_ref()
+    }
+
+    /// Look up a beatmap in the listing by its hash.
+    ///
+    /// If the listing has the same hash more than once, the first beatmap is returned.
+    pub fn beatmap(&self, hash: Md5Hash) -> Option<&Beatmap> {
+        self.beatmap_index
+            .get(&hash)
+            .map(|idx| &self.listing.beatmaps[idx])
+    }
+
+    /// The local scores set on a beatmap, in the order they are stored.
+    ///
+    /// Beatmaps without a hash have no scores.
+    pub fn scores_for(&self, beatmap: &Beatmap) -> &[Score] {
+        let scores = match (&self.scores, beatmap.hash.md5()) {
+            (Some(scores), Some(hash)) => self
+                .score_index
+                .get(&hash)
+                .map(|idx| &scores.beatmaps[idx].scores),
+            _ => None,
+        };
+        scores.map_or(&[], |scores| &scores[..])
+    }
+
+    /// The collections that contain a beatmap, in the order they are stored.
+    ///
+    /// Beatmaps without a hash are in no collection.
+    pub fn collections_containing<'a>(
+        &'a self,
+        beatmap: &Beatmap,
+    ) -> impl Iterator<Item = &'a Collection> + 'a {
+        let indices = beatmap
+            .hash
+            .md5()
+            .and_then(|hash| self.collection_index.get(&hash))
+            .map_or(&[][..], Vec::as_slice);
+        let collections = self
+            .collections
+            .as_ref()
+            .map_or(&[][..], |list| &list.collections);
+        indices.iter().map(move |&idx| &collections[idx])
+    }
+
+    /// The beatmaps of a collection that are present in the listing, in collection order.
+    pub fn beatmaps_in<'a>(
+        &'a self,
+        collection: &'a Collection,
+    ) -> impl Iterator<Item = &'a Beatmap> + 'a {
+        collection
+            .beatmap_hashes
+            .iter()
+            .filter_map(StoredHash::md5)
+            .filter_map(move |hash| self.beatmap(hash))
+    }
+
+    /// Cross-reference the databases with `check::full_report`.
+    ///
+    /// Missing databases are treated as empty.
+    pub fn integrity_report(&self) -> IntegrityReport {
+        let empty_scores;
+        let scores = match &self.scores {
+            Some(scores) => scores,
+            None => {
+                empty_scores = ScoreList {
+                    version: self.listing.version,
+                    beatmaps: Vec::new(),
+                };
+                &empty_scores
+            }
+        };
+        let empty_collections;
+        let collections = match &self.collections {
+            Some(collections) => collections,
+            None => {
+                empty_collections = CollectionList {
+                    version: self.listing.version,
+                    collections: Vec::new(),
+                };
+                &empty_collections
+            }
+        };
+        check::full_report(&self.listing, scores, collections)
+    }
+}
+
+/// Read a whole file, or `None` if it does not exist.
+fn read_optional(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ids::test::hash,
+        listing::{test::beatmap, Grade},
+        replay::test::replay_with,
+        score::BeatmapScores,
+    };
+
+    #[test]
+    fn joined_queries() {
+        let mut beatmaps = Vec::new();
+        for n in 1..=3 {
+            let mut bm = beatmap();
+            bm.hash = hash(n).into();
+            bm.std_grade = Grade::Unplayed;
+            bm.taiko_grade = Grade::Unplayed;
+            bm.ctb_grade = Grade::Unplayed;
+            bm.mania_grade = Grade::Unplayed;
+            beatmaps.push(bm);
+        }
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps,
+            user_permissions: 0,
+        };
+
+        let data = OsuData::from_parts(listing.clone(), None, None);
+        let bm = data.beatmap(hash(2)).unwrap();
+        assert_eq!(bm.hash, hash(2).into());
+        assert!(data.beatmap(hash(9)).is_none());
+        assert!(data.scores_for(bm).is_empty());
+        assert_eq!(data.collections_containing(bm).count(), 0);
+        assert!(data.integrity_report().is_clean());
+
+        let score = replay_with(Vec::new()).into_score_entry();
+        let scores = ScoreList {
+            version: 20211103,
+            beatmaps: vec![BeatmapScores {
+                hash: hash(2).into(),
+                scores: vec![score.clone(), score],
+            }],
+        };
+        let collections = CollectionList {
+            version: 20211103,
+            collections: vec![
+                Collection {
+                    name: Some("a".to_string()),
+                    beatmap_hashes: vec![
+                        hash(2).into(),
+                        hash(9).into(),
+                        hash(1).into(),
+                        hash(2).into(),
+                    ],
+                },
+                Collection {
+                    name: Some("b".to_string()),
+                    beatmap_hashes: vec![hash(3).into()],
+                },
+            ],
+        };
+        let data = OsuData::from_parts(listing, Some(scores), Some(collections));
+        let bm = data.beatmap(hash(2)).unwrap();
+        assert_eq!(data.scores_for(bm).len(), 2);
+        assert!(data.scores_for(data.beatmap(hash(1)).unwrap()).is_empty());
+        let containing: Vec<_> = data.collections_containing(bm).collect();
+        assert_eq!(containing.len(), 1);
+        assert_eq!(containing[0].name.as_deref(), Some("a"));
+        let in_collection: Vec<_> = data
+            .beatmaps_in(containing[0])
+            .map(|bm| bm.hash.md5())
+            .collect();
+        assert_eq!(
+            in_collection,
+            vec![hash(2).into(), hash(1).into(), hash(2).into()]
+        );
+        assert_eq!(
+            data.collections_containing(data.beatmap(hash(3)).unwrap())
+                .map(|c| c.name.as_deref())
+                .collect::<Vec<_>>(),
+            vec![Some("b")]
+        );
+        //The missing hash and the duplicate entry
+        assert_eq!(data.integrity_report().findings.len(), 2);
+    }
+
+    #[test]
+    fn load_with() {
+        let dir = std::env::temp_dir().join(format!("osu-db-data-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap(), beatmap()],
+            user_permissions: 0,
+        };
+        listing
+            .save(dir.join(Database::Listing.file_name()))
+            .unwrap();
+        let collections = CollectionList {
+            version: 20211103,
+            collections: vec![Collection {
+                name: Some("a".to_string()),
+                beatmap_hashes: vec![listing.beatmaps[0].hash.clone()],
+            }],
+        };
+        collections
+            .to_file(dir.join(Database::Collections.file_name()))
+            .unwrap();
+
+        let mut entries = Vec::new();
+        let mut sink = |progress: crate::Progress| entries.push(progress.entries);
+        let data = OsuData::load_with(&dir, ParseOptions::new().progress(&mut sink)).unwrap();
+        assert_eq!(entries, vec![1, 2]);
+        assert_eq!(data.listing(), &listing);
+        assert!(data.scores().is_none());
+        assert_eq!(data.collections_containing(&listing.beatmaps[0]).count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+//! Detection of likely duplicate difficulties across beatmapsets, such as graveyard re-uploads
+//! or older copies of updated maps.
+
+use crate::{listing::Beatmap, prelude::*, Listing};
+use alloc::collections::{BTreeMap, BTreeSet};
+
+/// Why a group of beatmaps was flagged as likely duplicates.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Similarity {
+    /// The beatmaps share the same online beatmap ID, but their contents differ.
+    /// Usually one of them is an older copy of a map that was since updated.
+    SameBeatmapId(BeatmapId),
+    /// The beatmaps share the same artist, title, difficulty name and creator, but live in
+    /// different beatmapsets.
+    /// Usually one of them is a re-upload.
+    SameMetadata,
+}
+
+/// A group of beatmaps that are likely duplicates of each other.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimilarDifficulties {
+    pub similarity: Similarity,
+    /// The indices of the beatmaps within the listing, in ascending order.
+    pub indices: Vec<usize>,
+}
+impl SimilarDifficulties {
+    /// Get the index of the most recently modified beatmap in the group, which is usually the
+    /// one to keep.
+    pub fn newest(&self, listing: &Listing) -> Option<usize> {
+        self.indices
+            .iter()
+            .copied()
+            .max_by_key(|&idx| listing.beatmaps[idx].last_modified)
+    }
+}
+
+impl Listing {
+    /// Find groups of beatmaps that are likely duplicates across different beatmapsets.
+    ///
+    /// Beatmaps are flagged if they share a submitted beatmap ID but have different hashes, or if
+    /// they share the same artist, title, difficulty name and creator while belonging to
+    /// different beatmapsets.
+    /// Beatmapsets are told apart by their ID, or by their folder if unsubmitted.
+    /// Metadata is compared exactly, and beatmaps missing any of these fields are not compared.
+    ///
+    /// Groups sharing a beatmap ID come first, in ascending ID order, followed by groups sharing
+    /// metadata, in order of their first beatmap.
+    /// A group of beatmaps is only reported once, even if they are similar for both reasons.
+    pub fn find_similar_difficulties(&self) -> Vec<SimilarDifficulties> {
+        let mut by_id: BTreeMap<BeatmapId, Vec<usize>> = BTreeMap::new();
+        let mut by_metadata: BTreeMap<[&str; 4], Vec<usize>> = BTreeMap::new();
+        for (idx, bm) in self.beatmaps.iter().enumerate() {
+            if bm.beatmap_id.is_submitted() {
+                by_id.entry(bm.beatmap_id).or_default().push(idx);
+            }
+            if let Some(metadata) = metadata(bm) {
+                by_metadata.entry(metadata).or_default().push(idx);
+            }
+        }
+
+        let mut groups = Vec::new();
+        for (id, indices) in by_id {
+            let hashes: BTreeSet<_> = indices
+                .iter()
+                .map(|&idx| self.beatmaps[idx].hash.md5())
+                .collect();
+            if hashes.len() > 1 {
+                groups.push(SimilarDifficulties {
+                    similarity: Similarity::SameBeatmapId(id),
+                    indices,
+                });
+            }
+        }
+        let mut by_metadata: Vec<Vec<usize>> = by_metadata
+            .into_values()
+            .filter(|indices| {
+                let sets: BTreeSet<_> = indices
+                    .iter()
+                    .map(|&idx| beatmapset_key(&self.beatmaps[idx]))
+                    .collect();
+                sets.len() > 1
+            })
+            .collect();
+        by_metadata.sort_unstable_by_key(|indices| indices[0]);
+        for indices in by_metadata {
+            if groups.iter().all(|group| group.indices != indices) {
+                groups.push(SimilarDifficulties {
+                    similarity: Similarity::SameMetadata,
+                    indices,
+                });
+            }
+        }
+        groups
+    }
+}
+
+/// The artist, title, difficulty name and creator of a beatmap, if all of them are present.
+fn metadata(bm: &Beatmap) -> Option<[&str; 4]> {
+    Some([
+        non_empty(&bm.artist_ascii)?,
+        non_empty(&bm.title_ascii)?,
+        non_empty(&bm.difficulty_name)?,
+        non_empty(&bm.creator)?,
+    ])
+}
+
+fn non_empty(field: &Option<String>) -> Option<&str> {
+    field.as_deref().filter(|s| !s.is_empty())
+}
+
+/// Identify the beatmapset of a beatmap, by ID if submitted or else by folder.
+fn beatmapset_key(bm: &Beatmap) -> Result<BeatmapsetId, Option<&str>> {
+    if bm.beatmapset_id.is_submitted() {
+        Ok(bm.beatmapset_id)
+    } else {
+        Err(bm.folder_name.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ids::test::hash, listing::test::beatmap};
+
+    fn difficulty(n: u8, id: i32, set: i32, name: &str) -> Beatmap {
+        let mut bm = beatmap();
+        bm.hash = hash(n).into();
+        bm.beatmap_id = BeatmapId(id);
+        bm.beatmapset_id = BeatmapsetId(set);
+        bm.artist_ascii = Some("Artist".to_string());
+        bm.title_ascii = Some("Title".to_string());
+        bm.creator = Some("Mapper".to_string());
+        bm.difficulty_name = Some(name.to_string());
+        bm.last_modified = OsuTimestamp(n as u64);
+        bm
+    }
+
+    #[test]
+    fn find_similar_difficulties() {
+        let mut unsubmitted = difficulty(6, 0, -1, "Hard");
+        unsubmitted.folder_name = Some("Artist - Title".to_string());
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![
+                difficulty(1, 100, 10, "Insane"),
+                difficulty(2, 100, 10, "Insane"),
+                difficulty(3, 101, 10, "Hard"),
+                difficulty(4, 200, 20, "Hard"),
+                difficulty(5, 201, 20, "Normal"),
+                unsubmitted,
+                difficulty(5, 201, 20, "Normal"),
+            ],
+            user_permissions: 0,
+        };
+        let groups = listing.find_similar_difficulties();
+        assert_eq!(
+            groups,
+            vec![
+                SimilarDifficulties {
+                    similarity: Similarity::SameBeatmapId(BeatmapId(100)),
+                    indices: vec![0, 1],
+                },
+                SimilarDifficulties {
+                    similarity: Similarity::SameMetadata,
+                    indices: vec![2, 3, 5],
+                },
+            ]
+        );
+        assert_eq!(groups[0].newest(&listing), Some(1));
+    }
+}
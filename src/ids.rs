@@ -0,0 +1,407 @@
+//! Strongly-typed beatmap IDs and MD5 hashes, so that they cannot be mixed up with each other or
+//! with plain numbers and strings.
+
+use crate::prelude::*;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::{
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hasher},
+};
+
+/// The online ID of a single beatmap (a difficulty within a beatmapset).
+///
+/// Unsubmitted beatmaps have an ID of `0` or below, usually `-1` in recent versions.
+/// IDs are stored as signed integers, so negative IDs are kept as-is when read and written.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "ser-de", serde(transparent))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BeatmapId(pub i32);
+
+/// The online ID of a beatmapset.
+///
+/// Unsubmitted beatmapsets have an ID of `0` or below, usually `-1` in recent versions.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "ser-de", serde(transparent))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BeatmapsetId(pub i32);
+
+macro_rules! impl_id {
+    ($ty:ident) => {
+        impl $ty {
+            /// Whether the ID refers to a submitted beatmap, ie. whether it is positive.
+            pub fn is_submitted(self) -> bool {
+                self.0 > 0
+            }
+        }
+        impl From<i32> for $ty {
+            fn from(id: i32) -> $ty {
+                $ty(id)
+            }
+        }
+        impl From<$ty> for i32 {
+            fn from(id: $ty) -> i32 {
+                id.0
+            }
+        }
+        writer!($ty [this,out] this.0.wr(out)?);
+        impl fmt::Display for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+        impl FromStr for $ty {
+            type Err = core::num::ParseIntError;
+            fn from_str(s: &str) -> Result<$ty, Self::Err> {
+                s.parse().map($ty)
+            }
+        }
+    };
+}
+impl_id!(BeatmapId);
+impl_id!(BeatmapsetId);
+
+/// An MD5 hash, as used to identify beatmaps and replays.
+///
+/// osu! stores hashes as 32-character hexadecimal strings, but they are kept here as their 16 raw
+/// bytes.
+/// They are formatted and parsed as lowercase hexadecimal, and parsing also accepts uppercase.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Md5Hash(pub [u8; 16]);
+impl Md5Hash {
+    /// Parse a hash from its 32-character hexadecimal representation.
+    pub fn from_hex(hex: &str) -> Result<Md5Hash, InvalidHash> {
+        let hex = hex.as_bytes();
+        if hex.len() != 32 {
+            return Err(InvalidHash);
+        }
+        let mut bytes = [0; 16];
+        for (byte, pair) in bytes.iter_mut().zip(hex.chunks_exact(2)) {
+            *byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        }
+        Ok(Md5Hash(bytes))
+    }
+
+    /// Get the lowercase hexadecimal representation of the hash, as osu! stores it.
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+
+    /// Write the lowercase hexadecimal representation of the hash into a fixed buffer.
+    pub(crate) fn hex_bytes(&self) -> [u8; 32] {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let mut hex = [0; 32];
+        for (pair, byte) in hex.chunks_exact_mut(2).zip(&self.0) {
+            pair[0] = DIGITS[(byte >> 4) as usize];
+            pair[1] = DIGITS[(byte & 0xf) as usize];
+        }
+        hex
+    }
+}
+impl fmt::Display for Md5Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = self.hex_bytes();
+        //Hex digits are always ASCII
+        f.write_str(core::str::from_utf8(&hex).unwrap_or_default())
+    }
+}
+impl fmt::Debug for Md5Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Md5Hash({})", self)
+    }
+}
+impl FromStr for Md5Hash {
+    type Err = InvalidHash;
+    fn from_str(hex: &str) -> Result<Md5Hash, InvalidHash> {
+        Md5Hash::from_hex(hex)
+    }
+}
+#[cfg(feature = "ser-de")]
+impl serde::Serialize for Md5Hash {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+#[cfg(feature = "ser-de")]
+impl<'de> serde::Deserialize<'de> for Md5Hash {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Md5Hash, D::Error> {
+        let hex = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        Md5Hash::from_hex(&hex).map_err(serde::de::Error::custom)
+    }
+}
+
+fn hex_digit(digit: u8) -> Result<u8, InvalidHash> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(InvalidHash),
+    }
+}
+
+/// The error returned when parsing a string that is not a valid hexadecimal MD5 hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidHash;
+impl fmt::Display for InvalidHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid MD5 hash, expected 32 hexadecimal digits")
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidHash {}
+
+/// A beatmap or replay hash as it is stored in the osu! databases.
+///
+/// osu! stores hashes as strings, which are normally 32 lowercase hexadecimal digits and are then
+/// kept as an `Md5Hash`.
+/// Any other string is kept verbatim, so that files with odd hashes can still be loaded, checked
+/// with the `validate` module and written back unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StoredHash {
+    /// No hash is stored.
+    #[default]
+    Missing,
+    /// A hash in the form osu! writes them, as 32 lowercase hexadecimal digits.
+    Md5(Md5Hash),
+    /// Any other string, such as an empty string, an uppercase hash or a corrupt value.
+    Raw(String),
+}
+impl StoredHash {
+    /// Interpret a stored string, keeping it verbatim if it is not in the usual form.
+    pub fn from_stored(raw: &str) -> StoredHash {
+        match Md5Hash::from_hex(raw) {
+            Ok(hash) if hash.hex_bytes() == raw.as_bytes() => StoredHash::Md5(hash),
+            _ => StoredHash::Raw(raw.to_owned()),
+        }
+    }
+
+    /// The string osu! stores for this hash, if any.
+    pub fn to_stored(&self) -> Option<String> {
+        match self {
+            StoredHash::Missing => None,
+            StoredHash::Md5(hash) => Some(hash.to_hex()),
+            StoredHash::Raw(raw) => Some(raw.clone()),
+        }
+    }
+
+    /// The MD5 hash this refers to, if any.
+    ///
+    /// Raw strings that hold a valid hash in a different form, such as in uppercase, are parsed.
+    pub fn md5(&self) -> Option<Md5Hash> {
+        match self {
+            StoredHash::Missing => None,
+            StoredHash::Md5(hash) => Some(*hash),
+            StoredHash::Raw(raw) => Md5Hash::from_hex(raw).ok(),
+        }
+    }
+
+    /// Whether no hash is stored.
+    pub fn is_missing(&self) -> bool {
+        *self == StoredHash::Missing
+    }
+
+    /// Whether a string is stored, but it is not a valid MD5 hash.
+    pub fn is_malformed(&self) -> bool {
+        match self {
+            StoredHash::Raw(raw) => Md5Hash::from_hex(raw).is_err(),
+            _ => false,
+        }
+    }
+}
+impl From<Md5Hash> for StoredHash {
+    fn from(hash: Md5Hash) -> StoredHash {
+        StoredHash::Md5(hash)
+    }
+}
+impl From<Option<Md5Hash>> for StoredHash {
+    fn from(hash: Option<Md5Hash>) -> StoredHash {
+        hash.map_or(StoredHash::Missing, StoredHash::Md5)
+    }
+}
+#[cfg(feature = "ser-de")]
+impl serde::Serialize for StoredHash {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            StoredHash::Missing => serializer.serialize_none(),
+            StoredHash::Md5(hash) => serializer.serialize_some(hash),
+            StoredHash::Raw(raw) => serializer.serialize_some(raw),
+        }
+    }
+}
+#[cfg(feature = "ser-de")]
+impl<'de> serde::Deserialize<'de> for StoredHash {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<StoredHash, D::Error> {
+        let raw = <Option<std::borrow::Cow<str>>>::deserialize(deserializer)?;
+        Ok(raw.map_or(StoredHash::Missing, |raw| StoredHash::from_stored(&raw)))
+    }
+}
+
+/// An index from MD5 hashes to the position of the entry they belong to, such as the position of
+/// a beatmap within a listing.
+///
+/// Used to join scores and collections, which only refer to beatmaps by hash, against a listing.
+/// Build it once with `Listing::hash_index` or `ScoreList::hash_index` and reuse it for every
+/// lookup.
+///
+/// Without the `std` feature, the index is a sorted map instead of a hash map.
+#[derive(Debug, Clone, Default)]
+pub struct HashIndex {
+    positions: Positions,
+}
+#[cfg(feature = "std")]
+type Positions = Md5Map<usize>;
+#[cfg(not(feature = "std"))]
+type Positions = alloc::collections::BTreeMap<Md5Hash, usize>;
+impl HashIndex {
+    /// Build an index out of a sequence of hashes.
+    ///
+    /// Missing hashes are skipped, but still take up a position.
+    /// If a hash appears more than once, its first position is kept.
+    pub fn new<I>(hashes: I) -> HashIndex
+    where
+        I: IntoIterator<Item = Option<Md5Hash>>,
+    {
+        let hashes = hashes.into_iter();
+        let mut positions = Positions::default();
+        #[cfg(feature = "std")]
+        positions.reserve(hashes.size_hint().0);
+        for (idx, hash) in hashes.enumerate() {
+            if let Some(hash) = hash {
+                positions.entry(hash).or_insert(idx);
+            }
+        }
+        HashIndex { positions }
+    }
+
+    /// Get the position of the given hash, if it is indexed.
+    pub fn get(&self, hash: &Md5Hash) -> Option<usize> {
+        self.positions.get(hash).copied()
+    }
+
+    /// Whether the given hash is indexed.
+    pub fn contains(&self, hash: &Md5Hash) -> bool {
+        self.positions.contains_key(hash)
+    }
+
+    /// The amount of distinct hashes indexed.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Whether there are no hashes indexed.
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+/// A hash map keyed by `Md5Hash`, using `Md5Hasher`.
+#[cfg(feature = "std")]
+pub(crate) type Md5Map<V> = HashMap<Md5Hash, V, BuildHasherDefault<Md5Hasher>>;
+
+/// A hasher for `Md5Hash` keys.
+///
+/// MD5 hashes are already uniformly distributed, so their bytes are used as-is instead of being
+/// hashed again by the default, DoS-resistant hasher.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct Md5Hasher(u64);
+#[cfg(feature = "std")]
+impl Hasher for Md5Hasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.0 = self.0.rotate_left(5) ^ u64::from_le_bytes(word);
+        }
+    }
+    fn write_usize(&mut self, _len: usize) {
+        //Only called for the length prefix of the hash bytes, which is always the same
+    }
+}
+
+// A hash, stored as an optional string.
+pub(crate) fn stored_hash(bytes: &[u8]) -> IResult<&[u8], StoredHash> {
+    let (rem, hex) = opt_str(bytes)?;
+    Ok((
+        rem,
+        hex.map_or(StoredHash::Missing, StoredHash::from_stored),
+    ))
+}
+
+writer!(StoredHash [this,out] {
+    match this {
+        StoredHash::Missing => 0x00_u8.wr(out)?,
+        StoredHash::Md5(hash) => {
+            0x0b_u8.wr(out)?;
+            32_usize.wr(out)?;
+            out.write_all(&hash.hex_bytes())?;
+        }
+        StoredHash::Raw(raw) => {
+            0x0b_u8.wr(out)?;
+            raw.len().wr(out)?;
+            out.write_all(raw.as_bytes())?;
+        }
+    }
+});
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// A dummy hash, with all bytes set to `n`.
+    pub(crate) fn hash(n: u8) -> Md5Hash {
+        Md5Hash([n; 16])
+    }
+
+    #[test]
+    fn hex() {
+        let hex = "0123456789abcdefFEDCBA9876543210";
+        let hash: Md5Hash = hex.parse().unwrap();
+        assert_eq!(hash.0[0], 0x01);
+        assert_eq!(hash.0[15], 0x10);
+        assert_eq!(hash.to_hex(), hex.to_lowercase());
+        assert_eq!(Md5Hash::from_hex(&hex[1..]), Err(InvalidHash));
+        assert_eq!(
+            Md5Hash::from_hex("g123456789abcdeffedcba9876543210"),
+            Err(InvalidHash)
+        );
+
+        let mut raw = Vec::new();
+        StoredHash::Md5(hash).wr(&mut raw).unwrap();
+        assert_eq!(stored_hash(&raw), Ok((&[][..], StoredHash::Md5(hash))));
+        assert_eq!(stored_hash(&[0x00]), Ok((&[][..], StoredHash::Missing)));
+    }
+
+    #[test]
+    fn raw_hashes() {
+        let upper = "0123456789ABCDEF0123456789ABCDEF";
+        for (stored, md5, malformed) in [
+            ("", None, true),
+            ("x", None, true),
+            (upper, Some(upper.parse().unwrap()), false),
+        ] {
+            let mut raw = vec![0x0b, stored.len() as u8];
+            raw.extend_from_slice(stored.as_bytes());
+            let (_rem, hash) = stored_hash(&raw).unwrap();
+            assert_eq!(hash, StoredHash::Raw(stored.to_string()));
+            assert_eq!(hash.md5(), md5);
+            assert_eq!(hash.is_malformed(), malformed);
+            assert_eq!(hash.to_stored().as_deref(), Some(stored));
+            let mut written = Vec::new();
+            hash.wr(&mut written).unwrap();
+            assert_eq!(written, raw);
+        }
+    }
+
+    #[test]
+    fn index() {
+        let index = HashIndex::new(vec![hash(1).into(), None, hash(2).into(), hash(1).into()]);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get(&hash(1)), Some(0));
+        assert_eq!(index.get(&hash(2)), Some(2));
+        assert!(!index.contains(&hash(3)));
+    }
+}
@@ -0,0 +1,358 @@
+//! Import of scores out of the JSON score dumps exported by osu! private servers.
+//!
+//! Dumps come in many shapes, as each server software names its columns differently, so every
+//! field is looked up under the names used by the common server implementations (`bancho.py`,
+//! Ripple-based servers and the osu! API v1):
+//!
+//! | Field              | Accepted names                                        |
+//! |--------------------|-------------------------------------------------------|
+//! | Beatmap hash       | `map_md5`, `beatmap_md5`, `beatmap_hash`              |
+//! | Game mode          | `mode`, `play_mode`                                   |
+//! | 300s               | `n300`, `count_300`, `count300`, `300_count`          |
+//! | 100s               | `n100`, `count_100`, `count100`, `100_count`          |
+//! | 50s                | `n50`, `count_50`, `count50`, `50_count`              |
+//! | Gekis              | `ngeki`, `count_geki`, `countgeki`, `gekis_count`     |
+//! | Katsus             | `nkatu`, `count_katu`, `countkatu`, `katus_count`     |
+//! | Misses             | `nmiss`, `count_miss`, `countmiss`, `misses_count`    |
+//! | Score              | `score`                                               |
+//! | Max combo          | `max_combo`, `maxcombo`                               |
+//! | Mods               | `mods`, `enabled_mods`                                |
+//! | Perfect combo      | `perfect`, `full_combo`                               |
+//! | Player name        | `player_name`, `username`                             |
+//! | Replay hash        | `online_checksum`, `replay_md5`, `checksum`           |
+//! | Timestamp          | `play_time`, `date`, `time`, `created_at`             |
+//! | Online score ID    | `score_id`, `id`                                      |
+//!
+//! Hit counts are also looked up within a nested `statistics` object.
+//! Numbers may also be given as numeric strings.
+//!
+//! Only available with the `json` feature enabled.
+
+use crate::{prelude::*, replay::Replay, score::ScoreList};
+use core::convert::TryFrom;
+use serde::de::Error as _;
+use serde_json::{Map, Value};
+
+/// The version given to imported scores, before they are migrated to the version of the score
+/// database they are added to.
+const IMPORT_VERSION: u32 = 20211103;
+
+impl Replay {
+    /// Build a score out of a single score object from a private server dump.
+    ///
+    /// See the `import` module documentation for the accepted field names.
+    /// The beatmap hash, game mode, hit counts, score, max combo and mods are required.
+    /// Missing player names and replay hashes are left empty, missing perfect combo flags are
+    /// taken as `false`, and missing timestamps are taken as the current time.
+    /// The accuracy and grade of the score are not stored, but derived from the hit counts (see
+    /// `Replay::accuracy` and `Replay::grade`).
+    ///
+    /// Only available with the `json` feature enabled.
+    pub fn from_server_json(value: &Value) -> Result<Replay, Error> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| serde_json::Error::custom("expected a json object"))?;
+        let empty = Map::new();
+        let stats = obj
+            .get("statistics")
+            .and_then(Value::as_object)
+            .unwrap_or(&empty);
+        let count = |names: &[&str]| -> Result<u16, serde_json::Error> {
+            get_uint(lookup(obj, names).or_else(|| lookup(stats, names)), names)
+        };
+
+        let beatmap_hash = match lookup(obj, &["map_md5", "beatmap_md5", "beatmap_hash"]) {
+            Some(Value::String(hex)) => {
+                Some(Md5Hash::from_hex(hex).map_err(|_| missing(&["map_md5", "beatmap_md5"]))?)
+            }
+            _ => return Err(missing(&["map_md5", "beatmap_md5", "beatmap_hash"]).into()),
+        };
+        let replay_hash = match lookup(obj, &["online_checksum", "replay_md5", "checksum"]) {
+            Some(Value::String(hex)) if !hex.is_empty() => Md5Hash::from_hex(hex).ok(),
+            _ => None,
+        };
+        let mode = lookup(obj, &["mode", "play_mode"])
+            .and_then(as_mode)
+            .ok_or_else(|| missing(&["mode", "play_mode"]))?;
+        let mods = lookup(obj, &["mods", "enabled_mods"])
+            .and_then(as_mods)
+            .ok_or_else(|| missing(&["mods", "enabled_mods"]))?;
+        let perfect_combo = match lookup(obj, &["perfect", "full_combo"]) {
+            None | Some(Value::Null) => false,
+            Some(Value::Bool(perfect)) => *perfect,
+            Some(value) => as_uint(value).ok_or_else(|| missing(&["perfect", "full_combo"]))? != 0,
+        };
+        let timestamp = match lookup(obj, &["play_time", "date", "time", "created_at"]) {
+            None | Some(Value::Null) => OsuTimestamp::now(),
+            Some(value) => {
+                as_timestamp(value).ok_or_else(|| missing(&["play_time", "date", "time"]))?
+            }
+        };
+        let player_name = match lookup(obj, &["player_name", "username"]) {
+            Some(Value::String(name)) => Some(name.clone()),
+            _ => None,
+        };
+        let online_score_id = lookup(obj, &["score_id", "id"])
+            .and_then(as_uint)
+            .unwrap_or(0);
+
+        Ok(Replay {
+            mode,
+            version: IMPORT_VERSION,
+            beatmap_hash: beatmap_hash.into(),
+            player_name,
+            replay_hash: replay_hash.into(),
+            count_300: count(&["n300", "count_300", "count300", "300_count"])?,
+            count_100: count(&["n100", "count_100", "count100", "100_count"])?,
+            count_50: count(&["n50", "count_50", "count50", "50_count"])?,
+            count_geki: count(&["ngeki", "count_geki", "countgeki", "gekis_count"])?,
+            count_katsu: count(&["nkatu", "count_katu", "countkatu", "katus_count"])?,
+            count_miss: count(&["nmiss", "count_miss", "countmiss", "misses_count"])?,
+            score: get_uint(lookup(obj, &["score"]), &["score"])?,
+            max_combo: get_uint(
+                lookup(obj, &["max_combo", "maxcombo"]),
+                &["max_combo", "maxcombo"],
+            )?,
+            perfect_combo,
+            mods,
+            life_graph: None,
+            timestamp,
+            replay_data: None,
+            raw_replay_data: None,
+            online_score_id,
+        })
+    }
+}
+
+impl ScoreList {
+    /// Import the scores of a private server dump into the score database.
+    ///
+    /// The dump may be an array of score objects, a single score object, or an object holding an
+    /// array of score objects under `scores`.
+    /// Scores are built with `Replay::from_server_json` and added with `append_scores`, so scores
+    /// that are already present are skipped.
+    /// If any score fails to be read, nothing is added.
+    /// Returns the amount of scores added.
+    ///
+    /// Only available with the `json` feature enabled.
+    pub fn import_server_json(&mut self, json: &str) -> Result<usize, Error> {
+        let value: Value = serde_json::from_str(json)?;
+        let scores = match &value {
+            Value::Array(scores) => &scores[..],
+            Value::Object(obj) => match obj.get("scores") {
+                Some(Value::Array(scores)) => &scores[..],
+                _ => core::slice::from_ref(&value),
+            },
+            _ => return Err(serde_json::Error::custom("expected a json array or object").into()),
+        };
+        let scores = scores
+            .iter()
+            .map(Replay::from_server_json)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.append_scores(scores))
+    }
+}
+
+fn missing(names: &[&str]) -> serde_json::Error {
+    serde_json::Error::custom(format_args!(
+        "missing or invalid field `{}`",
+        names.join("`/`")
+    ))
+}
+
+fn out_of_range(names: &[&str]) -> serde_json::Error {
+    serde_json::Error::custom(format_args!(
+        "field `{}` is out of range",
+        names.join("`/`")
+    ))
+}
+
+/// Get the first field present out of a list of alternative names.
+fn lookup<'a>(obj: &'a Map<String, Value>, names: &[&str]) -> Option<&'a Value> {
+    names.iter().find_map(|name| obj.get(*name))
+}
+
+/// Read a required unsigned integer, which must fit in the type of the field it is stored in.
+fn get_uint<T: TryFrom<u64>>(
+    value: Option<&Value>,
+    names: &[&str],
+) -> Result<T, serde_json::Error> {
+    let n = value.and_then(as_uint).ok_or_else(|| missing(names))?;
+    T::try_from(n).map_err(|_| out_of_range(names))
+}
+
+fn as_uint(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_mode(value: &Value) -> Option<Mode> {
+    match value {
+        Value::String(s) => match &s.to_lowercase()[..] {
+            "osu" | "std" | "standard" => Some(Mode::Standard),
+            "taiko" => Some(Mode::Taiko),
+            "fruits" | "ctb" | "catch" => Some(Mode::CatchTheBeat),
+            "mania" => Some(Mode::Mania),
+            _ => as_raw_mode(value),
+        },
+        _ => as_raw_mode(value),
+    }
+}
+
+fn as_raw_mode(value: &Value) -> Option<Mode> {
+    as_uint(value)
+        .and_then(|raw| u8::try_from(raw).ok())
+        .and_then(Mode::from_raw)
+}
+
+/// Read mods given as a bitmask, a list of acronyms, or a string of concatenated acronyms.
+fn as_mods(value: &Value) -> Option<ModSet> {
+    match value {
+        Value::Array(acronyms) => {
+            ModSet::from_acronyms(acronyms.iter().map(|m| m.as_str().unwrap_or_default()))
+        }
+        Value::String(s) if s.parse::<u32>().is_err() => {
+            let s = s.trim().to_uppercase();
+            if s.is_empty() || s == "NM" {
+                return Some(ModSet::empty());
+            }
+            if !s.is_ascii() || s.len() % 2 == 1 {
+                return None;
+            }
+            ModSet::from_acronyms(s.as_bytes().chunks(2).map(|pair| {
+                //Pairs of ASCII characters are always valid UTF-8
+                core::str::from_utf8(pair).unwrap_or_default()
+            }))
+        }
+        _ => as_uint(value)
+            .and_then(|bits| u32::try_from(bits).ok())
+            .map(ModSet::from_bits),
+    }
+}
+
+/// Read a timestamp given as unix seconds, an RFC 3339 date, or a `YYYY-MM-DD hh:mm:ss` date in
+/// UTC.
+fn as_timestamp(value: &Value) -> Option<OsuTimestamp> {
+    if let Some(secs) = as_uint(value) {
+        let secs = i64::try_from(secs).ok()?;
+        return Some(OsuTimestamp::from_unix_millis(secs.saturating_mul(1000)));
+    }
+    let date = value.as_str()?.trim();
+    OsuTimestamp::parse_rfc3339(date)
+        //Dates without an offset are in UTC
+        .or_else(|_| OsuTimestamp::parse_rfc3339(&format!("{}Z", date)))
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{listing::Grade, Mod};
+    use serde_json::json;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn from_server_json() {
+        let bancho = json!({
+            "id": 1234,
+            "map_md5": HASH,
+            "score": 1000000,
+            "acc": 98.5,
+            "max_combo": 500,
+            "mods": 24,
+            "n300": 490,
+            "n100": 8,
+            "n50": 0,
+            "nmiss": 0,
+            "ngeki": 100,
+            "nkatu": 5,
+            "mode": 0,
+            "play_time": "2021-06-01 12:00:00",
+            "perfect": 1,
+            "online_checksum": ""
+        });
+        let score = Replay::from_server_json(&bancho).unwrap();
+        assert_eq!(score.beatmap_hash.md5(), Some(HASH.parse().unwrap()));
+        assert_eq!(
+            score.mods,
+            ModSet::empty().with(Mod::Hidden).with(Mod::HardRock)
+        );
+        assert_eq!(score.count_300, 490);
+        assert!(score.perfect_combo);
+        assert_eq!(score.replay_hash, StoredHash::Missing);
+        assert_eq!(score.online_score_id, 1234);
+        assert_eq!(score.timestamp.unix_millis(), 1_622_548_800_000);
+        assert_eq!(score.grade(), Grade::SPlus);
+
+        let ripple = json!({
+            "beatmap_md5": HASH,
+            "score": "123456",
+            "max_combo": "300",
+            "full_combo": false,
+            "mods": "HDDT",
+            "300_count": "200",
+            "100_count": "50",
+            "50_count": "10",
+            "gekis_count": "0",
+            "katus_count": "0",
+            "misses_count": "5",
+            "time": "1622548800",
+            "play_mode": "mania",
+            "username": "player"
+        });
+        let score = Replay::from_server_json(&ripple).unwrap();
+        assert_eq!(score.mode, Mode::Mania);
+        assert_eq!(
+            score.mods,
+            ModSet::empty().with(Mod::Hidden).with(Mod::DoubleTime)
+        );
+        assert_eq!(score.score, 123456);
+        assert_eq!(score.player_name.as_deref(), Some("player"));
+        assert_eq!(score.timestamp.unix_millis(), 1_622_548_800_000);
+
+        let mut partial = ripple;
+        partial.as_object_mut().unwrap().remove("misses_count");
+        assert!(Replay::from_server_json(&partial).is_err());
+
+        partial["misses_count"] = json!(0);
+        for (field, value) in [
+            ("misses_count", json!(65536)),
+            ("score", json!(4294967296u64)),
+            ("max_combo", json!(70000)),
+            ("play_mode", json!(256)),
+        ] {
+            let mut overflowing = partial.clone();
+            overflowing[field] = value;
+            assert!(Replay::from_server_json(&overflowing).is_err(), "{}", field);
+        }
+    }
+
+    #[test]
+    fn import_server_json() {
+        let mut scores = ScoreList {
+            version: 20150101,
+            beatmaps: Vec::new(),
+        };
+        let dump = json!({"scores": [{
+            "map_md5": HASH,
+            "score": 1000,
+            "max_combo": 10,
+            "mods": ["HD"],
+            "statistics": {"count_300": 10, "count_100": 0, "count_50": 0, "count_geki": 0,
+                "count_katu": 0, "count_miss": 0},
+            "mode": "osu",
+            "created_at": "2021-06-01T12:00:00+00:00",
+        }]})
+        .to_string();
+        assert_eq!(scores.import_server_json(&dump).unwrap(), 1);
+        assert_eq!(scores.import_server_json(&dump).unwrap(), 0);
+        assert_eq!(scores.beatmaps.len(), 1);
+        assert_eq!(scores.beatmaps[0].scores[0].version, 20150101);
+        assert_eq!(scores.beatmaps[0].scores[0].grade(), Grade::SSPlus);
+        assert!(scores.import_server_json("[{}]").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! In-place patching of `osu!.db` files, for edits that do not change the size of any beatmap.
+
+use crate::{
+    listing::Beatmap,
+    prelude::*,
+    validate::{Issue, IssueKind, Location},
+    Listing,
+};
+use std::{
+    fs::OpenOptions,
+    io::{Read, Seek, SeekFrom},
+};
+
+/// An editor that patches individual beatmaps of an `osu!.db` file in place, instead of rewriting
+/// the whole file.
+///
+/// Only edits that keep the size of each beatmap entry are supported, such as changing the local
+/// offset, ranked status, grades or any other fixed-size field.
+/// Edits that change the length of a string do not fit in place, and must be saved by rewriting
+/// the listing, such as through `TrackedListing`.
+///
+/// The file must not be modified by anything else while the editor is open, so osu! should not
+/// be running.
+#[derive(Debug)]
+pub struct InPlaceEditor<F: Read + Write + Seek> {
+    inner: F,
+    len: u64,
+    listing: Listing,
+    spans: Vec<ops::Range<usize>>,
+    dirty: Vec<bool>,
+}
+impl InPlaceEditor<File> {
+    /// Open an `osu!.db` file for in-place editing.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::new(OpenOptions::new().read(true).write(true).open(path)?)
+    }
+}
+impl<F: Read + Write + Seek> InPlaceEditor<F> {
+    /// Parse a listing from the given readable and writable input, so that its beatmaps can be
+    /// patched in place.
+    pub fn new(mut inner: F) -> Result<Self, Error> {
+        let mut bytes = Vec::new();
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_to_end(&mut bytes)?;
+        let (listing, spans) = Listing::from_bytes_with_spans(&bytes)?;
+        Ok(InPlaceEditor {
+            inner,
+            len: bytes.len() as u64,
+            dirty: vec![false; listing.beatmaps.len()],
+            listing,
+            spans,
+        })
+    }
+
+    /// Read-only access to the listing.
+    pub fn listing(&self) -> &Listing {
+        &self.listing
+    }
+
+    /// Mutable access to a single beatmap, which is patched on the next `commit`.
+    pub fn beatmap_mut(&mut self, index: usize) -> Option<&mut Beatmap> {
+        let beatmap = self.listing.beatmaps.get_mut(index)?;
+        self.dirty[index] = true;
+        Some(beatmap)
+    }
+
+    /// Write the beatmaps accessed through `beatmap_mut` back in place, returning how many of
+    /// them actually changed.
+    ///
+    /// Only the bytes that differ from the original entry are written.
+    /// If any beatmap no longer fits in its original entry, nothing is written and
+    /// `Error::Unwritable` is returned, listing every such beatmap with
+    /// `IssueKind::EntrySizeChanged`.
+    pub fn commit(&mut self) -> Result<usize, Error> {
+        let version = self.listing.version;
+        let mut patches = Vec::new();
+        let mut issues = Vec::new();
+        for (idx, _) in self.dirty.iter().enumerate().filter(|(_, dirty)| **dirty) {
+            let mut entry = Vec::new();
+            self.listing.beatmaps[idx].wr_args(&mut entry, version)?;
+            let span = self.spans[idx].clone();
+            if entry.len() == span.len() {
+                patches.push((span.start as u64, entry));
+            } else {
+                issues.push(Issue {
+                    location: Location::Beatmap(idx),
+                    kind: IssueKind::EntrySizeChanged {
+                        original: span.len(),
+                        new: entry.len(),
+                    },
+                });
+            }
+        }
+        if !issues.is_empty() {
+            return Err(Error::Unwritable(issues));
+        }
+        if self.inner.seek(SeekFrom::End(0))? != self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the database was modified since it was opened",
+            )
+            .into());
+        }
+
+        let mut patched = 0;
+        let mut original = Vec::new();
+        for (start, entry) in patches {
+            original.resize(entry.len(), 0);
+            self.inner.seek(SeekFrom::Start(start))?;
+            self.inner.read_exact(&mut original)?;
+            let differs = |(a, b): (&u8, &u8)| a != b;
+            let first = original.iter().zip(&entry).position(differs);
+            let last = original.iter().zip(&entry).rposition(differs);
+            if let (Some(first), Some(last)) = (first, last) {
+                self.inner.seek(SeekFrom::Start(start + first as u64))?;
+                self.inner.write_all(&entry[first..=last])?;
+                patched += 1;
+            }
+        }
+        self.inner.flush()?;
+        self.dirty.iter_mut().for_each(|dirty| *dirty = false);
+        Ok(patched)
+    }
+
+    /// Stop editing and get the underlying input back.
+    ///
+    /// Uncommitted changes are lost.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::listing::{test::beatmap, RankedStatus};
+    use std::io::Cursor;
+
+    #[test]
+    fn in_place_editor() {
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 2,
+            unban_date: None,
+            player_name: Some("player".to_string()),
+            beatmaps: vec![beatmap(), beatmap()],
+            user_permissions: 0,
+        };
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+
+        let mut editor = InPlaceEditor::new(Cursor::new(raw.clone())).unwrap();
+        let bm = editor.beatmap_mut(1).unwrap();
+        bm.local_beatmap_offset = 25;
+        bm.status = RankedStatus::Loved;
+        editor.beatmap_mut(0).unwrap();
+        assert_eq!(editor.commit().unwrap(), 1);
+        let patched = editor.into_inner().into_inner();
+        let mut expected = listing.clone();
+        expected.beatmaps[1].local_beatmap_offset = 25;
+        expected.beatmaps[1].status = RankedStatus::Loved;
+        assert_eq!(Listing::from_bytes(&patched).unwrap(), expected);
+        assert_eq!(patched.len(), raw.len());
+
+        let mut editor = InPlaceEditor::new(Cursor::new(raw.clone())).unwrap();
+        editor.beatmap_mut(0).unwrap().title_ascii = Some("A longer title".to_string());
+        match editor.commit() {
+            Err(Error::Unwritable(issues)) => {
+                assert_eq!(issues.len(), 1);
+                assert_eq!(issues[0].location, Location::Beatmap(0));
+            }
+            other => panic!("expected unwritable beatmap, got {:?}", other),
+        }
+        assert_eq!(editor.into_inner().into_inner(), raw);
+    }
+}
@@ -0,0 +1,255 @@
+//! Conversion of replays to and from a JSON interchange format.
+//!
+//! The layout follows the one used by `osr2json`-style tools:
+//!
+//! ```json
+//! {
+//!     "gameMode": 0,
+//!     "gameVersion": 20211103,
+//!     "beatmapMD5": "...",
+//!     "playerName": "...",
+//!     "replayMD5": "...",
+//!     "number_300s": 100,
+//!     "number_100s": 2,
+//!     "number_50s": 0,
+//!     "gekis": 20,
+//!     "katus": 1,
+//!     "misses": 0,
+//!     "score": 1234567,
+//!     "max_combo": 150,
+//!     "perfect_combo": true,
+//!     "mods": ["HD", "DT"],
+//!     "life_bar": "...",
+//!     "timestamp": 637000000000000000,
+//!     "replay_data": [[0, 256.0, -500.0, 0.0], ...],
+//!     "score_id": 0
+//! }
+//! ```
+//!
+//! Absent strings are written as `null`.
+//! Hashes are written as they are stored, so malformed hashes are kept verbatim.
+//! `timestamp` is kept as raw .NET ticks so that it round-trips exactly.
+//! If the replay actions are not available but the raw compressed blob is, it is written as a
+//! byte array under `replay_data_raw` instead of `replay_data`.
+
+use crate::{
+    prelude::*,
+    replay::{Action, Replay},
+};
+use core::convert::TryFrom;
+use serde::de::Error as _;
+use serde_json::{json, Map, Value};
+
+impl Replay {
+    /// Convert the replay into the JSON interchange format, used by web-based editors and
+    /// scripting pipelines.
+    ///
+    /// Only available with the `json` feature enabled.
+    pub fn to_interchange_json(&self) -> String {
+        let mut obj = json!({
+            "gameMode": self.mode.raw(),
+            "gameVersion": self.version,
+            "beatmapMD5": self.beatmap_hash.to_stored(),
+            "playerName": self.player_name,
+            "replayMD5": self.replay_hash.to_stored(),
+            "number_300s": self.count_300,
+            "number_100s": self.count_100,
+            "number_50s": self.count_50,
+            "gekis": self.count_geki,
+            "katus": self.count_katsu,
+            "misses": self.count_miss,
+            "score": self.score,
+            "max_combo": self.max_combo,
+            "perfect_combo": self.perfect_combo,
+            "mods": self.mods.acronyms().collect::<Vec<_>>(),
+            "life_bar": self.life_graph,
+            "timestamp": self.timestamp.0,
+            "score_id": self.online_score_id,
+        });
+        let obj_map = obj.as_object_mut().expect("json object");
+        match (&self.replay_data, &self.raw_replay_data) {
+            (Some(actions), _) => {
+                let frames = actions
+                    .iter()
+                    .map(|a| json!([a.delta, a.x, a.y, a.z]))
+                    .collect();
+                obj_map.insert("replay_data".to_string(), Value::Array(frames));
+            }
+            (None, Some(raw)) => {
+                obj_map.insert("replay_data_raw".to_string(), json!(raw));
+            }
+            (None, None) => {
+                obj_map.insert("replay_data".to_string(), Value::Null);
+            }
+        }
+        obj.to_string()
+    }
+
+    /// Read a replay from the JSON interchange format, as produced by `to_interchange_json`.
+    ///
+    /// Only available with the `json` feature enabled.
+    pub fn from_interchange_json(json: &str) -> Result<Replay, Error> {
+        let value: Value = serde_json::from_str(json)?;
+        let obj = value
+            .as_object()
+            .ok_or_else(|| serde_json::Error::custom("expected a json object"))?;
+        let mods = obj
+            .get("mods")
+            .and_then(Value::as_array)
+            .ok_or_else(|| missing("mods"))?;
+        let mods = ModSet::from_acronyms(mods.iter().map(|m| m.as_str().unwrap_or_default()))
+            .ok_or_else(|| serde_json::Error::custom("unknown mod acronym"))?;
+        let replay_data = match obj.get("replay_data") {
+            Some(Value::Array(frames)) => Some(
+                frames
+                    .iter()
+                    .map(json_action)
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            _ => None,
+        };
+        let raw_replay_data = match obj.get("replay_data_raw") {
+            Some(raw) => Some(serde_json::from_value(raw.clone())?),
+            None => None,
+        };
+        Ok(Replay {
+            mode: Mode::from_raw(get_uint(obj, "gameMode")?)
+                .ok_or_else(|| serde_json::Error::custom("invalid game mode"))?,
+            version: get_uint(obj, "gameVersion")?,
+            beatmap_hash: get_hash(obj, "beatmapMD5")?,
+            player_name: get_string(obj, "playerName")?,
+            replay_hash: get_hash(obj, "replayMD5")?,
+            count_300: get_uint(obj, "number_300s")?,
+            count_100: get_uint(obj, "number_100s")?,
+            count_50: get_uint(obj, "number_50s")?,
+            count_geki: get_uint(obj, "gekis")?,
+            count_katsu: get_uint(obj, "katus")?,
+            count_miss: get_uint(obj, "misses")?,
+            score: get_uint(obj, "score")?,
+            max_combo: get_uint(obj, "max_combo")?,
+            perfect_combo: obj
+                .get("perfect_combo")
+                .and_then(Value::as_bool)
+                .ok_or_else(|| missing("perfect_combo"))?,
+            mods,
+            life_graph: get_string(obj, "life_bar")?,
+            timestamp: OsuTimestamp(get_uint(obj, "timestamp")?),
+            replay_data,
+            raw_replay_data,
+            online_score_id: get_uint(obj, "score_id")?,
+        })
+    }
+}
+
+pub(crate) fn missing(field: &str) -> serde_json::Error {
+    serde_json::Error::custom(format_args!("missing or invalid field `{}`", field))
+}
+
+pub(crate) fn out_of_range(field: &str) -> serde_json::Error {
+    serde_json::Error::custom(format_args!("field `{}` is out of range", field))
+}
+
+/// Get an unsigned integer field, failing instead of truncating it if it does not fit in `T`.
+pub(crate) fn get_uint<T: TryFrom<u64>>(
+    obj: &Map<String, Value>,
+    field: &str,
+) -> Result<T, serde_json::Error> {
+    let value = obj
+        .get(field)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| missing(field))?;
+    T::try_from(value).map_err(|_| out_of_range(field))
+}
+
+/// Get a field that must fit in an `i32`, such as an online ID, failing instead of wrapping
+/// around if it does not.
+pub(crate) fn get_i32(obj: &Map<String, Value>, field: &str) -> Result<i32, serde_json::Error> {
+    let value = obj
+        .get(field)
+        .and_then(Value::as_i64)
+        .ok_or_else(|| missing(field))?;
+    i32::try_from(value).map_err(|_| out_of_range(field))
+}
+
+pub(crate) fn get_string(
+    obj: &Map<String, Value>,
+    field: &str,
+) -> Result<Option<String>, serde_json::Error> {
+    match obj.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(missing(field)),
+    }
+}
+
+/// Get a hash field, keeping any string that is not a well-formed hash verbatim as
+/// `StoredHash::Raw`, the same as the databases do.
+pub(crate) fn get_hash(
+    obj: &Map<String, Value>,
+    field: &str,
+) -> Result<StoredHash, serde_json::Error> {
+    Ok(get_string(obj, field)?.map_or(StoredHash::Missing, |hash| StoredHash::from_stored(&hash)))
+}
+
+fn json_action(frame: &Value) -> Result<Action, serde_json::Error> {
+    let (delta, x, y, z): (i64, f32, f32, f32) = serde_json::from_value(frame.clone())?;
+    Ok(Action { delta, x, y, z })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Mod;
+
+    #[test]
+    fn round_trip() {
+        let replay = Replay {
+            mode: Mode::Mania,
+            version: 20211103,
+            //Hashes osu! would not write, which are still kept as-is
+            beatmap_hash: StoredHash::Raw("0123456789ABCDEF0123456789ABCDEF".to_string()),
+            player_name: Some("player".to_string()),
+            replay_hash: StoredHash::Raw("NOT A HASH".to_string()),
+            count_300: 300,
+            count_100: 100,
+            count_50: 50,
+            count_geki: 3,
+            count_katsu: 2,
+            count_miss: 1,
+            score: 987654,
+            max_combo: 432,
+            perfect_combo: false,
+            mods: ModSet::empty().with(Mod::Hidden).with(Mod::Key7),
+            life_graph: Some("0|1,1000|0.5,".to_string()),
+            timestamp: OsuTimestamp(637_000_000_000_000_123),
+            replay_data: Some(vec![Action {
+                delta: 16,
+                x: 0.1,
+                y: -500.0,
+                z: 3.0,
+            }]),
+            raw_replay_data: None,
+            online_score_id: 42,
+        };
+        let json = replay.to_interchange_json();
+        assert_eq!(Replay::from_interchange_json(&json).unwrap(), replay);
+
+        let value: Value = serde_json::from_str(&json).unwrap();
+        for (field, bad, message) in [
+            ("beatmapMD5", json!(42), "missing or invalid"),
+            ("number_300s", json!(70_000), "is out of range"),
+            ("max_combo", json!(5_000_000_000_u64), "is out of range"),
+            ("misses", json!(-1), "missing or invalid"),
+        ] {
+            let mut bad_value = value.clone();
+            bad_value[field] = bad;
+            match Replay::from_interchange_json(&bad_value.to_string()) {
+                Err(Error::Json(err)) => {
+                    let err = err.to_string();
+                    assert!(err.contains(field) && err.contains(message), "{}", err);
+                }
+                other => panic!("expected a json error, got {:?}", other),
+            }
+        }
+    }
+}
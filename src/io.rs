@@ -0,0 +1,123 @@
+//! The writing interface of the crate, usable with or without the `std` feature.
+//!
+//! With the `std` feature enabled, this module re-exports the relevant items of `std::io`, so
+//! any `std::io::Write` sink can be written to.
+//! Without it, a minimal stand-in is provided instead: a `Write` trait for byte sinks, implemented
+//! for `Vec<u8>`, and the error type it returns.
+//! Code naming `osu_db::io::Write` works the same either way.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Result, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::sink::{Error, ErrorKind, Result, Write};
+
+#[cfg(not(feature = "std"))]
+mod sink {
+    use alloc::vec::Vec;
+    use core::fmt;
+
+    /// The result of writing to a sink.
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// The different kinds of errors a sink can report.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum ErrorKind {
+        /// The sink is full, and cannot accept any more bytes.
+        WriteZero,
+        /// Any other error.
+        Other,
+    }
+
+    /// An error reported by a sink.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Error {
+        kind: ErrorKind,
+    }
+    impl Error {
+        /// Create an error of the given kind.
+        pub fn new(kind: ErrorKind) -> Error {
+            Error { kind }
+        }
+
+        /// The kind of the error.
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            Error::new(kind)
+        }
+    }
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.kind {
+                ErrorKind::WriteZero => f.write_str("failed to write whole buffer"),
+                ErrorKind::Other => f.write_str("failed to write to sink"),
+            }
+        }
+    }
+
+    /// A sink that bytes can be written to.
+    pub trait Write {
+        /// Write the whole buffer into the sink.
+        fn write_all(&mut self, buf: &[u8]) -> Result<()>;
+
+        /// Flush any buffered bytes into their final destination.
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        /// Write formatted text into the sink, so that the `write!` macro can be used on it.
+        fn write_fmt(&mut self, args: fmt::Arguments) -> Result<()> {
+            struct Adapter<'a, W: ?Sized> {
+                inner: &'a mut W,
+                error: Result<()>,
+            }
+            impl<W: Write + ?Sized> fmt::Write for Adapter<'_, W> {
+                fn write_str(&mut self, s: &str) -> fmt::Result {
+                    self.inner.write_all(s.as_bytes()).map_err(|err| {
+                        self.error = Err(err);
+                        fmt::Error
+                    })
+                }
+            }
+            let mut adapter = Adapter {
+                inner: self,
+                error: Ok(()),
+            };
+            match fmt::write(&mut adapter, args) {
+                Ok(()) => Ok(()),
+                //A formatting error without a sink error comes from a faulty `Display` impl
+                Err(fmt::Error) => adapter.error.and(Err(Error::new(ErrorKind::Other))),
+            }
+        }
+    }
+    impl Write for Vec<u8> {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            self.extend_from_slice(buf);
+            Ok(())
+        }
+    }
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            (**self).write_all(buf)
+        }
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_fmt() {
+        let mut out = Vec::new();
+        write!(out, "{}|{}", 16, 0.5).unwrap();
+        assert_eq!(out, b"16|0.5");
+    }
+}
@@ -9,6 +9,7 @@
 //! simple `String`.
 //! You can default to an empty string by using `string.unwrap_or_default()`, which does no
 //! allocations and is very cheap.
+//! MD5 hashes are the exception, as they are parsed into `Md5Hash` values.
 //!
 //! # A note on features and replays
 //!
@@ -26,6 +27,24 @@
 //! [`Replay::raw_replay_data`](replay/struct.Replay.html#structfield.raw_replay_data) field is
 //! always available.
 //!
+//! # A note on `no_std`
+//!
+//! Disabling the `std` feature (enabled by default) makes the crate `no_std`, only requiring
+//! `alloc`.
+//! Listings, collections, scores and replays can still be parsed from bytes and written to any
+//! sink implementing `io::Write`, which is then the crate's own minimal trait instead of
+//! `std::io::Write`.
+//! Anything involving files, threads or the system clock is left out, and so are the modules
+//! built on top of them.
+//! Every other optional feature requires `std`.
+//!
+//! # A note on timestamps
+//!
+//! Timestamps are stored as `OsuTimestamp` values, which hold the raw ticks osu! writes and do not
+//! depend on any datetime library.
+//! They convert to and from `chrono` types with the `chrono` feature (enabled by default), and to
+//! and from `time` types with the `time` feature.
+//!
 //! # A note on future-proofness
 //!
 //! Osu `.db` formats are used internally by osu!, and are not intended to be shared.
@@ -35,13 +54,31 @@
 //!
 //! It is currently guaranteed to work on osu! `.db` versions up to at least `20211103`.
 //! The current implementation might work for a long time, or break tomorrow.
+//!
+//! Databases newer than the newest known layout are parsed on a best-effort basis, assuming the
+//! newest known layout.
+//! If such a database fails to parse, or entries have to be skipped, a `Warning::UnknownVersion`
+//! is reported, which can be collected through `ParseOptions::warnings`.
 
 //Because otherwise compiling the large beatmap nom combinator fails
 #![recursion_limit = "128"]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
 
 use crate::prelude::*;
+use alloc::borrow::Cow;
 
-pub use crate::{collection::CollectionList, listing::Listing, replay::Replay, score::ScoreList};
+pub use crate::{
+    collection::CollectionList,
+    ids::{BeatmapId, BeatmapsetId, HashIndex, Md5Hash, StoredHash},
+    listing::Listing,
+    replay::Replay,
+    score::ScoreList,
+    timestamp::OsuTimestamp,
+};
+#[cfg(feature = "std")]
+pub use crate::data::OsuData;
 
 //Writer generator macro
 trait Writable {
@@ -79,50 +116,138 @@ macro_rules! writer {
 }
 
 mod prelude {
+    pub(crate) use crate::ids::{stored_hash, BeatmapId, BeatmapsetId, Md5Hash, StoredHash};
     pub(crate) use crate::{
-        boolean, byte, datetime, double, int, long, opt_string, short, single, Bit, Error, ModSet,
-        Mode, PrefixedList, SimpleWritable, Writable,
+        boolean, byte, datetime, double, int, long, opt_str, opt_str_with, opt_string_with, round,
+        short, signed_int, single, skip_str, Bit, Error, ModSet, Mode, OsuTimestamp, ParseOptions,
+        PrefixedList, SimpleWritable, StringFallback, Warning, Writable,
     };
-    pub(crate) use chrono::{DateTime, Duration, TimeZone, Utc};
     pub(crate) use nom::{
         bytes::complete::{tag, take, take_while, take_while1},
-        combinator::{cond, map, map_opt, map_res, opt},
+        combinator::{cond, map, map_opt, opt},
         error::{Error as NomError, ErrorKind as NomErrorKind},
         multi::{length_count, length_data, many0},
         Err as NomErr, IResult, Needed,
     };
     #[cfg(feature = "ser-de")]
     pub use serde_derive::{Deserialize, Serialize};
+    #[cfg(not(feature = "std"))]
+    pub(crate) use crate::io::{self, Write};
+    #[cfg(not(any(feature = "std", test)))]
+    pub(crate) use alloc::{
+        borrow::ToOwned,
+        format,
+        string::{String, ToString},
+        vec::Vec,
+    };
+    pub(crate) use core::{fmt, ops};
+    #[cfg(feature = "std")]
     pub(crate) use std::{
-        fmt,
         fs::{self, File},
         io::{self, BufWriter, Write},
-        ops,
         path::Path,
     };
     #[cfg(feature = "compression")]
     pub use liblzma::stream::Error as LzmaError;
 }
 
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "json")]
+mod api;
+#[cfg(feature = "backup")]
+pub mod backup;
+#[cfg(feature = "std")]
+pub mod beatmapsets;
+pub mod check;
 pub mod collection;
+#[cfg(feature = "std")]
+pub mod data;
+pub mod duplicates;
+pub mod ids;
+#[cfg(feature = "json")]
+pub mod import;
+#[cfg(feature = "std")]
+pub mod in_place;
+#[cfg(feature = "json")]
+mod interchange;
+pub mod io;
 pub mod listing;
+pub mod metadata;
+pub mod mode_view;
+#[cfg(feature = "std")]
+pub mod osu_file;
+#[cfg(feature = "osz")]
+pub mod osz;
+#[cfg(feature = "std")]
+pub mod playlist;
+pub mod profile;
+pub mod query;
 pub mod replay;
 pub mod score;
+pub mod settings;
+pub mod timestamp;
+#[cfg(feature = "std")]
+pub mod tracked;
+#[cfg(feature = "std")]
+pub mod validate;
+#[cfg(feature = "hash")]
+pub mod verify;
+pub mod visit;
+#[cfg(feature = "watch")]
+pub mod watch;
 
 #[derive(Debug)]
 pub enum Error {
     /// Only available with the `compression` feature enabled.
     #[cfg(feature = "compression")]
     Compression(LzmaError),
+    /// Replay data decompressed into more bytes than allowed by
+    /// `ParseOptions::replay_data_limit`.
+    /// Only available with the `compression` feature enabled.
+    #[cfg(feature = "compression")]
+    ReplayDataTooLarge { limit: usize },
+    /// Only available with the `json` feature enabled.
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    /// Only available with the `osz` or `backup` features enabled.
+    #[cfg(any(feature = "osz", feature = "backup"))]
+    Zip(zip::result::ZipError),
+    /// A backup archive is unusable.
+    /// Only available with the `backup` feature enabled.
+    #[cfg(feature = "backup")]
+    Backup(backup::BackupIssue),
+    /// Only available with the `watch` feature enabled.
+    #[cfg(feature = "watch")]
+    Watch(notify::Error),
     Io(io::Error),
     ParseError(NomErrorKind),
     ParseIncomplete(Needed),
+    /// Returned by the `write_checked` methods when the data cannot be written as-is.
+    /// Holds every issue that was found.
+    /// Only available with the `std` feature enabled.
+    #[cfg(feature = "std")]
+    Unwritable(Vec<validate::Issue>),
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             #[cfg(feature = "compression")]
             Error::Compression(_err) => f.write_str("failed to compress/decompress replay data"),
+            #[cfg(feature = "compression")]
+            Error::ReplayDataTooLarge { limit } => write!(
+                f,
+                "decompressed replay data exceeds the limit of {} bytes",
+                limit
+            ),
+            #[cfg(feature = "json")]
+            Error::Json(_err) => f.write_str("failed to read/write json data"),
+            #[cfg(any(feature = "osz", feature = "backup"))]
+            Error::Zip(_err) => f.write_str("failed to read/write zip archive"),
+            #[cfg(feature = "backup")]
+            Error::Backup(issue) => write!(f, "invalid backup: {}", issue),
+            #[cfg(feature = "watch")]
+            Error::Watch(_err) => f.write_str("failed to watch osu .db files"),
             Error::Io(_err) => f.write_str("failed to read osu .db file"),
             Error::ParseError(kind) => {
                 write!(f, "failed to parse osu file: {}", kind.description())
@@ -135,17 +260,40 @@ impl fmt::Display for Error {
             Error::ParseIncomplete(Needed::Unknown) => {
                 f.write_str("failed to parse osu file: parsing requires more data")
             }
+            #[cfg(feature = "std")]
+            Error::Unwritable(issues) => {
+                f.write_str("refusing to write invalid data")?;
+                if let Some(first) = issues.first() {
+                    write!(f, ": {}", first)?;
+                }
+                if issues.len() > 1 {
+                    write!(f, " (and {} more issues)", issues.len() - 1)?;
+                }
+                Ok(())
+            }
         }
     }
 }
+#[cfg(feature = "std")]
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             #[cfg(feature = "compression")]
             Error::Compression(err) => Some(err as &dyn std::error::Error),
+            #[cfg(feature = "compression")]
+            Error::ReplayDataTooLarge { .. } => None,
+            #[cfg(feature = "json")]
+            Error::Json(err) => Some(err as &dyn std::error::Error),
+            #[cfg(any(feature = "osz", feature = "backup"))]
+            Error::Zip(err) => Some(err as &dyn std::error::Error),
+            #[cfg(feature = "backup")]
+            Error::Backup(_issue) => None,
+            #[cfg(feature = "watch")]
+            Error::Watch(err) => Some(err as &dyn std::error::Error),
             Error::Io(err) => Some(err as &dyn std::error::Error),
             Error::ParseError(_kind) => None,
             Error::ParseIncomplete(_needed) => None,
+            Error::Unwritable(_issues) => None,
         }
     }
 }
@@ -170,10 +318,342 @@ impl From<LzmaError> for Error {
     }
 }
 
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+#[cfg(any(feature = "osz", feature = "backup"))]
+impl From<zip::result::ZipError> for Error {
+    fn from(err: zip::result::ZipError) -> Self {
+        Error::Zip(err)
+    }
+}
+
+#[cfg(feature = "backup")]
+impl From<backup::BackupIssue> for Error {
+    fn from(issue: backup::BackupIssue) -> Self {
+        Error::Backup(issue)
+    }
+}
+
+#[cfg(feature = "watch")]
+impl From<notify::Error> for Error {
+    fn from(err: notify::Error) -> Self {
+        Error::Watch(err)
+    }
+}
+
+/// Progress information reported while parsing a large database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Amount of entries processed so far.
+    ///
+    /// Entries are beatmaps in an `osu!.db` listing, and per-beatmap score lists in a `scores.db`.
+    pub entries: usize,
+    /// Total amount of entries, as declared by the database.
+    pub total_entries: usize,
+    /// Amount of bytes consumed so far.
+    pub bytes: usize,
+    /// Total amount of bytes in the input.
+    pub total_bytes: usize,
+}
+
+/// A receiver of progress updates during long parse operations.
+///
+/// Implemented for any `FnMut(Progress)` closure.
+pub trait ProgressSink {
+    fn progress(&mut self, progress: Progress);
+}
+impl<F> ProgressSink for F
+where
+    F: FnMut(Progress),
+{
+    fn progress(&mut self, progress: Progress) {
+        self(progress)
+    }
+}
+
+/// A non-fatal problem found while parsing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The file version is newer than any version this library knows the layout of, and parsing
+    /// ran into trouble (it failed, or entries had to be skipped), which a layout change would
+    /// explain.
+    /// Parsing was attempted assuming the layout of version `assumed`, which is the newest known
+    /// layout.
+    ///
+    /// Versions follow osu! releases rather than layout changes, so newer versions that parse
+    /// without trouble are not reported.
+    UnknownVersion { version: u32, assumed: u32 },
+    /// The replay action stream was malformed, and had to be fixed up to be parsed.
+    /// Only reported when parsing with `ParseOptions::lenient_actions`.
+    MalformedActions(replay::ActionFix),
+    /// The size prefix of a beatmap in a listing does not match the amount of bytes it takes up.
+    /// Only reported when parsing with `ParseOptions::size_prefixes`.
+    BeatmapSizeMismatch {
+        index: usize,
+        declared: u32,
+        actual: usize,
+    },
+    /// A beatmap in a listing could not be parsed, and was skipped using its size prefix.
+    /// Only reported when parsing with `SizePrefixes::Recover`.
+    SkippedBeatmap { index: usize },
+    /// Score entries in a score database could not be parsed, and were skipped up to the next
+    /// entry that could.
+    /// `beatmap` is the index of the beatmap the scores belong to, and `offset` and `len` give the
+    /// skipped bytes within the input.
+    /// Only reported when parsing with `ParseOptions::skipped_scores`.
+    SkippedScores {
+        beatmap: usize,
+        offset: usize,
+        len: usize,
+    },
+}
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::UnknownVersion { version, assumed } => write!(
+                f,
+                "unknown version {}, parsed assuming the layout of version {}",
+                version, assumed
+            ),
+            Warning::MalformedActions(fix) => write!(f, "malformed replay actions: {}", fix),
+            Warning::BeatmapSizeMismatch {
+                index,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "beatmap #{} takes up {} bytes but its size prefix says {}",
+                index, actual, declared
+            ),
+            Warning::SkippedBeatmap { index } => {
+                write!(f, "beatmap #{} could not be parsed and was skipped", index)
+            }
+            Warning::SkippedScores {
+                beatmap,
+                offset,
+                len,
+            } => write!(
+                f,
+                "skipped {} bytes of unparseable scores of beatmap #{} at offset {}",
+                len, beatmap, offset
+            ),
+        }
+    }
+}
+
+/// Options controlling how databases are parsed.
+///
+/// The default options parse exactly like the plain `from_bytes` methods.
+#[derive(Default)]
+pub struct ParseOptions<'a> {
+    progress: Option<&'a mut dyn ProgressSink>,
+    warnings: Option<&'a mut Vec<Warning>>,
+    spans: Option<&'a mut Vec<ops::Range<usize>>>,
+    pub(crate) arena: Option<&'a mut listing::ParseArena>,
+    pub(crate) lenient_actions: bool,
+    pub(crate) size_prefixes: listing::SizePrefixes,
+    pub(crate) skipped_scores: Option<&'a mut Vec<score::SkippedScores>>,
+    pub(crate) string_fallback: StringFallback,
+    #[cfg(feature = "compression")]
+    pub(crate) lzma_memory_limit: Option<u64>,
+    #[cfg(feature = "compression")]
+    pub(crate) replay_data_limit: Option<usize>,
+}
+impl<'a> ParseOptions<'a> {
+    /// Create the default parsing options.
+    pub fn new() -> ParseOptions<'a> {
+        ParseOptions::default()
+    }
+
+    /// Report parsing progress to the given sink after each entry is parsed.
+    pub fn progress(mut self, sink: &'a mut dyn ProgressSink) -> ParseOptions<'a> {
+        self.progress = Some(sink);
+        self
+    }
+
+    /// Collect any non-fatal problems found while parsing into the given list.
+    pub fn warnings(mut self, warnings: &'a mut Vec<Warning>) -> ParseOptions<'a> {
+        self.warnings = Some(warnings);
+        self
+    }
+
+    /// Record the byte range of each entry within the input into the given list, in the order
+    /// they are parsed.
+    ///
+    /// Entries are the beatmaps of a listing (including their size prefix, if any) and the
+    /// scores of a score database.
+    /// Entries that are skipped while recovering from corruption have no range recorded.
+    /// See `Listing::from_bytes_with_spans` and `ScoreList::from_bytes_with_spans` for a simpler
+    /// interface.
+    pub fn spans(mut self, spans: &'a mut Vec<ops::Range<usize>>) -> ParseOptions<'a> {
+        self.spans = Some(spans);
+        self
+    }
+
+    /// Take buffers from the given arena instead of allocating new ones, where supported.
+    ///
+    /// Currently only listings make use of the arena.
+    pub fn arena(mut self, arena: &'a mut listing::ParseArena) -> ParseOptions<'a> {
+        self.arena = Some(arena);
+        self
+    }
+
+    /// Parse replay actions leniently, accepting the malformed action streams found in some old or
+    /// third-party replays instead of stopping at the first malformed action.
+    ///
+    /// Every fix applied is reported as a `Warning::MalformedActions`.
+    pub fn lenient_actions(mut self, lenient: bool) -> ParseOptions<'a> {
+        self.lenient_actions = lenient;
+        self
+    }
+
+    /// Choose how the size prefix of each beatmap is handled, in listings older than `20191106`.
+    ///
+    /// By default the prefix is ignored, as osu! does.
+    pub fn size_prefixes(mut self, mode: listing::SizePrefixes) -> ParseOptions<'a> {
+        self.size_prefixes = mode;
+        self
+    }
+
+    /// Skip over score entries that fail to parse in score databases, collecting their raw bytes
+    /// into the given vector instead of failing.
+    ///
+    /// As score entries have no size prefix, parsing resumes at the next score of the same
+    /// beatmap or the next beatmap that parses, and everything in between is skipped.
+    /// Each skipped run of bytes is also reported as a `Warning::SkippedScores`.
+    /// If no such entry is found, the rest of the input is skipped and parsing stops there.
+    pub fn skipped_scores(
+        mut self,
+        skipped: &'a mut Vec<score::SkippedScores>,
+    ) -> ParseOptions<'a> {
+        self.skipped_scores = Some(skipped);
+        self
+    }
+
+    /// Choose how strings that are not valid UTF-8 are decoded, instead of failing to parse.
+    ///
+    /// Applies to listings (including those parsed with `visit::parse_listing_events_with`), score
+    /// databases, replays and collection lists.
+    pub fn string_fallback(mut self, fallback: StringFallback) -> ParseOptions<'a> {
+        self.string_fallback = fallback;
+        self
+    }
+
+    /// Limit the memory the LZMA decoder may use while decompressing replay data, in bytes.
+    ///
+    /// Replay data that needs more memory fails to parse with `Error::Compression`.
+    /// Defaults to `replay::DEFAULT_LZMA_MEMORY_LIMIT`.
+    ///
+    /// Only available with the `compression` feature enabled.
+    #[cfg(feature = "compression")]
+    pub fn lzma_memory_limit(mut self, bytes: u64) -> ParseOptions<'a> {
+        self.lzma_memory_limit = Some(bytes);
+        self
+    }
+
+    /// Limit the size of decompressed replay data, in bytes, so that small malicious replays
+    /// cannot decompress into huge amounts of data.
+    ///
+    /// Replay data that decompresses into more bytes fails to parse with
+    /// `Error::ReplayDataTooLarge`.
+    /// Defaults to `replay::DEFAULT_REPLAY_DATA_LIMIT`.
+    ///
+    /// Only available with the `compression` feature enabled.
+    #[cfg(feature = "compression")]
+    pub fn replay_data_limit(mut self, bytes: usize) -> ParseOptions<'a> {
+        self.replay_data_limit = Some(bytes);
+        self
+    }
+
+    /// Borrow the options for a single parse, so that the same options can be used to parse
+    /// several databases in turn.
+    #[cfg(feature = "std")]
+    pub(crate) fn reborrow(&mut self) -> ParseOptions<'_> {
+        ParseOptions {
+            progress: match self.progress.as_mut() {
+                Some(sink) => Some(&mut **sink),
+                None => None,
+            },
+            warnings: self.warnings.as_deref_mut(),
+            spans: self.spans.as_deref_mut(),
+            arena: self.arena.as_deref_mut(),
+            lenient_actions: self.lenient_actions,
+            size_prefixes: self.size_prefixes,
+            skipped_scores: self.skipped_scores.as_deref_mut(),
+            string_fallback: self.string_fallback,
+            #[cfg(feature = "compression")]
+            lzma_memory_limit: self.lzma_memory_limit,
+            #[cfg(feature = "compression")]
+            replay_data_limit: self.replay_data_limit,
+        }
+    }
+
+    /// Report a non-fatal problem.
+    fn warn(&mut self, warning: Warning) {
+        if let Some(warnings) = self.warnings.as_mut() {
+            warnings.push(warning);
+        }
+    }
+
+    /// Report a `Warning::UnknownVersion` if `version` is newer than `latest_known`.
+    /// Called when parsing ran into trouble.
+    fn unknown_version(&mut self, version: u32, latest_known: u32) {
+        if version > latest_known {
+            self.warn(Warning::UnknownVersion {
+                version,
+                assumed: latest_known,
+            });
+        }
+    }
+
+    /// Report progress, given the full input and the remaining unparsed input.
+    fn report(&mut self, entries: usize, total_entries: usize, input: &[u8], rem: &[u8]) {
+        if let Some(sink) = self.progress.as_mut() {
+            sink.progress(Progress {
+                entries,
+                total_entries,
+                bytes: input.len() - rem.len(),
+                total_bytes: input.len(),
+            });
+        }
+    }
+
+    /// Record the span of an entry, given the full input and the remaining input before and after
+    /// parsing the entry.
+    fn record_span(&mut self, input: &[u8], before: &[u8], after: &[u8]) {
+        if let Some(spans) = self.spans.as_mut() {
+            spans.push(input.len() - before.len()..input.len() - after.len());
+        }
+    }
+}
+impl fmt::Debug for ParseOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut f = f.debug_struct("ParseOptions");
+        f.field("progress", &self.progress.is_some())
+            .field("warnings", &self.warnings.is_some())
+            .field("spans", &self.spans.is_some())
+            .field("arena", &self.arena.is_some())
+            .field("lenient_actions", &self.lenient_actions)
+            .field("size_prefixes", &self.size_prefixes)
+            .field("skipped_scores", &self.skipped_scores.is_some())
+            .field("string_fallback", &self.string_fallback);
+        #[cfg(feature = "compression")]
+        f.field("lzma_memory_limit", &self.lzma_memory_limit)
+            .field("replay_data_limit", &self.replay_data_limit);
+        f.finish()
+    }
+}
+
 trait Bit {
     fn bit(&self, pos: u32) -> bool;
     fn bit_range(&self, pos: ops::Range<u32>) -> Self;
     fn set_bit(&mut self, pos: u32, val: bool);
+    #[allow(dead_code)]
     fn set_bit_range(&mut self, pos: ops::Range<u32>, val: Self);
 }
 macro_rules! impl_bit {
@@ -205,6 +685,7 @@ impl_bit!(u8, u16, u32, u64);
 //Common fixed-size osu `.db` primitives.
 use nom::number::complete::le_f32 as single;
 use nom::number::complete::le_f64 as double;
+use nom::number::complete::le_i32 as signed_int;
 use nom::number::complete::le_u16 as short;
 use nom::number::complete::le_u32 as int;
 use nom::number::complete::le_u64 as long;
@@ -218,6 +699,7 @@ writer!(u8 [this,out] out.write_all(&this.to_le_bytes())?);
 writer!(u16 [this,out] out.write_all(&this.to_le_bytes())?);
 writer!(u32 [this,out] out.write_all(&this.to_le_bytes())?);
 writer!(u64 [this,out] out.write_all(&this.to_le_bytes())?);
+writer!(i32 [this,out] out.write_all(&this.to_le_bytes())?);
 writer!(f32 [this,out] this.to_bits().wr(out)?);
 writer!(f64 [this,out] this.to_bits().wr(out)?);
 writer!(bool [this,out] (if *this {1_u8} else {0_u8}).wr(out)?);
@@ -239,43 +721,39 @@ where
     }
 }
 
-/// Get a datetime from an amount of "windows ticks":
-/// The amount of 100-nanosecond units since midnight of the date 0001/01/01.
-fn windows_ticks_to_datetime(ticks: u64) -> DateTime<Utc> {
-    let epoch = Utc.ymd(1, 1, 1).and_hms(0, 0, 0);
-    epoch
-        + Duration::microseconds((ticks / 10) as i64)
-        + Duration::nanoseconds((ticks % 10 * 100) as i64)
+fn datetime(bytes: &[u8]) -> IResult<&[u8], OsuTimestamp> {
+    map(long, OsuTimestamp)(bytes)
 }
 
-fn datetime(bytes: &[u8]) -> IResult<&[u8], DateTime<Utc>> {
-    map(long, windows_ticks_to_datetime)(bytes)
-}
-
-fn datetime_to_windows_ticks(datetime: &DateTime<Utc>) -> u64 {
-    let epoch = Utc.ymd(1, 1, 1).and_hms(0, 0, 0);
-    let duration = datetime.signed_duration_since(epoch);
-    let ticks_since: i64 = (duration * 10).num_microseconds().unwrap_or(0);
-    ticks_since.max(0) as u64
-}
-writer!(DateTime<Utc> [this,out] datetime_to_windows_ticks(this).wr(out)?);
-
 // The variable-length ULEB128 encoding used mainly for string lengths.
+//
+// Decoded by hand rather than through combinators, with a shortcut for single-byte values, as
+// almost all strings are shorter than 128 bytes.
+#[inline]
 fn uleb(bytes: &[u8]) -> IResult<&[u8], usize> {
-    let (rem, prelude) = take_while(|b: u8| b.bit(7))(bytes)?;
-    let (rem, finalizer) = byte(rem)?;
+    match bytes.first() {
+        Some(&first) if !first.bit(7) => return Ok((&bytes[1..], first as usize)),
+        Some(_) => {}
+        None => return Err(NomErr::Error(NomError::new(bytes, NomErrorKind::Eof))),
+    }
 
     let mut out = 0;
     let mut offset = 0;
-
-    for byte in prelude {
+    for (idx, &byte) in bytes.iter().enumerate() {
+        if offset >= usize::BITS {
+            return Err(NomErr::Error(NomError::new(bytes, NomErrorKind::TooLarge)));
+        }
         out |= (byte.bit_range(0..7) as usize) << offset;
         offset += 7;
+        if !byte.bit(7) {
+            return Ok((&bytes[idx + 1..], out));
+        }
     }
 
-    out |= (finalizer as usize) << offset;
-
-    Ok((rem, out))
+    Err(NomErr::Error(NomError::new(
+        &bytes[bytes.len()..],
+        NomErrorKind::Eof,
+    )))
 }
 
 writer!(usize [this,out] {
@@ -290,22 +768,135 @@ writer!(usize [this,out] {
     }
 });
 
-// An optional string.
-fn opt_string(bytes: &[u8]) -> IResult<&[u8], Option<String>> {
+// The raw bytes of an optional string, without validating them.
+fn opt_str_bytes(bytes: &[u8]) -> IResult<&[u8], Option<&[u8]>> {
     let (rem, first_byte) = byte(bytes)?;
 
     match first_byte {
         0x00 => Ok((rem, None)),
         0x0b => {
             let (rem, len) = uleb(rem)?;
-            let (rem, string) = map_res(take(len), std::str::from_utf8)(rem)?;
+            if rem.len() < len {
+                return Err(NomErr::Error(NomError::new(rem, NomErrorKind::Eof)));
+            }
+            let (raw, rest) = rem.split_at(len);
+            Ok((rest, Some(raw)))
+        }
+        _ => Err(NomErr::Error(NomError::new(bytes, NomErrorKind::Switch))),
+    }
+}
 
-            Ok((rem, Some(string.to_owned())))
+// An optional string, borrowed from the input.
+fn opt_str(bytes: &[u8]) -> IResult<&[u8], Option<&str>> {
+    match opt_str_bytes(bytes)? {
+        (rest, None) => Ok((rest, None)),
+        (rest, Some(raw)) => match utf8(raw) {
+            Some(string) => Ok((rest, Some(string))),
+            None => Err(NomErr::Error(NomError::new(raw, NomErrorKind::MapRes))),
+        },
+    }
+}
+
+// An optional string, decoded with the given fallback if it is not valid UTF-8.
+fn opt_str_with(bytes: &[u8], fallback: StringFallback) -> IResult<&[u8], Option<Cow<'_, str>>> {
+    match opt_str_bytes(bytes)? {
+        (rest, None) => Ok((rest, None)),
+        (rest, Some(raw)) => match fallback.decode(raw) {
+            Some(string) => Ok((rest, Some(string))),
+            None => Err(NomErr::Error(NomError::new(raw, NomErrorKind::MapRes))),
+        },
+    }
+}
+
+/// Round half away from zero, like `f64::round`, which is not available without `std`.
+fn round(x: f64) -> f64 {
+    //Beyond this magnitude every float is already a whole number, and NaN stays NaN
+    if x.is_nan() || x.abs() >= (1u64 << 52) as f64 {
+        return x;
+    }
+    let whole = x as i64 as f64;
+    let fraction = x - whole;
+    if fraction >= 0.5 {
+        whole + 1.0
+    } else if fraction <= -0.5 {
+        whole - 1.0
+    } else {
+        whole
+    }
+}
+
+// Skip over an optional string, without validating its contents.
+fn skip_str(bytes: &[u8]) -> IResult<&[u8], ()> {
+    let (rem, first_byte) = byte(bytes)?;
+
+    match first_byte {
+        0x00 => Ok((rem, ())),
+        0x0b => {
+            let (rem, len) = uleb(rem)?;
+            if rem.len() < len {
+                return Err(NomErr::Error(NomError::new(rem, NomErrorKind::Eof)));
+            }
+            Ok((&rem[len..], ()))
         }
         _ => Err(NomErr::Error(NomError::new(bytes, NomErrorKind::Switch))),
     }
 }
 
+// An optional owned string, decoded with the given fallback if it is not valid UTF-8.
+fn opt_string_with(bytes: &[u8], fallback: StringFallback) -> IResult<&[u8], Option<String>> {
+    let (rem, string) = opt_str_with(bytes, fallback)?;
+    Ok((rem, string.map(Cow::into_owned)))
+}
+
+/// How to decode strings that are not valid UTF-8.
+///
+/// osu! always writes strings as UTF-8, but very old databases and some third-party tools wrote
+/// them in a local codepage instead, such as Shift-JIS or GBK.
+/// Strings that are valid UTF-8 are always decoded as such, so a fallback only kicks in for the
+/// strings that would otherwise fail to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StringFallback {
+    /// Fail to parse, as the plain `from_bytes` methods do.
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD (`�`).
+    Lossy,
+    /// Decode the whole string with the given legacy encoding, such as
+    /// `encoding_rs::SHIFT_JIS`.
+    /// Sequences that are invalid in that encoding are replaced with U+FFFD.
+    ///
+    /// Only available with the `encoding` feature enabled.
+    #[cfg(feature = "encoding")]
+    Encoding(&'static encoding_rs::Encoding),
+}
+impl StringFallback {
+    /// Decode a raw string, or `None` if it cannot be decoded.
+    pub fn decode(self, raw: &[u8]) -> Option<Cow<'_, str>> {
+        if let Some(string) = utf8(raw) {
+            return Some(Cow::Borrowed(string));
+        }
+        match self {
+            StringFallback::Strict => None,
+            StringFallback::Lossy => Some(String::from_utf8_lossy(raw)),
+            #[cfg(feature = "encoding")]
+            StringFallback::Encoding(encoding) => Some(encoding.decode_without_bom_handling(raw).0),
+        }
+    }
+}
+
+// Validate UTF-8, using SIMD-accelerated validation if the `simd` feature is enabled.
+#[inline]
+fn utf8(bytes: &[u8]) -> Option<&str> {
+    #[cfg(feature = "simd")]
+    {
+        simdutf8::basic::from_utf8(bytes).ok()
+    }
+    #[cfg(not(feature = "simd"))]
+    {
+        core::str::from_utf8(bytes).ok()
+    }
+}
+
 writer!(Option<String> [this,out] {
     match this {
         Some(string) => {
@@ -317,6 +908,32 @@ writer!(Option<String> [this,out] {
     }
 });
 
+/// One of the database files osu! keeps in its directory.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Database {
+    /// The `osu!.db` beatmap listing.
+    Listing,
+    /// The `scores.db` score database.
+    Scores,
+    /// The `collection.db` collection list.
+    Collections,
+}
+impl Database {
+    #[cfg_attr(not(any(feature = "watch", feature = "backup")), allow(dead_code))]
+    pub(crate) const ALL: [Database; 3] =
+        [Database::Listing, Database::Scores, Database::Collections];
+
+    /// The name of the database file within the osu! directory.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Database::Listing => "osu!.db",
+            Database::Scores => "scores.db",
+            Database::Collections => "collection.db",
+        }
+    }
+}
+
 /// An osu! gamemode.
 #[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -382,16 +999,56 @@ pub enum Mod {
     Key1,
     Key3,
     Key2,
+    ScoreV2,
+    Mirror,
 }
 impl Mod {
-    /// Each of the 29 mods have a corresponding integer between [0,28], inclusive.
+    /// All mods, in the order of their corresponding integers.
+    pub const ALL: [Mod; 31] = {
+        use self::Mod::*;
+        [
+            NoFail,
+            Easy,
+            TouchDevice,
+            Hidden,
+            HardRock,
+            SuddenDeath,
+            DoubleTime,
+            Relax,
+            HalfTime,
+            Nightcore,
+            Flashlight,
+            Autoplay,
+            SpunOut,
+            Autopilot,
+            Perfect,
+            Key4,
+            Key5,
+            Key6,
+            Key7,
+            Key8,
+            FadeIn,
+            Random,
+            LastMod,
+            TargetPractice,
+            Key9,
+            Coop,
+            Key1,
+            Key3,
+            Key2,
+            ScoreV2,
+            Mirror,
+        ]
+    };
+
+    /// Each of the 31 mods have a corresponding integer between [0,30], inclusive.
     /// This method retrieves its integer.
     pub fn raw(&self) -> u8 {
         *self as u8
     }
 
     /// Build a mod from its corresponding integer.
-    /// Returns `None` if the integer is out-of-range (>28).
+    /// Returns `None` if the integer is out-of-range (>30).
     pub fn from_raw(bit_offset: u8) -> Option<Mod> {
         use self::Mod::*;
         Some(match bit_offset {
@@ -424,9 +1081,58 @@ impl Mod {
             26 => Key1,
             27 => Key3,
             28 => Key2,
+            29 => ScoreV2,
+            30 => Mirror,
             _ => return None,
         })
     }
+
+    /// The two-character acronym osu! uses to display this mod (eg. `"HD"` for `Hidden`).
+    pub fn acronym(&self) -> &'static str {
+        use self::Mod::*;
+        match self {
+            NoFail => "NF",
+            Easy => "EZ",
+            TouchDevice => "TD",
+            Hidden => "HD",
+            HardRock => "HR",
+            SuddenDeath => "SD",
+            DoubleTime => "DT",
+            Relax => "RX",
+            HalfTime => "HT",
+            Nightcore => "NC",
+            Flashlight => "FL",
+            Autoplay => "AT",
+            SpunOut => "SO",
+            Autopilot => "AP",
+            Perfect => "PF",
+            Key4 => "4K",
+            Key5 => "5K",
+            Key6 => "6K",
+            Key7 => "7K",
+            Key8 => "8K",
+            FadeIn => "FI",
+            Random => "RD",
+            LastMod => "CN",
+            TargetPractice => "TP",
+            Key9 => "9K",
+            Coop => "CO",
+            Key1 => "1K",
+            Key3 => "3K",
+            Key2 => "2K",
+            ScoreV2 => "V2",
+            Mirror => "MR",
+        }
+    }
+
+    /// Build a mod from its acronym, as returned by `acronym`.
+    /// Case-insensitive.
+    pub fn from_acronym(acronym: &str) -> Option<Mod> {
+        Mod::ALL
+            .iter()
+            .copied()
+            .find(|m| m.acronym().eq_ignore_ascii_case(acronym))
+    }
 }
 
 /// A combination of `Mod`s.
@@ -469,6 +1175,43 @@ impl ModSet {
     pub fn without(&self, m: Mod) -> ModSet {
         self.set(m, false)
     }
+
+    /// The speed at which the song plays with these mods: `1.5` with DoubleTime or Nightcore,
+    /// `0.75` with HalfTime, and `1.0` otherwise.
+    pub fn clock_rate(&self) -> f64 {
+        if self.contains(Mod::DoubleTime) || self.contains(Mod::Nightcore) {
+            1.5
+        } else if self.contains(Mod::HalfTime) {
+            0.75
+        } else {
+            1.0
+        }
+    }
+
+    /// Iterate over the mods contained in this set, in the order of their corresponding integers.
+    pub fn mods(&self) -> impl Iterator<Item = Mod> {
+        let this = *self;
+        Mod::ALL.iter().copied().filter(move |&m| this.contains(m))
+    }
+
+    /// Iterate over the acronyms of the mods contained in this set (eg. `["HD", "DT"]`).
+    pub fn acronyms(&self) -> impl Iterator<Item = &'static str> {
+        self.mods().map(|m| m.acronym())
+    }
+
+    /// Build a set of mods from a list of acronyms.
+    /// Returns `None` if any of the acronyms is unknown.
+    pub fn from_acronyms<I, S>(acronyms: I) -> Option<ModSet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        acronyms
+            .into_iter()
+            .try_fold(ModSet::empty(), |set, acronym| {
+                Mod::from_acronym(acronym.as_ref()).map(|m| set.with(m))
+            })
+    }
 }
 
 #[cfg(test)]
@@ -531,10 +1274,25 @@ mod test {
         );
         assert_eq!(uleb(&[129, 2]), Ok((&[][..], 257)));
         assert_eq!(uleb(&[124, 2]), Ok((&[2][..], 124)));
+        assert_eq!(uleb(&[0xe5, 0x8e, 0x26, 7]), Ok((&[7][..], 624485)));
+        assert_eq!(
+            uleb(&[0x80, 0x80]),
+            Err(NomErr::Error(NomError::new(&[][..], NomErrorKind::Eof)))
+        );
+        assert_eq!(
+            uleb(&[0xff; 11]),
+            Err(NomErr::Error(NomError::new(
+                &[0xff; 11][..],
+                NomErrorKind::TooLarge
+            )))
+        );
     }
 
     #[test]
     fn strings() {
+        fn opt_string(bytes: &[u8]) -> IResult<&[u8], Option<String>> {
+            opt_string_with(bytes, StringFallback::Strict)
+        }
         let long_str = "w".repeat(129);
 
         assert_eq!(opt_string(b"\x00sf"), Ok((&b"sf"[..], None)));
@@ -546,6 +1304,11 @@ mod test {
         assert!(opt_string(b"\x01ww").is_err());
         //Invalid utf-8
         assert!(opt_string(b"\x0b\x01\xff").is_err());
+        //Truncated string
+        assert_eq!(
+            opt_string(b"\x0b\x03ab"),
+            Err(NomErr::Error(NomError::new(&b"ab"[..], NomErrorKind::Eof)))
+        );
         //Missing string length
         assert_eq!(
             opt_string(b"\x0b"),
@@ -556,5 +1319,159 @@ mod test {
         raw.extend_from_slice(long_str.as_bytes());
         raw.extend_from_slice(&b"afaf"[..]);
         assert_eq!(opt_string(&raw), Ok((&b"afaf"[..], Some(long_str))));
+        //Fallbacks for invalid utf-8
+        assert_eq!(
+            opt_string_with(b"\x0b\x03a\xffb", StringFallback::Lossy),
+            Ok((&[][..], Some("a\u{fffd}b".to_string())))
+        );
+        assert_eq!(
+            StringFallback::Lossy.decode("ok".as_bytes()),
+            Some(Cow::Borrowed("ok"))
+        );
+        #[cfg(feature = "encoding")]
+        assert_eq!(
+            opt_string_with(
+                b"\x0b\x06\x83\x5e\x83\x43\x83\x67",
+                StringFallback::Encoding(encoding_rs::SHIFT_JIS)
+            ),
+            Ok((&[][..], Some("タイト".to_string())))
+        );
+    }
+
+    #[test]
+    fn rounding() {
+        for &x in &[0.0, 0.4, 0.5, -0.5, 2.5, -2.7, 1e300, -1e17 - 0.5] {
+            assert_eq!(round(x), x.round());
+        }
+        assert!(round(f64::NAN).is_nan());
+    }
+
+    #[test]
+    fn progress() {
+        use crate::{
+            ids::test::hash, listing::test::beatmap, replay::test::replay_with,
+            score::BeatmapScores,
+        };
+
+        fn collect<F: FnOnce(ParseOptions)>(parse: F) -> Vec<Progress> {
+            let mut updates = Vec::new();
+            let mut sink = |progress| updates.push(progress);
+            parse(ParseOptions::new().progress(&mut sink));
+            updates
+        }
+
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap(); 3],
+            user_permissions: 0,
+        };
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+        let updates = collect(|opts| {
+            Listing::from_bytes_with(&raw, opts).unwrap();
+        });
+        let entries: Vec<usize> = updates.iter().map(|p| p.entries).collect();
+        assert_eq!(entries, vec![1, 2, 3]);
+        assert!(updates
+            .iter()
+            .all(|p| p.total_entries == 3 && p.total_bytes == raw.len()));
+        assert!(updates.windows(2).all(|w| w[0].bytes < w[1].bytes));
+        //Only the user permissions follow the last beatmap
+        assert_eq!(updates[2].bytes, raw.len() - 4);
+
+        let scores = ScoreList {
+            version: 20211103,
+            beatmaps: (1..=2)
+                .map(|n| BeatmapScores {
+                    hash: hash(n).into(),
+                    scores: vec![replay_with(Vec::new()).into_score_entry()],
+                })
+                .collect(),
+        };
+        let mut raw = Vec::new();
+        scores.to_writer(&mut raw).unwrap();
+        let updates = collect(|opts| {
+            ScoreList::from_bytes_with(&raw, opts).unwrap();
+        });
+        assert_eq!(
+            updates.last(),
+            Some(&Progress {
+                entries: 2,
+                total_entries: 2,
+                bytes: raw.len(),
+                total_bytes: raw.len(),
+            })
+        );
+        assert_eq!(updates.len(), 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unknown_version() {
+        use crate::{collection::Collection, ids::test::hash, listing::test::beatmap};
+
+        //Newer versions parse silently as long as nothing goes wrong, and only report the
+        //unknown version once a truncated file fails to parse
+        fn check<T>(
+            mut raw: Vec<u8>,
+            latest: u32,
+            parse: fn(&[u8], ParseOptions) -> Result<T, Error>,
+        ) {
+            let warnings = |raw: &[u8]| {
+                let mut warnings = Vec::new();
+                let _ = parse(raw, ParseOptions::new().warnings(&mut warnings));
+                warnings
+            };
+            raw[..4].copy_from_slice(&(latest + 1).to_le_bytes());
+            assert_eq!(warnings(&raw), Vec::new());
+            raw.pop();
+            assert_eq!(
+                warnings(&raw),
+                vec![Warning::UnknownVersion {
+                    version: latest + 1,
+                    assumed: latest,
+                }]
+            );
+            raw[..4].copy_from_slice(&latest.to_le_bytes());
+            assert_eq!(warnings(&raw), Vec::new());
+        }
+
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap()],
+            user_permissions: 0,
+        };
+        listing.migrate_to(listing::LATEST_KNOWN_VERSION);
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+        check(raw, listing::LATEST_KNOWN_VERSION, Listing::from_bytes_with);
+
+        let scores = ScoreList {
+            version: 20211103,
+            beatmaps: Vec::new(),
+        };
+        let mut raw = Vec::new();
+        scores.to_writer(&mut raw).unwrap();
+        check(raw, score::LATEST_KNOWN_VERSION, ScoreList::from_bytes_with);
+
+        let collections = CollectionList {
+            version: 20211103,
+            collections: vec![Collection {
+                name: Some("favourites".to_string()),
+                beatmap_hashes: vec![hash(1).into()],
+            }],
+        };
+        let mut raw = Vec::new();
+        collections.to_writer(&mut raw).unwrap();
+        check(
+            raw,
+            collection::LATEST_KNOWN_VERSION,
+            CollectionList::from_bytes_with,
+        );
+    }
+}
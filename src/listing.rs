@@ -1,14 +1,32 @@
 //! Parsing for the `osu!.db` file, containing cached information about the beatmap listing.
 
-use crate::prelude::*;
-use std::{convert::identity, hash::Hash};
+use crate::{
+    ids::HashIndex,
+    prelude::*,
+    visit::{listing_events, BeatmapField, HeaderField, ListingVisitor},
+    Mod,
+};
+use alloc::collections::BTreeSet;
+use core::hash::Hash;
+#[cfg(feature = "std")]
+use std::path::PathBuf;
 
 /// In these `osu!.db` versions several breaking changes were introduced.
 /// While parsing, these changes are automatically handled depending on the `osu!.db` version.
-const CHANGE_20140609: u32 = 20140609;
-const CHANGE_20191106: u32 = 20191106;
+pub(crate) const CHANGE_20140609: u32 = 20140609;
+pub(crate) const CHANGE_20191106: u32 = 20191106;
 const CHANGE_20250107: u32 = 20250107;
 
+/// The newest `osu!.db` version whose layout is known.
+///
+/// Newer versions are parsed on a best-effort basis, assuming they share this layout.
+/// If parsing them fails or skips beatmaps, a `Warning::UnknownVersion` is reported.
+pub const LATEST_KNOWN_VERSION: u32 = CHANGE_20250107;
+
+/// A lower bound on the size of a beatmap entry in any `osu!.db` version, used to keep the
+/// declared beatmap count from reserving more memory than the file could possibly need.
+const MIN_BEATMAP_SIZE: usize = 100;
+
 /// A structure representing the `osu!.db` binary database.
 /// This database contains pre-processed data and settings for all available osu! beatmaps.
 #[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
@@ -23,7 +41,7 @@ pub struct Listing {
     pub folder_count: u32,
 
     /// Whether the account is locked/banned, and when will be it be unbanned.
-    pub unban_date: Option<DateTime<Utc>>,
+    pub unban_date: Option<OsuTimestamp>,
 
     /// Self-explanatory.
     pub player_name: Option<String>,
@@ -38,10 +56,31 @@ pub struct Listing {
 }
 impl Listing {
     pub fn from_bytes(bytes: &[u8]) -> Result<Listing, Error> {
-        Ok(listing(bytes).map(|(_rem, listing)| listing)?)
+        Self::from_bytes_with(bytes, ParseOptions::new())
+    }
+
+    /// Parse a listing from its raw bytes, with the given parsing options.
+    ///
+    /// Progress, if requested, is reported after each beatmap is parsed.
+    /// If an arena is given, buffers are taken from it instead of being allocated.
+    pub fn from_bytes_with(bytes: &[u8], mut opts: ParseOptions) -> Result<Listing, Error> {
+        Ok(listing(bytes, &mut opts).map(|(_rem, listing)| listing)?)
+    }
+
+    /// Parse a listing from its raw bytes, along with the byte range of each beatmap within them.
+    ///
+    /// Ranges include the size prefix of each beatmap, in versions that have one, so that entries
+    /// can be extracted or spliced without serializing them again.
+    pub fn from_bytes_with_spans(
+        bytes: &[u8],
+    ) -> Result<(Listing, Vec<ops::Range<usize>>), Error> {
+        let mut spans = Vec::new();
+        let listing = Self::from_bytes_with(bytes, ParseOptions::new().spans(&mut spans))?;
+        Ok((listing, spans))
     }
 
     /// Parse a listing from the `osu!.db` database file.
+    #[cfg(feature = "std")]
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Listing, Error> {
         Self::from_bytes(&fs::read(path)?)
     }
@@ -52,9 +91,319 @@ impl Listing {
     }
 
     /// Similar to `to_writer` but writes the listing to a file (ie. `osu!.db`).
+    #[cfg(feature = "std")]
     pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
         self.to_writer(BufWriter::new(File::create(path)?))
     }
+
+    /// Write the listing to an arbitrary writer, with the given save options.
+    #[cfg(feature = "std")]
+    pub fn to_writer_with<W: Write>(&self, mut out: W, opts: &SaveOptions) -> io::Result<()> {
+        let folder_count = match &opts.folder_count {
+            FolderCount::Keep => self.folder_count,
+            FolderCount::FromEntries => self.count_folders_from_entries(),
+            FolderCount::FromDisk(songs_dir) => count_folders_on_disk(songs_dir)?,
+        };
+        let order = opts.beatmap_order.arrange(&self.beatmaps);
+        write_listing(self, folder_count, &mut out, |idx, _beatmap, out| {
+            self.beatmaps[order[idx]].wr_args(out, self.version)
+        })
+    }
+
+    /// Similar to `to_writer_with` but writes the listing to a file (ie. `osu!.db`).
+    #[cfg(feature = "std")]
+    pub fn save_with<P: AsRef<Path>>(&self, path: P, opts: &SaveOptions) -> io::Result<()> {
+        self.to_writer_with(BufWriter::new(File::create(path)?), opts)
+    }
+
+    /// Change the version of the listing, adjusting any version-dependent data so that it can be
+    /// written in the layout of the target version and read back unchanged.
+    ///
+    /// In particular, migrating to a version older than `20140609` rounds difficulty values to
+    /// whole numbers (as they are stored as bytes), clears star ratings (which are not stored)
+    /// and fills in `mysterious_short`, while newer versions drop `mysterious_short`.
+    pub fn migrate_to(&mut self, version: u32) {
+        for beatmap in self.beatmaps.iter_mut() {
+            if version < CHANGE_20140609 {
+                for value in [
+                    &mut beatmap.approach_rate,
+                    &mut beatmap.circle_size,
+                    &mut beatmap.hp_drain,
+                    &mut beatmap.overall_difficulty,
+                ] {
+                    *value = (round(*value as f64) as f32).clamp(0.0, u8::MAX as f32);
+                }
+                beatmap.std_ratings.clear();
+                beatmap.taiko_ratings.clear();
+                beatmap.ctb_ratings.clear();
+                beatmap.mania_ratings.clear();
+                beatmap.mysterious_short.get_or_insert(0);
+            } else {
+                beatmap.mysterious_short = None;
+            }
+            if version >= CHANGE_20250107 {
+                //Star ratings are stored as single-precision floats
+                for ratings in [
+                    &mut beatmap.std_ratings,
+                    &mut beatmap.taiko_ratings,
+                    &mut beatmap.ctb_ratings,
+                    &mut beatmap.mania_ratings,
+                ] {
+                    for (_mods, stars) in ratings.iter_mut() {
+                        *stars = *stars as f32 as f64;
+                    }
+                }
+            }
+        }
+        self.version = version;
+    }
+
+    /// Count the distinct beatmap folder names in the listing.
+    pub fn count_folders_from_entries(&self) -> u32 {
+        self.beatmaps
+            .iter()
+            .filter_map(|bm| bm.folder_name.as_deref())
+            .collect::<BTreeSet<_>>()
+            .len() as u32
+    }
+
+    /// Set `folder_count` to the amount of folders within the given "Songs" directory.
+    ///
+    /// osu! compares this count against the actual directory to decide whether to rescan the
+    /// beatmap folders, so keeping it up to date avoids a full rescan on startup.
+    /// Returns the new folder count.
+    #[cfg(feature = "std")]
+    pub fn recount_folders<P: AsRef<Path>>(&mut self, songs_dir: P) -> io::Result<u32> {
+        self.folder_count = count_folders_on_disk(songs_dir.as_ref())?;
+        Ok(self.folder_count)
+    }
+
+    /// Set `folder_count` to the amount of distinct beatmap folder names in the listing.
+    /// Returns the new folder count.
+    pub fn recount_folders_from_entries(&mut self) -> u32 {
+        self.folder_count = self.count_folders_from_entries();
+        self.folder_count
+    }
+
+    /// Sort the beatmaps by the date they were added, as guessed by `Beatmap::date_added`: oldest
+    /// first, with beatmaps of unknown date before every other beatmap.
+    ///
+    /// The sort is stable, so beatmaps added at the same time keep their relative order.
+    pub fn sort_by_date_added(&mut self) {
+        self.beatmaps.sort_by_key(Beatmap::date_added);
+    }
+
+    /// Build an index from beatmap hashes to their position within `beatmaps`, to quickly look
+    /// up the beatmaps referred to by scores and collections.
+    pub fn hash_index(&self) -> HashIndex {
+        HashIndex::new(self.beatmaps.iter().map(|bm| bm.hash.md5()))
+    }
+}
+
+/// Options controlling how a listing is written.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct SaveOptions {
+    /// How to compute the `folder_count` written to the listing.
+    pub folder_count: FolderCount,
+    /// The order to write the beatmaps in.
+    /// The listing itself is left untouched.
+    pub beatmap_order: BeatmapOrder,
+}
+
+/// The order beatmaps are written in, as chosen through `SaveOptions::beatmap_order`.
+///
+/// osu! does not care about the order of the beatmaps in a listing, but keeping it stable makes
+/// logically identical listings serialize to identical bytes, so that snapshots can be
+/// deduplicated by hash or diffed meaningfully.
+/// Sorted orders break any remaining ties by folder name and then file name, so only beatmaps
+/// pointing to the very same `.osu` file keep their relative order.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BeatmapOrder {
+    /// Write the beatmaps in the order they are in.
+    #[default]
+    Keep,
+    /// Sort by beatmap hash, placing beatmaps without a hash first.
+    Hash,
+    /// Sort by beatmapset ID and then beatmap ID, breaking ties by hash.
+    BeatmapsetId,
+}
+#[cfg(feature = "std")]
+impl BeatmapOrder {
+    /// The indices of the beatmaps in the order they should be written.
+    ///
+    /// Beatmaps that compare equal keep their relative order.
+    fn arrange(self, beatmaps: &[Beatmap]) -> Vec<usize> {
+        fn path(bm: &Beatmap) -> (Option<&str>, Option<&str>) {
+            (bm.folder_name.as_deref(), bm.file_name.as_deref())
+        }
+        let mut order: Vec<usize> = (0..beatmaps.len()).collect();
+        match self {
+            BeatmapOrder::Keep => {}
+            BeatmapOrder::Hash => order.sort_by(|&a, &b| {
+                let (a, b) = (&beatmaps[a], &beatmaps[b]);
+                a.hash.cmp(&b.hash).then_with(|| path(a).cmp(&path(b)))
+            }),
+            BeatmapOrder::BeatmapsetId => order.sort_by(|&a, &b| {
+                let key = |bm: &Beatmap| (bm.beatmapset_id, bm.beatmap_id);
+                let (a, b) = (&beatmaps[a], &beatmaps[b]);
+                key(a)
+                    .cmp(&key(b))
+                    .then_with(|| a.hash.cmp(&b.hash))
+                    .then_with(|| path(a).cmp(&path(b)))
+            }),
+        }
+        order
+    }
+}
+
+/// Where to take the folder count from when writing a listing.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub enum FolderCount {
+    /// Write the `folder_count` field as-is.
+    #[default]
+    Keep,
+    /// Count the distinct beatmap folder names in the listing.
+    FromEntries,
+    /// Count the folders within the given "Songs" directory.
+    FromDisk(PathBuf),
+}
+
+#[cfg(feature = "std")]
+fn count_folders_on_disk(songs_dir: &Path) -> io::Result<u32> {
+    let mut count = 0;
+    for entry in fs::read_dir(songs_dir)? {
+        if entry?.file_type()?.is_dir() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// How the size prefix of each beatmap is handled, in listings older than `20191106`.
+///
+/// osu! itself reads and discards the prefix, so it can disagree with the actual size of the
+/// entry without anyone noticing.
+/// A mismatch is still a strong sign of corruption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizePrefixes {
+    /// Read and discard the prefix, as osu! does.
+    #[default]
+    Ignore,
+    /// Fail to parse if the size of a beatmap does not match its prefix.
+    /// The mismatch is also reported as a `Warning::BeatmapSizeMismatch` before failing.
+    Strict,
+    /// Trust the prefix to find the end of each beatmap, skipping beatmaps that fail to parse or
+    /// whose size does not match their prefix, and carry on with the next one.
+    /// Each skipped beatmap is reported as a `Warning::SkippedBeatmap`.
+    Recover,
+}
+
+/// A pool of buffers that can be reused across repeated listing parses.
+///
+/// Parsing a listing makes many small allocations: strings, star rating lists and timing point
+/// lists for every single beatmap.
+/// When the same database is parsed over and over (eg. a long-running tool reloading `osu!.db`
+/// every few minutes), handing the previous listing back through `recycle` lets the next parse
+/// reuse these allocations instead of making new ones.
+///
+/// Use it by passing it to `Listing::from_bytes_with` through `ParseOptions::arena`.
+#[derive(Debug, Default)]
+pub struct ParseArena {
+    strings: Vec<String>,
+    ratings: Vec<Vec<(ModSet, f64)>>,
+    timing_points: Vec<Vec<TimingPoint>>,
+    beatmaps: Vec<Vec<Beatmap>>,
+}
+impl ParseArena {
+    /// Create an empty arena.
+    pub fn new() -> ParseArena {
+        ParseArena::default()
+    }
+
+    /// Take back the allocations of a listing that is no longer needed, to be reused by later
+    /// parses.
+    pub fn recycle(&mut self, mut listing: Listing) {
+        self.recycle_string(listing.player_name);
+        for beatmap in listing.beatmaps.drain(..) {
+            self.recycle_beatmap(beatmap);
+        }
+        self.beatmaps.push(listing.beatmaps);
+    }
+
+    /// Take back the allocations of a single beatmap, to be reused by later parses.
+    pub fn recycle_beatmap(&mut self, beatmap: Beatmap) {
+        for string in [
+            beatmap.artist_ascii,
+            beatmap.artist_unicode,
+            beatmap.title_ascii,
+            beatmap.title_unicode,
+            beatmap.creator,
+            beatmap.difficulty_name,
+            beatmap.audio,
+            beatmap.file_name,
+            beatmap.song_source,
+            beatmap.tags,
+            beatmap.title_font,
+            beatmap.folder_name,
+        ] {
+            self.recycle_string(string);
+        }
+        for mut ratings in [
+            beatmap.std_ratings,
+            beatmap.taiko_ratings,
+            beatmap.ctb_ratings,
+            beatmap.mania_ratings,
+        ] {
+            if ratings.capacity() != 0 {
+                ratings.clear();
+                self.ratings.push(ratings);
+            }
+        }
+        let mut timing_points = beatmap.timing_points;
+        if timing_points.capacity() != 0 {
+            timing_points.clear();
+            self.timing_points.push(timing_points);
+        }
+    }
+
+    /// Release all pooled memory.
+    pub fn clear(&mut self) {
+        *self = ParseArena::default();
+    }
+
+    fn recycle_string(&mut self, string: Option<String>) {
+        if let Some(mut string) = string {
+            if string.capacity() != 0 {
+                string.clear();
+                self.strings.push(string);
+            }
+        }
+    }
+
+    fn string(&mut self, string: Option<&str>) -> Option<String> {
+        string.map(|string| match self.strings.pop() {
+            Some(mut buf) => {
+                buf.push_str(string);
+                buf
+            }
+            None => string.to_owned(),
+        })
+    }
+
+    fn ratings(&mut self) -> Vec<(ModSet, f64)> {
+        self.ratings.pop().unwrap_or_default()
+    }
+
+    fn timing_points(&mut self) -> Vec<TimingPoint> {
+        self.timing_points.pop().unwrap_or_default()
+    }
+
+    fn beatmaps(&mut self) -> Vec<Beatmap> {
+        self.beatmaps.pop().unwrap_or_default()
+    }
 }
 
 #[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
@@ -75,14 +424,14 @@ pub struct Beatmap {
     /// The filename of the song file.
     pub audio: Option<String>,
     /// The MD5 hash of the beatmap.
-    pub hash: Option<String>,
+    pub hash: StoredHash,
     /// The filename of the `.osu` file corresponding to this specific difficulty.
     pub file_name: Option<String>,
     pub status: RankedStatus,
     pub hitcircle_count: u16,
     pub slider_count: u16,
     pub spinner_count: u16,
-    pub last_modified: DateTime<Utc>,
+    pub last_modified: OsuTimestamp,
     pub approach_rate: f32,
     pub circle_size: f32,
     pub hp_drain: f32,
@@ -100,8 +449,8 @@ pub struct Beatmap {
     /// song.
     pub preview_time: u32,
     pub timing_points: Vec<TimingPoint>,
-    pub beatmap_id: i32,
-    pub beatmapset_id: i32,
+    pub beatmap_id: BeatmapId,
+    pub beatmapset_id: BeatmapsetId,
     pub thread_id: u32,
     pub std_grade: Grade,
     pub taiko_grade: Grade,
@@ -117,13 +466,13 @@ pub struct Beatmap {
     pub online_offset: u16,
     pub title_font: Option<String>,
     /// Whether the beatmap has been played, and if it has, when was it last played.
-    pub last_played: Option<DateTime<Utc>>,
+    pub last_played: Option<OsuTimestamp>,
     /// Whether the beatmap was in `osz2` format.
     pub is_osz2: bool,
     /// The folder name of the beatmapset within the "Songs" folder.
     pub folder_name: Option<String>,
     /// When was the beatmap last checked against the online osu! repository.
-    pub last_online_check: DateTime<Utc>,
+    pub last_online_check: OsuTimestamp,
     pub ignore_sounds: bool,
     pub ignore_skin: bool,
     pub disable_storyboard: bool,
@@ -135,10 +484,119 @@ pub struct Beatmap {
     ///
     /// Perhaps an early attempt at "last modified", but scrapped once peppy noticed it only had
     /// 32 bits.
+    /// `date_added` and friends read it as the date the beatmap was added, in seconds since the
+    /// unix epoch, with `0` as unknown.
+    /// This is a guess that nothing official confirms.
     pub mysterious_last_modified: u32,
     pub mania_scroll_speed: u8,
 }
 
+impl Beatmap {
+    /// The name of the beatmap as osu! displays it, as `Artist - Title [Difficulty]`.
+    ///
+    /// Missing fields are left empty.
+    pub fn display_name(&self) -> String {
+        format!(
+            "{} - {} [{}]",
+            self.artist_ascii.as_deref().unwrap_or_default(),
+            self.title_ascii.as_deref().unwrap_or_default(),
+            self.difficulty_name.as_deref().unwrap_or_default(),
+        )
+    }
+
+    /// Whether the beatmap was never submitted online, either because it has no beatmapset ID
+    /// or because its ranked status says so.
+    ///
+    /// Beatmaps that were submitted have positive IDs, while unsubmitted ones have `0` or `-1`
+    /// depending on the version that wrote them.
+    pub fn is_unsubmitted(&self) -> bool {
+        !self.beatmapset_id.is_submitted() || self.status == RankedStatus::Unsubmitted
+    }
+
+    /// When the beatmap was presumably added to the listing, if known.
+    ///
+    /// This reads `mysterious_last_modified` as whole seconds since the unix epoch, with `0`
+    /// meaning unknown. No other field is involved.
+    /// The meaning of that field is not documented anywhere, so the result is a guess.
+    pub fn date_added(&self) -> Option<OsuTimestamp> {
+        match self.mysterious_last_modified {
+            0 => None,
+            secs => Some(OsuTimestamp::from_unix_secs(secs as i64)),
+        }
+    }
+
+    /// Set the date the beatmap was presumably added to the listing, see `date_added`.
+    ///
+    /// This writes `mysterious_last_modified` and nothing else. The date is stored with a
+    /// precision of seconds, and clamped to the range that fits in it without meaning unknown.
+    pub fn set_date_added(&mut self, date: OsuTimestamp) {
+        self.mysterious_last_modified = date.unix_secs().clamp(1, u32::MAX as i64) as u32;
+    }
+
+    /// The star ratings of the beatmap when played in the given mode.
+    ///
+    /// Only osu!standard beatmaps have ratings for other modes, as they can be converted.
+    pub fn ratings(&self, mode: Mode) -> &StarRatings {
+        match mode {
+            Mode::Standard => &self.std_ratings,
+            Mode::Taiko => &self.taiko_ratings,
+            Mode::CatchTheBeat => &self.ctb_ratings,
+            Mode::Mania => &self.mania_ratings,
+        }
+    }
+
+    /// The star ratings of the beatmap when played in the given mode, as a table that can be
+    /// queried by mods.
+    pub fn ratings_for(&self, mode: Mode) -> RatingTable<'_> {
+        RatingTable {
+            ratings: self.ratings(mode),
+        }
+    }
+
+    /// The star rating of the beatmap when played in the given mode with the given mods.
+    ///
+    /// See `RatingTable::with` for how mods are looked up.
+    /// Returns `None` if there is no suitable rating, such as in listings older than `20140609`.
+    pub fn stars(&self, mode: Mode, mods: ModSet) -> Option<f64> {
+        self.ratings_for(mode).with(mods)
+    }
+
+    /// Mutable access to the star ratings of the beatmap when played in the given mode.
+    pub fn ratings_mut(&mut self, mode: Mode) -> &mut StarRatings {
+        match mode {
+            Mode::Standard => &mut self.std_ratings,
+            Mode::Taiko => &mut self.taiko_ratings,
+            Mode::CatchTheBeat => &mut self.ctb_ratings,
+            Mode::Mania => &mut self.mania_ratings,
+        }
+    }
+
+    /// The best grade achieved on the beatmap in the given mode.
+    pub fn grade(&self, mode: Mode) -> Grade {
+        match mode {
+            Mode::Standard => self.std_grade,
+            Mode::Taiko => self.taiko_grade,
+            Mode::CatchTheBeat => self.ctb_grade,
+            Mode::Mania => self.mania_grade,
+        }
+    }
+
+    /// Mutable access to the best grade achieved on the beatmap in the given mode.
+    pub fn grade_mut(&mut self, mode: Mode) -> &mut Grade {
+        match mode {
+            Mode::Standard => &mut self.std_grade,
+            Mode::Taiko => &mut self.taiko_grade,
+            Mode::CatchTheBeat => &mut self.ctb_grade,
+            Mode::Mania => &mut self.mania_grade,
+        }
+    }
+
+    /// Whether the beatmap can be played in the given mode, either natively or as a convert.
+    pub fn is_playable_in(&self, mode: Mode) -> bool {
+        self.mode == mode || self.mode == Mode::Standard
+    }
+}
+
 #[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RankedStatus {
@@ -180,16 +638,115 @@ impl RankedStatus {
     }
 }
 
+/// The mods that change the playback rate of a beatmap, as a bitmask.
+const RATE_MODS: u32 = 1 << Mod::DoubleTime as u32 | 1 << Mod::HalfTime as u32;
+/// The mods that osu! computes star ratings for, as a bitmask.
+const DIFFICULTY_MODS: u32 = RATE_MODS | 1 << Mod::Easy as u32 | 1 << Mod::HardRock as u32;
+
 /// A list of the precalculated amount of difficulty stars a given mod combination yields for a
 /// beatmap.
 ///
-/// You might want to convert this list into a map using
-/// `ratings.into_iter().collect::<HashMap<_>>()` or variations, allowing for quick indexing with
-/// different mod combinations.
+/// Use `Beatmap::ratings_for` to look up ratings by mods.
 ///
 /// Note that old "osu!.db" files (before the 2014/06/09 version) do not have these ratings.
 pub type StarRatings = Vec<(ModSet, f64)>;
 
+/// A read-only view of the star ratings of a beatmap in a single mode, as returned by
+/// `Beatmap::ratings_for`.
+///
+/// Since `20250107` ratings are stored as single-precision floats, so ratings read from newer
+/// listings only have about 7 significant digits, even though they are exposed as `f64`.
+/// Lookups compare mods, never star values, so they work the same regardless of precision.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RatingTable<'a> {
+    ratings: &'a [(ModSet, f64)],
+}
+impl<'a> RatingTable<'a> {
+    /// The rating without any mods.
+    pub fn nomod(&self) -> Option<f64> {
+        self.get(ModSet::empty())
+    }
+
+    /// The rating for exactly the given mod combination, if it is in the table.
+    pub fn get(&self, mods: ModSet) -> Option<f64> {
+        self.ratings
+            .iter()
+            .find(|&&(m, _)| m == mods)
+            .map(|&(_, stars)| stars)
+    }
+
+    /// The rating for the given mods, the way a leaderboard would show it.
+    ///
+    /// osu! only precomputes ratings for combinations of the mods that change difficulty
+    /// (`Easy`, `HardRock`, `DoubleTime` and `HalfTime`), so the rest are ignored and
+    /// `Nightcore` counts as `DoubleTime`.
+    /// If the exact combination is not in the table, the rating with only the rate-changing mods
+    /// is used, and then the no-mod rating.
+    pub fn with(&self, mods: ModSet) -> Option<f64> {
+        let mut mods = mods;
+        if mods.contains(Mod::Nightcore) {
+            mods = mods.with(Mod::DoubleTime);
+        }
+        let rate = ModSet::from_bits(mods.bits() & RATE_MODS);
+        let difficulty = ModSet::from_bits(mods.bits() & DIFFICULTY_MODS);
+        self.get(difficulty)
+            .or_else(|| self.get(rate))
+            .or_else(|| self.nomod())
+    }
+
+    /// The highest rating in the table, along with its mods.
+    ///
+    /// If several mod combinations share the highest rating, the first one is returned.
+    pub fn max(&self) -> Option<(ModSet, f64)> {
+        self.ratings
+            .iter()
+            .copied()
+            .reduce(|max, rating| if rating.1 > max.1 { rating } else { max })
+    }
+
+    /// The lowest rating in the table, along with its mods.
+    ///
+    /// If several mod combinations share the lowest rating, the first one is returned.
+    pub fn min(&self) -> Option<(ModSet, f64)> {
+        self.ratings
+            .iter()
+            .copied()
+            .reduce(|min, rating| if rating.1 < min.1 { rating } else { min })
+    }
+
+    /// Iterate over the ratings in the order they are stored.
+    pub fn iter(&self) -> impl Iterator<Item = (ModSet, f64)> + 'a {
+        self.ratings.iter().copied()
+    }
+
+    /// Iterate over the ratings from the lowest to the highest amount of stars.
+    ///
+    /// Ratings with the same amount of stars keep the order they are stored in.
+    pub fn iter_by_stars(&self) -> impl Iterator<Item = (ModSet, f64)> {
+        let mut sorted = self.ratings.to_vec();
+        sorted.sort_by(|a, b| a.1.total_cmp(&b.1));
+        sorted.into_iter()
+    }
+
+    /// The amount of mod combinations in the table.
+    pub fn len(&self) -> usize {
+        self.ratings.len()
+    }
+
+    /// Whether the table has no ratings, such as in listings older than `20140609`.
+    pub fn is_empty(&self) -> bool {
+        self.ratings.is_empty()
+    }
+
+    /// Whether every rating can be stored as a single-precision float without losing precision,
+    /// as is the case for ratings read from listings since `20250107`.
+    pub fn is_single_precision(&self) -> bool {
+        self.ratings
+            .iter()
+            .all(|&(_, stars)| stars as f32 as f64 == stars || stars.is_nan())
+    }
+}
+
 #[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct TimingPoint {
@@ -264,150 +821,235 @@ impl Grade {
     }
 }
 
-fn listing(bytes: &[u8]) -> IResult<&[u8], Listing> {
-    let (rem, version) = int(bytes)?;
-    let (rem, folder_count) = int(rem)?;
-    let (rem, account_unlocked) = boolean(rem)?;
-    let (rem, unlock_date) = datetime(rem)?;
-    let (rem, player_name) = opt_string(rem)?;
-    let (rem, beatmaps) = length_count(map(int, identity), |bytes| beatmap(bytes, version))(rem)?;
-    let (rem, user_permissions) = int(rem)?;
-
-    let listing = Listing {
-        version,
-        folder_count,
-        unban_date: build_option(account_unlocked, unlock_date),
-        player_name,
-        beatmaps,
-        user_permissions,
+fn listing<'a>(bytes: &'a [u8], opts: &mut ParseOptions) -> IResult<&'a [u8], Listing> {
+    let mut scratch = ParseArena::new();
+    let arena = match opts.arena.take() {
+        Some(arena) => arena,
+        None => &mut scratch,
     };
+    let mut builder = ListingBuilder {
+        listing: Listing {
+            version: 0,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps: arena.beatmaps(),
+            user_permissions: 0,
+        },
+        max_beatmaps: bytes.len() / MIN_BEATMAP_SIZE,
+        beatmap: None,
+        arena,
+    };
+    let (rem, ()) = listing_events(bytes, opts, &mut builder)?;
+    Ok((rem, builder.listing))
+}
 
-    Ok((rem, listing))
+/// Assembles a `Listing` out of the parse events of `visit::listing_events`, taking its buffers
+/// from an arena.
+struct ListingBuilder<'r> {
+    arena: &'r mut ParseArena,
+    /// An upper bound on the amount of beatmaps the input can hold, to avoid reserving room for a
+    /// bogus count.
+    max_beatmaps: usize,
+    listing: Listing,
+    beatmap: Option<Beatmap>,
+}
+impl ListingVisitor for ListingBuilder<'_> {
+    fn header(&mut self, field: HeaderField) {
+        let listing = &mut self.listing;
+        match field {
+            HeaderField::Version(version) => listing.version = version,
+            HeaderField::FolderCount(count) => listing.folder_count = count,
+            HeaderField::UnbanDate(date) => listing.unban_date = date,
+            HeaderField::PlayerName(name) => listing.player_name = self.arena.string(name),
+            HeaderField::BeatmapCount(len) => listing
+                .beatmaps
+                .reserve((len as usize).min(self.max_beatmaps)),
+            HeaderField::UserPermissions(permissions) => listing.user_permissions = permissions,
+        }
+    }
+
+    fn begin_beatmap(&mut self, _index: usize) {
+        self.beatmap = Some(blank_beatmap(self.arena));
+    }
+
+    fn beatmap_field(&mut self, field: BeatmapField) {
+        use crate::visit::BeatmapField as F;
+        let bm = match &mut self.beatmap {
+            Some(bm) => bm,
+            None => return,
+        };
+        let arena = &mut *self.arena;
+        match field {
+            F::ArtistAscii(s) => bm.artist_ascii = arena.string(s),
+            F::ArtistUnicode(s) => bm.artist_unicode = arena.string(s),
+            F::TitleAscii(s) => bm.title_ascii = arena.string(s),
+            F::TitleUnicode(s) => bm.title_unicode = arena.string(s),
+            F::Creator(s) => bm.creator = arena.string(s),
+            F::DifficultyName(s) => bm.difficulty_name = arena.string(s),
+            F::Audio(s) => bm.audio = arena.string(s),
+            F::Hash(hash) => bm.hash = hash.map_or(StoredHash::Missing, StoredHash::from_stored),
+            F::FileName(s) => bm.file_name = arena.string(s),
+            F::Status(status) => bm.status = status,
+            F::HitcircleCount(count) => bm.hitcircle_count = count,
+            F::SliderCount(count) => bm.slider_count = count,
+            F::SpinnerCount(count) => bm.spinner_count = count,
+            F::LastModified(date) => bm.last_modified = date,
+            F::ApproachRate(value) => bm.approach_rate = value,
+            F::CircleSize(value) => bm.circle_size = value,
+            F::HpDrain(value) => bm.hp_drain = value,
+            F::OverallDifficulty(value) => bm.overall_difficulty = value,
+            F::SliderVelocity(value) => bm.slider_velocity = value,
+            F::DrainTime(time) => bm.drain_time = time,
+            F::TotalTime(time) => bm.total_time = time,
+            F::PreviewTime(time) => bm.preview_time = time,
+            F::BeatmapId(id) => bm.beatmap_id = id,
+            F::BeatmapsetId(id) => bm.beatmapset_id = id,
+            F::ThreadId(id) => bm.thread_id = id,
+            F::Grade(Mode::Standard, grade) => bm.std_grade = grade,
+            F::Grade(Mode::Taiko, grade) => bm.taiko_grade = grade,
+            F::Grade(Mode::CatchTheBeat, grade) => bm.ctb_grade = grade,
+            F::Grade(Mode::Mania, grade) => bm.mania_grade = grade,
+            F::LocalBeatmapOffset(offset) => bm.local_beatmap_offset = offset,
+            F::StackLeniency(value) => bm.stack_leniency = value,
+            F::Mode(mode) => bm.mode = mode,
+            F::SongSource(s) => bm.song_source = arena.string(s),
+            F::Tags(s) => bm.tags = arena.string(s),
+            F::OnlineOffset(offset) => bm.online_offset = offset,
+            F::TitleFont(s) => bm.title_font = arena.string(s),
+            F::LastPlayed(date) => bm.last_played = date,
+            F::IsOsz2(value) => bm.is_osz2 = value,
+            F::FolderName(s) => bm.folder_name = arena.string(s),
+            F::LastOnlineCheck(date) => bm.last_online_check = date,
+            F::IgnoreSounds(value) => bm.ignore_sounds = value,
+            F::IgnoreSkin(value) => bm.ignore_skin = value,
+            F::DisableStoryboard(value) => bm.disable_storyboard = value,
+            F::DisableVideo(value) => bm.disable_video = value,
+            F::VisualOverride(value) => bm.visual_override = value,
+            F::MysteriousShort(value) => bm.mysterious_short = value,
+            F::MysteriousLastModified(value) => bm.mysterious_last_modified = value,
+            F::ManiaScrollSpeed(speed) => bm.mania_scroll_speed = speed,
+        }
+    }
+
+    fn star_rating(&mut self, mode: Mode, mods: ModSet, stars: f64) {
+        if let Some(bm) = &mut self.beatmap {
+            let ratings = match mode {
+                Mode::Standard => &mut bm.std_ratings,
+                Mode::Taiko => &mut bm.taiko_ratings,
+                Mode::CatchTheBeat => &mut bm.ctb_ratings,
+                Mode::Mania => &mut bm.mania_ratings,
+            };
+            ratings.push((mods, stars));
+        }
+    }
+
+    fn timing_point(&mut self, point: TimingPoint) {
+        if let Some(bm) = &mut self.beatmap {
+            bm.timing_points.push(point);
+        }
+    }
+
+    fn end_beatmap(&mut self, _index: usize) {
+        if let Some(bm) = self.beatmap.take() {
+            self.listing.beatmaps.push(bm);
+        }
+    }
+
+    fn skip_beatmap(&mut self, _index: usize) {
+        if let Some(bm) = self.beatmap.take() {
+            self.arena.recycle_beatmap(bm);
+        }
+    }
+}
+
+/// A beatmap with every field empty, for `ListingBuilder` to fill in.
+fn blank_beatmap(arena: &mut ParseArena) -> Beatmap {
+    Beatmap {
+        artist_ascii: None,
+        artist_unicode: None,
+        title_ascii: None,
+        title_unicode: None,
+        creator: None,
+        difficulty_name: None,
+        audio: None,
+        hash: StoredHash::Missing,
+        file_name: None,
+        status: RankedStatus::Unknown,
+        hitcircle_count: 0,
+        slider_count: 0,
+        spinner_count: 0,
+        last_modified: OsuTimestamp::default(),
+        approach_rate: 0.0,
+        circle_size: 0.0,
+        hp_drain: 0.0,
+        overall_difficulty: 0.0,
+        slider_velocity: 0.0,
+        std_ratings: arena.ratings(),
+        taiko_ratings: arena.ratings(),
+        ctb_ratings: arena.ratings(),
+        mania_ratings: arena.ratings(),
+        drain_time: 0,
+        total_time: 0,
+        preview_time: 0,
+        timing_points: arena.timing_points(),
+        beatmap_id: BeatmapId::default(),
+        beatmapset_id: BeatmapsetId::default(),
+        thread_id: 0,
+        std_grade: Grade::Unplayed,
+        taiko_grade: Grade::Unplayed,
+        ctb_grade: Grade::Unplayed,
+        mania_grade: Grade::Unplayed,
+        local_beatmap_offset: 0,
+        stack_leniency: 0.0,
+        mode: Mode::Standard,
+        song_source: None,
+        tags: None,
+        online_offset: 0,
+        title_font: None,
+        last_played: None,
+        is_osz2: false,
+        folder_name: None,
+        last_online_check: OsuTimestamp::default(),
+        ignore_sounds: false,
+        ignore_skin: false,
+        disable_storyboard: false,
+        disable_video: false,
+        visual_override: false,
+        mysterious_short: None,
+        mysterious_last_modified: 0,
+        mania_scroll_speed: 0,
+    }
 }
 
 writer!(Listing [this, out] {
+    write_listing(this, this.folder_count, out, |_idx, beatmap, out| {
+        beatmap.wr_args(out, this.version)
+    })?;
+});
+
+/// Write a listing with the given folder count, delegating the writing of each beatmap to the
+/// given closure.
+pub(crate) fn write_listing<W, F>(
+    this: &Listing,
+    folder_count: u32,
+    out: &mut W,
+    mut write_beatmap: F,
+) -> io::Result<()>
+where
+    W: Write,
+    F: FnMut(usize, &Beatmap, &mut W) -> io::Result<()>,
+{
     this.version.wr(out)?;
-    this.folder_count.wr(out)?;
-    write_option(out,this.unban_date,0_u64)?;
+    folder_count.wr(out)?;
+    write_option(out, this.unban_date, 0_u64)?;
     this.player_name.wr(out)?;
-    PrefixedList(&this.beatmaps).wr_args(out,this.version)?;
+    (this.beatmaps.len() as u32).wr(out)?;
+    for (idx, beatmap) in this.beatmaps.iter().enumerate() {
+        write_beatmap(idx, beatmap, out)?;
+    }
     this.user_permissions.wr(out)?;
-});
-
-fn beatmap(bytes: &[u8], version: u32) -> IResult<&[u8], Beatmap> {
-    let (rem, _beatmap_size) = cond(version < CHANGE_20191106, int)(bytes)?;
-    let (rem, artist_ascii) = opt_string(rem)?;
-    let (rem, artist_unicode) = opt_string(rem)?;
-    let (rem, title_ascii) = opt_string(rem)?;
-    let (rem, title_unicode) = opt_string(rem)?;
-    let (rem, creator) = opt_string(rem)?;
-    let (rem, difficulty_name) = opt_string(rem)?;
-    let (rem, audio) = opt_string(rem)?;
-    let (rem, hash) = opt_string(rem)?;
-    let (rem, file_name) = opt_string(rem)?;
-    let (rem, status) = ranked_status(rem)?;
-    let (rem, hitcircle_count) = short(rem)?;
-    let (rem, slider_count) = short(rem)?;
-    let (rem, spinner_count) = short(rem)?;
-    let (rem, last_modified) = datetime(rem)?;
-    let (rem, approach_rate) = difficulty_value(rem, version)?;
-    let (rem, circle_size) = difficulty_value(rem, version)?;
-    let (rem, hp_drain) = difficulty_value(rem, version)?;
-    let (rem, overall_difficulty) = difficulty_value(rem, version)?;
-    let (rem, slider_velocity) = double(rem)?;
-    let (rem, std_ratings) = star_ratings(rem, version)?;
-    let (rem, taiko_ratings) = star_ratings(rem, version)?;
-    let (rem, ctb_ratings) = star_ratings(rem, version)?;
-    let (rem, mania_ratings) = star_ratings(rem, version)?;
-    let (rem, drain_time) = int(rem)?;
-    let (rem, total_time) = int(rem)?;
-    let (rem, preview_time) = int(rem)?;
-    let (rem, timing_points) = length_count(map(int, identity), timing_point)(rem)?;
-    let (rem, beatmap_id) = int(rem)?;
-    let (rem, beatmapset_id) = int(rem)?;
-    let (rem, thread_id) = int(rem)?;
-    let (rem, std_grade) = grade(rem)?;
-    let (rem, taiko_grade) = grade(rem)?;
-    let (rem, ctb_grade) = grade(rem)?;
-    let (rem, mania_grade) = grade(rem)?;
-    let (rem, local_beatmap_offset) = short(rem)?;
-    let (rem, stack_leniency) = single(rem)?;
-    let (rem, mode) = map_opt(byte, Mode::from_raw)(rem)?;
-    let (rem, song_source) = opt_string(rem)?;
-    let (rem, tags) = opt_string(rem)?;
-    let (rem, online_offset) = short(rem)?;
-    let (rem, title_font) = opt_string(rem)?;
-    let (rem, unplayed) = boolean(rem)?;
-    let (rem, last_played) = datetime(rem)?;
-    let (rem, is_osz2) = boolean(rem)?;
-    let (rem, folder_name) = opt_string(rem)?;
-    let (rem, last_online_check) = datetime(rem)?;
-    let (rem, ignore_sounds) = boolean(rem)?;
-    let (rem, ignore_skin) = boolean(rem)?;
-    let (rem, disable_storyboard) = boolean(rem)?;
-    let (rem, disable_video) = boolean(rem)?;
-    let (rem, visual_override) = boolean(rem)?;
-    let (rem, mysterious_short) = cond(version < CHANGE_20140609, short)(rem)?;
-    let (rem, mysterious_last_modified) = int(rem)?;
-    let (rem, mania_scroll_speed) = byte(rem)?;
-
-    let map = Beatmap {
-        artist_ascii,
-        artist_unicode,
-        title_ascii,
-        title_unicode,
-        creator,
-        difficulty_name,
-        audio,
-        hash,
-        file_name,
-        status,
-        hitcircle_count,
-        slider_count,
-        spinner_count,
-        last_modified,
-        approach_rate,
-        circle_size,
-        hp_drain,
-        overall_difficulty,
-        slider_velocity,
-        std_ratings,
-        taiko_ratings,
-        ctb_ratings,
-        mania_ratings,
-        drain_time,
-        total_time,
-        preview_time,
-        timing_points,
-        beatmap_id: beatmap_id as i32,
-        beatmapset_id: beatmapset_id as i32,
-        thread_id,
-        std_grade,
-        taiko_grade,
-        ctb_grade,
-        mania_grade,
-        local_beatmap_offset,
-        stack_leniency,
-        mode,
-        song_source,
-        tags,
-        online_offset,
-        title_font,
-        last_played: build_option(unplayed, last_played),
-        is_osz2,
-        folder_name,
-        last_online_check,
-        ignore_sounds,
-        ignore_skin,
-        disable_storyboard,
-        disable_video,
-        visual_override,
-        mysterious_short,
-        mysterious_last_modified,
-        mania_scroll_speed,
-    };
-
-    Ok((rem, map))
+    Ok(())
 }
 
 writer!(Beatmap [this,out,version: u32] {
@@ -449,8 +1091,8 @@ writer!(Beatmap [this,out,version: u32] {
         this.total_time.wr(out)?;
         this.preview_time.wr(out)?;
         PrefixedList(&this.timing_points).wr(out)?;
-        (this.beatmap_id as u32).wr(out)?;
-        (this.beatmapset_id as u32).wr(out)?;
+        this.beatmap_id.wr(out)?;
+        this.beatmapset_id.wr(out)?;
         this.thread_id.wr(out)?;
         this.std_grade.wr(out)?;
         this.taiko_grade.wr(out)?;
@@ -493,7 +1135,7 @@ writer!(Beatmap [this,out,version: u32] {
     }
 });
 
-fn timing_point(bytes: &[u8]) -> IResult<&[u8], TimingPoint> {
+pub(crate) fn timing_point(bytes: &[u8]) -> IResult<&[u8], TimingPoint> {
     let (rem, bpm) = double(bytes)?;
     let (rem, offset) = double(rem)?;
     let (rem, inherits) = boolean(rem)?;
@@ -513,17 +1155,9 @@ writer!(TimingPoint [this,out] {
     this.inherits.wr(out)?;
 });
 
-fn star_ratings(bytes: &[u8], version: u32) -> IResult<&[u8], Vec<(ModSet, f64)>> {
-    if version >= CHANGE_20140609 {
-        length_count(map(int, identity), |bytes| star_rating(bytes, version))(bytes)
-    } else {
-        Ok((bytes, Vec::new()))
-    }
-}
-
 // Before breaking change 20250107 this was an Int-Double pair, which changed
 // to an Int-Float pair to massively reduce storage overhead.
-fn star_rating(bytes: &[u8], version: u32) -> IResult<&[u8], (ModSet, f64)> {
+pub(crate) fn star_rating(bytes: &[u8], version: u32) -> IResult<&[u8], (ModSet, f64)> {
     let (rem, _tag) = tag(&[0x08])(bytes)?;
     let (rem, mods) = map(int, ModSet::from_bits)(rem)?;
 
@@ -559,7 +1193,7 @@ writer!((ModSet,f64) [this,out,version: u32] {
 /// Before the breaking change in 2014 several difficulty values were stored as bytes.
 /// After it they were stored as single floats.
 /// Accomodate this differences.
-fn difficulty_value(bytes: &[u8], version: u32) -> IResult<&[u8], f32> {
+pub(crate) fn difficulty_value(bytes: &[u8], version: u32) -> IResult<&[u8], f32> {
     if version >= CHANGE_20140609 {
         single(bytes)
     } else {
@@ -567,19 +1201,19 @@ fn difficulty_value(bytes: &[u8], version: u32) -> IResult<&[u8], f32> {
     }
 }
 
-fn ranked_status(bytes: &[u8]) -> IResult<&[u8], RankedStatus> {
+pub(crate) fn ranked_status(bytes: &[u8]) -> IResult<&[u8], RankedStatus> {
     map_opt(byte, RankedStatus::from_raw)(bytes)
 }
 
 writer!(RankedStatus [this,out] this.raw().wr(out)?);
 
-fn grade(bytes: &[u8]) -> IResult<&[u8], Grade> {
+pub(crate) fn grade(bytes: &[u8]) -> IResult<&[u8], Grade> {
     map_opt(byte, Grade::from_raw)(bytes)
 }
 
 writer!(Grade [this,out] this.raw().wr(out)?);
 
-fn build_option<T>(is_none: bool, content: T) -> Option<T> {
+pub(crate) fn build_option<T>(is_none: bool, content: T) -> Option<T> {
     if is_none {
         None
     } else {
@@ -603,3 +1237,508 @@ fn write_option<W: Write, T: SimpleWritable, D: SimpleWritable>(
     }
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub(crate) fn beatmap() -> Beatmap {
+        //2012-03-04 05:06:07
+        let date = OsuTimestamp::from_unix_millis(1_330_837_567_000);
+        Beatmap {
+            artist_ascii: Some("Artist".to_string()),
+            artist_unicode: None,
+            title_ascii: Some("Title".to_string()),
+            title_unicode: Some("タイトル".to_string()),
+            creator: Some("Mapper".to_string()),
+            difficulty_name: Some("Insane".to_string()),
+            audio: Some("audio.mp3".to_string()),
+            hash: StoredHash::Md5("0123456789abcdef0123456789abcdef".parse().unwrap()),
+            file_name: Some("Artist - Title (Mapper) [Insane].osu".to_string()),
+            status: RankedStatus::Ranked,
+            hitcircle_count: 300,
+            slider_count: 200,
+            spinner_count: 2,
+            last_modified: date,
+            approach_rate: 9.3,
+            circle_size: 4.0,
+            hp_drain: 6.5,
+            overall_difficulty: 8.0,
+            slider_velocity: 1.8,
+            std_ratings: vec![(ModSet::empty(), 5.123456789)],
+            taiko_ratings: Vec::new(),
+            ctb_ratings: Vec::new(),
+            mania_ratings: Vec::new(),
+            drain_time: 120,
+            total_time: 125_000,
+            preview_time: 40_000,
+            timing_points: vec![TimingPoint {
+                bpm: 300.0,
+                offset: 1000.0,
+                inherits: true,
+            }],
+            beatmap_id: BeatmapId(12345),
+            beatmapset_id: BeatmapsetId(-1),
+            thread_id: 0,
+            std_grade: Grade::A,
+            taiko_grade: Grade::Unplayed,
+            ctb_grade: Grade::Unplayed,
+            mania_grade: Grade::Unplayed,
+            local_beatmap_offset: 0,
+            stack_leniency: 0.7,
+            mode: Mode::Standard,
+            song_source: None,
+            tags: Some("some tags".to_string()),
+            online_offset: 0,
+            title_font: None,
+            last_played: Some(date),
+            is_osz2: false,
+            folder_name: Some("1 Artist - Title".to_string()),
+            last_online_check: date,
+            ignore_sounds: false,
+            ignore_skin: true,
+            disable_storyboard: false,
+            disable_video: true,
+            visual_override: false,
+            mysterious_short: None,
+            mysterious_last_modified: 0,
+            mania_scroll_speed: 20,
+        }
+    }
+
+    fn round_trip(version: u32) {
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: Some("player".to_string()),
+            beatmaps: vec![beatmap(), beatmap()],
+            user_permissions: 1,
+        };
+        listing.migrate_to(version);
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+        assert_eq!(Listing::from_bytes(&raw).unwrap(), listing);
+    }
+
+    #[test]
+    fn legacy_versions() {
+        round_trip(20120101);
+        round_trip(20140608);
+        round_trip(20140609);
+        round_trip(20191105);
+    }
+
+    #[test]
+    #[cfg(feature = "encoding")]
+    fn legacy_upgrade() {
+        //An old listing whose artist was written in Shift-JIS rather than UTF-8
+        let mut bm = beatmap();
+        bm.artist_unicode = Some("@@@@@@".to_string());
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: Some("player".to_string()),
+            beatmaps: vec![bm],
+            user_permissions: 1,
+        };
+        listing.migrate_to(20120101);
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+        let at = raw.windows(6).position(|w| w == b"@@@@@@").unwrap();
+        raw[at..at + 6].copy_from_slice(b"\x83\x5e\x83\x43\x83\x67");
+        assert!(Listing::from_bytes(&raw).is_err());
+
+        let opts =
+            ParseOptions::new().string_fallback(StringFallback::Encoding(encoding_rs::SHIFT_JIS));
+        let mut old = Listing::from_bytes_with(&raw, opts).unwrap();
+        listing.beatmaps[0].artist_unicode = Some("タイト".to_string());
+        assert_eq!(old, listing);
+
+        //Upgrading writes it back in the current layout, as UTF-8
+        old.migrate_to(LATEST_KNOWN_VERSION);
+        let mut raw = Vec::new();
+        old.to_writer(&mut raw).unwrap();
+        let upgraded = Listing::from_bytes(&raw).unwrap();
+        assert_eq!(upgraded, old);
+        assert_eq!(upgraded.version, LATEST_KNOWN_VERSION);
+        assert_eq!(
+            upgraded.beatmaps[0].artist_unicode.as_deref(),
+            Some("タイト")
+        );
+    }
+
+    #[test]
+    fn current_versions() {
+        round_trip(20191106);
+        round_trip(20250107);
+    }
+
+    #[test]
+    fn huge_beatmap_count() {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&20211103_u32.to_le_bytes());
+        raw.extend_from_slice(&[0; 4 + 1 + 8 + 1]);
+        raw.extend_from_slice(&u32::MAX.to_le_bytes());
+        raw.extend_from_slice(&[0; 12]);
+        assert!(Listing::from_bytes(&raw).is_err());
+    }
+
+    #[test]
+    fn signed_ids() {
+        let mut unsubmitted = beatmap();
+        unsubmitted.beatmap_id = BeatmapId(-1);
+        unsubmitted.beatmapset_id = BeatmapsetId(i32::MIN);
+        let mut submitted = beatmap();
+        submitted.beatmapset_id = BeatmapsetId(i32::MAX);
+        submitted.status = RankedStatus::Ranked;
+        assert!(unsubmitted.is_unsubmitted());
+        assert!(!submitted.is_unsubmitted());
+        submitted.status = RankedStatus::Unsubmitted;
+        assert!(submitted.is_unsubmitted());
+
+        for &version in &[20140608, 20191106] {
+            let mut listing = Listing {
+                version: 20211103,
+                folder_count: 1,
+                unban_date: None,
+                player_name: None,
+                beatmaps: vec![unsubmitted.clone(), submitted.clone()],
+                user_permissions: 1,
+            };
+            listing.migrate_to(version);
+            let mut raw = Vec::new();
+            listing.to_writer(&mut raw).unwrap();
+            let mut ids = (-1_i32).to_le_bytes().to_vec();
+            ids.extend_from_slice(&i32::MIN.to_le_bytes());
+            assert!(raw.windows(8).any(|window| window == &ids[..]));
+            let parsed = Listing::from_bytes(&raw).unwrap();
+            assert_eq!(parsed.beatmaps[0].beatmap_id, BeatmapId(-1));
+            assert_eq!(parsed.beatmaps[0].beatmapset_id, BeatmapsetId(i32::MIN));
+            assert_eq!(parsed.beatmaps[1].beatmapset_id, BeatmapsetId(i32::MAX));
+        }
+    }
+
+    #[test]
+    fn date_added() {
+        let mut bm = beatmap();
+        assert_eq!(bm.date_added(), None);
+        bm.set_date_added(OsuTimestamp::from_unix_secs(1_600_000_000));
+        assert_eq!(bm.mysterious_last_modified, 1_600_000_000);
+        assert_eq!(
+            bm.date_added(),
+            Some(OsuTimestamp::from_unix_secs(1_600_000_000))
+        );
+        bm.set_date_added(OsuTimestamp::from_unix_secs(-5));
+        assert_eq!(bm.date_added(), Some(OsuTimestamp::from_unix_secs(1)));
+
+        let added = |secs: u32| {
+            let mut bm = beatmap();
+            bm.mysterious_last_modified = secs;
+            bm
+        };
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![added(30), added(0), added(10), added(20)],
+            user_permissions: 1,
+        };
+        listing.sort_by_date_added();
+        let order: Vec<u32> = listing
+            .beatmaps
+            .iter()
+            .map(|bm| bm.mysterious_last_modified)
+            .collect();
+        assert_eq!(order, vec![0, 10, 20, 30]);
+    }
+
+    #[test]
+    fn size_prefixes() {
+        let listing = Listing {
+            version: 20150101,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap(), beatmap()],
+            user_permissions: 1,
+        };
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+        //Version, folder count, unban date, player name and beatmap count come first
+        let prefix = 4 + 4 + 1 + 8 + 1 + 4;
+        let mut size = [0; 4];
+        size.copy_from_slice(&raw[prefix..prefix + 4]);
+        let size = u32::from_le_bytes(size) as usize;
+
+        let mut wrong_size = raw.clone();
+        wrong_size[prefix..prefix + 4].copy_from_slice(&(size as u32 + 1).to_le_bytes());
+        assert_eq!(Listing::from_bytes(&wrong_size).unwrap(), listing);
+        let mut warnings = Vec::new();
+        let opts = ParseOptions::new()
+            .size_prefixes(SizePrefixes::Strict)
+            .warnings(&mut warnings);
+        assert!(Listing::from_bytes_with(&wrong_size, opts).is_err());
+        assert_eq!(
+            warnings,
+            vec![Warning::BeatmapSizeMismatch {
+                index: 0,
+                declared: size as u32 + 1,
+                actual: size,
+            }]
+        );
+
+        let mut garbage = raw.clone();
+        for byte in &mut garbage[prefix + 4..prefix + 4 + size] {
+            *byte = 0xff;
+        }
+        assert!(Listing::from_bytes(&garbage).is_err());
+        let mut warnings = Vec::new();
+        let opts = ParseOptions::new()
+            .size_prefixes(SizePrefixes::Recover)
+            .warnings(&mut warnings);
+        let recovered = Listing::from_bytes_with(&garbage, opts).unwrap();
+        assert_eq!(recovered.beatmaps, vec![beatmap()]);
+        assert_eq!(warnings, vec![Warning::SkippedBeatmap { index: 0 }]);
+    }
+
+    #[test]
+    fn stars() {
+        let dt = ModSet::empty().with(Mod::DoubleTime);
+        let mut bm = beatmap();
+        bm.std_ratings = vec![
+            (ModSet::empty(), 5.0),
+            (dt, 7.0),
+            (dt.with(Mod::HardRock), 7.5),
+        ];
+        let mods = |mods: &[Mod]| mods.iter().fold(ModSet::empty(), |set, &m| set.with(m));
+        assert_eq!(bm.stars(Mode::Standard, ModSet::empty()), Some(5.0));
+        assert_eq!(
+            bm.stars(Mode::Standard, mods(&[Mod::Hidden, Mod::DoubleTime])),
+            Some(7.0)
+        );
+        assert_eq!(
+            bm.stars(Mode::Standard, mods(&[Mod::Nightcore, Mod::HardRock])),
+            Some(7.5)
+        );
+        assert_eq!(
+            bm.stars(Mode::Standard, mods(&[Mod::Easy, Mod::DoubleTime])),
+            Some(7.0)
+        );
+        assert_eq!(bm.stars(Mode::Standard, mods(&[Mod::HalfTime])), Some(5.0));
+        assert_eq!(bm.stars(Mode::Mania, ModSet::empty()), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn beatmap_order() {
+        use crate::ids::test::hash;
+
+        let mut beatmaps = Vec::new();
+        //The same beatmap twice under different file names, which only the file name tells apart
+        for &(n, set, id, file) in &[
+            (3, 20, 200, "c.osu"),
+            (1, 20, 201, "b.osu"),
+            (2, 10, 100, "a.osu"),
+            (1, 20, 201, "a.osu"),
+        ] {
+            let mut bm = beatmap();
+            bm.hash = hash(n).into();
+            bm.beatmapset_id = BeatmapsetId(set);
+            bm.beatmap_id = BeatmapId(id);
+            bm.file_name = Some(file.to_string());
+            beatmaps.push(bm);
+        }
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps,
+            user_permissions: 0,
+        };
+        let write = |listing: &Listing, beatmap_order| {
+            let mut raw = Vec::new();
+            let opts = SaveOptions {
+                beatmap_order,
+                ..SaveOptions::default()
+            };
+            listing.to_writer_with(&mut raw, &opts).unwrap();
+            raw
+        };
+        let order = |raw: &[u8]| -> Vec<_> {
+            let listing = Listing::from_bytes(raw).unwrap();
+            listing
+                .beatmaps
+                .iter()
+                .map(|bm| (bm.hash.md5().unwrap(), bm.file_name.clone().unwrap()))
+                .collect()
+        };
+        let entry = |n, file: &str| (hash(n), file.to_string());
+
+        let by_hash = write(&listing, BeatmapOrder::Hash);
+        assert_eq!(
+            order(&by_hash),
+            vec![
+                entry(1, "a.osu"),
+                entry(1, "b.osu"),
+                entry(2, "a.osu"),
+                entry(3, "c.osu"),
+            ]
+        );
+        let by_set = write(&listing, BeatmapOrder::BeatmapsetId);
+        assert_eq!(
+            order(&by_set),
+            vec![
+                entry(2, "a.osu"),
+                entry(3, "c.osu"),
+                entry(1, "a.osu"),
+                entry(1, "b.osu"),
+            ]
+        );
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+        assert_eq!(write(&listing, BeatmapOrder::Keep), raw);
+
+        listing.beatmaps.reverse();
+        assert_eq!(write(&listing, BeatmapOrder::Hash), by_hash);
+        assert_eq!(write(&listing, BeatmapOrder::BeatmapsetId), by_set);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn folder_count() {
+        let mut beatmaps = Vec::new();
+        for folder in &[Some("1 a"), Some("2 b"), Some("1 a"), None] {
+            let mut bm = beatmap();
+            bm.folder_name = folder.map(str::to_string);
+            beatmaps.push(bm);
+        }
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 7,
+            unban_date: None,
+            player_name: None,
+            beatmaps,
+            user_permissions: 0,
+        };
+        let written = |listing: &Listing, folder_count| {
+            let mut raw = Vec::new();
+            let opts = SaveOptions {
+                folder_count,
+                ..SaveOptions::default()
+            };
+            listing.to_writer_with(&mut raw, &opts).unwrap();
+            Listing::from_bytes(&raw).unwrap().folder_count
+        };
+        assert_eq!(written(&listing, FolderCount::Keep), 7);
+        assert_eq!(written(&listing, FolderCount::FromEntries), 2);
+        //Saving never touches the listing itself
+        assert_eq!(listing.folder_count, 7);
+
+        let songs = std::env::temp_dir().join(format!("osu-db-folders-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&songs);
+        for folder in &["1 a", "2 b", "3 c"] {
+            fs::create_dir_all(songs.join(folder)).unwrap();
+        }
+        //Loose files are not beatmap folders
+        fs::write(songs.join("notes.txt"), b"").unwrap();
+        assert_eq!(written(&listing, FolderCount::FromDisk(songs.clone())), 3);
+        let missing = SaveOptions {
+            folder_count: FolderCount::FromDisk(songs.join("missing")),
+            ..SaveOptions::default()
+        };
+        assert!(listing.to_writer_with(Vec::new(), &missing).is_err());
+
+        assert_eq!(listing.recount_folders(&songs).unwrap(), 3);
+        assert_eq!(listing.folder_count, 3);
+        assert_eq!(listing.recount_folders_from_entries(), 2);
+        assert_eq!(listing.folder_count, 2);
+        fs::remove_dir_all(&songs).unwrap();
+    }
+
+    #[test]
+    fn rating_table() {
+        let dt = ModSet::empty().with(Mod::DoubleTime);
+        let ez = ModSet::empty().with(Mod::Easy);
+        let mut bm = beatmap();
+        bm.std_ratings = vec![
+            (ModSet::empty(), 5.1),
+            (dt, 7.2),
+            (ez, 4.3),
+            (dt.with(Mod::Easy), 7.2),
+        ];
+        let table = bm.ratings_for(Mode::Standard);
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.nomod(), Some(5.1));
+        assert_eq!(table.get(dt.with(Mod::Hidden)), None);
+        assert_eq!(table.with(dt.with(Mod::Hidden)), Some(7.2));
+        assert_eq!(table.max(), Some((dt, 7.2)));
+        assert_eq!(table.min(), Some((ez, 4.3)));
+        assert_eq!(
+            table
+                .iter_by_stars()
+                .map(|(mods, _)| mods)
+                .collect::<Vec<_>>(),
+            vec![ez, ModSet::empty(), dt, dt.with(Mod::Easy)]
+        );
+        assert!(!table.is_single_precision());
+        assert!(bm.ratings_for(Mode::Mania).is_empty());
+        assert_eq!(bm.ratings_for(Mode::Mania).max(), None);
+
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![bm],
+            user_permissions: 0,
+        };
+        listing.migrate_to(LATEST_KNOWN_VERSION);
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+        let listing = Listing::from_bytes(&raw).unwrap();
+        let table = listing.beatmaps[0].ratings_for(Mode::Standard);
+        assert!(table.is_single_precision());
+        assert_eq!(table.max(), Some((dt, 7.2f32 as f64)));
+        assert_eq!(table.with(dt.with(Mod::Nightcore)), Some(7.2f32 as f64));
+    }
+
+    #[test]
+    fn arena_reuse() {
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: Some("player".to_string()),
+            beatmaps: vec![beatmap(), beatmap()],
+            user_permissions: 1,
+        };
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+
+        let mut arena = ParseArena::new();
+        let first = Listing::from_bytes_with(&raw, ParseOptions::new().arena(&mut arena)).unwrap();
+        assert_eq!(first, listing);
+        let first_points: BTreeSet<_> = first
+            .beatmaps
+            .iter()
+            .map(|bm| bm.timing_points.as_ptr())
+            .collect();
+
+        arena.recycle(first);
+        assert_eq!(arena.strings.len(), 1 + 2 * 9);
+        assert_eq!(arena.ratings.len(), 2);
+        let second = Listing::from_bytes_with(&raw, ParseOptions::new().arena(&mut arena)).unwrap();
+        assert_eq!(second, listing);
+        assert!(arena.strings.is_empty());
+        assert!(arena.ratings.is_empty());
+        assert!(arena.timing_points.is_empty());
+        assert!(arena.beatmaps.is_empty());
+        for bm in &second.beatmaps {
+            assert!(first_points.contains(&bm.timing_points.as_ptr()));
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Normalization of the artist and title metadata of beatmaps, which osu! stores twice: once in
+//! romanised form (`artist_ascii`, `title_ascii`) and once in its original script
+//! (`artist_unicode`, `title_unicode`).
+//!
+//! A character is considered romanised if it is within the ASCII range.
+
+use crate::{
+    listing::Beatmap,
+    query::{fold_width, strip_diacritic},
+};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// Whether the character can be used in the romanised metadata fields.
+pub fn is_romanised_char(c: char) -> bool {
+    c.is_ascii()
+}
+
+/// Whether the whole string can be used in the romanised metadata fields.
+pub fn is_romanised(s: &str) -> bool {
+    s.chars().all(is_romanised_char)
+}
+
+/// Strip the characters that cannot be used in the romanised metadata fields, along with any
+/// leading and trailing whitespace left behind.
+///
+/// Full-width forms are first folded into plain ASCII, and diacritics are stripped from latin
+/// letters while keeping their case, as in `query::fold_search_text`.
+/// Note that this does not transliterate anything, so text in non-latin scripts is stripped
+/// entirely.
+pub fn strip_non_romanised(s: &str) -> String {
+    let stripped: String = s
+        .chars()
+        .map(romanise_char)
+        .filter(|&c| is_romanised_char(c))
+        .collect();
+    stripped.trim().to_string()
+}
+
+/// Fold the width and strip the diacritic of a character, keeping its case.
+fn romanise_char(c: char) -> char {
+    let c = fold_width(c);
+    if c.is_ascii() {
+        return c;
+    }
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) if lower != c => strip_diacritic(lower).to_ascii_uppercase(),
+        (Some(lower), None) => strip_diacritic(lower),
+        _ => c,
+    }
+}
+
+/// A pair of romanised and unicode metadata fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetadataField {
+    /// `artist_ascii` and `artist_unicode`.
+    Artist,
+    /// `title_ascii` and `title_unicode`.
+    Title,
+}
+
+/// How a pair of romanised and unicode metadata fields disagree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MismatchKind {
+    /// The romanised field contains non-romanised characters.
+    NonRomanisedAscii,
+    /// The unicode field is set but the romanised field is empty.
+    MissingAscii,
+    /// The romanised field is set but the unicode field is empty.
+    MissingUnicode,
+    /// The unicode field only has romanised characters, so both fields should be equal, but they
+    /// differ.
+    Differs,
+}
+
+/// A single mismatch between the romanised and unicode metadata of a beatmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MetadataMismatch {
+    pub field: MetadataField,
+    pub kind: MismatchKind,
+}
+
+impl Beatmap {
+    /// Find the artist and title fields whose romanised and unicode variants disagree.
+    ///
+    /// Empty strings count as missing.
+    /// An empty list means the metadata is consistent.
+    pub fn metadata_mismatches(&self) -> Vec<MetadataMismatch> {
+        let mut mismatches = Vec::new();
+        for &(field, ascii, unicode) in &[
+            (
+                MetadataField::Artist,
+                &self.artist_ascii,
+                &self.artist_unicode,
+            ),
+            (MetadataField::Title, &self.title_ascii, &self.title_unicode),
+        ] {
+            let kind = match (non_empty(ascii), non_empty(unicode)) {
+                (Some(ascii), _) if !is_romanised(ascii) => MismatchKind::NonRomanisedAscii,
+                (None, Some(_)) => MismatchKind::MissingAscii,
+                (Some(_), None) => MismatchKind::MissingUnicode,
+                (Some(ascii), Some(unicode)) if is_romanised(unicode) && ascii != unicode => {
+                    MismatchKind::Differs
+                }
+                _ => continue,
+            };
+            mismatches.push(MetadataMismatch { field, kind });
+        }
+        mismatches
+    }
+
+    /// Fill in the romanised and unicode artist and title fields from each other, so that both
+    /// variants are consistent.
+    ///
+    /// A missing unicode field is copied from the romanised one.
+    /// A missing romanised field, or one with non-romanised characters, is generated from the
+    /// unicode field with `strip_non_romanised`, unless that leaves nothing, in which case the
+    /// romanised field is left as is.
+    /// Romanised fields that are already valid are kept, as they usually hold a proper
+    /// transliteration.
+    /// Returns whether anything changed.
+    pub fn fill_romanised_metadata(&mut self) -> bool {
+        fill_pair(&mut self.artist_ascii, &mut self.artist_unicode)
+            | fill_pair(&mut self.title_ascii, &mut self.title_unicode)
+    }
+}
+
+fn fill_pair(ascii: &mut Option<String>, unicode: &mut Option<String>) -> bool {
+    let mut changed = false;
+    if non_empty(unicode).is_none() {
+        if let Some(ascii) = non_empty(ascii) {
+            *unicode = Some(ascii.to_string());
+            changed = true;
+        }
+    }
+    let valid = non_empty(ascii).is_some_and(is_romanised);
+    if !valid {
+        if let Some(unicode) = non_empty(unicode) {
+            let stripped = strip_non_romanised(unicode);
+            if !stripped.is_empty() && ascii.as_deref() != Some(&stripped) {
+                *ascii = Some(stripped);
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+fn non_empty(field: &Option<String>) -> Option<&str> {
+    field.as_deref().filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::listing::test::beatmap;
+
+    #[test]
+    fn strip_non_romanised() {
+        assert!(is_romanised("Camellia feat. Nanahira"));
+        assert!(!is_romanised("かめりあ"));
+        assert_eq!(super::strip_non_romanised(" Ωmega Rhythm "), "mega Rhythm");
+        assert_eq!(super::strip_non_romanised("ナナヒラ"), "");
+        assert_eq!(
+            super::strip_non_romanised("Ｆｉｒｅ　Élan Ŝtraße"),
+            "Fire Elan Strae"
+        );
+    }
+
+    #[test]
+    fn fill_romanised_metadata() {
+        let mut bm = beatmap();
+        bm.artist_ascii = Some("Kameria".to_string());
+        bm.artist_unicode = Some("かめりあ".to_string());
+        bm.title_ascii = Some("Töwa".to_string());
+        bm.title_unicode = None;
+        assert_eq!(
+            bm.metadata_mismatches(),
+            vec![MetadataMismatch {
+                field: MetadataField::Title,
+                kind: MismatchKind::NonRomanisedAscii,
+            }]
+        );
+
+        assert!(bm.fill_romanised_metadata());
+        assert_eq!(bm.artist_ascii.as_deref(), Some("Kameria"));
+        assert_eq!(bm.title_unicode.as_deref(), Some("Töwa"));
+        assert_eq!(bm.title_ascii.as_deref(), Some("Towa"));
+        assert_eq!(bm.metadata_mismatches(), Vec::new());
+        assert!(!bm.fill_romanised_metadata());
+
+        //A transliteration is never replaced by nothing
+        bm.artist_ascii = Some("かめりあ".to_string());
+        assert!(!bm.fill_romanised_metadata());
+        assert_eq!(bm.artist_ascii.as_deref(), Some("かめりあ"));
+        bm.artist_ascii = None;
+        assert!(!bm.fill_romanised_metadata());
+        assert_eq!(bm.artist_ascii, None);
+        bm.artist_ascii = Some("Kameria".to_string());
+
+        bm.title_unicode = Some("Towa!".to_string());
+        assert_eq!(
+            bm.metadata_mismatches(),
+            vec![MetadataMismatch {
+                field: MetadataField::Title,
+                kind: MismatchKind::Differs,
+            }]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Views over the beatmaps of a listing that are playable in a single gamemode, picking the right
+//! one of the four per-mode grade and star rating fields.
+
+use crate::{
+    listing::{Beatmap, Grade, StarRatings},
+    prelude::*,
+    Listing,
+};
+use alloc::collections::BTreeMap;
+
+impl Listing {
+    /// Get a view over the beatmaps native to the given mode.
+    ///
+    /// Use `ModeView::with_converts` to also include osu!standard beatmaps, which can be played
+    /// converted in any other mode.
+    pub fn for_mode(&self, mode: Mode) -> ModeView<'_> {
+        ModeView {
+            listing: self,
+            mode,
+            converts: false,
+        }
+    }
+}
+
+/// A view over the beatmaps of a listing that are playable in a single mode.
+///
+/// Built with `Listing::for_mode`.
+#[derive(Debug, Clone, Copy)]
+pub struct ModeView<'a> {
+    listing: &'a Listing,
+    mode: Mode,
+    converts: bool,
+}
+impl<'a> ModeView<'a> {
+    /// Also include osu!standard beatmaps, as they can be played converted in any mode.
+    ///
+    /// Has no effect on osu!standard views.
+    pub fn with_converts(self) -> ModeView<'a> {
+        ModeView {
+            converts: true,
+            ..self
+        }
+    }
+
+    /// The mode of the view.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Iterate over the beatmaps in the view, in listing order.
+    pub fn iter(&self) -> impl Iterator<Item = ModeBeatmap<'a>> {
+        let (mode, converts) = (self.mode, self.converts);
+        self.listing
+            .beatmaps
+            .iter()
+            .enumerate()
+            .filter(move |(_, bm)| bm.mode == mode || (converts && bm.is_playable_in(mode)))
+            .map(move |(index, beatmap)| ModeBeatmap {
+                index,
+                beatmap,
+                view_mode: mode,
+            })
+    }
+
+    /// The amount of beatmaps in the view.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Whether there are no beatmaps in the view.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Compute statistics over the beatmaps in the view.
+    pub fn stats(&self) -> ModeStats {
+        let mut stats = ModeStats::default();
+        let mut total_stars = 0.0;
+        let mut rated = 0;
+        for bm in self.iter() {
+            stats.beatmaps += 1;
+            if bm.is_convert() {
+                stats.converts += 1;
+            }
+            let grade = bm.grade();
+            if grade != Grade::Unplayed {
+                stats.played += 1;
+                *stats.grades.entry(grade).or_insert(0) += 1;
+            }
+            if let Some(stars) = bm.nomod_stars() {
+                total_stars += stars;
+                rated += 1;
+            }
+        }
+        if rated != 0 {
+            stats.average_stars = Some(total_stars / rated as f64);
+        }
+        stats
+    }
+}
+
+/// A beatmap within a `ModeView`, with accessors for the mode of the view.
+///
+/// Dereferences to the underlying `Beatmap`.
+#[derive(Debug, Clone, Copy)]
+pub struct ModeBeatmap<'a> {
+    /// The index of the beatmap within the listing.
+    pub index: usize,
+    /// The underlying beatmap.
+    pub beatmap: &'a Beatmap,
+    view_mode: Mode,
+}
+impl<'a> ModeBeatmap<'a> {
+    /// The mode of the view this beatmap belongs to.
+    ///
+    /// Note that `mode` (through `Deref`) is the native mode of the beatmap instead.
+    pub fn view_mode(&self) -> Mode {
+        self.view_mode
+    }
+
+    /// Whether the beatmap is an osu!standard beatmap converted to another mode.
+    pub fn is_convert(&self) -> bool {
+        self.beatmap.mode != self.view_mode
+    }
+
+    /// The star ratings of the beatmap in the mode of the view.
+    pub fn ratings(&self) -> &'a StarRatings {
+        self.beatmap.ratings(self.view_mode)
+    }
+
+    /// The star rating of the beatmap in the mode of the view, without any mods.
+    pub fn nomod_stars(&self) -> Option<f64> {
+        self.stars(ModSet::empty())
+    }
+
+    /// The star rating of the beatmap in the mode of the view, with the given mods.
+    ///
+    /// See `Beatmap::stars` for how missing mod combinations are handled.
+    pub fn stars(&self, mods: ModSet) -> Option<f64> {
+        self.beatmap.stars(self.view_mode, mods)
+    }
+
+    /// The best grade achieved on the beatmap in the mode of the view.
+    pub fn grade(&self) -> Grade {
+        self.beatmap.grade(self.view_mode)
+    }
+}
+impl<'a> ops::Deref for ModeBeatmap<'a> {
+    type Target = Beatmap;
+    fn deref(&self) -> &Beatmap {
+        self.beatmap
+    }
+}
+
+/// Statistics over the beatmaps of a `ModeView`.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModeStats {
+    /// The amount of beatmaps in the view.
+    pub beatmaps: usize,
+    /// The amount of converted osu!standard beatmaps in the view.
+    pub converts: usize,
+    /// The amount of beatmaps with a grade in the mode of the view.
+    pub played: usize,
+    /// The amount of beatmaps with each grade, not including unplayed beatmaps.
+    pub grades: BTreeMap<Grade, usize>,
+    /// The average no-mod star rating of the beatmaps that have one.
+    pub average_stars: Option<f64>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::listing::test::beatmap;
+
+    #[test]
+    fn for_mode() {
+        let mut std = beatmap();
+        std.mode = Mode::Standard;
+        std.mania_ratings = vec![(ModSet::empty(), 3.0)];
+        std.mania_grade = Grade::A;
+        let mut mania = beatmap();
+        mania.mode = Mode::Mania;
+        mania.mania_ratings = vec![(ModSet::empty(), 5.0)];
+        mania.mania_grade = Grade::Unplayed;
+        let mut taiko = beatmap();
+        taiko.mode = Mode::Taiko;
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![std, mania, taiko],
+            user_permissions: 0,
+        };
+
+        let view = listing.for_mode(Mode::Mania);
+        assert_eq!(view.len(), 1);
+        assert_eq!(view.iter().next().unwrap().index, 1);
+        let view = view.with_converts();
+        let indices: Vec<usize> = view.iter().map(|bm| bm.index).collect();
+        assert_eq!(indices, vec![0, 1]);
+        let convert = view.iter().next().unwrap();
+        assert!(convert.is_convert());
+        assert_eq!(convert.grade(), Grade::A);
+        assert_eq!(convert.nomod_stars(), Some(3.0));
+        assert_eq!(convert.mode, Mode::Standard);
+        assert_eq!(convert.view_mode(), Mode::Mania);
+
+        let stats = view.stats();
+        assert_eq!(stats.beatmaps, 2);
+        assert_eq!(stats.converts, 1);
+        assert_eq!(stats.played, 1);
+        assert_eq!(stats.grades.get(&Grade::A), Some(&1));
+        assert_eq!(stats.average_stars, Some(4.0));
+    }
+}
@@ -0,0 +1,564 @@
+//! Parsing of the textual `.osu` beatmap files, as far as needed to build listing entries out of
+//! them.
+//!
+//! Only the information that ends up in `osu!.db` is extracted: general settings, metadata,
+//! difficulty values, timing points, breaks and the timing and kind of each hit object.
+//! Parsing is lenient, like osu! itself: unknown sections and keys are ignored, and malformed
+//! lines are skipped.
+
+use crate::{
+    listing::{Beatmap, Grade, RankedStatus, TimingPoint},
+    prelude::*,
+};
+
+/// The slider velocity osu! assumes when a beatmap does not specify one.
+pub(crate) const DEFAULT_SLIDER_VELOCITY: f64 = 1.4;
+/// The stack leniency osu! assumes when a beatmap does not specify one.
+pub(crate) const DEFAULT_STACK_LENIENCY: f32 = 0.7;
+
+/// The contents of an `.osu` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OsuFile {
+    /// The `osu file format vXX` version, if the header line is present.
+    pub format_version: Option<u32>,
+    pub audio_filename: Option<String>,
+    /// Preview point in milliseconds, or `-1` if there is none.
+    pub preview_time: i32,
+    pub stack_leniency: f32,
+    pub mode: Mode,
+    pub title: Option<String>,
+    pub title_unicode: Option<String>,
+    pub artist: Option<String>,
+    pub artist_unicode: Option<String>,
+    pub creator: Option<String>,
+    /// The difficulty name.
+    pub version: Option<String>,
+    pub source: Option<String>,
+    pub tags: Option<String>,
+    pub beatmap_id: BeatmapId,
+    pub beatmapset_id: BeatmapsetId,
+    pub hp_drain: f32,
+    pub circle_size: f32,
+    pub overall_difficulty: f32,
+    pub approach_rate: f32,
+    pub slider_multiplier: f64,
+    /// Timing points, in the same representation as used by `osu!.db`.
+    pub timing_points: Vec<TimingPoint>,
+    /// Breaks, as pairs of start and end times in milliseconds.
+    pub breaks: Vec<(i32, i32)>,
+    pub hit_objects: Vec<HitObject>,
+}
+
+/// A single hit object.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitObject {
+    pub x: f32,
+    pub y: f32,
+    /// Start time in milliseconds.
+    pub time: i32,
+    pub kind: HitObjectKind,
+}
+
+/// The kind of a hit object, along with the information needed to know when it ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HitObjectKind {
+    Circle,
+    Slider {
+        /// The amount of times the slider is traversed (`1` if it has no repeats).
+        slides: u32,
+        /// The length of a single slide, in osu!pixels.
+        length: f64,
+    },
+    Spinner {
+        end_time: i32,
+    },
+    /// A mania hold note.
+    Hold {
+        end_time: i32,
+    },
+}
+
+impl OsuFile {
+    /// Parse an `.osu` file from its raw bytes.
+    ///
+    /// Invalid UTF-8 is replaced, and a leading byte order mark is skipped.
+    pub fn from_bytes(bytes: &[u8]) -> OsuFile {
+        Self::parse(&String::from_utf8_lossy(bytes))
+    }
+
+    /// Parse an `.osu` file from a file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<OsuFile> {
+        Ok(Self::from_bytes(&fs::read(path)?))
+    }
+
+    /// Parse an `.osu` file from its text.
+    pub fn parse(text: &str) -> OsuFile {
+        let mut file = OsuFile {
+            format_version: None,
+            audio_filename: None,
+            preview_time: -1,
+            stack_leniency: DEFAULT_STACK_LENIENCY,
+            mode: Mode::Standard,
+            title: None,
+            title_unicode: None,
+            artist: None,
+            artist_unicode: None,
+            creator: None,
+            version: None,
+            source: None,
+            tags: None,
+            beatmap_id: BeatmapId(0),
+            beatmapset_id: BeatmapsetId(-1),
+            hp_drain: 5.0,
+            circle_size: 5.0,
+            overall_difficulty: 5.0,
+            approach_rate: -1.0,
+            slider_multiplier: DEFAULT_SLIDER_VELOCITY,
+            timing_points: Vec::new(),
+            breaks: Vec::new(),
+            hit_objects: Vec::new(),
+        };
+        let mut section = "";
+        for line in text.trim_start_matches('\u{feff}').lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            if let Some(version) = line.strip_prefix("osu file format v") {
+                file.format_version = version.trim().parse().ok();
+            } else if line.starts_with('[') && line.ends_with(']') {
+                section = &line[1..line.len() - 1];
+            } else {
+                match section {
+                    "General" | "Metadata" | "Difficulty" => file.key_value(line),
+                    "Events" => file.event(line),
+                    "TimingPoints" => file.timing_point(line),
+                    "HitObjects" => file.hit_object(line),
+                    _ => {}
+                }
+            }
+        }
+        //Before AR was introduced, it was the same as OD
+        if file.approach_rate < 0.0 {
+            file.approach_rate = file.overall_difficulty;
+        }
+        file
+    }
+
+    fn key_value(&mut self, line: &str) {
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return,
+        };
+        let string = || Some(value.to_string()).filter(|s| !s.is_empty());
+        match key {
+            "AudioFilename" => self.audio_filename = string(),
+            "PreviewTime" => self.preview_time = value.parse().unwrap_or(self.preview_time),
+            "StackLeniency" => self.stack_leniency = value.parse().unwrap_or(self.stack_leniency),
+            "Mode" => {
+                self.mode = value
+                    .parse()
+                    .ok()
+                    .and_then(Mode::from_raw)
+                    .unwrap_or(self.mode)
+            }
+            "Title" => self.title = string(),
+            "TitleUnicode" => self.title_unicode = string(),
+            "Artist" => self.artist = string(),
+            "ArtistUnicode" => self.artist_unicode = string(),
+            "Creator" => self.creator = string(),
+            "Version" => self.version = string(),
+            "Source" => self.source = string(),
+            "Tags" => self.tags = string(),
+            "BeatmapID" => self.beatmap_id = value.parse().unwrap_or(self.beatmap_id),
+            "BeatmapSetID" => self.beatmapset_id = value.parse().unwrap_or(self.beatmapset_id),
+            "HPDrainRate" => self.hp_drain = value.parse().unwrap_or(self.hp_drain),
+            "CircleSize" => self.circle_size = value.parse().unwrap_or(self.circle_size),
+            "OverallDifficulty" => {
+                self.overall_difficulty = value.parse().unwrap_or(self.overall_difficulty)
+            }
+            "ApproachRate" => self.approach_rate = value.parse().unwrap_or(self.approach_rate),
+            "SliderMultiplier" => {
+                self.slider_multiplier = value.parse().unwrap_or(self.slider_multiplier)
+            }
+            _ => {}
+        }
+    }
+
+    fn event(&mut self, line: &str) {
+        let mut parts = line.split(',').map(str::trim);
+        if let (Some("2"), Some(start), Some(end)) | (Some("Break"), Some(start), Some(end)) =
+            (parts.next(), parts.next(), parts.next())
+        {
+            if let (Ok(start), Ok(end)) = (start.parse::<f64>(), end.parse::<f64>()) {
+                self.breaks.push((start as i32, end as i32));
+            }
+        }
+    }
+
+    fn timing_point(&mut self, line: &str) {
+        let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+        let (offset, beat_length) = match (parts.first(), parts.get(1)) {
+            (Some(offset), Some(beat_length)) => {
+                match (offset.parse::<f64>(), beat_length.parse::<f64>()) {
+                    (Ok(offset), Ok(beat_length)) => (offset, beat_length),
+                    _ => return,
+                }
+            }
+            _ => return,
+        };
+        //Old formats have no explicit flag, and tell them apart by the sign of the beat length
+        let uninherited = match parts.get(6) {
+            Some(flag) => *flag != "0",
+            None => beat_length > 0.0,
+        };
+        self.timing_points.push(TimingPoint {
+            bpm: beat_length,
+            offset,
+            inherits: uninherited,
+        });
+    }
+
+    fn hit_object(&mut self, line: &str) {
+        let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+        let num = |idx: usize| parts.get(idx).and_then(|s| s.parse::<f64>().ok());
+        let (x, y, time, ty) = match (num(0), num(1), num(2), num(3)) {
+            (Some(x), Some(y), Some(time), Some(ty)) => (x, y, time, ty as u32),
+            _ => return,
+        };
+        let kind = if ty.bit(1) {
+            HitObjectKind::Slider {
+                slides: num(6).map_or(1, |s| s as u32).max(1),
+                length: num(7).unwrap_or(0.0),
+            }
+        } else if ty.bit(3) {
+            HitObjectKind::Spinner {
+                end_time: num(5).map_or(time, |t| t) as i32,
+            }
+        } else if ty.bit(7) {
+            //Mania hold notes store their end time before the hit sample, separated by a colon
+            let end_time = parts
+                .get(5)
+                .and_then(|s| s.split(':').next())
+                .and_then(|s| s.parse::<f64>().ok())
+                .unwrap_or(time);
+            HitObjectKind::Hold {
+                end_time: end_time as i32,
+            }
+        } else {
+            HitObjectKind::Circle
+        };
+        self.hit_objects.push(HitObject {
+            x: x as f32,
+            y: y as f32,
+            time: time as i32,
+            kind,
+        });
+    }
+
+    /// The time at which a hit object ends, in milliseconds.
+    ///
+    /// For sliders this takes the timing points into account.
+    pub fn end_time(&self, object: &HitObject) -> i32 {
+        match object.kind {
+            HitObjectKind::Circle => object.time,
+            HitObjectKind::Spinner { end_time } | HitObjectKind::Hold { end_time } => end_time,
+            HitObjectKind::Slider { slides, length } => {
+                let (beat_length, velocity) = self.timing_at(object.time as f64);
+                let px_per_beat = self.slider_multiplier * 100.0 * velocity;
+                if px_per_beat <= 0.0 {
+                    return object.time;
+                }
+                let duration = length * slides as f64 / px_per_beat * beat_length;
+                object.time.saturating_add(duration as i32)
+            }
+        }
+    }
+
+    /// Get the beat length and slider velocity multiplier in effect at the given time.
+    fn timing_at(&self, time: f64) -> (f64, f64) {
+        let mut beat_length = self
+            .timing_points
+            .iter()
+            .find(|point| point.inherits)
+            .map_or(1000.0, |point| point.bpm);
+        let mut velocity = 1.0;
+        for point in &self.timing_points {
+            if point.offset > time {
+                break;
+            }
+            if point.inherits {
+                beat_length = point.bpm;
+                velocity = 1.0;
+            } else if point.bpm < 0.0 {
+                velocity = (-100.0 / point.bpm).clamp(0.1, 10.0);
+            }
+        }
+        (beat_length, velocity)
+    }
+
+    /// The total length of the beatmap in milliseconds, up to the end of the last hit object.
+    pub fn total_time(&self) -> u32 {
+        self.hit_objects
+            .iter()
+            .map(|object| self.end_time(object))
+            .max()
+            .unwrap_or(0)
+            .max(0) as u32
+    }
+
+    /// The drain time of the beatmap in seconds: the time from the first hit object to the end
+    /// of the last one, excluding breaks.
+    pub fn drain_time(&self) -> u32 {
+        let first = match self.hit_objects.iter().map(|object| object.time).min() {
+            Some(first) => first,
+            None => return 0,
+        };
+        //Times span the whole `i32` range in broken files, so they are added up as `i64`s
+        let last = self.total_time() as i64;
+        let breaks: i64 = self
+            .breaks
+            .iter()
+            .map(|&(start, end)| (end as i64 - start as i64).max(0))
+            .sum();
+        ((last - first as i64 - breaks).max(0) / 1000) as u32
+    }
+
+    /// The preview time as stored in listings, where a missing preview time (`-1`) wraps around
+    /// to `u32::MAX`.
+    fn listing_preview_time(&self) -> u32 {
+        self.preview_time as u32
+    }
+
+    /// The name osu! would give to the folder of this beatmapset within the "Songs" directory,
+    /// built out of its beatmapset ID, artist and title.
+    pub fn default_folder_name(&self) -> String {
+        default_folder_name(
+            self.beatmapset_id,
+            self.artist.as_deref().unwrap_or_default(),
+            self.title.as_deref().unwrap_or_default(),
+        )
+    }
+
+    /// Build a listing entry out of the parsed file, the name of its folder within the "Songs"
+    /// directory, its file name and the MD5 hash of its contents.
+    ///
+    /// Information that is not in the file is filled in the way osu! does for freshly imported
+    /// maps: the beatmap is unplayed, has an unknown ranked status and has no star ratings yet.
+    /// osu! fills these in the next time it processes the beatmap.
+    pub fn to_beatmap(&self, folder_name: &str, file_name: &str, hash: Md5Hash) -> Beatmap {
+        let count = |pred: fn(&HitObjectKind) -> bool| {
+            self.hit_objects
+                .iter()
+                .filter(|object| pred(&object.kind))
+                .count() as u16
+        };
+        let now = OsuTimestamp::now();
+        Beatmap {
+            artist_ascii: self.artist.clone(),
+            artist_unicode: self.artist_unicode.clone(),
+            title_ascii: self.title.clone(),
+            title_unicode: self.title_unicode.clone(),
+            creator: self.creator.clone(),
+            difficulty_name: self.version.clone(),
+            audio: self.audio_filename.clone(),
+            hash: hash.into(),
+            file_name: Some(file_name.to_string()),
+            status: RankedStatus::Unknown,
+            hitcircle_count: count(|kind| matches!(kind, HitObjectKind::Circle)),
+            //osu! counts mania hold notes as sliders
+            slider_count: count(|kind| {
+                matches!(
+                    kind,
+                    HitObjectKind::Slider { .. } | HitObjectKind::Hold { .. }
+                )
+            }),
+            spinner_count: count(|kind| matches!(kind, HitObjectKind::Spinner { .. })),
+            last_modified: now,
+            approach_rate: self.approach_rate,
+            circle_size: self.circle_size,
+            hp_drain: self.hp_drain,
+            overall_difficulty: self.overall_difficulty,
+            slider_velocity: self.slider_multiplier,
+            std_ratings: Vec::new(),
+            taiko_ratings: Vec::new(),
+            ctb_ratings: Vec::new(),
+            mania_ratings: Vec::new(),
+            drain_time: self.drain_time(),
+            total_time: self.total_time(),
+            preview_time: self.listing_preview_time(),
+            timing_points: self.timing_points.clone(),
+            beatmap_id: self.beatmap_id,
+            beatmapset_id: self.beatmapset_id,
+            thread_id: 0,
+            std_grade: Grade::Unplayed,
+            taiko_grade: Grade::Unplayed,
+            ctb_grade: Grade::Unplayed,
+            mania_grade: Grade::Unplayed,
+            local_beatmap_offset: 0,
+            stack_leniency: self.stack_leniency,
+            mode: self.mode,
+            song_source: self.source.clone(),
+            tags: self.tags.clone(),
+            online_offset: 0,
+            title_font: None,
+            last_played: None,
+            is_osz2: false,
+            folder_name: Some(folder_name.to_string()),
+            last_online_check: now,
+            ignore_sounds: false,
+            ignore_skin: false,
+            disable_storyboard: false,
+            disable_video: false,
+            visual_override: false,
+            mysterious_short: None,
+            mysterious_last_modified: 0,
+            mania_scroll_speed: 0,
+        }
+    }
+}
+
+impl Beatmap {
+    /// Recompute `drain_time`, `total_time` and `preview_time` out of the parsed `.osu` file of
+    /// the beatmap, taking breaks into account.
+    ///
+    /// Useful for entries built by hand or imported from elsewhere, which may have these left as
+    /// zero, breaking length sorting in song select.
+    pub fn recompute_times(&mut self, file: &OsuFile) {
+        self.drain_time = file.drain_time();
+        self.total_time = file.total_time();
+        self.preview_time = file.listing_preview_time();
+    }
+}
+
+/// The folder name osu! gives to a beatmapset within the "Songs" directory.
+pub(crate) fn default_folder_name(
+    beatmapset_id: BeatmapsetId,
+    artist: &str,
+    title: &str,
+) -> String {
+    sanitize_file_name(&format!("{} {} - {}", beatmapset_id, artist, title))
+}
+
+/// Remove the characters that are not allowed in file names on Windows, as osu! does.
+pub(crate) fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub(crate) const SAMPLE: &str = "\u{feff}osu file format v14
+
+[General]
+AudioFilename: audio.mp3
+PreviewTime: 5000
+StackLeniency: 0.5
+Mode: 0
+
+[Metadata]
+Title:Title
+TitleUnicode:タイトル
+Artist:Artist
+ArtistUnicode:Artist
+Creator:Mapper
+Version:Insane
+Source:
+Tags:some tags
+BeatmapID:123
+BeatmapSetID:45
+
+[Difficulty]
+HPDrainRate:6
+CircleSize:4
+OverallDifficulty:8
+ApproachRate:9
+SliderMultiplier:1.6
+SliderTickRate:1
+
+[Events]
+//Break Periods
+2,4000,9000
+
+[TimingPoints]
+1000,500,4,2,0,50,1,0
+3000,-50,4,2,0,50,0,0
+
+[HitObjects]
+256,192,1000,1,0,0:0:0:0:
+256,192,2000,2,0,L|356:192,1,160
+256,192,3000,6,0,L|356:192,2,160
+256,192,10000,12,0,12000,0:0:0:0:
+";
+
+    #[test]
+    fn parse() {
+        let file = OsuFile::parse(SAMPLE);
+        assert_eq!(file.format_version, Some(14));
+        assert_eq!(file.audio_filename.as_deref(), Some("audio.mp3"));
+        assert_eq!(file.title_unicode.as_deref(), Some("タイトル"));
+        assert_eq!(file.source, None);
+        assert_eq!(file.beatmapset_id, BeatmapsetId(45));
+        assert_eq!(file.approach_rate, 9.0);
+        assert_eq!(file.breaks, vec![(4000, 9000)]);
+        assert_eq!(file.timing_points.len(), 2);
+        assert!(file.timing_points[0].inherits);
+        assert!(!file.timing_points[1].inherits);
+        assert_eq!(file.hit_objects.len(), 4);
+
+        //160px at 1.6x100px per beat is one beat, 500ms
+        assert_eq!(file.end_time(&file.hit_objects[1]), 2500);
+        //Twice the slider velocity and two slides cancel out
+        assert_eq!(file.end_time(&file.hit_objects[2]), 3500);
+        assert_eq!(file.total_time(), 12000);
+        assert_eq!(file.drain_time(), 6);
+
+        let bm = file.to_beatmap("45 Artist - Title", "map.osu", Md5Hash::default());
+        assert_eq!(bm.hitcircle_count, 1);
+        assert_eq!(bm.slider_count, 2);
+        assert_eq!(bm.spinner_count, 1);
+        assert_eq!(bm.preview_time, 5000);
+    }
+
+    #[test]
+    fn recompute_times() {
+        let file = OsuFile::parse(SAMPLE);
+        let mut bm = crate::listing::test::beatmap();
+        bm.drain_time = 0;
+        bm.total_time = 0;
+        bm.preview_time = 0;
+        bm.recompute_times(&file);
+        assert_eq!(
+            (bm.drain_time, bm.total_time, bm.preview_time),
+            (6, 12000, 5000)
+        );
+    }
+
+    #[test]
+    fn extreme_times() {
+        let file = OsuFile::parse(
+            "osu file format v14
+
+[Events]
+2,-2000000000,2000000000
+
+[TimingPoints]
+0,500,4,2,0,100,1,0
+
+[HitObjects]
+256,192,-2147483648,1,0,0:0:0:0:
+256,192,2147483000,2,0,L|356:192,1000,160000
+",
+        );
+        assert_eq!(file.end_time(&file.hit_objects[1]), i32::MAX);
+        assert_eq!(file.total_time(), i32::MAX as u32);
+        assert_eq!(file.drain_time(), 294_967);
+
+        let mut file = file;
+        file.breaks = vec![(i32::MIN, i32::MAX); 3];
+        assert_eq!(file.drain_time(), 0);
+    }
+}
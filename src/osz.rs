@@ -0,0 +1,100 @@
+//! Reading of `.osz` beatmapset archives, to build listing entries at download time.
+//!
+//! Only available with the `osz` feature enabled.
+
+use crate::{
+    listing::Beatmap,
+    osu_file::{sanitize_file_name, OsuFile},
+    prelude::*,
+};
+use std::io::{Read, Seek};
+use zip::ZipArchive;
+
+/// The beatmaps contained in an `.osz` archive, ready to be inserted into a listing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OszImport {
+    /// The name of the folder within the "Songs" directory where the archive should be
+    /// extracted to.
+    /// All `beatmaps` already refer to this folder.
+    pub folder_name: String,
+    /// One entry per `.osu` file in the archive.
+    pub beatmaps: Vec<Beatmap>,
+}
+impl OszImport {
+    /// Read an `.osz` archive from a file.
+    ///
+    /// As osu! does, the archive is meant to be extracted to a folder named after the archive
+    /// file itself.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<OszImport, Error> {
+        let path = path.as_ref();
+        let folder_name = path
+            .file_stem()
+            .map(|stem| sanitize_file_name(&stem.to_string_lossy()));
+        read_osz(File::open(path)?, folder_name)
+    }
+
+    /// Read an `.osz` archive from an arbitrary reader.
+    ///
+    /// As there is no archive file name to go by, the folder name is built out of the
+    /// beatmapset ID, artist and title of the first beatmap.
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<OszImport, Error> {
+        read_osz(reader, None)
+    }
+}
+
+fn read_osz<R: Read + Seek>(reader: R, folder_name: Option<String>) -> Result<OszImport, Error> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut files = Vec::new();
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+        let name = entry.name().to_string();
+        //Difficulties live at the root of the archive, so skip anything within subfolders
+        if !entry.is_file() || name.contains('/') || !name.to_lowercase().ends_with(".osu") {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        files.push((name, Md5Hash::compute(&bytes), OsuFile::from_bytes(&bytes)));
+    }
+    let folder_name = folder_name.unwrap_or_else(|| match files.first() {
+        Some((_, _, file)) => file.default_folder_name(),
+        None => String::new(),
+    });
+    let beatmaps = files
+        .iter()
+        .map(|(name, hash, file)| file.to_beatmap(&folder_name, name, *hash))
+        .collect();
+    Ok(OszImport {
+        folder_name,
+        beatmaps,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::osu_file::test::SAMPLE;
+    use std::io::Cursor;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    #[test]
+    fn read_osz() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("map.osu", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(SAMPLE.as_bytes()).unwrap();
+        zip.start_file("audio.mp3", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"not really audio").unwrap();
+        let raw = zip.finish().unwrap().into_inner();
+
+        let import = OszImport::from_reader(Cursor::new(raw)).unwrap();
+        assert_eq!(import.folder_name, "45 Artist - Title");
+        assert_eq!(import.beatmaps.len(), 1);
+        let bm = &import.beatmaps[0];
+        assert_eq!(bm.file_name.as_deref(), Some("map.osu"));
+        assert_eq!(bm.folder_name.as_deref(), Some("45 Artist - Title"));
+        assert_eq!(bm.hash.md5(), Some(Md5Hash::compute(SAMPLE.as_bytes())));
+        assert_eq!(bm.beatmap_id, BeatmapId(123));
+    }
+}
@@ -0,0 +1,211 @@
+//! Export of the songs in a listing as audio playlists, to listen to them outside of osu!.
+
+use crate::{listing::Beatmap, prelude::*, query::Filter, Listing};
+use std::collections::HashSet;
+
+/// The file format of an exported playlist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PlaylistFormat {
+    /// An extended M3U playlist in UTF-8 (`.m3u8`).
+    M3u8,
+    /// An XML Shareable Playlist Format playlist (`.xspf`).
+    Xspf,
+}
+
+/// Which entries count as duplicates when exporting a playlist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PlaylistDedup {
+    /// Include each audio file once, even if several difficulties share it.
+    #[default]
+    AudioFile,
+    /// Include a single track per beatmapset, even if its difficulties use different audio files.
+    Beatmapset,
+}
+
+impl Listing {
+    /// Write a playlist with the audio file of every beatmap in the listing, each included once.
+    ///
+    /// Paths are written as `folder_name/audio`, relative to the osu! "Songs" directory, so the
+    /// playlist is meant to be saved in there.
+    /// Beatmaps without a folder name or audio file are skipped.
+    pub fn export_playlist<W: Write>(&self, out: W, format: PlaylistFormat) -> io::Result<()> {
+        self.export_playlist_with(out, format, PlaylistDedup::AudioFile, |_: &Beatmap| true)
+    }
+
+    /// Similar to `export_playlist`, but only includes beatmaps matching the given filter, and
+    /// allows choosing how duplicates are removed.
+    pub fn export_playlist_with<W, F>(
+        &self,
+        mut out: W,
+        format: PlaylistFormat,
+        dedup: PlaylistDedup,
+        filter: F,
+    ) -> io::Result<()>
+    where
+        W: Write,
+        F: Filter,
+    {
+        let mut seen = HashSet::new();
+        let tracks = self
+            .beatmaps
+            .iter()
+            .filter(|bm| filter.matches(bm))
+            .filter_map(|bm| {
+                let folder = bm.folder_name.as_deref().filter(|s| !s.is_empty())?;
+                let audio = bm.audio.as_deref().filter(|s| !s.is_empty())?;
+                //Beatmapsets are told apart by their id, or by their folder if they have no id
+                let key = match dedup {
+                    PlaylistDedup::AudioFile => (BeatmapsetId(0), folder, audio),
+                    PlaylistDedup::Beatmapset if bm.beatmapset_id.is_submitted() => {
+                        (bm.beatmapset_id, "", "")
+                    }
+                    PlaylistDedup::Beatmapset => (BeatmapsetId(0), folder, ""),
+                };
+                if seen.insert(key) {
+                    Some((bm, format!("{}/{}", folder, audio)))
+                } else {
+                    None
+                }
+            });
+        match format {
+            PlaylistFormat::M3u8 => {
+                writeln!(out, "#EXTM3U")?;
+                for (bm, path) in tracks {
+                    writeln!(
+                        out,
+                        "#EXTINF:{},{} - {}",
+                        bm.total_time / 1000,
+                        artist(bm),
+                        title(bm)
+                    )?;
+                    writeln!(out, "{}", path)?;
+                }
+            }
+            PlaylistFormat::Xspf => {
+                writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+                writeln!(
+                    out,
+                    r#"<playlist version="1" xmlns="http://xspf.org/ns/0/">"#
+                )?;
+                writeln!(out, "  <trackList>")?;
+                for (bm, path) in tracks {
+                    writeln!(out, "    <track>")?;
+                    writeln!(
+                        out,
+                        "      <location>{}</location>",
+                        xml_escape(&uri_escape(&path))
+                    )?;
+                    writeln!(out, "      <creator>{}</creator>", xml_escape(artist(bm)))?;
+                    writeln!(out, "      <title>{}</title>", xml_escape(title(bm)))?;
+                    writeln!(out, "      <duration>{}</duration>", bm.total_time)?;
+                    writeln!(out, "    </track>")?;
+                }
+                writeln!(out, "  </trackList>")?;
+                writeln!(out, "</playlist>")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Similar to `export_playlist` but writes the playlist to a file.
+    pub fn save_playlist<P: AsRef<Path>>(&self, path: P, format: PlaylistFormat) -> io::Result<()> {
+        self.export_playlist(BufWriter::new(File::create(path)?), format)
+    }
+}
+
+fn artist(bm: &Beatmap) -> &str {
+    bm.artist_unicode
+        .as_deref()
+        .filter(|s| !s.is_empty())
+        .or(bm.artist_ascii.as_deref())
+        .unwrap_or_default()
+}
+
+fn title(bm: &Beatmap) -> &str {
+    bm.title_unicode
+        .as_deref()
+        .filter(|s| !s.is_empty())
+        .or(bm.title_ascii.as_deref())
+        .unwrap_or_default()
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Percent-encode a relative path so that it can be used as a URI reference.
+fn uri_escape(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for &b in path.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::listing::test::beatmap;
+
+    fn listing() -> Listing {
+        let mut other_diff = beatmap();
+        other_diff.difficulty_name = Some("Hard".to_string());
+        let mut other_audio = beatmap();
+        other_audio.audio = Some("audio & more.mp3".to_string());
+        Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap(), other_diff, other_audio],
+            user_permissions: 0,
+        }
+    }
+
+    #[test]
+    fn m3u8() {
+        let mut out = Vec::new();
+        listing()
+            .export_playlist(&mut out, PlaylistFormat::M3u8)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "#EXTM3U\n\
+             #EXTINF:125,Artist - タイトル\n\
+             1 Artist - Title/audio.mp3\n\
+             #EXTINF:125,Artist - タイトル\n\
+             1 Artist - Title/audio & more.mp3\n"
+        );
+    }
+
+    #[test]
+    fn xspf_per_beatmapset() {
+        let mut out = Vec::new();
+        listing()
+            .export_playlist_with(
+                &mut out,
+                PlaylistFormat::Xspf,
+                PlaylistDedup::Beatmapset,
+                |_: &Beatmap| true,
+            )
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("<track>").count(), 1);
+        assert!(out.contains("<location>1%20Artist%20-%20Title/audio.mp3</location>"));
+    }
+}
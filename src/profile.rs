@@ -0,0 +1,215 @@
+//! Aggregation of the local scores of a player into a profile, for offline statistics.
+
+use crate::{listing::Grade, prelude::*, score::Score, Listing, ScoreList};
+use alloc::collections::BTreeMap;
+
+/// The statistics of a player, aggregated from the scores in a score database.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// The name of the player.
+    pub player_name: String,
+    /// The amount of scores set by the player, in all modes.
+    pub plays: usize,
+    /// The sum of the score of every play, in all modes.
+    pub total_score: u64,
+    /// The average accuracy of all plays, from `0` to `1`, or `None` if there are no plays.
+    pub average_accuracy: Option<f64>,
+    /// Statistics for each mode the player has scores in, in mode order.
+    pub modes: Vec<ModeProfile>,
+}
+impl Profile {
+    /// Get the statistics of the given mode, if the player has scores in it.
+    pub fn mode(&self, mode: Mode) -> Option<&ModeProfile> {
+        self.modes.iter().find(|profile| profile.mode == mode)
+    }
+}
+
+/// The statistics of a player in a single mode.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeProfile {
+    pub mode: Mode,
+    /// The amount of scores set in this mode.
+    pub plays: usize,
+    /// The sum of the score of every play in this mode.
+    pub total_score: u64,
+    /// The average accuracy of the plays in this mode, from `0` to `1`.
+    pub average_accuracy: f64,
+    /// The amount of plays with each grade, as derived with `Replay::grade`.
+    pub grades: BTreeMap<Grade, usize>,
+    /// The play with the highest max combo in this mode.
+    pub best_combo: ComboRecord,
+}
+
+/// A play that holds a record, such as the highest combo in a mode.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComboRecord {
+    pub max_combo: u16,
+    pub score: u32,
+    pub mods: ModSet,
+    pub timestamp: OsuTimestamp,
+    pub beatmap_hash: Option<Md5Hash>,
+    /// The name of the beatmap, as given by `Beatmap::display_name`.
+    ///
+    /// Only available if a listing containing the beatmap was given.
+    pub beatmap_name: Option<String>,
+}
+
+impl ScoreList {
+    /// Aggregate the scores of the given player into a profile.
+    ///
+    /// Player names are compared ignoring ASCII case, as osu! does.
+    /// If a listing is given, beatmap names are filled in by looking up the beatmap hashes in it.
+    pub fn profile(&self, player_name: &str, listing: Option<&Listing>) -> Profile {
+        let index = listing.map(Listing::hash_index);
+        let beatmap_name = |hash: Option<Md5Hash>| {
+            let (listing, index) = (listing?, index.as_ref()?);
+            let bm = &listing.beatmaps[index.get(&hash?)?];
+            Some(bm.display_name())
+        };
+
+        let mut modes: BTreeMap<u8, (ModeProfile, f64)> = BTreeMap::new();
+        let scores = self
+            .beatmaps
+            .iter()
+            .flat_map(|bm| &bm.scores)
+            .filter(|score| {
+                score
+                    .player_name
+                    .as_deref()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(player_name))
+            });
+        for score in scores {
+            let (profile, accuracy_sum) = modes.entry(score.mode.raw()).or_insert_with(|| {
+                (
+                    ModeProfile {
+                        mode: score.mode,
+                        plays: 0,
+                        total_score: 0,
+                        average_accuracy: 0.0,
+                        grades: BTreeMap::new(),
+                        best_combo: combo_record(score),
+                    },
+                    0.0,
+                )
+            });
+            profile.plays += 1;
+            profile.total_score += score.score as u64;
+            *accuracy_sum += score.accuracy();
+            *profile.grades.entry(score.grade()).or_insert(0) += 1;
+            let best = &profile.best_combo;
+            if (score.max_combo, score.score) > (best.max_combo, best.score) {
+                profile.best_combo = combo_record(score);
+            }
+        }
+
+        let mut accuracy_sum = 0.0;
+        let modes: Vec<ModeProfile> = modes
+            .into_values()
+            .map(|(mut profile, sum)| {
+                accuracy_sum += sum;
+                profile.average_accuracy = sum / profile.plays as f64;
+                profile.best_combo.beatmap_name = beatmap_name(profile.best_combo.beatmap_hash);
+                profile
+            })
+            .collect();
+        let plays = modes.iter().map(|profile| profile.plays).sum();
+        Profile {
+            player_name: player_name.to_string(),
+            plays,
+            total_score: modes.iter().map(|profile| profile.total_score).sum(),
+            average_accuracy: if plays == 0 {
+                None
+            } else {
+                Some(accuracy_sum / plays as f64)
+            },
+            modes,
+        }
+    }
+}
+
+fn combo_record(score: &Score) -> ComboRecord {
+    ComboRecord {
+        max_combo: score.max_combo,
+        score: score.score,
+        mods: score.mods,
+        timestamp: score.timestamp,
+        beatmap_hash: score.beatmap_hash.md5(),
+        //Filled in once the best combo is known
+        beatmap_name: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ids::test::hash, listing::test::beatmap, replay::test::replay_with, score::BeatmapScores,
+    };
+
+    fn score(player: &str, mode: Mode, n300: u16, combo: u16, map: u8) -> Score {
+        let mut score = replay_with(Vec::new()).into_score_entry();
+        score.player_name = Some(player.to_string());
+        score.mode = mode;
+        score.count_300 = n300;
+        score.count_miss = 100 - n300;
+        score.max_combo = combo;
+        score.score = combo as u32 * 10;
+        score.beatmap_hash = hash(map).into();
+        score
+    }
+
+    #[test]
+    fn profile() {
+        let scores = ScoreList {
+            version: 20211103,
+            beatmaps: vec![
+                BeatmapScores {
+                    hash: hash(1).into(),
+                    scores: vec![
+                        score("Player", Mode::Standard, 100, 50, 1),
+                        score("someone", Mode::Standard, 100, 900, 1),
+                        score("player", Mode::Taiko, 50, 10, 1),
+                    ],
+                },
+                BeatmapScores {
+                    hash: hash(2).into(),
+                    scores: vec![score("player", Mode::Standard, 50, 200, 2)],
+                },
+            ],
+        };
+        let mut bm = beatmap();
+        bm.hash = hash(2).into();
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![bm.clone()],
+            user_permissions: 0,
+        };
+
+        let profile = scores.profile("PLAYER", Some(&listing));
+        assert_eq!(profile.plays, 3);
+        assert_eq!(profile.total_score, 2600);
+        assert_eq!(profile.average_accuracy, Some(2.0 / 3.0));
+        assert_eq!(profile.modes.len(), 2);
+        let std = profile.mode(Mode::Standard).unwrap();
+        assert_eq!(std.plays, 2);
+        assert_eq!(std.average_accuracy, 0.75);
+        assert_eq!(std.grades.get(&Grade::SS), Some(&1));
+        assert_eq!(std.grades.get(&Grade::D), Some(&1));
+        assert_eq!(std.best_combo.max_combo, 200);
+        assert_eq!(std.best_combo.beatmap_name, Some(bm.display_name()));
+        assert_eq!(
+            profile.mode(Mode::Taiko).unwrap().best_combo.beatmap_name,
+            None
+        );
+
+        let nobody = scores.profile("nobody", None);
+        assert_eq!(nobody.plays, 0);
+        assert_eq!(nobody.average_accuracy, None);
+    }
+}
@@ -0,0 +1,676 @@
+//! Filtering of listing beatmaps, either through plain predicates or through a search query
+//! string similar to the one used by the osu! song select screen.
+//!
+//! A query is a list of whitespace-separated terms, all of which must match:
+//!
+//! - `key<op>value` compares a beatmap property against a value, where `<op>` is one of `=`,
+//!   `!=`, `<`, `<=`, `>` or `>=` (`:` and `==` are accepted as `=`).
+//! - Any other term is matched against the artist, title, creator, difficulty name, source and
+//!   tags of the beatmap. This includes terms whose key is not one of the keys below, so that
+//!   searches like `Re:Zero` work as they do in game.
+//!
+//! Text is matched the way the game does, ignoring case, full-width and half-width forms and
+//! diacritics, so that `uta` matches `Ｕｔá` and `ｳﾀ` matches `ウタ`.
+//! See `fold_search_text` for the details.
+//!
+//! Values and free-text terms can be wrapped in double quotes to include whitespace.
+//!
+//! The supported keys are:
+//!
+//! | Key | Meaning |
+//! |-----|---------|
+//! | `stars`, `star`, `sr` | No-mod star rating in the mode of the beatmap |
+//! | `ar`, `cs`, `od`, `hp` | Difficulty values |
+//! | `keys` | Key count of osu!mania beatmaps, which is stored as `cs` |
+//! | `bpm` | Bpm of the first uninherited timing point |
+//! | `length` | Drain time, in seconds |
+//! | `objects` | Amount of hitcircles, sliders and spinners |
+//! | `status` | `ranked`, `approved`, `qualified`, `loved`, `pending`, `graveyard`, `unsubmitted` or `unknown` |
+//! | `mode` | `osu`, `taiko`, `ctb` (or `catch`, `fruits`) or `mania` |
+//! | `grade` | Local grade in the mode of the beatmap; `grade<S` means worse than S |
+//! | `played` | `yes` or `no` |
+//! | `added` | Date added as guessed by `Beatmap::date_added`, as `YYYY-MM-DD` (the whole day, in UTC) or a full RFC 3339 date; `added>2020-01-01` means added after that day |
+//! | `artist`, `title`, `creator`, `diff`, `source`, `tag` | Substring match, ignoring case, width and diacritics |
+//!
+//! For example, `stars>=6 status=ranked grade<S` matches all ranked beatmaps of at least 6 stars
+//! that do not have an S rank or better yet.
+
+use crate::{
+    listing::{Beatmap, Grade, RankedStatus},
+    prelude::*,
+    Listing,
+};
+use core::str::FromStr;
+
+/// Anything that can decide whether a beatmap should be selected.
+///
+/// Implemented for plain `Fn(&Beatmap) -> bool` closures and for parsed `Query`s (by value or by
+/// reference).
+pub trait Filter {
+    fn matches(&self, beatmap: &Beatmap) -> bool;
+}
+impl<F> Filter for F
+where
+    F: Fn(&Beatmap) -> bool,
+{
+    fn matches(&self, beatmap: &Beatmap) -> bool {
+        self(beatmap)
+    }
+}
+
+/// A parsed search query.
+///
+/// See the module documentation for the query syntax.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+impl Query {
+    /// Parse a query from its string form.
+    pub fn parse(query: &str) -> Result<Query, QueryError> {
+        let terms = tokenize(query)?
+            .into_iter()
+            .map(parse_term)
+            .collect::<Result<_, _>>()?;
+        Ok(Query { terms })
+    }
+}
+impl FromStr for Query {
+    type Err = QueryError;
+    fn from_str(query: &str) -> Result<Query, QueryError> {
+        Query::parse(query)
+    }
+}
+impl Filter for Query {
+    fn matches(&self, beatmap: &Beatmap) -> bool {
+        self.terms.iter().all(|term| term.matches(beatmap))
+    }
+}
+impl Filter for &Query {
+    fn matches(&self, beatmap: &Beatmap) -> bool {
+        (*self).matches(beatmap)
+    }
+}
+
+impl Listing {
+    /// Search the beatmaps of the listing for plain text, as typed in the song select search
+    /// box.
+    ///
+    /// Every whitespace-separated word must be found in the artist, title, creator, difficulty
+    /// name, source or tags of a beatmap, ignoring case, width and diacritics.
+    /// Unlike `Query`, the text is never interpreted as `key=value` filters.
+    pub fn search<'a>(&'a self, text: &str) -> impl Iterator<Item = &'a Beatmap> + 'a {
+        let query = Query {
+            terms: text
+                .split_whitespace()
+                .map(|word| Term::FreeText(fold_search_text(word)))
+                .collect(),
+        };
+        self.beatmaps.iter().filter(move |bm| query.matches(bm))
+    }
+}
+
+/// An error found while parsing a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    /// A double quote was opened but never closed.
+    UnclosedQuote,
+    /// The value of a term is not valid for its key.
+    InvalidValue { key: String, value: String },
+    /// The comparison operator is not supported for the key.
+    InvalidOperator { key: String },
+}
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::UnclosedQuote => f.write_str("unclosed double quote"),
+            QueryError::InvalidValue { key, value } => {
+                write!(f, "invalid value {:?} for query key `{}`", value, key)
+            }
+            QueryError::InvalidOperator { key } => {
+                write!(f, "unsupported comparison for query key `{}`", key)
+            }
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for QueryError {}
+
+/// The length of a day, in the ticks of `OsuTimestamp`.
+const TICKS_PER_DAY: u64 = 24 * 60 * 60 * 10_000_000;
+
+/// Tolerance for `=` and `!=` comparisons of numeric values.
+const EQ_TOLERANCE: f64 = 0.005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+impl Op {
+    fn cmp<T: PartialOrd>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            Op::Eq => lhs == rhs,
+            Op::Ne => lhs != rhs,
+            Op::Lt => lhs < rhs,
+            Op::Le => lhs <= rhs,
+            Op::Gt => lhs > rhs,
+            Op::Ge => lhs >= rhs,
+        }
+    }
+
+    fn cmp_f64(self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Op::Eq => (lhs - rhs).abs() < EQ_TOLERANCE,
+            Op::Ne => (lhs - rhs).abs() >= EQ_TOLERANCE,
+            _ => self.cmp(lhs, rhs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumKey {
+    Stars,
+    Ar,
+    Cs,
+    Od,
+    Hp,
+    Bpm,
+    Length,
+    Objects,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextKey {
+    Artist,
+    Title,
+    Creator,
+    Difficulty,
+    Source,
+    Tags,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Num(NumKey, Op, f64),
+    Text(TextKey, bool, String),
+    Status(bool, RankedStatus),
+    Mode(bool, Mode),
+    Grade(Op, Grade),
+    Played(bool),
+    /// The range covered by the value, from its start to right before its end.
+    Added(Op, OsuTimestamp, OsuTimestamp),
+    FreeText(String),
+}
+impl Term {
+    fn matches(&self, bm: &Beatmap) -> bool {
+        match self {
+            Term::Num(key, op, value) => match num_value(bm, *key) {
+                Some(lhs) => op.cmp_f64(lhs, *value),
+                None => false,
+            },
+            Term::Text(key, negate, needle) => {
+                let found = text_values(bm, *key)
+                    .iter()
+                    .any(|hay| contains_folded(hay, needle));
+                found != *negate
+            }
+            Term::Status(negate, status) => (bm.status == *status) != *negate,
+            Term::Mode(negate, mode) => (bm.mode == *mode) != *negate,
+            //Grades are ordered from best to worst, so comparisons are flipped to make `<` mean
+            //"worse than"
+            Term::Grade(op, grade) => op.cmp(*grade, bm.grade(bm.mode)),
+            Term::Played(played) => bm.last_played.is_some() == *played,
+            Term::Added(op, start, end) => match bm.date_added() {
+                Some(added) => match op {
+                    Op::Eq => *start <= added && added < *end,
+                    Op::Ne => added < *start || *end <= added,
+                    Op::Lt => added < *start,
+                    Op::Le => added < *end,
+                    Op::Gt => *end <= added,
+                    Op::Ge => *start <= added,
+                },
+                None => false,
+            },
+            Term::FreeText(needle) => [
+                TextKey::Artist,
+                TextKey::Title,
+                TextKey::Creator,
+                TextKey::Difficulty,
+                TextKey::Source,
+                TextKey::Tags,
+            ]
+            .iter()
+            .flat_map(|&key| text_values(bm, key))
+            .any(|hay| contains_folded(hay, needle)),
+        }
+    }
+}
+
+fn num_value(bm: &Beatmap, key: NumKey) -> Option<f64> {
+    Some(match key {
+        NumKey::Stars => bm.stars(bm.mode, ModSet::empty())?,
+        NumKey::Ar => bm.approach_rate as f64,
+        NumKey::Cs => bm.circle_size as f64,
+        NumKey::Od => bm.overall_difficulty as f64,
+        NumKey::Hp => bm.hp_drain as f64,
+        NumKey::Bpm => {
+            let point = bm.timing_points.iter().find(|point| point.inherits)?;
+            if point.bpm <= 0.0 {
+                return None;
+            }
+            60000.0 / point.bpm
+        }
+        NumKey::Length => bm.drain_time as f64,
+        NumKey::Objects => {
+            bm.hitcircle_count as f64 + bm.slider_count as f64 + bm.spinner_count as f64
+        }
+    })
+}
+
+fn text_values(bm: &Beatmap, key: TextKey) -> Vec<&str> {
+    let fields = match key {
+        TextKey::Artist => [&bm.artist_ascii, &bm.artist_unicode],
+        TextKey::Title => [&bm.title_ascii, &bm.title_unicode],
+        TextKey::Creator => [&bm.creator, &None],
+        TextKey::Difficulty => [&bm.difficulty_name, &None],
+        TextKey::Source => [&bm.song_source, &None],
+        TextKey::Tags => [&bm.tags, &None],
+    };
+    fields.iter().filter_map(|s| s.as_deref()).collect()
+}
+
+/// Whether `haystack` contains a needle that was already folded with `fold_search_text`.
+fn contains_folded(haystack: &str, needle: &str) -> bool {
+    fold_search_text(haystack).contains(needle)
+}
+
+/// Whether `haystack` contains `needle`, ignoring case, character width and diacritics, as the
+/// osu! search does.
+///
+/// See `fold_search_text` for what is considered equal.
+pub fn search_matches(haystack: &str, needle: &str) -> bool {
+    contains_folded(haystack, &fold_search_text(needle))
+}
+
+/// Normalize text for searching, so that text that the osu! search considers equal is folded into
+/// the same string.
+///
+/// - Full-width ASCII forms (such as `Ａ`) and the ideographic space become plain ASCII.
+/// - Half-width katakana (such as `ｶﾞ`) become full-width katakana (`ガ`).
+/// - Text is lowercased.
+/// - Diacritics are stripped from latin letters, such as `é` becoming `e`.
+///   Letters that do not decompose into a base letter, such as `æ` or `ß`, are kept.
+pub fn fold_search_text(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars().map(fold_width) {
+        if let Some(voiced) = folded
+            .chars()
+            .next_back()
+            .and_then(|last| voice_kana(last, c))
+        {
+            folded.pop();
+            folded.push(voiced);
+            continue;
+        }
+        for c in c.to_lowercase() {
+            //Combining diacritics
+            if ('\u{300}'..='\u{36f}').contains(&c) {
+                continue;
+            }
+            folded.push(strip_diacritic(c));
+        }
+    }
+    folded
+}
+
+/// Half-width katakana from `U+FF65` to `U+FF9F`, in their full-width forms.
+const HALF_WIDTH_KATAKANA: &str =
+    "・ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン゛゜";
+
+pub(crate) fn fold_width(c: char) -> char {
+    match c as u32 {
+        0x3000 => ' ',
+        full @ 0xff01..=0xff5e => char::from_u32(full - 0xfee0).unwrap_or(c),
+        half @ 0xff65..=0xff9f => HALF_WIDTH_KATAKANA
+            .chars()
+            .nth((half - 0xff65) as usize)
+            .unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// Combine a katakana with a following voiced sound mark, as half-width katakana write them as
+/// separate characters.
+fn voice_kana(kana: char, mark: char) -> Option<char> {
+    let offset = match mark {
+        '゛' | '\u{3099}' if kana == 'ウ' => return Some('ヴ'),
+        '゛' | '\u{3099}' if "カキクケコサシスセソタチツテトハヒフヘホ".contains(kana) => {
+            1
+        }
+        '゜' | '\u{309a}' if "ハヒフヘホ".contains(kana) => 2,
+        _ => return None,
+    };
+    char::from_u32(kana as u32 + offset)
+}
+
+/// Lowercase latin letters from `U+00E0` to `U+017F`, without their diacritics.
+const LATIN_BASE_LETTERS: &str = concat!(
+    "aaaaaaæceeeeiiiiðnooooo÷ouuuuyþy",
+    "aaaaaaccccccccddddeeeeeeeeeegggggggghhhhiiiiiiiiiiĳĳjjkkĸllllllllllnnnnnnŉŋŋooooooœœrrrrrr",
+    "ssssssssttttttuuuuuuuuuuuuwwyyyzzzzzzſ",
+);
+
+pub(crate) fn strip_diacritic(c: char) -> char {
+    match c as u32 {
+        latin @ 0xe0..=0x17f => LATIN_BASE_LETTERS
+            .chars()
+            .nth((latin - 0xe0) as usize)
+            .unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// Split a query into terms, keeping quoted sections together and removing the quotes.
+fn tokenize(query: &str) -> Result<Vec<String>, QueryError> {
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_term = false;
+    for c in query.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_term = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_term {
+                    terms.push(core::mem::take(&mut current));
+                    has_term = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_term = true;
+            }
+        }
+    }
+    if in_quotes {
+        return Err(QueryError::UnclosedQuote);
+    }
+    if has_term {
+        terms.push(current);
+    }
+    Ok(terms)
+}
+
+fn parse_term(term: String) -> Result<Term, QueryError> {
+    let split = term
+        .char_indices()
+        .find(|&(_, c)| matches!(c, '=' | '!' | '<' | '>' | ':'));
+    let (key_end, op, value_start) = match split {
+        Some((idx, _)) if idx > 0 => {
+            let rest = &term[idx..];
+            let (op, len) = if rest.starts_with("==") {
+                (Op::Eq, 2)
+            } else if rest.starts_with("!=") {
+                (Op::Ne, 2)
+            } else if rest.starts_with("<=") {
+                (Op::Le, 2)
+            } else if rest.starts_with(">=") {
+                (Op::Ge, 2)
+            } else if rest.starts_with('<') {
+                (Op::Lt, 1)
+            } else if rest.starts_with('>') {
+                (Op::Gt, 1)
+            } else if rest.starts_with('=') || rest.starts_with(':') {
+                (Op::Eq, 1)
+            } else {
+                return Ok(Term::FreeText(fold_search_text(&term)));
+            };
+            (idx, op, idx + len)
+        }
+        _ => return Ok(Term::FreeText(fold_search_text(&term))),
+    };
+    let key = term[..key_end].to_lowercase();
+    let value = &term[value_start..];
+    let invalid_value = || QueryError::InvalidValue {
+        key: key.clone(),
+        value: value.to_string(),
+    };
+    let equality_only = |op: Op| -> Result<bool, QueryError> {
+        match op {
+            Op::Eq => Ok(false),
+            Op::Ne => Ok(true),
+            _ => Err(QueryError::InvalidOperator { key: key.clone() }),
+        }
+    };
+
+    let num_key = match key.as_str() {
+        "stars" | "star" | "sr" => Some(NumKey::Stars),
+        "ar" => Some(NumKey::Ar),
+        "cs" | "keys" => Some(NumKey::Cs),
+        "od" => Some(NumKey::Od),
+        "hp" => Some(NumKey::Hp),
+        "bpm" => Some(NumKey::Bpm),
+        "length" => Some(NumKey::Length),
+        "objects" => Some(NumKey::Objects),
+        _ => None,
+    };
+    if let Some(num_key) = num_key {
+        let value = value.parse().map_err(|_| invalid_value())?;
+        return Ok(Term::Num(num_key, op, value));
+    }
+
+    let text_key = match key.as_str() {
+        "artist" => Some(TextKey::Artist),
+        "title" => Some(TextKey::Title),
+        "creator" | "mapper" => Some(TextKey::Creator),
+        "diff" | "difficulty" => Some(TextKey::Difficulty),
+        "source" => Some(TextKey::Source),
+        "tag" | "tags" => Some(TextKey::Tags),
+        _ => None,
+    };
+    if let Some(text_key) = text_key {
+        let negate = equality_only(op)?;
+        return Ok(Term::Text(text_key, negate, fold_search_text(value)));
+    }
+
+    match key.as_str() {
+        "status" => {
+            let status = match value.to_lowercase().as_str() {
+                "ranked" | "r" => RankedStatus::Ranked,
+                "approved" | "a" => RankedStatus::Approved,
+                "qualified" | "q" => RankedStatus::Qualified,
+                "loved" | "l" => RankedStatus::Loved,
+                "pending" | "graveyard" | "wip" | "p" | "g" => RankedStatus::PendingWipGraveyard,
+                "unsubmitted" | "u" => RankedStatus::Unsubmitted,
+                "unknown" => RankedStatus::Unknown,
+                _ => return Err(invalid_value()),
+            };
+            Ok(Term::Status(equality_only(op)?, status))
+        }
+        "mode" => {
+            let mode = match value.to_lowercase().as_str() {
+                "osu" | "std" | "standard" | "o" => Mode::Standard,
+                "taiko" | "t" => Mode::Taiko,
+                "ctb" | "catch" | "fruits" | "c" | "f" => Mode::CatchTheBeat,
+                "mania" | "m" => Mode::Mania,
+                _ => return Err(invalid_value()),
+            };
+            Ok(Term::Mode(equality_only(op)?, mode))
+        }
+        "grade" | "rank" => {
+            let grade = match value.to_uppercase().as_str() {
+                "XH" | "SSH" | "SS+" => Grade::SSPlus,
+                "SH" | "S+" => Grade::SPlus,
+                "X" | "SS" => Grade::SS,
+                "S" => Grade::S,
+                "A" => Grade::A,
+                "B" => Grade::B,
+                "C" => Grade::C,
+                "D" => Grade::D,
+                "UNPLAYED" | "NONE" => Grade::Unplayed,
+                _ => return Err(invalid_value()),
+            };
+            Ok(Term::Grade(op, grade))
+        }
+        "played" => {
+            let played = match value.to_lowercase().as_str() {
+                "yes" | "true" | "1" => true,
+                "no" | "false" | "0" => false,
+                _ => return Err(invalid_value()),
+            };
+            Ok(Term::Played(played != equality_only(op)?))
+        }
+        "added" => {
+            //A date on its own covers the whole day
+            let (date, length) = match value.len() {
+                10 => (
+                    OsuTimestamp::parse_rfc3339(&format!("{}T00:00:00Z", value)),
+                    TICKS_PER_DAY,
+                ),
+                _ => (OsuTimestamp::parse_rfc3339(value), 1),
+            };
+            let start = date.map_err(|_| invalid_value())?;
+            let end = OsuTimestamp::from_ticks(start.ticks().saturating_add(length));
+            Ok(Term::Added(op, start, end))
+        }
+        _ => Ok(Term::FreeText(fold_search_text(&term))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{listing::test::beatmap, CollectionList};
+
+    #[test]
+    fn parse() {
+        assert!(Query::parse("").unwrap().terms.is_empty());
+        assert_eq!(
+            Query::parse("\"some artist\" ar>=9.5").unwrap().terms,
+            vec![
+                Term::FreeText("some artist".to_string()),
+                Term::Num(NumKey::Ar, Op::Ge, 9.5)
+            ]
+        );
+        //Unknown keys are plain text
+        assert_eq!(
+            Query::parse("Re:Zero keys=7").unwrap().terms,
+            vec![
+                Term::FreeText("re:zero".to_string()),
+                Term::Num(NumKey::Cs, Op::Eq, 7.0)
+            ]
+        );
+        assert_eq!(
+            Query::parse("status>ranked"),
+            Err(QueryError::InvalidOperator {
+                key: "status".to_string()
+            })
+        );
+        assert_eq!(Query::parse("title=\"a"), Err(QueryError::UnclosedQuote));
+    }
+
+    #[test]
+    fn matches() {
+        let bm = beatmap();
+        let matches = |query: &str| Query::parse(query).unwrap().matches(&bm);
+        assert!(matches("stars>=5 status=ranked mode=osu"));
+        assert!(!matches("stars>6"));
+        assert!(matches("grade<S grade>=A"));
+        assert!(matches("タイトル creator=mapper"));
+        assert!(!matches("title!=title"));
+        assert!(matches("bpm=200 length<=120 played=yes"));
+        assert!(!matches("Re:Zero"));
+        let mut named = beatmap();
+        named.title_ascii = Some("Re:ZERO".to_string());
+        assert!(Query::parse("re:zero").unwrap().matches(&named));
+    }
+
+    #[test]
+    fn added() {
+        let mut bm = beatmap();
+        let matches = |bm: &Beatmap, query: &str| Query::parse(query).unwrap().matches(bm);
+        assert!(!matches(&bm, "added>2000-01-01"));
+        bm.set_date_added(OsuTimestamp::parse_rfc3339("2021-06-01T12:00:00Z").unwrap());
+        assert!(matches(&bm, "added>2021-01-01 added<=2021-06-02"));
+        assert!(matches(&bm, "added>=2021-06-01T12:00:00Z"));
+        assert!(matches(&bm, "added=2021-06-01T12:00:00Z"));
+        assert!(!matches(&bm, "added<2021-06-01"));
+        //Dates on their own cover the whole day
+        assert!(matches(
+            &bm,
+            "added=2021-06-01 added<=2021-06-01 added>=2021-06-01"
+        ));
+        assert!(!matches(&bm, "added!=2021-06-01"));
+        assert!(!matches(&bm, "added>2021-06-01"));
+        assert!(!matches(&bm, "added=2021-06-02"));
+        assert!(matches(&bm, "added<2021-06-02 added>2021-05-31"));
+        assert!(Query::parse("added>yesterday").is_err());
+    }
+
+    #[test]
+    fn fold_search_text() {
+        assert_eq!(super::fold_search_text("Ｕｔá　Ｎｏ"), "uta no");
+        assert_eq!(super::fold_search_text("ｳﾀ ｶﾞﾊﾟ"), "ウタ ガパ");
+        assert_eq!(super::fold_search_text("ŁÓDŹ Straße"), "lodz straße");
+        assert_eq!(super::fold_search_text("Cafe\u{301}"), "cafe");
+        assert!(search_matches("Shūmatsu", "shumatsu"));
+        assert!(search_matches("ウタ", "ｳﾀ"));
+        assert!(!search_matches("ウタ", "uta"));
+    }
+
+    #[test]
+    fn search() {
+        let mut bm = beatmap();
+        bm.title_ascii = Some("Utá".to_string());
+        bm.title_unicode = Some("ウタ".to_string());
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap(), bm],
+            user_permissions: 0,
+        };
+        let found = |text: &str| listing.search(text).count();
+        assert_eq!(found("ＵＴＡ"), 1);
+        assert_eq!(found("ｳﾀ mapper"), 1);
+        assert_eq!(found("uta nothing"), 0);
+        assert_eq!(found("creator=mapper"), 0);
+        assert_eq!(found(""), 2);
+        assert!(Query::parse("title=uta")
+            .unwrap()
+            .matches(&listing.beatmaps[1]));
+    }
+
+    #[test]
+    fn generate() {
+        let mut hard = beatmap();
+        hard.hash = StoredHash::Md5("fedcba9876543210fedcba9876543210".parse().unwrap());
+        hard.std_ratings = vec![(ModSet::empty(), 6.5)];
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap(), hard.clone(), hard.clone()],
+            user_permissions: 0,
+        };
+        let mut collections = CollectionList {
+            version: 20211103,
+            collections: Vec::new(),
+        };
+        let query = Query::parse("stars>=6").unwrap();
+        collections.generate("hard", &listing, &query);
+        assert_eq!(collections.collections[0].beatmap_hashes, vec![hard.hash]);
+        collections.generate("hard", &listing, |bm: &Beatmap| bm.mode == Mode::Standard);
+        assert_eq!(collections.collections.len(), 1);
+        assert_eq!(collections.collections[0].beatmap_hashes.len(), 2);
+    }
+}
@@ -1,11 +1,40 @@
 //! Parsing for replay and score files, which are very similar.
 
-use crate::prelude::*;
+use crate::{listing::Grade, prelude::*, Mod};
 
 /// The LZMA compression level (a number between 0 and 9) used to write replay data when it is
 /// not otherwise specified.
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
 const DEFAULT_COMPRESSION_LEVEL: u32 = 5;
 
+/// The default limit on the memory used by the LZMA decoder when decompressing replay data, in
+/// bytes.
+///
+/// Replays written by osu! need less than a tenth of it.
+/// See `ParseOptions::lzma_memory_limit`.
+#[cfg(feature = "compression")]
+pub const DEFAULT_LZMA_MEMORY_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// The default limit on the size of decompressed replay data, in bytes.
+///
+/// A replay of a map several hours long takes up a few tens of megabytes.
+/// See `ParseOptions::replay_data_limit`.
+#[cfg(feature = "compression")]
+pub const DEFAULT_REPLAY_DATA_LIMIT: usize = 256 * 1024 * 1024;
+
+/// In these replay versions the size of the online score id changed.
+/// Before `20121008` it is not present at all, and before `20140721` it is a 32-bit integer.
+const CHANGE_20121008: u32 = 20121008;
+const CHANGE_20140721: u32 = 20140721;
+
+/// Starting with this version, the replay RNG seed is stored in a trailing action.
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
+const CHANGE_20130319: u32 = 20130319;
+
+/// The `delta` value of the trailing action osu! uses to store the replay RNG seed, instead of an
+/// actual input frame.
+pub const SEED_FRAME_DELTA: i64 = -12345;
+
 /// An osu! replay.
 /// The replay might come from a large `ScoreList` score database, or from an individual standalone
 /// `.osr` file.
@@ -18,11 +47,11 @@ pub struct Replay {
     /// If the replay is inside a `scores.db` file, the version should be redundant with it (?).
     pub version: u32,
     /// The MD5 hash of the beatmap played.
-    pub beatmap_hash: Option<String>,
+    pub beatmap_hash: StoredHash,
     /// The name of the player who scored the replay.
     pub player_name: Option<String>,
     /// The replay-specific MD5 hash.
-    pub replay_hash: Option<String>,
+    pub replay_hash: StoredHash,
     /// Amount of 300s (fruits in ctb).
     pub count_300: u16,
     /// Amount of 100s (drops in ctb, 150s in taiko and 200s in mania).
@@ -48,7 +77,7 @@ pub struct Replay {
     /// number between 0 and 1 representing the amount of life left.
     pub life_graph: Option<String>,
     /// When was the replay scored.
-    pub timestamp: DateTime<Utc>,
+    pub timestamp: OsuTimestamp,
     /// Decompressed replay data.
     ///
     /// Only available on standalone `.osr` replays, and if the `compression` feature is enabled
@@ -57,6 +86,8 @@ pub struct Replay {
     /// When writing `.osr` files (and `.osr` files only), if the `compression` feature is enabled
     /// and this field is `Some`, these actions will be compressed and written. Otherwise,
     /// `raw_replay_data` will be written instead.
+    /// If the actions are unchanged from the ones stored in `raw_replay_data`, the raw data is
+    /// written as-is, so that unmodified replays round-trip byte by byte.
     pub replay_data: Option<Vec<Action>>,
     /// Raw replay data, available on `.osr` files even if the `compression` feature is not enabled.
     ///
@@ -65,47 +96,595 @@ pub struct Replay {
     pub raw_replay_data: Option<Vec<u8>>,
     /// Online score id.
     /// Only has a useful value on replays embedded in a `ScoreList`.
+    ///
+    /// Replays older than version `20121008` do not store this id, and it is always `0` for them.
+    /// Up to version `20140721` it is stored in 32 bits, so larger values are truncated when
+    /// writing.
     pub online_score_id: u64,
 }
 impl Replay {
     /// Parse a replay from its raw bytes.
     pub fn from_bytes(bytes: &[u8]) -> Result<Replay, Error> {
-        replay(bytes, true).map(|(_rem, replay)| replay)
+        Self::from_bytes_with(bytes, ParseOptions::new())
+    }
+
+    /// Parse a replay from its raw bytes, with the given parsing options.
+    pub fn from_bytes_with(bytes: &[u8], mut opts: ParseOptions) -> Result<Replay, Error> {
+        replay(bytes, true, &mut opts).map(|(_rem, replay)| replay)
     }
 
     /// Read a replay from a standalone `.osr` osu! replay file.
+    #[cfg(feature = "std")]
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Replay, Error> {
         Self::from_bytes(&fs::read(path)?)
     }
 
     /// Write the replay to an arbitrary writer, with the given compression level.
     ///
-    /// If the compression level is `None` the arbitrary default
-    /// `replay::DEFAULT_COMPRESSION_LEVEL` will be used.
+    /// If the compression level is `None` and `raw_replay_data` still encodes the actions, it is
+    /// written as-is. Otherwise the actions are compressed anew, with the arbitrary default
+    /// `replay::DEFAULT_COMPRESSION_LEVEL` if the compression level is `None`.
     /// If the `compression` feature is disabled this argument has no effect.
     pub fn to_writer<W: Write>(
         &self,
         mut out: W,
         compression_level: Option<u32>,
     ) -> io::Result<()> {
-        self.wr_args(
-            &mut out,
-            Some(compression_level.unwrap_or(DEFAULT_COMPRESSION_LEVEL)),
-        )
+        self.wr_args(&mut out, Some(compression_level))
     }
 
     /// Similar to `to_writer` but writes the replay to an `osr` file.
+    #[cfg(feature = "std")]
     pub fn save<P: AsRef<Path>>(&self, path: P, compression_level: Option<u32>) -> io::Result<()> {
         self.to_writer(BufWriter::new(File::create(path)?), compression_level)
     }
+
+    /// Change the version of the replay, adjusting any version-dependent data so that it can be
+    /// written in the layout of the target version.
+    pub fn migrate_to(&mut self, version: u32) {
+        if version < CHANGE_20121008 {
+            self.online_score_id = 0;
+        } else if version < CHANGE_20140721 {
+            self.online_score_id &= u32::MAX as u64;
+        }
+        self.version = version;
+    }
+
+    /// Strip the replay data, turning a standalone `.osr` replay into a `scores.db` entry.
+    ///
+    /// Score entries do not carry replay data, and are written with a `0xffffffff` marker in its
+    /// place.
+    pub fn into_score_entry(self) -> Replay {
+        Replay {
+            replay_data: None,
+            raw_replay_data: None,
+            ..self
+        }
+    }
+
+    /// Attach replay actions to a score, such as a `scores.db` entry, so that it can be written as
+    /// a standalone `.osr` replay.
+    ///
+    /// Any previous `raw_replay_data` is discarded, as it would not match the new actions.
+    pub fn with_replay_data(self, actions: Vec<Action>) -> Replay {
+        Replay {
+            replay_data: Some(actions),
+            raw_replay_data: None,
+            ..self
+        }
+    }
+
+    /// Set the timestamp of the replay to the current time.
+    ///
+    /// Like osu! does, the timestamp is stored as the ticks of the current UTC time, which is what
+    /// the online servers expect of submitted replays.
+    #[cfg(feature = "std")]
+    pub fn set_timestamp_now(&mut self) {
+        self.timestamp = OsuTimestamp::now();
+    }
+
+    /// The accuracy of the score, from `0` to `1`, computed with the formula of the gamemode it
+    /// was played in.
+    ///
+    /// A score without any judgement has an accuracy of `1`.
+    pub fn accuracy(&self) -> f64 {
+        let n300 = self.count_300 as f64;
+        let n100 = self.count_100 as f64;
+        let n50 = self.count_50 as f64;
+        let geki = self.count_geki as f64;
+        let katsu = self.count_katsu as f64;
+        let miss = self.count_miss as f64;
+        let (hit, total) = match self.mode {
+            Mode::Standard => (
+                300.0 * n300 + 100.0 * n100 + 50.0 * n50,
+                300.0 * (n300 + n100 + n50 + miss),
+            ),
+            Mode::Taiko => (n300 + 0.5 * n100, n300 + n100 + miss),
+            Mode::CatchTheBeat => (n300 + n100 + n50, n300 + n100 + n50 + katsu + miss),
+            Mode::Mania => (
+                300.0 * (geki + n300) + 200.0 * katsu + 100.0 * n100 + 50.0 * n50,
+                300.0 * (geki + n300 + katsu + n100 + n50 + miss),
+            ),
+        };
+        if total == 0.0 {
+            1.0
+        } else {
+            hit / total
+        }
+    }
+
+    /// The grade of the score, derived from its hit counts and mods with the rules of the gamemode
+    /// it was played in.
+    ///
+    /// Silver grades are given to scores with `Hidden` or `Flashlight` (or `FadeIn` in mania).
+    /// A score without any judgement is graded `SS`.
+    pub fn grade(&self) -> Grade {
+        let n300 = self.count_300 as f64;
+        let n100 = self.count_100 as f64;
+        let n50 = self.count_50 as f64;
+        let miss = self.count_miss as f64;
+        let total = match self.mode {
+            Mode::Standard => n300 + n100 + n50 + miss,
+            Mode::Taiko => n300 + n100 + miss,
+            Mode::CatchTheBeat | Mode::Mania => 0.0,
+        };
+        let ratio300 = if total == 0.0 { 1.0 } else { n300 / total };
+        let ratio50 = if total == 0.0 { 0.0 } else { n50 / total };
+        let accuracy = self.accuracy();
+        let no_miss = self.count_miss == 0;
+        let grade = match self.mode {
+            _ if accuracy >= 1.0 => Grade::SS,
+            Mode::Standard | Mode::Taiko => {
+                if ratio300 > 0.9 && ratio50 < 0.01 && no_miss {
+                    Grade::S
+                } else if ratio300 > 0.9 || (ratio300 > 0.8 && no_miss) {
+                    Grade::A
+                } else if ratio300 > 0.8 || (ratio300 > 0.7 && no_miss) {
+                    Grade::B
+                } else if ratio300 > 0.6 {
+                    Grade::C
+                } else {
+                    Grade::D
+                }
+            }
+            Mode::CatchTheBeat => match accuracy {
+                acc if acc > 0.98 => Grade::S,
+                acc if acc > 0.94 => Grade::A,
+                acc if acc > 0.9 => Grade::B,
+                acc if acc > 0.85 => Grade::C,
+                _ => Grade::D,
+            },
+            Mode::Mania => match accuracy {
+                acc if acc > 0.95 => Grade::S,
+                acc if acc > 0.9 => Grade::A,
+                acc if acc > 0.8 => Grade::B,
+                acc if acc > 0.7 => Grade::C,
+                _ => Grade::D,
+            },
+        };
+        let silver = self.mods.contains(Mod::Hidden)
+            || self.mods.contains(Mod::Flashlight)
+            || (self.mode == Mode::Mania && self.mods.contains(Mod::FadeIn));
+        match grade {
+            Grade::SS if silver => Grade::SSPlus,
+            Grade::S if silver => Grade::SPlus,
+            grade => grade,
+        }
+    }
+
+    /// Iterate over the replay actions along with their absolute time, in milliseconds since the
+    /// start of the song.
+    ///
+    /// The leading header frames and the trailing seed frame are not included, the same as with
+    /// the editing operations.
+    /// If `replay_data` is `None`, nothing is yielded.
+    pub fn timed_actions(&self) -> impl Iterator<Item = (i64, &Action)> {
+        let actions = self.replay_data.as_deref().unwrap_or_default();
+        let (head, body) = actions.split_at(header_len(actions));
+        let mut time: i64 = head.iter().map(|action| action.delta).sum();
+        body.iter()
+            .filter(|action| !action.is_seed())
+            .map(move |action| {
+                time += action.delta;
+                (time, action)
+            })
+    }
+
+    /// Insert the given actions into the replay, each one at the given absolute time (in
+    /// milliseconds since the start of the song).
+    ///
+    /// The `delta` field of the inserted actions is ignored, and the deltas of the surrounding
+    /// actions are adjusted so that every other action keeps its absolute time.
+    /// Actions inserted at the same time as an existing action are placed after it.
+    /// Actions inserted before the first input frame are placed right after the header frames
+    /// instead, as the deltas of a replay never go negative.
+    pub fn insert_frames<I>(&mut self, frames: I)
+    where
+        I: IntoIterator<Item = (i64, Action)>,
+    {
+        self.edit_timeline(|body| {
+            for (time, action) in frames {
+                let idx = body.partition_point(|&(t, _)| t <= time);
+                body.insert(idx, (time, action));
+            }
+        });
+    }
+
+    /// Remove all actions whose absolute time falls within the given range, in milliseconds since
+    /// the start of the song.
+    ///
+    /// The remaining actions keep their absolute time.
+    /// The leading header frames and the trailing seed frame are never removed.
+    /// Returns the amount of actions removed.
+    pub fn remove_range(&mut self, range: ops::Range<i64>) -> usize {
+        let mut removed = 0;
+        self.edit_timeline(|body| {
+            let before = body.len();
+            body.retain(|(time, _)| !range.contains(time));
+            removed = before - body.len();
+        });
+        removed
+    }
+
+    /// Move all actions `offset_ms` milliseconds forward in time (or backwards, if negative).
+    ///
+    /// The leading header frames and the trailing seed frame are left as-is.
+    /// Actions moved back to before the end of the header frames are placed right after them, as
+    /// the deltas of a replay never go negative.
+    pub fn shift_time(&mut self, offset_ms: i64) {
+        self.edit_timeline(|body| {
+            for (time, _action) in body.iter_mut() {
+                *time += offset_ms;
+            }
+        });
+    }
+
+    /// Stretch the action timeline by the given factor, such that an action at time `t` ends up
+    /// at time `t * factor`, which multiplies the deltas by the factor.
+    ///
+    /// A factor above `1.0` slows the replay down, and a factor below `1.0` speeds it up.
+    /// Use `convert_rate` to retime a replay between the clocks of rate-changing mods.
+    /// Absolute times are rounded to the nearest millisecond, so that rounding errors do not
+    /// accumulate along the replay.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `factor` is not a positive finite number, as the actions would not stay in order.
+    pub fn scale_rate(&mut self, factor: f64) {
+        assert!(
+            factor.is_finite() && factor > 0.0,
+            "rate factor must be positive"
+        );
+        self.edit_timeline(|body| {
+            for (time, _action) in body.iter_mut() {
+                *time = round(*time as f64 * factor) as i64;
+            }
+        });
+    }
+
+    /// Move the replay from the clock of the `from` mods to the clock of the `to` mods, such as
+    /// from DoubleTime to nomod, so that replays played with different rates can be compared on a
+    /// common clock.
+    ///
+    /// The action times and the life graph offsets are multiplied by the ratio of the clock rates
+    /// of the two sets, as given by `ModSet::clock_rate`, through `scale_rate`. For example, going
+    /// from DoubleTime to nomod multiplies them by `1.5`.
+    /// The rate-changing mods of the replay (DoubleTime, Nightcore and HalfTime) are swapped for
+    /// those of `to`, other mods are left as-is.
+    pub fn convert_rate(&mut self, from: ModSet, to: ModSet) {
+        let factor = from.clock_rate() / to.clock_rate();
+        if factor != 1.0 {
+            self.scale_rate(factor);
+            if let Some(life_graph) = &self.life_graph {
+                self.life_graph = Some(scale_life_graph(life_graph, factor));
+            }
+        }
+        for &m in &[Mod::DoubleTime, Mod::Nightcore, Mod::HalfTime] {
+            self.mods = self.mods.without(m);
+            if to.contains(m) {
+                self.mods = self.mods.with(m);
+            }
+        }
+    }
+
+    /// Whether the play was failed, as told by its life graph ending with no health left.
+    ///
+    /// Plays with `NoFail` never fail, and plays without a life graph count as passed, which
+    /// includes every `scores.db` entry osu! writes.
+    pub fn is_failed(&self) -> bool {
+        !self.mods.contains(Mod::NoFail)
+            && self
+                .life_graph
+                .as_deref()
+                .and_then(final_life)
+                .is_some_and(|life| life <= 0.0)
+    }
+
+    /// Check the life graph for problems that make osu! draw a glitched health bar, or that
+    /// contradict the rest of the replay.
+    ///
+    /// Entries must be `<offset>|<life>` pairs with integer offsets that never go back in time,
+    /// and life values between 0 and 1.
+    /// Without `NoFail` or `Easy` (which revives the player) the play ends as soon as life drops
+    /// to zero, so it must only do so at the last entry.
+    /// Plays that cannot have survived must end with no life left: `SuddenDeath` plays with
+    /// misses, and `Perfect` plays with any judgement short of a 300 (as counted by `accuracy`).
+    /// `NoFail` plays never have to fail.
+    /// Empty entries, such as the one left by the usual trailing comma, are ignored.
+    /// A replay without a life graph has no issues.
+    pub fn validate_life_graph(&self) -> Vec<LifeGraphIssue> {
+        let mut issues = Vec::new();
+        let life_graph = match &self.life_graph {
+            Some(life_graph) => life_graph,
+            None => return issues,
+        };
+        let mut previous: Option<i64> = None;
+        let mut entries = life_graph
+            .split(',')
+            .enumerate()
+            .filter(|(_, entry)| !entry.trim().is_empty())
+            .peekable();
+        let mut last_life = None;
+        while let Some((idx, entry)) = entries.next() {
+            let (offset, life) = match parse_life_entry(entry) {
+                Some(parsed) => parsed,
+                None => {
+                    issues.push(LifeGraphIssue::MalformedEntry { entry: idx });
+                    continue;
+                }
+            };
+            if let Some(previous) = previous.filter(|&previous| offset < previous) {
+                issues.push(LifeGraphIssue::NonMonotonicOffset {
+                    entry: idx,
+                    offset,
+                    previous,
+                });
+            }
+            previous = Some(offset);
+            if !(0.0..=1.0).contains(&life) {
+                issues.push(LifeGraphIssue::LifeOutOfRange { entry: idx, life });
+            }
+            if life <= 0.0
+                && entries.peek().is_some()
+                && !self.mods.contains(Mod::NoFail)
+                && !self.mods.contains(Mod::Easy)
+            {
+                issues.push(LifeGraphIssue::ContinuesAfterFail { entry: idx });
+            }
+            last_life = Some(life);
+        }
+        let imperfect = match self.mode {
+            Mode::Standard | Mode::Taiko => {
+                self.count_100 > 0 || self.count_50 > 0 || self.count_miss > 0
+            }
+            Mode::CatchTheBeat => self.count_katsu > 0 || self.count_miss > 0,
+            Mode::Mania => {
+                self.count_katsu > 0
+                    || self.count_100 > 0
+                    || self.count_50 > 0
+                    || self.count_miss > 0
+            }
+        };
+        let must_fail = !self.mods.contains(Mod::NoFail)
+            && ((self.mods.contains(Mod::SuddenDeath) && self.count_miss > 0)
+                || (self.mods.contains(Mod::Perfect) && imperfect));
+        if must_fail && last_life.is_some_and(|life| life > 0.0) {
+            issues.push(LifeGraphIssue::MissingFail);
+        }
+        issues
+    }
+
+    /// Run an edit on the absolute-time timeline of the replay actions, and then recompute the
+    /// action deltas from it.
+    ///
+    /// Any `raw_replay_data` is discarded after editing, as it no longer matches the actions.
+    /// The replay hash is cleared too, as it was computed by osu! for the original replay.
+    fn edit_timeline<F>(&mut self, edit: F)
+    where
+        F: FnOnce(&mut Vec<(i64, Action)>),
+    {
+        if let Some(actions) = self.replay_data.take() {
+            let mut timeline = Timeline::split(actions);
+            edit(&mut timeline.body);
+            self.replay_data = Some(timeline.join());
+            self.raw_replay_data = None;
+            self.replay_hash = StoredHash::Missing;
+        }
+    }
 }
 
-pub(crate) fn replay(bytes: &[u8], standalone: bool) -> Result<(&[u8], Replay), Error> {
+/// Scale the offsets of a life graph, made up of comma-separated `offset|life` pairs, by the given
+/// factor.
+///
+/// Life values are kept verbatim, as are any entries that do not have an integer offset.
+fn scale_life_graph(life_graph: &str, factor: f64) -> String {
+    let entries: Vec<String> = life_graph
+        .split(',')
+        .map(|entry| {
+            let parsed = entry
+                .split_once('|')
+                .and_then(|(offset, life)| Some((offset.trim().parse::<i64>().ok()?, life)));
+            match parsed {
+                Some((offset, life)) => {
+                    format!("{}|{}", round(offset as f64 * factor) as i64, life)
+                }
+                None => entry.to_string(),
+            }
+        })
+        .collect();
+    entries.join(",")
+}
+
+/// Build a well-formed life graph out of `(offset, life)` samples, in milliseconds and between 0
+/// and 1 respectively.
+///
+/// Samples may come in any order.
+/// They are sorted by offset, samples at the same offset are merged into the last one given,
+/// life values are clamped between 0 and 1, and samples with a NaN life are dropped.
+/// The graph is written the way osu! writes it, with a trailing comma.
+/// Samples after life reaches zero are kept, so truncate them unless the play used `NoFail`.
+pub fn synthesize_life_graph<I>(points: I) -> String
+where
+    I: IntoIterator<Item = (i64, f64)>,
+{
+    let mut points: Vec<(i64, f64)> = points
+        .into_iter()
+        .filter(|(_, life)| !life.is_nan())
+        .collect();
+    //Stable, so that the last sample given at each offset ends up last
+    points.sort_by_key(|&(offset, _)| offset);
+    let mut graph = String::new();
+    for (idx, &(offset, life)) in points.iter().enumerate() {
+        if points.get(idx + 1).is_some_and(|next| next.0 == offset) {
+            continue;
+        }
+        graph.push_str(&format!("{}|{},", offset, life.clamp(0.0, 1.0)));
+    }
+    graph
+}
+
+/// A problem with the life graph of a replay, as found by `Replay::validate_life_graph`.
+///
+/// Entries are counted from zero, including empty ones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LifeGraphIssue {
+    /// An entry is not an `<offset>|<life>` pair of an integer and a number.
+    MalformedEntry { entry: usize },
+    /// An entry has an earlier offset than the entry before it.
+    NonMonotonicOffset {
+        entry: usize,
+        offset: i64,
+        previous: i64,
+    },
+    /// A life value is not between 0 and 1.
+    LifeOutOfRange { entry: usize, life: f64 },
+    /// Life drops to zero before the last entry, although the play has neither `NoFail` nor
+    /// `Easy`.
+    ContinuesAfterFail { entry: usize },
+    /// The play must have failed, as it has misses with `SuddenDeath` or judgements short of a 300
+    /// with `Perfect`, but the graph ends with life left.
+    MissingFail,
+}
+impl fmt::Display for LifeGraphIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LifeGraphIssue::MalformedEntry { entry } => {
+                write!(f, "life graph entry #{} is malformed", entry)
+            }
+            LifeGraphIssue::NonMonotonicOffset {
+                entry,
+                offset,
+                previous,
+            } => write!(
+                f,
+                "life graph entry #{} at {}ms comes before the previous entry at {}ms",
+                entry, offset, previous
+            ),
+            LifeGraphIssue::LifeOutOfRange { entry, life } => write!(
+                f,
+                "life graph entry #{} has life {}, outside of 0-1",
+                entry, life
+            ),
+            LifeGraphIssue::ContinuesAfterFail { entry } => write!(
+                f,
+                "life graph reaches zero at entry #{} but goes on without NoFail",
+                entry
+            ),
+            LifeGraphIssue::MissingFail => {
+                f.write_str("life graph ends with life left although the play must have failed")
+            }
+        }
+    }
+}
+
+/// Parse a single `<offset>|<life>` life graph entry.
+fn parse_life_entry(entry: &str) -> Option<(i64, f64)> {
+    let (offset, life) = entry.split_once('|')?;
+    Some((offset.trim().parse().ok()?, life.trim().parse().ok()?))
+}
+
+/// The life value of the last entry of a life graph, ignoring empty entries such as the one left
+/// by a trailing comma.
+fn final_life(life_graph: &str) -> Option<f64> {
+    let last = life_graph.split(',').rfind(|e| !e.trim().is_empty())?;
+    last.split_once('|')?.1.trim().parse().ok()
+}
+
+/// The amount of leading header frames in a list of actions.
+///
+/// osu! starts replays with a couple of non-positive delta frames placed offscreen, which do not
+/// represent actual input.
+pub(crate) fn header_len(actions: &[Action]) -> usize {
+    actions
+        .iter()
+        .take_while(|action| action.delta <= 0)
+        .count()
+}
+
+/// A list of replay actions split into the parts editing should not touch (the leading header
+/// frames and the trailing seed frame) and the actual input frames, with absolute times.
+struct Timeline {
+    head: Vec<Action>,
+    start: i64,
+    body: Vec<(i64, Action)>,
+    seed: Option<Action>,
+}
+impl Timeline {
+    fn split(mut actions: Vec<Action>) -> Timeline {
+        let seed = match actions.last() {
+            Some(last) if last.is_seed() => actions.pop(),
+            _ => None,
+        };
+        let body = actions.split_off(header_len(&actions));
+        let head = actions;
+        let start = head.iter().map(|action| action.delta).sum();
+        let mut time = start;
+        let body = body
+            .into_iter()
+            .map(|action| {
+                time += action.delta;
+                (time, action)
+            })
+            .collect();
+        Timeline {
+            head,
+            start,
+            body,
+            seed,
+        }
+    }
+
+    /// Turn the absolute times back into deltas.
+    ///
+    /// Actions are kept in order, and moved forward if they come before the previous action, or
+    /// at or before the end of the header frames, so that they are not mistaken for one.
+    fn join(self) -> Vec<Action> {
+        let mut actions = self.head;
+        actions.reserve(self.body.len() + 1);
+        let mut last = self.start;
+        let mut earliest = self.start + 1;
+        for (time, mut action) in self.body {
+            let time = time.max(earliest);
+            earliest = time;
+            action.delta = time - last;
+            last = time;
+            actions.push(action);
+        }
+        actions.extend(self.seed);
+        actions
+    }
+}
+
+pub(crate) fn replay<'a>(
+    bytes: &'a [u8],
+    standalone: bool,
+    opts: &mut ParseOptions,
+) -> Result<(&'a [u8], Replay), Error> {
     let (rem, mode) = map_opt(byte, Mode::from_raw)(bytes)?;
     let (rem, version) = int(rem)?;
-    let (rem, beatmap_hash) = opt_string(rem)?;
-    let (rem, player_name) = opt_string(rem)?;
-    let (rem, replay_hash) = opt_string(rem)?;
+    let (rem, beatmap_hash) = stored_hash(rem)?;
+    let (rem, player_name) = opt_string_with(rem, opts.string_fallback)?;
+    let (rem, replay_hash) = stored_hash(rem)?;
     let (rem, count_300) = short(rem)?;
     let (rem, count_100) = short(rem)?;
     let (rem, count_50) = short(rem)?;
@@ -116,7 +695,7 @@ pub(crate) fn replay(bytes: &[u8], standalone: bool) -> Result<(&[u8], Replay),
     let (rem, max_combo) = short(rem)?;
     let (rem, perfect_combo) = boolean(rem)?;
     let (rem, mods) = map(int, ModSet::from_bits)(rem)?;
-    let (rem, life_graph) = opt_string(rem)?;
+    let (rem, life_graph) = opt_string_with(rem, opts.string_fallback)?;
     let (rem, timestamp) = datetime(rem)?;
 
     let (rem, raw_replay_data) = if standalone {
@@ -127,8 +706,8 @@ pub(crate) fn replay(bytes: &[u8], standalone: bool) -> Result<(&[u8], Replay),
         (rem, None)
     };
 
-    let replay_data = parse_replay_data(raw_replay_data)?;
-    let (rem, online_score_id) = long(rem)?;
+    let replay_data = parse_replay_data(raw_replay_data, version, opts)?;
+    let (rem, online_score_id) = online_score_id(rem, version)?;
 
     let replay = Replay {
         mode,
@@ -155,7 +734,24 @@ pub(crate) fn replay(bytes: &[u8], standalone: bool) -> Result<(&[u8], Replay),
 
     Ok((rem, replay))
 }
-writer!(Replay [this,out,compress_data: Option<u32>] {
+/// Skip over a replay entry within a `scores.db` file, without decoding its fields.
+pub(crate) fn skip_score(bytes: &[u8]) -> Result<&[u8], Error> {
+    let (rem, _mode) = byte(bytes)?;
+    let (rem, version) = int(rem)?;
+    let (rem, _beatmap_hash) = skip_str(rem)?;
+    let (rem, _player_name) = skip_str(rem)?;
+    let (rem, _replay_hash) = skip_str(rem)?;
+    //Hit counts, score, max combo, perfect combo and mods
+    let (rem, _fixed) = take(6 * 2 + 4 + 2 + 1 + 4usize)(rem)?;
+    let (rem, _life_graph) = skip_str(rem)?;
+    let (rem, _timestamp) = long(rem)?;
+    let (rem, _tag) = tag(&[0xff, 0xff, 0xff, 0xff])(rem)?;
+    let (rem, _online_score_id) = online_score_id(rem, version)?;
+    Ok(rem)
+}
+
+//`None` writes a score entry, without replay data
+writer!(Replay [this,out,compress_data: Option<Option<u32>>] {
     this.mode.raw().wr(out)?;
     this.version.wr(out)?;
     this.beatmap_hash.wr(out)?;
@@ -183,9 +779,23 @@ writer!(Replay [this,out,compress_data: Option<u32>] {
     }else{
         0xffffffff_u32.wr(out)?;
     }
-    this.online_score_id.wr(out)?;
+    if this.version >= CHANGE_20140721 {
+        this.online_score_id.wr(out)?;
+    } else if this.version >= CHANGE_20121008 {
+        (this.online_score_id as u32).wr(out)?;
+    }
 });
 
+fn online_score_id(bytes: &[u8], version: u32) -> IResult<&[u8], u64> {
+    if version >= CHANGE_20140721 {
+        long(bytes)
+    } else if version >= CHANGE_20121008 {
+        map(int, u64::from)(bytes)
+    } else {
+        Ok((bytes, 0))
+    }
+}
+
 /// Represents a single action within a replay.
 /// The meaning of an action depends on the gamemode of the replay, but all actions
 /// contain:
@@ -213,15 +823,29 @@ pub struct Action {
     /// Third bit of payload in the action.
     ///
     /// In standard:
-    /// Represents the bitwise combination of buttons pressed.
+    /// Represents the bitwise combination of buttons pressed, along with whether smoke is being
+    /// drawn.
     pub z: f32,
 }
 impl Action {
+    /// Whether this is the trailing action that holds the replay RNG seed in its `z` field,
+    /// rather than an actual input frame.
+    pub fn is_seed(&self) -> bool {
+        self.delta == SEED_FRAME_DELTA
+    }
+
     /// Get the pressed osu!standard buttons.
+    ///
+    /// The set includes the smoke bit, use `StandardButtonSet::keys` to leave it out.
     pub fn std_buttons(&self) -> StandardButtonSet {
         StandardButtonSet::from_bits(self.z as u32)
     }
 
+    /// Whether smoke is being drawn in this osu!standard action.
+    pub fn std_smoke(&self) -> bool {
+        self.std_buttons().is_down(StandardButton::Smoke)
+    }
+
     /// Get the pressed osu!mania buttons.
     pub fn mania_buttons(&self) -> ManiaButtonSet {
         ManiaButtonSet::from_bits(self.x as u32)
@@ -236,6 +860,8 @@ pub enum StandardButton {
     MouseSecondary,
     KeyPrimary,
     KeySecondary,
+    /// The smoke key, which draws a cursor trail instead of playing.
+    Smoke,
 }
 impl StandardButton {
     pub fn raw(&self) -> u32 {
@@ -243,9 +869,17 @@ impl StandardButton {
     }
 }
 
+/// Any combination of osu!standard buttons being pressed.
+///
+/// Note that osu! also sets the matching mouse button whenever a key is pressed.
+/// The buttons of `StandardButton` are the only bits the replay format is known to use, there is
+/// no known bit for hiding the cursor. Any other bits are kept by `bits` and left out by `keys`.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct StandardButtonSet(pub u32);
 impl StandardButtonSet {
+    /// The bits of the buttons used for playing, as opposed to smoke.
+    const KEY_BITS: u32 = 0b1111;
+
     pub fn bits(self) -> u32 {
         self.0
     }
@@ -260,13 +894,25 @@ impl StandardButtonSet {
 
     /// Check whether the combination lists the button as pressed.
     pub fn is_down(&self, button: StandardButton) -> bool {
-        self.bits().bit(button.raw() as u32)
+        self.bits().bit(button.raw())
+    }
+
+    /// Only the buttons used for playing, leaving out smoke and any unknown bits.
+    pub fn keys(&self) -> StandardButtonSet {
+        StandardButtonSet::from_bits(self.bits() & Self::KEY_BITS)
+    }
+
+    /// Whether any button used for playing is pressed.
+    ///
+    /// Drawing smoke does not count as pressing a button.
+    pub fn any_key_down(&self) -> bool {
+        self.keys().bits() != 0
     }
 
     /// Set the pressed status of the given button.
     pub fn set_down(&self, button: StandardButton, is_down: bool) -> StandardButtonSet {
         let mut bits = self.bits();
-        bits.set_bit(button.raw() as u32, is_down);
+        bits.set_bit(button.raw(), is_down);
         StandardButtonSet::from_bits(bits)
     }
     /// Set the pressed status of a button to `true`.
@@ -319,48 +965,163 @@ impl ManiaButtonSet {
     }
 }
 
-fn parse_replay_data(raw: Option<&[u8]>) -> Result<Option<Vec<Action>>, Error> {
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+fn parse_replay_data(
+    raw: Option<&[u8]>,
+    version: u32,
+    opts: &mut ParseOptions,
+) -> Result<Option<Vec<Action>>, Error> {
     #[cfg(feature = "compression")]
     {
         if let Some(raw) = raw {
-            // use xz2::{stream::Stream, write::XzDecoder};
-            use liblzma::{stream::Stream, write::XzDecoder};
-
-            let mut decoder =
-                XzDecoder::new_stream(Vec::new(), Stream::new_lzma_decoder(u64::MAX)?);
-            decoder.write_all(raw)?;
-            let data = decoder.finish()?;
-            let actions = actions(&data)?.1;
+            let data = decompress(
+                raw,
+                opts.lzma_memory_limit.unwrap_or(DEFAULT_LZMA_MEMORY_LIMIT),
+                opts.replay_data_limit.unwrap_or(DEFAULT_REPLAY_DATA_LIMIT),
+            )?;
+            let actions = if opts.lenient_actions {
+                lenient_actions(&data, version, opts)
+            } else {
+                actions(&data)?.1
+            };
             return Ok(Some(actions));
         }
     }
     Ok(None)
 }
 
+/// Decompress and parse a bare LZMA blob of replay data, such as the replay data of a `.osr` file
+/// or the blobs osu! keeps in `Data/r`.
+///
+/// The default limits of `ParseOptions` apply, see `decompress_actions_with` to change them.
+#[cfg(feature = "compression")]
+pub fn decompress_actions(raw: &[u8]) -> Result<Vec<Action>, Error> {
+    decompress_actions_with(raw, ParseOptions::new())
+}
+
+/// Similar to `decompress_actions`, but with the given parsing options.
+///
+/// Only the options related to replay data have an effect.
+/// When parsing leniently, the missing seed frame fix is never reported, as whether a seed frame
+/// is expected depends on the version of the replay.
+#[cfg(feature = "compression")]
+pub fn decompress_actions_with(raw: &[u8], mut opts: ParseOptions) -> Result<Vec<Action>, Error> {
+    Ok(parse_replay_data(Some(raw), 0, &mut opts)?.unwrap_or_default())
+}
+
+/// Encode and compress actions into a bare LZMA blob of replay data, exactly as it is stored
+/// within `.osr` files.
+///
+/// If the compression level is `None` the arbitrary default
+/// `replay::DEFAULT_COMPRESSION_LEVEL` will be used.
+#[cfg(feature = "compression")]
+pub fn compress_actions(actions: &[Action], compression_level: Option<u32>) -> io::Result<Vec<u8>> {
+    use liblzma::{
+        stream::{LzmaOptions, Stream},
+        write::XzEncoder,
+    };
+    let preset = compression_level.unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+    let mut encoder = XzEncoder::new_stream(
+        Vec::new(),
+        Stream::new_lzma_encoder(&LzmaOptions::new_preset(preset)?)?,
+    );
+    for action in actions {
+        action.wr(&mut encoder)?;
+    }
+    encoder.finish()
+}
+
+/// Decompress replay data, within the given limits on decoder memory and output size.
+#[cfg(feature = "compression")]
+fn decompress(raw: &[u8], memory_limit: u64, size_limit: usize) -> Result<Vec<u8>, Error> {
+    // use xz2::{stream::Stream, write::XzDecoder};
+    use liblzma::{stream::Stream, write::XzDecoder};
+
+    /// A buffer that refuses to grow past a limit.
+    struct Limited {
+        data: Vec<u8>,
+        limit: usize,
+        exceeded: bool,
+    }
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.limit - self.data.len() {
+                self.exceeded = true;
+                return Err(io::Error::other("decompressed replay data too large"));
+            }
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let out = Limited {
+        data: Vec::new(),
+        limit: size_limit,
+        exceeded: false,
+    };
+    let mut decoder = XzDecoder::new_stream(out, Stream::new_lzma_decoder(memory_limit)?);
+    let result = decoder.write_all(raw).and_then(|()| decoder.try_finish());
+    let out = decoder.get_ref();
+    match result {
+        Err(_) if out.exceeded => Err(Error::ReplayDataTooLarge { limit: size_limit }),
+        //The decoder reports its own errors, such as hitting the memory limit, wrapped in io errors
+        Err(err) => {
+            let lzma = err
+                .get_ref()
+                .and_then(|err| err.downcast_ref::<LzmaError>());
+            match lzma {
+                Some(lzma) => Err(Error::Compression(*lzma)),
+                None => Err(err.into()),
+            }
+        }
+        Ok(()) => Ok(decoder.finish()?.data),
+    }
+}
+
+/// Check whether the given raw replay data decodes to exactly the given actions.
+///
+/// The textual floats in the raw data may be written differently from how they would be written
+/// back (eg. `1.50` or `1E-05`), so if the actions were not modified the raw data should be
+/// written instead of re-encoding them.
+#[cfg(feature = "compression")]
+fn encodes_actions(raw: &[u8], actions: &[Action]) -> bool {
+    let same = |a: &Action, b: &Action| {
+        a.delta == b.delta
+            && a.x.to_bits() == b.x.to_bits()
+            && a.y.to_bits() == b.y.to_bits()
+            && a.z.to_bits() == b.z.to_bits()
+    };
+    match decompress(raw, DEFAULT_LZMA_MEMORY_LIMIT, DEFAULT_REPLAY_DATA_LIMIT) {
+        Ok(data) => match self::actions(&data) {
+            Ok((_rem, decoded)) => {
+                decoded.len() == actions.len()
+                    && decoded.iter().zip(actions).all(|(a, b)| same(a, b))
+            }
+            Err(_) => false,
+        },
+        Err(_) => false,
+    }
+}
+
+#[cfg_attr(not(feature = "compression"), allow(unused_variables, unused_mut))]
 fn write_replay_data<W: Write>(
     actions: Option<&[Action]>,
     raw: Option<&[u8]>,
     out: &mut W,
-    compression_level: u32,
+    compression_level: Option<u32>,
 ) -> io::Result<()> {
-    let mut raw = raw.as_deref();
+    let mut raw = raw;
     let compress_buf: Vec<u8>;
-    //Compress if it's enabled and available
+    //Compress if it's enabled and available, and either requested or needed
     #[cfg(feature = "compression")]
     {
-        if let Some(actions) = actions {
-            use liblzma::{
-                stream::{LzmaOptions, Stream},
-                write::XzEncoder,
-            };
-            let mut encoder = XzEncoder::new_stream(
-                Vec::new(),
-                Stream::new_lzma_encoder(&LzmaOptions::new_preset(compression_level)?)?,
-            );
-            for action in actions.iter() {
-                action.wr(&mut encoder)?;
-            }
-            compress_buf = encoder.finish()?;
+        let keep_raw = compression_level.is_none()
+            && matches!((actions, raw), (Some(actions), Some(raw)) if encodes_actions(raw, actions));
+        if let (Some(actions), false) = (actions, keep_raw) {
+            compress_buf = compress_actions(actions, compression_level)?;
             raw = Some(&compress_buf[..]);
         }
     }
@@ -372,10 +1133,12 @@ fn write_replay_data<W: Write>(
 }
 
 // Parse the plaintext list of actions.
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
 fn actions(bytes: &[u8]) -> IResult<&[u8], Vec<Action>> {
     many0(action)(bytes)
 }
 
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
 fn action(bytes: &[u8]) -> IResult<&[u8], Action> {
     let (rem, delta) = number(bytes)?;
     let (rem, _tag) = tag(b"|")(rem)?;
@@ -401,6 +1164,7 @@ writer!(Action [this,out] {
 });
 
 // Parse a textually encoded decimal number.
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
 fn number(bytes: &[u8]) -> IResult<&[u8], f64> {
     let (rem, sign) = opt(tag(b"-"))(bytes)?;
     let (rem, whole) = take_while1(|b: u8| b.is_ascii_digit())(rem)?;
@@ -429,8 +1193,530 @@ fn number(bytes: &[u8]) -> IResult<&[u8], f64> {
     Ok((rem, num))
 }
 
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
 fn number_bytes(bytes: &[u8]) -> IResult<&[u8], &[u8]> {
     let (rem, _tag) = tag(b".")(bytes)?;
 
     take_while(|b: u8| b.is_ascii_digit())(rem)
 }
+
+/// A fix applied to a malformed replay action stream while parsing it leniently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionFix {
+    /// Some actions contained numbers in a notation osu! does not write, such as scientific
+    /// notation (`1E-05`), and were parsed anyway.
+    NonstandardNumbers { count: usize },
+    /// Some actions could not be parsed at all and were skipped.
+    SkippedActions { count: usize },
+    /// There was unparseable data after the last action, which was ignored.
+    TrailingGarbage { len: usize },
+    /// The replay version stores an RNG seed frame, but the actions have none.
+    /// No seed frame is made up.
+    MissingSeedFrame,
+}
+impl fmt::Display for ActionFix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActionFix::NonstandardNumbers { count } => {
+                write!(f, "{} actions with nonstandard numbers", count)
+            }
+            ActionFix::SkippedActions { count } => write!(f, "skipped {} invalid actions", count),
+            ActionFix::TrailingGarbage { len } => {
+                write!(f, "ignored {} bytes of trailing garbage", len)
+            }
+            ActionFix::MissingSeedFrame => write!(f, "missing seed frame"),
+        }
+    }
+}
+
+// Parse the plaintext list of actions, recovering from malformed actions instead of stopping at
+// the first one.
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
+fn lenient_actions(bytes: &[u8], version: u32, opts: &mut ParseOptions) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let mut nonstandard = 0;
+    let mut skipped = 0;
+    let mut segments = bytes.split(|&b| b == b',').peekable();
+    while let Some(segment) = segments.next() {
+        let is_last = segments.peek().is_none();
+        match lenient_action(segment) {
+            Some((action, standard)) => {
+                if !standard {
+                    nonstandard += 1;
+                }
+                actions.push(action);
+            }
+            None if segment.iter().all(u8::is_ascii_whitespace) => {}
+            None if is_last => opts.warn(Warning::MalformedActions(ActionFix::TrailingGarbage {
+                len: segment.len(),
+            })),
+            None => skipped += 1,
+        }
+    }
+    if nonstandard > 0 {
+        opts.warn(Warning::MalformedActions(ActionFix::NonstandardNumbers {
+            count: nonstandard,
+        }));
+    }
+    if skipped > 0 {
+        opts.warn(Warning::MalformedActions(ActionFix::SkippedActions {
+            count: skipped,
+        }));
+    }
+    if version >= CHANGE_20130319 && !actions.last().is_some_and(Action::is_seed) {
+        opts.warn(Warning::MalformedActions(ActionFix::MissingSeedFrame));
+    }
+    actions
+}
+
+// Parse a single action without its trailing comma, accepting any floating point notation.
+// Also returns whether all of its numbers were in the notation osu! writes.
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
+fn lenient_action(segment: &[u8]) -> Option<(Action, bool)> {
+    let text = core::str::from_utf8(segment).ok()?;
+    let mut fields = text.split('|').map(str::trim);
+    let mut standard = true;
+    let mut next = || {
+        let field = fields.next()?;
+        standard &= matches!(number(field.as_bytes()), Ok((rem, _)) if rem.is_empty());
+        field.parse::<f64>().ok().filter(|num| num.is_finite())
+    };
+    let (delta, x, y, z) = (next()?, next()?, next()?, next()?);
+    if fields.next().is_some() {
+        return None;
+    }
+    let action = Action {
+        delta: delta as i64,
+        x: x as f32,
+        y: y as f32,
+        z: z as f32,
+    };
+    Some((action, standard))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub(crate) fn action(delta: i64, x: f32) -> Action {
+        Action {
+            delta,
+            x,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    pub(crate) fn replay_with(actions: Vec<Action>) -> Replay {
+        Replay {
+            mode: Mode::Standard,
+            version: 20211103,
+            beatmap_hash: StoredHash::Missing,
+            player_name: None,
+            replay_hash: StoredHash::Missing,
+            count_300: 0,
+            count_100: 0,
+            count_50: 0,
+            count_geki: 0,
+            count_katsu: 0,
+            count_miss: 0,
+            score: 0,
+            max_combo: 0,
+            perfect_combo: false,
+            mods: ModSet::empty(),
+            life_graph: None,
+            timestamp: OsuTimestamp::from_unix_millis(1_577_836_800_000),
+            replay_data: Some(actions),
+            raw_replay_data: Some(vec![1, 2, 3]),
+            online_score_id: 0,
+        }
+    }
+
+    fn deltas(replay: &Replay) -> Vec<i64> {
+        replay
+            .replay_data
+            .iter()
+            .flatten()
+            .map(|action| action.delta)
+            .collect()
+    }
+
+    fn base() -> Replay {
+        replay_with(vec![
+            action(0, 256.0),
+            action(-1, 256.0),
+            action(10, 1.0),
+            action(10, 2.0),
+            action(10, 3.0),
+            action(SEED_FRAME_DELTA, 0.0),
+        ])
+    }
+
+    #[test]
+    fn legacy_versions() {
+        for &(version, id) in &[(20110101, 0), (20130101, 0xdead_beef), (20211103, 1 << 40)] {
+            let mut replay = base();
+            replay.version = version;
+            replay.online_score_id = id;
+            let mut raw = Vec::new();
+            replay.to_writer(&mut raw, None).unwrap();
+            let mut parsed = Replay::from_bytes(&raw).unwrap();
+            if cfg!(feature = "compression") {
+                assert_eq!(parsed.replay_data, replay.replay_data);
+            }
+            parsed.replay_data = replay.replay_data.clone();
+            parsed.raw_replay_data = replay.raw_replay_data.clone();
+            assert_eq!(parsed, replay);
+        }
+    }
+
+    #[test]
+    fn score_entry() {
+        let mut replay = base();
+        replay.score = 123456;
+        replay.online_score_id = 42;
+        let entry = replay.clone().into_score_entry();
+        assert_eq!(entry.replay_data, None);
+        assert_eq!(entry.raw_replay_data, None);
+        assert_eq!(
+            entry,
+            Replay {
+                replay_data: None,
+                raw_replay_data: None,
+                ..replay.clone()
+            }
+        );
+
+        //Score entries are written without replay data, and read back as-is
+        let mut raw = Vec::new();
+        entry.wr_args(&mut raw, None).unwrap();
+        let (rem, parsed) = super::replay(&raw, false, &mut ParseOptions::new()).unwrap();
+        assert!(rem.is_empty());
+        assert_eq!(parsed, entry);
+
+        let actions = replay.replay_data.clone().unwrap();
+        let restored = entry.with_replay_data(actions.clone());
+        assert_eq!(restored.replay_data.as_ref(), Some(&actions));
+        assert_eq!(restored.raw_replay_data, None);
+        let mut raw = Vec::new();
+        restored.to_writer(&mut raw, None).unwrap();
+        let mut parsed = Replay::from_bytes(&raw).unwrap();
+        if cfg!(feature = "compression") {
+            assert_eq!(parsed.replay_data, Some(actions));
+        }
+        parsed.replay_data = replay.replay_data.clone();
+        parsed.raw_replay_data = replay.raw_replay_data.clone();
+        assert_eq!(parsed, replay);
+    }
+
+    #[test]
+    fn timed_actions() {
+        let replay = base();
+        let times: Vec<i64> = replay.timed_actions().map(|(t, _)| t).collect();
+        assert_eq!(times, vec![9, 19, 29]);
+    }
+
+    #[test]
+    fn edit_timeline() {
+        let mut replay = base();
+        replay.replay_hash = crate::ids::test::hash(1).into();
+        replay.insert_frames(vec![(14, action(0, 9.0))]);
+        assert_eq!(deltas(&replay), vec![0, -1, 10, 5, 5, 10, SEED_FRAME_DELTA]);
+        assert_eq!(replay.raw_replay_data, None);
+        assert_eq!(replay.replay_hash, StoredHash::Missing);
+
+        assert_eq!(replay.remove_range(10..20), 2);
+        assert_eq!(deltas(&replay), vec![0, -1, 10, 20, SEED_FRAME_DELTA]);
+
+        replay.shift_time(5);
+        assert_eq!(deltas(&replay), vec![0, -1, 15, 20, SEED_FRAME_DELTA]);
+
+        replay.scale_rate(2.0);
+        assert_eq!(deltas(&replay), vec![0, -1, 29, 40, SEED_FRAME_DELTA]);
+
+        //Deltas never go negative, and input frames never end up looking like header frames
+        replay.shift_time(-50);
+        assert_eq!(deltas(&replay), vec![0, -1, 1, 18, SEED_FRAME_DELTA]);
+        let mut replay = base();
+        replay.insert_frames(vec![(-50, action(0, 9.0))]);
+        assert_eq!(deltas(&replay), vec![0, -1, 1, 9, 10, 10, SEED_FRAME_DELTA]);
+        assert_eq!(replay.timed_actions().count(), 4);
+    }
+
+    #[test]
+    fn convert_rate() {
+        let dt = ModSet::empty().with(Mod::DoubleTime);
+        let ht = ModSet::empty().with(Mod::HalfTime);
+        let times = |replay: &Replay| -> Vec<i64> {
+            replay.timed_actions().map(|(time, _)| time).collect()
+        };
+        let mut replay = base();
+        replay.mods = dt.with(Mod::Hidden);
+        replay.life_graph = Some("100|1,2000|0.5,".to_string());
+        assert_eq!(times(&replay), vec![9, 19, 29]);
+
+        replay.convert_rate(dt, ModSet::empty());
+        assert_eq!(replay.mods, ModSet::empty().with(Mod::Hidden));
+        assert_eq!(times(&replay), vec![14, 29, 44]);
+        assert_eq!(deltas(&replay), vec![0, -1, 15, 15, 15, SEED_FRAME_DELTA]);
+        assert_eq!(replay.life_graph.as_deref(), Some("150|1,3000|0.5,"));
+
+        replay.convert_rate(ModSet::empty(), ht);
+        assert_eq!(replay.mods, ht.with(Mod::Hidden));
+        assert_eq!(times(&replay), vec![19, 39, 59]);
+        assert_eq!(replay.life_graph.as_deref(), Some("200|1,4000|0.5,"));
+
+        //Sets with the same clock rate leave the timing alone
+        replay.convert_rate(ht, ht.with(Mod::Hidden));
+        assert_eq!(times(&replay), vec![19, 39, 59]);
+        assert_eq!(replay.life_graph.as_deref(), Some("200|1,4000|0.5,"));
+    }
+
+    #[test]
+    fn life_graph() {
+        let mut replay = replay_with(Vec::new());
+        replay.life_graph = Some("0|1,500|0.75,1000|0.5,".to_string());
+        assert_eq!(replay.validate_life_graph(), Vec::new());
+
+        replay.life_graph = Some("0|1,x|1,500|1.5,400|0,900|0.5,".to_string());
+        assert_eq!(
+            replay.validate_life_graph(),
+            vec![
+                LifeGraphIssue::MalformedEntry { entry: 1 },
+                LifeGraphIssue::LifeOutOfRange {
+                    entry: 2,
+                    life: 1.5
+                },
+                LifeGraphIssue::NonMonotonicOffset {
+                    entry: 3,
+                    offset: 400,
+                    previous: 500
+                },
+                LifeGraphIssue::ContinuesAfterFail { entry: 3 },
+            ]
+        );
+        replay.mods = ModSet::empty().with(Mod::NoFail);
+        assert_eq!(replay.validate_life_graph().len(), 3);
+        replay.mods = ModSet::empty().with(Mod::Easy);
+        assert_eq!(replay.validate_life_graph().len(), 3);
+
+        replay.mods = ModSet::empty().with(Mod::Perfect);
+        replay.count_100 = 1;
+        replay.life_graph = Some(synthesize_life_graph(vec![(0, 1.0), (500, 0.9)]));
+        assert_eq!(
+            replay.validate_life_graph(),
+            vec![LifeGraphIssue::MissingFail]
+        );
+        replay.mode = Mode::CatchTheBeat;
+        assert_eq!(replay.validate_life_graph(), Vec::new());
+        replay.mode = Mode::Standard;
+        replay.count_100 = 0;
+
+        replay.mods = ModSet::empty().with(Mod::SuddenDeath);
+        replay.count_miss = 1;
+        replay.life_graph = Some(synthesize_life_graph(vec![(500, 0.9), (0, 1.0)]));
+        assert_eq!(
+            replay.validate_life_graph(),
+            vec![LifeGraphIssue::MissingFail]
+        );
+        replay.life_graph = Some(synthesize_life_graph(vec![
+            (800, -0.2),
+            (0, 1.0),
+            (500, 0.3),
+            (500, f64::NAN),
+            (500, 0.9),
+        ]));
+        assert_eq!(replay.life_graph.as_deref(), Some("0|1,500|0.9,800|0,"));
+        assert_eq!(replay.validate_life_graph(), Vec::new());
+        assert!(replay.is_failed());
+        assert_eq!(synthesize_life_graph(Vec::new()), "");
+    }
+
+    #[test]
+    fn accuracy() {
+        let mut replay = replay_with(Vec::new());
+        assert_eq!(replay.accuracy(), 1.0);
+        replay.count_300 = 2;
+        replay.count_100 = 1;
+        replay.count_miss = 1;
+        assert_eq!(replay.accuracy(), 700.0 / 1200.0);
+        replay.mode = Mode::Taiko;
+        assert_eq!(replay.accuracy(), 2.5 / 4.0);
+        replay.mode = Mode::Mania;
+        replay.count_geki = 1;
+        replay.count_katsu = 1;
+        assert_eq!(replay.accuracy(), 1200.0 / 1800.0);
+    }
+
+    #[test]
+    fn grade() {
+        let mut replay = replay_with(Vec::new());
+        replay.count_300 = 95;
+        replay.count_100 = 5;
+        assert_eq!(replay.grade(), Grade::S);
+        replay.mods = ModSet::empty().with(Mod::Hidden);
+        assert_eq!(replay.grade(), Grade::SPlus);
+        replay.count_miss = 1;
+        assert_eq!(replay.grade(), Grade::A);
+        replay.count_300 = 0;
+        assert_eq!(replay.grade(), Grade::D);
+        replay.mode = Mode::Mania;
+        replay.count_geki = 100;
+        replay.count_100 = 0;
+        replay.count_miss = 0;
+        assert_eq!(replay.grade(), Grade::SSPlus);
+    }
+
+    #[test]
+    fn lenient_actions() {
+        let data =
+            b"0|256|-500|0,-1|256|-500|0,16|1.5E+02|2e1|1,bad,8|1|2|3,-12345|0|0|7,\x01garbage";
+        assert_eq!(actions(data).unwrap().1.len(), 2);
+
+        let mut warnings = Vec::new();
+        let mut opts = ParseOptions::new().warnings(&mut warnings);
+        let fixed = super::lenient_actions(data, 20211103, &mut opts);
+        assert_eq!(fixed.len(), 5);
+        assert_eq!(
+            fixed[2],
+            Action {
+                delta: 16,
+                x: 150.0,
+                y: 20.0,
+                z: 1.0
+            }
+        );
+        assert!(fixed[4].is_seed());
+        assert_eq!(
+            warnings,
+            vec![
+                Warning::MalformedActions(ActionFix::TrailingGarbage { len: 8 }),
+                Warning::MalformedActions(ActionFix::NonstandardNumbers { count: 1 }),
+                Warning::MalformedActions(ActionFix::SkippedActions { count: 1 }),
+            ]
+        );
+
+        let mut warnings = Vec::new();
+        let mut opts = ParseOptions::new().warnings(&mut warnings);
+        let fixed = super::lenient_actions(b"0|1|2|3,5|1|2|3", 20211103, &mut opts);
+        assert_eq!(fixed.len(), 2);
+        assert_eq!(
+            warnings,
+            vec![Warning::MalformedActions(ActionFix::MissingSeedFrame)]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn lossless_actions() {
+        let text = b"0|256|-500|0,-1|256|-500|0,16|1.50|-0|1,8|0.10|2|0,-12345|0|0|7,";
+        let mut replay = replay_with(Vec::new());
+        let mut encoder = liblzma::write::XzEncoder::new_stream(
+            Vec::new(),
+            liblzma::stream::Stream::new_lzma_encoder(
+                &liblzma::stream::LzmaOptions::new_preset(1).unwrap(),
+            )
+            .unwrap(),
+        );
+        encoder.write_all(text).unwrap();
+        let raw = encoder.finish().unwrap();
+        replay.raw_replay_data = Some(raw.clone());
+        replay.replay_data =
+            parse_replay_data(Some(&raw), replay.version, &mut ParseOptions::new()).unwrap();
+
+        let mut out = Vec::new();
+        replay.to_writer(&mut out, None).unwrap();
+        let reparsed = Replay::from_bytes(&out).unwrap();
+        assert_eq!(reparsed.raw_replay_data, Some(raw.clone()));
+
+        //An explicit compression level is honored even if the actions were not modified
+        let mut out = Vec::new();
+        replay.to_writer(&mut out, Some(1)).unwrap();
+        let reparsed = Replay::from_bytes(&out).unwrap();
+        assert_eq!(
+            reparsed.raw_replay_data,
+            Some(super::compress_actions(replay.replay_data.as_deref().unwrap(), Some(1)).unwrap())
+        );
+        assert_eq!(reparsed.replay_data, replay.replay_data);
+
+        replay.replay_data.as_mut().unwrap()[3].x = 2.0;
+        let mut out = Vec::new();
+        replay.to_writer(&mut out, None).unwrap();
+        let reparsed = Replay::from_bytes(&out).unwrap();
+        assert_ne!(reparsed.raw_replay_data, Some(raw));
+        assert_eq!(reparsed.replay_data, replay.replay_data);
+    }
+
+    #[test]
+    fn std_buttons() {
+        let smoke = Action {
+            z: 16.0,
+            ..action(10, 0.0)
+        };
+        assert!(smoke.std_smoke());
+        assert!(!smoke.std_buttons().any_key_down());
+        assert!(!smoke.std_buttons().is_down(StandardButton::MousePrimary));
+
+        let buttons = StandardButtonSet::none()
+            .press(StandardButton::KeyPrimary)
+            .press(StandardButton::MousePrimary)
+            .press(StandardButton::Smoke);
+        assert_eq!(buttons.bits(), 21);
+        assert_eq!(buttons.keys().bits(), 5);
+        assert!(buttons.any_key_down());
+        assert_eq!(buttons.release(StandardButton::Smoke), buttons.keys());
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compress_actions() {
+        let actions = vec![action(0, 256.0), action(-1, 256.0), action(16, 1.5)];
+        for &level in &[None, Some(1)] {
+            let raw = super::compress_actions(&actions, level).unwrap();
+            assert_eq!(decompress_actions(&raw).unwrap(), actions);
+        }
+        let raw = super::compress_actions(&actions, None).unwrap();
+        assert!(matches!(
+            decompress_actions_with(&raw, ParseOptions::new().replay_data_limit(10)),
+            Err(Error::ReplayDataTooLarge { limit: 10 })
+        ));
+        assert!(decompress_actions(b"not lzma").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn decompression_limits() {
+        let mut encoder = liblzma::write::XzEncoder::new_stream(
+            Vec::new(),
+            liblzma::stream::Stream::new_lzma_encoder(
+                &liblzma::stream::LzmaOptions::new_preset(1).unwrap(),
+            )
+            .unwrap(),
+        );
+        encoder.write_all(&[b'0'; 100_000]).unwrap();
+        let raw = encoder.finish().unwrap();
+
+        assert_eq!(
+            super::decompress(&raw, DEFAULT_LZMA_MEMORY_LIMIT, 100_000).unwrap(),
+            vec![b'0'; 100_000]
+        );
+        assert!(matches!(
+            super::decompress(&raw, DEFAULT_LZMA_MEMORY_LIMIT, 1000),
+            Err(Error::ReplayDataTooLarge { limit: 1000 })
+        ));
+        assert!(matches!(
+            super::decompress(&raw, 1024, DEFAULT_REPLAY_DATA_LIMIT),
+            Err(Error::Compression(LzmaError::MemLimit))
+        ));
+
+        let mut replay = replay_with(Vec::new());
+        replay.raw_replay_data = Some(raw);
+        let mut out = Vec::new();
+        replay.to_writer(&mut out, None).unwrap();
+        let opts = ParseOptions::new().replay_data_limit(1000);
+        assert!(matches!(
+            Replay::from_bytes_with(&out, opts),
+            Err(Error::ReplayDataTooLarge { limit: 1000 })
+        ));
+    }
+}
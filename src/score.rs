@@ -1,9 +1,32 @@
 //! Parsing for the `scores.db` osu file, which contains partial replay data locally.
 
 use crate::{
+    ids::HashIndex,
     prelude::*,
-    replay::{replay, Replay},
+    replay::{replay, skip_score, Replay},
+    Listing, Mod,
 };
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::cmp::Ordering;
+#[cfg(feature = "std")]
+use std::io::{Seek, SeekFrom};
+
+/// The newest `scores.db` version whose layout is known.
+///
+/// Newer versions are parsed assuming they share this layout.
+/// If parsing them fails or skips scores, a `Warning::UnknownVersion` is reported.
+pub const LATEST_KNOWN_VERSION: u32 = 20211103;
+
+/// A single score within a `scores.db` database.
+///
+/// Scores share their format with standalone `.osr` replays, except for the replay data, which is
+/// never present.
+/// Use `Replay::into_score_entry` and `Score::with_replay_data` to convert between the two.
+pub type Score = Replay;
+
+/// The byte range of each score within a raw score database, grouped like the scores
+/// themselves, so that the range of `beatmaps[i].scores[j]` is at `[i][j]`.
+pub type ScoreSpans = Vec<Vec<ops::Range<usize>>>;
 
 /// A score database, usually coming from a `scores.db` file.
 #[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
@@ -15,23 +38,349 @@ pub struct ScoreList {
 impl ScoreList {
     /// Read a score database from its raw bytes.
     pub fn from_bytes(bytes: &[u8]) -> Result<ScoreList, Error> {
-        scores(bytes).map(|(_rem, scores)| scores)
+        Self::from_bytes_with(bytes, ParseOptions::new())
+    }
+
+    /// Read a score database from its raw bytes, with the given parsing options.
+    ///
+    /// Progress, if requested, is reported after the scores of each beatmap are parsed.
+    pub fn from_bytes_with(bytes: &[u8], mut opts: ParseOptions) -> Result<ScoreList, Error> {
+        scores(bytes, &mut opts).map(|(_rem, scores)| scores)
+    }
+
+    /// Read a score database from its raw bytes, along with the byte range of each score within
+    /// them.
+    ///
+    /// See `ScoreSpans` for how the ranges are laid out.
+    pub fn from_bytes_with_spans(bytes: &[u8]) -> Result<(ScoreList, ScoreSpans), Error> {
+        let mut spans = Vec::new();
+        let list = Self::from_bytes_with(bytes, ParseOptions::new().spans(&mut spans))?;
+        let mut spans = spans.into_iter();
+        let grouped = list
+            .beatmaps
+            .iter()
+            .map(|bm| spans.by_ref().take(bm.scores.len()).collect())
+            .collect();
+        Ok((list, grouped))
     }
 
     /// Read a score database from a `scores.db` file.
+    #[cfg(feature = "std")]
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ScoreList, Error> {
         Self::from_bytes(&fs::read(path)?)
     }
 
+    /// Read only the scores of the beatmap with the given MD5 hash out of a raw score database.
+    ///
+    /// The scores of all other beatmaps are skipped over without decoding them, which is much
+    /// faster than parsing the whole database when only a handful of scores are needed.
+    /// Returns an empty list if there are no scores for the beatmap.
+    pub fn scores_for_hash_from_bytes(bytes: &[u8], hash: Md5Hash) -> Result<Vec<Score>, Error> {
+        let (rem, _version) = int(bytes)?;
+        let (mut rem, len) = int(rem)?;
+        let mut found = Vec::new();
+        for _ in 0..len {
+            let (rem_, beatmap_hash) = stored_hash(rem)?;
+            let (rem_, count) = int(rem_)?;
+            rem = rem_;
+            if beatmap_hash.md5() == Some(hash) {
+                for _ in 0..count {
+                    let (rem_, score) = replay(rem, false, &mut ParseOptions::new())?;
+                    rem = rem_;
+                    found.push(score);
+                }
+            } else {
+                for _ in 0..count {
+                    rem = skip_score(rem)?;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Similar to `scores_for_hash_from_bytes`, but reads the scores from a `scores.db` file.
+    #[cfg(feature = "std")]
+    pub fn scores_for_hash_from_file<P: AsRef<Path>>(
+        path: P,
+        hash: Md5Hash,
+    ) -> Result<Vec<Score>, Error> {
+        Self::scores_for_hash_from_bytes(&fs::read(path)?, hash)
+    }
+
     /// Write the score database to an arbitrary writer.
     pub fn to_writer<W: Write>(&self, mut out: W) -> io::Result<()> {
         self.wr(&mut out)
     }
 
     /// Similar to `to_writer` but writes the scores to a file.
+    #[cfg(feature = "std")]
     pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
         self.to_writer(BufWriter::new(File::create(path)?))
     }
+
+    /// Build an index from beatmap hashes to their position within `beatmaps`.
+    pub fn hash_index(&self) -> HashIndex {
+        HashIndex::new(self.beatmaps.iter().map(|bm| bm.hash.md5()))
+    }
+
+    /// Change the version of the score database and all of its scores, adjusting any
+    /// version-dependent data so that it can be written in the layout of the target version.
+    pub fn migrate_to(&mut self, version: u32) {
+        self.version = version;
+        for score in self.beatmaps.iter_mut().flat_map(|bm| bm.scores.iter_mut()) {
+            score.migrate_to(version);
+        }
+    }
+
+    /// Add scores to the database, grouping them with the other scores of their beatmap.
+    ///
+    /// Each score is migrated to the version of the database and turned into a score entry.
+    /// Scores that are already present are skipped: scores with a replay hash are compared by
+    /// replay hash, and scores without one are compared by beatmap, player, score and timestamp.
+    /// Returns the amount of scores added.
+    pub fn append_scores<I>(&mut self, scores: I) -> usize
+    where
+        I: IntoIterator<Item = Score>,
+    {
+        let mut positions = BTreeMap::new();
+        for (idx, bm) in self.beatmaps.iter().enumerate().rev() {
+            positions.insert(bm.hash.clone(), idx);
+        }
+        let mut added = 0;
+        for score in scores {
+            let mut score = score.into_score_entry();
+            score.migrate_to(self.version);
+            let beatmaps = &mut self.beatmaps;
+            let idx = *positions
+                .entry(score.beatmap_hash.clone())
+                .or_insert_with(|| {
+                    beatmaps.push(BeatmapScores {
+                        hash: score.beatmap_hash.clone(),
+                        scores: Vec::new(),
+                    });
+                    beatmaps.len() - 1
+                });
+            let existing = &mut self.beatmaps[idx].scores;
+            let duplicate = existing.iter().any(|other| match score.replay_hash {
+                StoredHash::Missing => {
+                    other.replay_hash.is_missing()
+                        && other.player_name == score.player_name
+                        && other.score == score.score
+                        && other.timestamp == score.timestamp
+                }
+                _ => other.replay_hash == score.replay_hash,
+            });
+            if !duplicate {
+                existing.push(score);
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Keep only the scores for which the filter returns `true`, removing the rest.
+    ///
+    /// Beatmaps left without any scores are removed as well, so that the database stays
+    /// consistent when written.
+    /// Returns the amount of scores removed.
+    pub fn retain<F>(&mut self, mut filter: F) -> usize
+    where
+        F: FnMut(&Score) -> bool,
+    {
+        let mut removed = 0;
+        for bm in &mut self.beatmaps {
+            let before = bm.scores.len();
+            bm.scores.retain(|score| filter(score));
+            removed += before - bm.scores.len();
+        }
+        self.beatmaps.retain(|bm| !bm.scores.is_empty());
+        removed
+    }
+
+    /// Remove every failed score, as told by `Replay::is_failed`.
+    ///
+    /// This only works for scores that carry a life graph, such as the ones built out of `.osr`
+    /// replays with `Replay::into_score_entry`.
+    /// osu! writes `scores.db` entries without a life graph (and does not store failed plays in
+    /// it to begin with), so nothing is removed from a database as osu! wrote it.
+    ///
+    /// Returns the amount of scores removed.
+    pub fn remove_failed(&mut self) -> usize {
+        self.retain(|score| !score.is_failed())
+    }
+
+    /// Remove every score set before the given date.
+    ///
+    /// Returns the amount of scores removed.
+    pub fn remove_older_than(&mut self, date: OsuTimestamp) -> usize {
+        self.retain(|score| score.timestamp >= date)
+    }
+
+    /// Keep only the best `n` scores of each beatmap in each gamemode, as ranked by
+    /// `RankBy::Score`, which are the scores shown in the local leaderboard.
+    ///
+    /// Scores of the same beatmap are compared together even if they are split across several
+    /// entries of the database.
+    /// The kept scores stay in their original order.
+    /// Returns the amount of scores removed.
+    pub fn keep_top_n_per_map(&mut self, n: usize) -> usize {
+        let mut leaderboards = BTreeMap::new();
+        for (bm_idx, bm) in self.beatmaps.iter().enumerate() {
+            for (idx, score) in bm.scores.iter().enumerate() {
+                leaderboards
+                    .entry((bm.hash.clone(), score.mode.raw()))
+                    .or_insert_with(Vec::new)
+                    .push((bm_idx, idx));
+            }
+        }
+        let mut keep = BTreeSet::new();
+        for mut positions in leaderboards.into_values() {
+            positions.sort_by(|&(a, i), &(b, j)| {
+                RankBy::Score.compare(&self.beatmaps[a].scores[i], &self.beatmaps[b].scores[j])
+            });
+            keep.extend(positions.into_iter().take(n));
+        }
+        let mut removed = 0;
+        for (bm_idx, bm) in self.beatmaps.iter_mut().enumerate() {
+            let before = bm.scores.len();
+            let mut idx = 0;
+            bm.scores.retain(|_| {
+                idx += 1;
+                keep.contains(&(bm_idx, idx - 1))
+            });
+            removed += before - bm.scores.len();
+        }
+        self.beatmaps.retain(|bm| !bm.scores.is_empty());
+        removed
+    }
+
+    /// Build the local leaderboard of the beatmap with the given hash, ranking its scores by the
+    /// given criteria.
+    ///
+    /// Ties are broken the way osu! does: by score, and then by the earliest timestamp.
+    /// ScoreV2 scores are on a different scale than regular scores, so when ranking by score
+    /// they are ranked after all regular scores.
+    pub fn leaderboard(&self, hash: Md5Hash, ranking: RankBy) -> Vec<LeaderboardEntry<'_>> {
+        let mut scores: Vec<&Score> = self
+            .beatmaps
+            .iter()
+            .filter(|bm| bm.hash.md5() == Some(hash))
+            .flat_map(|bm| &bm.scores)
+            .collect();
+        scores.sort_by(|a, b| ranking.compare(a, b));
+        scores
+            .into_iter()
+            .enumerate()
+            .map(|(idx, score)| LeaderboardEntry {
+                rank: idx + 1,
+                score,
+            })
+            .collect()
+    }
+}
+
+impl Listing {
+    /// Recompute the per-mode grades of every beatmap from the local scores in a score
+    /// database, such as after a reinstall that lost the listing but kept `scores.db`.
+    ///
+    /// Like the song select screen, the grade of each mode is the grade of the top score of the
+    /// local leaderboard in that mode, as ranked by `RankBy::Score`, derived with
+    /// `Replay::grade`.
+    /// Grades of modes without any scores are left untouched.
+    /// Returns the amount of grades that changed.
+    pub fn update_grades_from_scores(&mut self, scores: &ScoreList) -> usize {
+        let mut best: BTreeMap<(Md5Hash, u8), &Score> = BTreeMap::new();
+        for bm in &scores.beatmaps {
+            let hash = match bm.hash.md5() {
+                Some(hash) => hash,
+                None => continue,
+            };
+            for score in &bm.scores {
+                best.entry((hash, score.mode.raw()))
+                    .and_modify(|best| {
+                        if RankBy::Score.compare(score, best) == Ordering::Less {
+                            *best = score;
+                        }
+                    })
+                    .or_insert(score);
+            }
+        }
+
+        let mut changed = 0;
+        for bm in self.beatmaps.iter_mut() {
+            let hash = match bm.hash.md5() {
+                Some(hash) => hash,
+                None => continue,
+            };
+            for (_, score) in best.range((hash, 0)..=(hash, u8::MAX)) {
+                let grade = bm.grade_mut(score.mode);
+                if *grade != score.grade() {
+                    *grade = score.grade();
+                    changed += 1;
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// The criteria to rank scores by in a leaderboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RankBy {
+    /// Highest score first, as the in-game local leaderboard does.
+    #[default]
+    Score,
+    /// Highest accuracy first.
+    Accuracy,
+    /// Highest max combo first.
+    Combo,
+}
+
+impl RankBy {
+    /// Compare two scores by these criteria, ordering the better score first.
+    ///
+    /// See `ScoreList::leaderboard` for how ties are broken.
+    pub fn compare(self, a: &Score, b: &Score) -> Ordering {
+        let by_score = |a: &Score, b: &Score| {
+            b.score
+                .cmp(&a.score)
+                .then_with(|| a.timestamp.cmp(&b.timestamp))
+        };
+        match self {
+            RankBy::Score => {
+                let v2 = |s: &Score| s.mods.contains(Mod::ScoreV2);
+                v2(a).cmp(&v2(b)).then_with(|| by_score(a, b))
+            }
+            RankBy::Accuracy => b
+                .accuracy()
+                .partial_cmp(&a.accuracy())
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| by_score(a, b)),
+            RankBy::Combo => b.max_combo.cmp(&a.max_combo).then_with(|| by_score(a, b)),
+        }
+    }
+}
+
+/// A single ranked score within a leaderboard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LeaderboardEntry<'a> {
+    /// The position of the score within the leaderboard, starting at `1`.
+    pub rank: usize,
+    pub score: &'a Score,
+}
+
+/// A run of score entries that could not be parsed, skipped when parsing with
+/// `ParseOptions::skipped_scores`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedScores {
+    /// The index of the beatmap the scores belong to, within `ScoreList::beatmaps`.
+    pub beatmap: usize,
+    /// The hash of the beatmap the scores belong to.
+    pub hash: StoredHash,
+    /// The range of the skipped bytes within the input.
+    pub range: ops::Range<usize>,
+    /// The raw skipped bytes, which may hold one or more score entries.
+    pub bytes: Vec<u8>,
 }
 
 /// The scores for a single beatmap.
@@ -40,20 +389,119 @@ impl ScoreList {
 pub struct BeatmapScores {
     /// The beatmap hash.
     /// Should be redundant with the individual replay hashes.
-    pub hash: Option<String>,
+    pub hash: StoredHash,
     /// All the scored replays for this beatmap.
     pub scores: Vec<Replay>,
 }
 
-fn scores(bytes: &[u8]) -> Result<(&[u8], ScoreList), Error> {
+/// A writer that serializes a score database incrementally, one beatmap at a time, without
+/// holding the whole `ScoreList` in memory.
+///
+/// As the amount of beatmaps is only known at the end, the output must be seekable so that it can
+/// be filled in by `finish`.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct ScoreListWriter<W: Write + Seek> {
+    out: W,
+    count_pos: u64,
+    beatmaps: u32,
+    buf: Vec<u8>,
+}
+#[cfg(feature = "std")]
+impl ScoreListWriter<BufWriter<File>> {
+    /// Create a writer that writes a score database to a `scores.db` file.
+    pub fn create<P: AsRef<Path>>(path: P, version: u32) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), version)
+    }
+}
+#[cfg(feature = "std")]
+impl<W: Write + Seek> ScoreListWriter<W> {
+    /// Start writing a score database with the given version to the given output.
+    pub fn new(mut out: W, version: u32) -> io::Result<Self> {
+        version.wr(&mut out)?;
+        let count_pos = out.stream_position()?;
+        0_u32.wr(&mut out)?;
+        Ok(ScoreListWriter {
+            out,
+            count_pos,
+            beatmaps: 0,
+            buf: Vec::new(),
+        })
+    }
+
+    /// Write the scores of a single beatmap.
+    ///
+    /// Only the scores of the current beatmap are buffered, so that their amount can be written
+    /// before them.
+    pub fn write_beatmap<'a, I>(&mut self, hash: &StoredHash, scores: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a Replay>,
+    {
+        self.buf.clear();
+        let mut count = 0_u32;
+        for score in scores {
+            score.wr_args(&mut self.buf, None)?;
+            count += 1;
+        }
+        hash.wr(&mut self.out)?;
+        count.wr(&mut self.out)?;
+        self.out.write_all(&self.buf)?;
+        self.beatmaps += 1;
+        Ok(())
+    }
+
+    /// Write the scores of a single beatmap, out of a `BeatmapScores` entry.
+    pub fn write_beatmap_scores(&mut self, beatmap: &BeatmapScores) -> io::Result<()> {
+        self.write_beatmap(&beatmap.hash, &beatmap.scores)
+    }
+
+    /// Fill in the amount of beatmaps written and flush the output, returning it.
+    pub fn finish(mut self) -> io::Result<W> {
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(self.count_pos))?;
+        self.beatmaps.wr(&mut self.out)?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn scores<'a>(bytes: &'a [u8], opts: &mut ParseOptions) -> Result<(&'a [u8], ScoreList), Error> {
     let (rem, version) = int(bytes)?;
+    let skipped = opts
+        .skipped_scores
+        .as_ref()
+        .map_or(0, |skipped| skipped.len());
+    let parsed = score_beatmaps(bytes, rem, version, opts);
+    let skipped_more = opts
+        .skipped_scores
+        .as_ref()
+        .is_some_and(|now| now.len() > skipped);
+    if parsed.is_err() || skipped_more {
+        opts.unknown_version(version, LATEST_KNOWN_VERSION);
+    }
+    parsed
+}
+
+fn score_beatmaps<'a>(
+    bytes: &'a [u8],
+    rem: &'a [u8],
+    version: u32,
+    opts: &mut ParseOptions,
+) -> Result<(&'a [u8], ScoreList), Error> {
     let (mut rem, len) = int(rem)?;
-    let mut beatmaps = Vec::with_capacity(len as usize);
+    let len = len as usize;
+    let mut beatmaps = Vec::with_capacity(len);
 
-    for _ in 0..len {
-        let (rem_, beatmap_scores) = beatmap_scores(rem)?;
+    for i in 0..len {
+        if rem.is_empty() && opts.skipped_scores.is_some() {
+            //The rest of the input was skipped while recovering
+            break;
+        }
+        let (rem_, beatmap_scores) = beatmap_scores(bytes, rem, i, opts)?;
         beatmaps.push(beatmap_scores);
         rem = rem_;
+        opts.report(i + 1, len, bytes, rem);
     }
 
     let list = ScoreList { version, beatmaps };
@@ -61,15 +509,49 @@ fn scores(bytes: &[u8]) -> Result<(&[u8], ScoreList), Error> {
     Ok((rem, list))
 }
 
-fn beatmap_scores(bytes: &[u8]) -> Result<(&[u8], BeatmapScores), Error> {
-    let (rem, hash) = opt_string(bytes)?;
-    let (mut rem, len) = int(rem)?;
+fn beatmap_scores<'a>(
+    input: &[u8],
+    bytes: &'a [u8],
+    index: usize,
+    opts: &mut ParseOptions,
+) -> Result<(&'a [u8], BeatmapScores), Error> {
+    let (mut rem, (hash, len)) = beatmap_header(bytes)?;
     let mut scores = Vec::with_capacity(len as usize);
 
     for _ in 0..len {
-        let (rem_, replay) = replay(rem, false)?;
+        let err = match replay(rem, false, opts) {
+            Ok((rem_, replay)) => {
+                opts.record_span(input, rem, rem_);
+                rem = rem_;
+                scores.push(replay);
+                continue;
+            }
+            Err(err) => err,
+        };
+        let skipped = match opts.skipped_scores.as_mut() {
+            Some(skipped) => skipped,
+            None => return Err(err),
+        };
+        let (rem_, next_beatmap) = resync(rem, &hash);
+        let len = rem.len() - rem_.len();
+        if len != 0 {
+            let offset = input.len() - rem.len();
+            skipped.push(SkippedScores {
+                beatmap: index,
+                hash: hash.clone(),
+                range: offset..offset + len,
+                bytes: rem[..len].to_vec(),
+            });
+            opts.warn(Warning::SkippedScores {
+                beatmap: index,
+                offset,
+                len,
+            });
+        }
         rem = rem_;
-        scores.push(replay);
+        if next_beatmap {
+            break;
+        }
     }
 
     let scores = BeatmapScores { hash, scores };
@@ -77,6 +559,36 @@ fn beatmap_scores(bytes: &[u8]) -> Result<(&[u8], BeatmapScores), Error> {
     Ok((rem, scores))
 }
 
+/// Find the next entry that parses after a score that does not, either a score of the beatmap
+/// with the given hash or the scores of the next beatmap.
+///
+/// Returns the input starting at that entry, and whether it belongs to the next beatmap.
+/// If no entry is found, the input is skipped up to its end.
+fn resync<'a>(bytes: &'a [u8], hash: &StoredHash) -> (&'a [u8], bool) {
+    let score_of = |bytes: &[u8], hash: &StoredHash| {
+        replay(bytes, false, &mut ParseOptions::new())
+            .is_ok_and(|(_rem, score)| score.beatmap_hash == *hash)
+    };
+    for start in 0..bytes.len() {
+        let rem = &bytes[start..];
+        if start != 0 && score_of(rem, hash) {
+            return (rem, false);
+        }
+        if let Ok((rem_, (next @ StoredHash::Md5(_), count))) = beatmap_header(rem) {
+            if count == 0 || score_of(rem_, &next) {
+                return (rem, true);
+            }
+        }
+    }
+    (&bytes[bytes.len()..], true)
+}
+
+fn beatmap_header(bytes: &[u8]) -> IResult<&[u8], (StoredHash, u32)> {
+    let (rem, hash) = stored_hash(bytes)?;
+    let (rem, count) = int(rem)?;
+    Ok((rem, (hash, count)))
+}
+
 writer!(ScoreList [this,out] {
     this.version.wr(out)?;
     PrefixedList(&this.beatmaps).wr(out)?;
@@ -85,3 +597,318 @@ writer!(BeatmapScores [this,out] {
     this.hash.wr(out)?;
     PrefixedList(&this.scores).wr_args(out,None)?;
 });
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ids::test::hash,
+        listing::{test::beatmap, Grade},
+        replay::test::replay_with,
+    };
+
+    fn score(score: u32, count_100: u16, max_combo: u16, day: u32, mods: ModSet) -> Score {
+        Score {
+            score,
+            count_300: 100,
+            count_100,
+            max_combo,
+            mods,
+            timestamp: OsuTimestamp::from_unix_millis(
+                1_577_836_800_000 + (day as i64 - 1) * 86_400_000,
+            ),
+            ..replay_with(Vec::new()).into_score_entry()
+        }
+    }
+
+    #[test]
+    fn leaderboard() {
+        let v2 = ModSet::empty().with(Mod::ScoreV2);
+        let list = ScoreList {
+            version: 20211103,
+            beatmaps: vec![BeatmapScores {
+                hash: hash(1).into(),
+                scores: vec![
+                    score(1000, 5, 50, 2, ModSet::empty()),
+                    score(900_000, 0, 10, 1, v2),
+                    score(1000, 1, 60, 1, ModSet::empty()),
+                    score(2000, 9, 40, 3, ModSet::empty()),
+                ],
+            }],
+        };
+        let ranked = |ranking| {
+            list.leaderboard(hash(1), ranking)
+                .into_iter()
+                .map(|entry| (entry.rank, entry.score.score, entry.score.max_combo))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ranked(RankBy::Score),
+            vec![
+                (1, 2000, 40),
+                (2, 1000, 60),
+                (3, 1000, 50),
+                (4, 900_000, 10)
+            ]
+        );
+        assert_eq!(
+            ranked(RankBy::Accuracy),
+            vec![
+                (1, 900_000, 10),
+                (2, 1000, 60),
+                (3, 1000, 50),
+                (4, 2000, 40)
+            ]
+        );
+        assert_eq!(
+            ranked(RankBy::Combo),
+            vec![
+                (1, 1000, 60),
+                (2, 1000, 50),
+                (3, 2000, 40),
+                (4, 900_000, 10)
+            ]
+        );
+        assert!(list.leaderboard(hash(2), RankBy::Score).is_empty());
+    }
+
+    #[test]
+    fn scores_for_hash() {
+        let mut list = ScoreList {
+            version: 20211103,
+            beatmaps: (0..5)
+                .map(|i| BeatmapScores {
+                    hash: hash(i as u8).into(),
+                    scores: (0..i)
+                        .map(|day| score(i, 0, 1, day + 1, ModSet::empty()))
+                        .collect(),
+                })
+                .collect(),
+        };
+        list.beatmaps[2].scores[0].life_graph = Some("0|1,100|0.5".to_string());
+        let mut raw = Vec::new();
+        list.to_writer(&mut raw).unwrap();
+        assert_eq!(
+            ScoreList::scores_for_hash_from_bytes(&raw, hash(3)).unwrap(),
+            list.beatmaps[3].scores
+        );
+        assert_eq!(
+            ScoreList::scores_for_hash_from_bytes(&raw, hash(9)).unwrap(),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn prune() {
+        let mut list = ScoreList {
+            version: 20211103,
+            beatmaps: vec![
+                BeatmapScores {
+                    hash: hash(1).into(),
+                    scores: vec![
+                        score(300, 0, 10, 3, ModSet::empty()),
+                        score(100, 0, 10, 1, ModSet::empty()),
+                        score(200, 0, 10, 4, ModSet::empty()),
+                    ],
+                },
+                BeatmapScores {
+                    hash: hash(2).into(),
+                    scores: vec![score(500, 0, 10, 1, ModSet::empty())],
+                },
+                BeatmapScores {
+                    hash: hash(1).into(),
+                    scores: vec![score(400, 0, 10, 2, ModSet::empty())],
+                },
+            ],
+        };
+        list.beatmaps[0].scores[2].life_graph = Some("0|1,500|0.2,900|0,".to_string());
+        list.beatmaps[1].scores[0].life_graph = Some("0|1,900|0".to_string());
+        list.beatmaps[1].scores[0].mods = ModSet::empty().with(Mod::NoFail);
+        let scores = |list: &ScoreList| {
+            list.beatmaps
+                .iter()
+                .map(|bm| bm.scores.iter().map(|s| s.score).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        let mut failed = list.clone();
+        assert_eq!(failed.remove_failed(), 1);
+        assert_eq!(scores(&failed), vec![vec![300, 100], vec![500], vec![400]]);
+
+        let mut recent = list.clone();
+        let day2 = list.beatmaps[2].scores[0].timestamp;
+        assert_eq!(recent.remove_older_than(day2), 2);
+        assert_eq!(scores(&recent), vec![vec![300, 200], vec![400]]);
+
+        let mut top = list.clone();
+        top.beatmaps[0].scores[0].mode = Mode::Taiko;
+        assert_eq!(top.keep_top_n_per_map(1), 2);
+        assert_eq!(scores(&top), vec![vec![300], vec![500], vec![400]]);
+        assert_eq!(top.keep_top_n_per_map(0), 3);
+        assert!(top.beatmaps.is_empty());
+
+        let mut raw = Vec::new();
+        recent.to_writer(&mut raw).unwrap();
+        assert_eq!(ScoreList::from_bytes(&raw).unwrap(), recent);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn streaming_writer() {
+        let list = ScoreList {
+            version: 20211103,
+            beatmaps: (0..4)
+                .map(|i| BeatmapScores {
+                    hash: hash(i as u8).into(),
+                    scores: (0..i)
+                        .map(|day| score(i, 0, 1, day + 1, ModSet::empty()))
+                        .collect(),
+                })
+                .collect(),
+        };
+        let mut serial = Vec::new();
+        list.to_writer(&mut serial).unwrap();
+        let mut writer = ScoreListWriter::new(std::io::Cursor::new(Vec::new()), 20211103).unwrap();
+        for beatmap in &list.beatmaps {
+            writer
+                .write_beatmap(&beatmap.hash, beatmap.scores.iter())
+                .unwrap();
+        }
+        assert_eq!(writer.finish().unwrap().into_inner(), serial);
+    }
+
+    #[test]
+    fn skipped_scores() {
+        let list = ScoreList {
+            version: 20211103,
+            beatmaps: (1..4)
+                .map(|i| BeatmapScores {
+                    hash: hash(i).into(),
+                    scores: (0..4 - i)
+                        .map(|day| Score {
+                            beatmap_hash: hash(i).into(),
+                            ..score(i as u32, 0, 1, day as u32 + 1, ModSet::empty())
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+        let mut clean = Vec::new();
+        list.to_writer(&mut clean).unwrap();
+        let mut raw = clean.clone();
+        let mut entry = Vec::new();
+        list.beatmaps[0].scores[0]
+            .wr_args(&mut entry, None)
+            .unwrap();
+        //The database header, followed by the hash and count of each beatmap
+        let (len, header, beatmap) = (entry.len(), 8, 38);
+        let first = header + beatmap + len;
+        let second = header + 2 * beatmap + 4 * len;
+        raw[first] = 0xaa;
+        raw[second] = 0xaa;
+        assert!(ScoreList::from_bytes(&raw).is_err());
+
+        let (mut skipped, mut warnings) = (Vec::new(), Vec::new());
+        let opts = ParseOptions::new()
+            .skipped_scores(&mut skipped)
+            .warnings(&mut warnings);
+        let parsed = ScoreList::from_bytes_with(&raw, opts).unwrap();
+        let mut expected = list.clone();
+        expected.beatmaps[0].scores.remove(1);
+        expected.beatmaps[1].scores.remove(1);
+        assert_eq!(parsed, expected);
+        assert_eq!(
+            skipped.iter().map(|s| s.range.clone()).collect::<Vec<_>>(),
+            vec![first..first + len, second..second + len]
+        );
+        assert_eq!(skipped[1].hash, hash(2).into());
+        assert_eq!(skipped[1].bytes, raw[second..second + len]);
+        assert_eq!(
+            warnings[0],
+            Warning::SkippedScores {
+                beatmap: 0,
+                offset: first,
+                len,
+            }
+        );
+
+        let truncated = &clean[..clean.len() - 10];
+        let mut skipped = Vec::new();
+        let opts = ParseOptions::new().skipped_scores(&mut skipped);
+        let parsed = ScoreList::from_bytes_with(truncated, opts).unwrap();
+        assert_eq!(parsed.beatmaps.len(), 3);
+        assert!(parsed.beatmaps[2].scores.is_empty());
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].range.end, truncated.len());
+    }
+
+    #[test]
+    fn update_grades_from_scores() {
+        let perfect = score(1000, 0, 100, 1, ModSet::empty());
+        let worse = score(2000, 30, 80, 2, ModSet::empty());
+        let mut taiko = score(500, 0, 100, 3, ModSet::empty());
+        taiko.mode = Mode::Taiko;
+        let scores = ScoreList {
+            version: 20211103,
+            beatmaps: vec![BeatmapScores {
+                hash: hash(1).into(),
+                scores: vec![perfect, worse.clone(), taiko],
+            }],
+        };
+        let mut played = beatmap();
+        played.hash = hash(1).into();
+        played.std_grade = Grade::Unplayed;
+        played.taiko_grade = Grade::Unplayed;
+        played.mania_grade = Grade::B;
+        let mut unplayed = beatmap();
+        unplayed.hash = hash(2).into();
+        unplayed.std_grade = Grade::C;
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![played, unplayed],
+            user_permissions: 0,
+        };
+
+        assert_eq!(listing.update_grades_from_scores(&scores), 2);
+        //The top score by score wins, even if it has a worse grade
+        assert_eq!(listing.beatmaps[0].std_grade, worse.grade());
+        assert_ne!(worse.grade(), Grade::SS);
+        assert_eq!(listing.beatmaps[0].taiko_grade, Grade::SS);
+        assert_eq!(listing.beatmaps[0].mania_grade, Grade::B);
+        assert_eq!(listing.beatmaps[1].std_grade, Grade::C);
+        assert_eq!(listing.update_grades_from_scores(&scores), 0);
+    }
+
+    #[test]
+    fn spans() {
+        let list = ScoreList {
+            version: 20211103,
+            beatmaps: (0..3)
+                .map(|i| BeatmapScores {
+                    hash: hash(i as u8).into(),
+                    scores: (0..i)
+                        .map(|day| score(i, 0, 1, day + 1, ModSet::empty()))
+                        .collect(),
+                })
+                .collect(),
+        };
+        let mut raw = Vec::new();
+        list.to_writer(&mut raw).unwrap();
+        let (parsed, spans) = ScoreList::from_bytes_with_spans(&raw).unwrap();
+        assert_eq!(parsed, list);
+        assert_eq!(
+            spans.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        for (bm, spans) in list.beatmaps.iter().zip(&spans) {
+            for (score, span) in bm.scores.iter().zip(spans) {
+                let mut entry = Vec::new();
+                score.wr_args(&mut entry, None).unwrap();
+                assert_eq!(raw[span.clone()], entry[..]);
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Bulk editing of the per-beatmap local settings stored in a listing, such as the local offset or
+//! whether the storyboard is shown.
+
+use crate::{listing::Beatmap, query::Filter, Listing};
+
+/// A set of local beatmap settings to apply to many beatmaps at once.
+///
+/// Only the settings that are explicitly set are changed, every other setting of a beatmap is
+/// left as-is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BeatmapSettings {
+    local_offset: Option<u16>,
+    ignore_sounds: Option<bool>,
+    ignore_skin: Option<bool>,
+    disable_storyboard: Option<bool>,
+    disable_video: Option<bool>,
+    visual_override: Option<bool>,
+    mania_scroll_speed: Option<u8>,
+}
+impl BeatmapSettings {
+    /// Create a set of settings that changes nothing.
+    pub fn new() -> BeatmapSettings {
+        BeatmapSettings::default()
+    }
+
+    /// Set `Beatmap::local_beatmap_offset`.
+    pub fn local_offset(mut self, offset: u16) -> BeatmapSettings {
+        self.local_offset = Some(offset);
+        self
+    }
+
+    /// Set `Beatmap::ignore_sounds`.
+    pub fn ignore_sounds(mut self, ignore: bool) -> BeatmapSettings {
+        self.ignore_sounds = Some(ignore);
+        self
+    }
+
+    /// Set `Beatmap::ignore_skin`.
+    pub fn ignore_skin(mut self, ignore: bool) -> BeatmapSettings {
+        self.ignore_skin = Some(ignore);
+        self
+    }
+
+    /// Set `Beatmap::disable_storyboard`.
+    pub fn disable_storyboard(mut self, disable: bool) -> BeatmapSettings {
+        self.disable_storyboard = Some(disable);
+        self
+    }
+
+    /// Set `Beatmap::disable_video`.
+    pub fn disable_video(mut self, disable: bool) -> BeatmapSettings {
+        self.disable_video = Some(disable);
+        self
+    }
+
+    /// Set `Beatmap::visual_override`.
+    pub fn visual_override(mut self, visual_override: bool) -> BeatmapSettings {
+        self.visual_override = Some(visual_override);
+        self
+    }
+
+    /// Set `Beatmap::mania_scroll_speed`.
+    pub fn mania_scroll_speed(mut self, speed: u8) -> BeatmapSettings {
+        self.mania_scroll_speed = Some(speed);
+        self
+    }
+
+    /// Apply the settings to a single beatmap.
+    ///
+    /// Returns whether any setting of the beatmap actually changed.
+    pub fn apply(&self, bm: &mut Beatmap) -> bool {
+        //Not short-circuiting, so that every setting is applied
+        set(&mut bm.local_beatmap_offset, self.local_offset)
+            | set(&mut bm.ignore_sounds, self.ignore_sounds)
+            | set(&mut bm.ignore_skin, self.ignore_skin)
+            | set(&mut bm.disable_storyboard, self.disable_storyboard)
+            | set(&mut bm.disable_video, self.disable_video)
+            | set(&mut bm.visual_override, self.visual_override)
+            | set(&mut bm.mania_scroll_speed, self.mania_scroll_speed)
+    }
+}
+
+/// Set a field to the given value, if any, returning whether it changed.
+fn set<T: PartialEq>(field: &mut T, value: Option<T>) -> bool {
+    match value {
+        Some(value) if *field != value => {
+            *field = value;
+            true
+        }
+        _ => false,
+    }
+}
+
+impl Listing {
+    /// Apply the given local settings to every beatmap that matches a filter, which can be either
+    /// a closure or a parsed `query::Query`.
+    ///
+    /// For example, applying `BeatmapSettings::new().disable_storyboard(true)` with the query
+    /// `stars>5` disables storyboards on every beatmap above 5 stars.
+    /// Returns the amount of beatmaps whose settings actually changed.
+    pub fn apply_settings<F: Filter>(&mut self, filter: F, settings: &BeatmapSettings) -> usize {
+        self.beatmaps
+            .iter_mut()
+            .filter(|bm| filter.matches(bm))
+            .map(|bm| settings.apply(bm))
+            .filter(|&changed| changed)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{listing::test::beatmap, query::Query, ModSet};
+
+    #[test]
+    fn apply_settings() {
+        let mut hard = beatmap();
+        hard.std_ratings = vec![(ModSet::empty(), 6.0)];
+        hard.disable_storyboard = false;
+        let mut easy = beatmap();
+        easy.std_ratings = vec![(ModSet::empty(), 2.0)];
+        easy.disable_storyboard = false;
+        let mut done = hard.clone();
+        done.disable_storyboard = true;
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 0,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![hard, easy, done],
+            user_permissions: 0,
+        };
+
+        let settings = BeatmapSettings::new().disable_storyboard(true);
+        let query = Query::parse("stars>5").unwrap();
+        assert_eq!(listing.apply_settings(&query, &settings), 1);
+        let disabled: Vec<bool> = listing
+            .beatmaps
+            .iter()
+            .map(|bm| bm.disable_storyboard)
+            .collect();
+        assert_eq!(disabled, vec![true, false, true]);
+
+        let settings = BeatmapSettings::new()
+            .local_offset(15)
+            .mania_scroll_speed(20);
+        assert_eq!(listing.apply_settings(|_: &Beatmap| true, &settings), 3);
+        assert!(listing
+            .beatmaps
+            .iter()
+            .all(|bm| bm.local_beatmap_offset == 15 && bm.mania_scroll_speed == 20));
+        assert_eq!(listing.apply_settings(|_: &Beatmap| true, &settings), 0);
+    }
+}
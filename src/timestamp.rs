@@ -0,0 +1,386 @@
+//! Timestamps as osu! stores them, independent of any particular datetime library.
+//!
+//! Conversions to and from `chrono` and `time` types are available with the `chrono` (enabled by
+//! default) and `time` features respectively.
+
+use crate::prelude::*;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TICKS_PER_SECOND: u64 = 10_000_000;
+const TICKS_PER_DAY: u64 = 24 * 60 * 60 * TICKS_PER_SECOND;
+/// The amount of ticks from `0001-01-01` to the unix epoch, `1970-01-01`.
+const UNIX_EPOCH_TICKS: u64 = 621_355_968_000_000_000;
+/// The amount of days from `0001-01-01` to `1970-01-01`.
+const UNIX_EPOCH_DAYS: i64 = 719_162;
+
+/// A point in time, in UTC, stored as an amount of .NET "ticks": 100-nanosecond units since
+/// midnight of `0001-01-01`.
+///
+/// This is exactly what osu! writes, so timestamps always round-trip unchanged.
+#[cfg_attr(feature = "ser-de", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "ser-de", serde(transparent))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OsuTimestamp(pub u64);
+impl OsuTimestamp {
+    /// The current time, according to the system clock.
+    #[cfg(feature = "std")]
+    pub fn now() -> OsuTimestamp {
+        SystemTime::now().into()
+    }
+
+    /// Create a timestamp out of raw ticks.
+    pub fn from_ticks(ticks: u64) -> OsuTimestamp {
+        OsuTimestamp(ticks)
+    }
+
+    /// Get the raw ticks of the timestamp.
+    pub fn ticks(self) -> u64 {
+        self.0
+    }
+
+    /// Create a timestamp out of milliseconds since the unix epoch.
+    ///
+    /// Times before `0001-01-01` are clamped to it.
+    pub fn from_unix_millis(millis: i64) -> OsuTimestamp {
+        let ticks = UNIX_EPOCH_TICKS as i128 + millis as i128 * 10_000;
+        OsuTimestamp(ticks.clamp(0, u64::MAX as i128) as u64)
+    }
+
+    /// Get the amount of whole milliseconds since the unix epoch, negative for earlier times.
+    pub fn unix_millis(self) -> i64 {
+        (self.0 as i128 - UNIX_EPOCH_TICKS as i128).div_euclid(10_000) as i64
+    }
+
+    /// Create a timestamp out of whole seconds since the unix epoch.
+    ///
+    /// Times before `0001-01-01` are clamped to it.
+    pub fn from_unix_secs(secs: i64) -> OsuTimestamp {
+        OsuTimestamp::from_unix_millis(secs.saturating_mul(1000))
+    }
+
+    /// Get the amount of whole seconds since the unix epoch, negative for earlier times.
+    pub fn unix_secs(self) -> i64 {
+        self.unix_millis().div_euclid(1000)
+    }
+
+    /// Parse an RFC 3339 date, such as `2021-11-03T12:34:56.789+02:00`.
+    ///
+    /// A space is also accepted instead of the `T` separator.
+    /// Fractional seconds beyond the 100-nanosecond precision of ticks are truncated, and leap
+    /// seconds are not supported.
+    pub fn parse_rfc3339(date: &str) -> Result<OsuTimestamp, InvalidTimestamp> {
+        let bytes = date.as_bytes();
+        let digits = |range: ops::Range<usize>| -> Result<i64, InvalidTimestamp> {
+            let digits = bytes.get(range).ok_or(InvalidTimestamp)?;
+            digits.iter().try_fold(0, |acc, &digit| match digit {
+                b'0'..=b'9' => Ok(acc * 10 + (digit - b'0') as i64),
+                _ => Err(InvalidTimestamp),
+            })
+        };
+        let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+        if separators
+            .iter()
+            .any(|&(idx, sep)| bytes.get(idx) != Some(&sep))
+            || !matches!(bytes.get(10), Some(b'T') | Some(b't') | Some(b' '))
+        {
+            return Err(InvalidTimestamp);
+        }
+        let (year, month, day) = (digits(0..4)?, digits(5..7)?, digits(8..10)?);
+        let (hour, minute, second) = (digits(11..13)?, digits(14..16)?, digits(17..19)?);
+        let days = days_from_civil(year, month as u32, day as u32);
+        if civil_from_days(days) != (year, month as u32, day as u32)
+            || hour >= 24
+            || minute >= 60
+            || second >= 60
+        {
+            return Err(InvalidTimestamp);
+        }
+
+        let mut rest = &bytes[19..];
+        let mut fraction = 0;
+        if let Some((b'.', frac)) = rest.split_first() {
+            let len = frac.iter().take_while(|b| b.is_ascii_digit()).count();
+            if len == 0 {
+                return Err(InvalidTimestamp);
+            }
+            for (idx, digit) in frac[..len].iter().enumerate().take(7) {
+                fraction += (digit - b'0') as i64 * 10_i64.pow(6 - idx as u32);
+            }
+            rest = &frac[len..];
+        }
+        let offset_secs = match rest {
+            b"Z" | b"z" => 0,
+            [sign @ b'+', ..] | [sign @ b'-', ..] if rest.len() == 6 && rest[3] == b':' => {
+                let start = bytes.len() - rest.len();
+                let (hours, minutes) =
+                    (digits(start + 1..start + 3)?, digits(start + 4..start + 6)?);
+                if hours >= 24 || minutes >= 60 {
+                    return Err(InvalidTimestamp);
+                }
+                let offset = hours * 3600 + minutes * 60;
+                if *sign == b'-' {
+                    -offset
+                } else {
+                    offset
+                }
+            }
+            _ => return Err(InvalidTimestamp),
+        };
+
+        let secs = (days + UNIX_EPOCH_DAYS) * 24 * 60 * 60 + hour * 3600 + minute * 60 + second
+            - offset_secs;
+        let ticks = secs as i128 * TICKS_PER_SECOND as i128 + fraction as i128;
+        if ticks < 0 || ticks > u64::MAX as i128 {
+            return Err(InvalidTimestamp);
+        }
+        Ok(OsuTimestamp(ticks as u64))
+    }
+
+    /// Format the timestamp as an RFC 3339 date in UTC.
+    ///
+    /// Fractional seconds are only included if there are any, and then always with the 7 digits
+    /// of tick precision:
+    ///
+    /// ```
+    /// # use osu_db::OsuTimestamp;
+    /// let date = OsuTimestamp::parse_rfc3339("2021-11-03T12:34:56.789+02:00").unwrap();
+    /// assert_eq!(date.to_rfc3339(), "2021-11-03T10:34:56.7890000Z");
+    /// let date = OsuTimestamp::parse_rfc3339("2021-11-03T10:34:56Z").unwrap();
+    /// assert_eq!(date.to_rfc3339(), "2021-11-03T10:34:56Z");
+    /// ```
+    pub fn to_rfc3339(self) -> String {
+        let mut date = String::new();
+        //Writing into a string cannot fail
+        let _ = self.write_parts(&mut date, 'T', "Z");
+        date
+    }
+
+    /// Write the date, the given separator, the time and the given suffix.
+    fn write_parts<W: fmt::Write>(self, out: &mut W, sep: char, suffix: &str) -> fmt::Result {
+        let days = (self.0 / TICKS_PER_DAY) as i64;
+        let time = self.0 % TICKS_PER_DAY;
+        let (year, month, day) = civil_from_days(days - UNIX_EPOCH_DAYS);
+        let (secs, fraction) = (time / TICKS_PER_SECOND, time % TICKS_PER_SECOND);
+        write!(
+            out,
+            "{:04}-{:02}-{:02}{}{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            sep,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )?;
+        if fraction != 0 {
+            write!(out, ".{:07}", fraction)?;
+        }
+        out.write_str(suffix)
+    }
+
+    /// Convert the timestamp into a `SystemTime`.
+    #[cfg(feature = "std")]
+    pub fn to_system_time(self) -> SystemTime {
+        let since_unix = |ticks: u64| {
+            Duration::new(
+                ticks / TICKS_PER_SECOND,
+                (ticks % TICKS_PER_SECOND * 100) as u32,
+            )
+        };
+        if self.0 >= UNIX_EPOCH_TICKS {
+            UNIX_EPOCH + since_unix(self.0 - UNIX_EPOCH_TICKS)
+        } else {
+            UNIX_EPOCH - since_unix(UNIX_EPOCH_TICKS - self.0)
+        }
+    }
+
+    /// Convert the timestamp into a `time::OffsetDateTime` in UTC.
+    ///
+    /// Returns `None` if the timestamp is past the year 9999, which `time` cannot represent
+    /// unless its `large-dates` feature is enabled.
+    ///
+    /// Only available with the `time` feature enabled.
+    #[cfg(feature = "time")]
+    pub fn to_offset_date_time(self) -> Option<time::OffsetDateTime> {
+        let nanos = (self.0 as i128 - UNIX_EPOCH_TICKS as i128) * 100;
+        time::OffsetDateTime::from_unix_timestamp_nanos(nanos).ok()
+    }
+}
+#[cfg(feature = "std")]
+impl From<SystemTime> for OsuTimestamp {
+    /// Times before `0001-01-01` are clamped to it.
+    fn from(time: SystemTime) -> OsuTimestamp {
+        let ticks = |since: Duration| {
+            since.as_secs() as i128 * TICKS_PER_SECOND as i128 + since.subsec_nanos() as i128 / 100
+        };
+        let ticks = match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => UNIX_EPOCH_TICKS as i128 + ticks(after),
+            Err(before) => UNIX_EPOCH_TICKS as i128 - ticks(before.duration()),
+        };
+        OsuTimestamp(ticks.clamp(0, u64::MAX as i128) as u64)
+    }
+}
+#[cfg(feature = "chrono")]
+impl From<chrono::DateTime<chrono::Utc>> for OsuTimestamp {
+    /// Times before `0001-01-01` are clamped to it.
+    fn from(datetime: chrono::DateTime<chrono::Utc>) -> OsuTimestamp {
+        use chrono::TimeZone;
+        let epoch = chrono::Utc.ymd(1, 1, 1).and_hms(0, 0, 0);
+        let duration = datetime.signed_duration_since(epoch);
+        let ticks_since: i64 = (duration * 10).num_microseconds().unwrap_or(0);
+        OsuTimestamp(ticks_since.max(0) as u64)
+    }
+}
+#[cfg(feature = "chrono")]
+impl From<OsuTimestamp> for chrono::DateTime<chrono::Utc> {
+    fn from(timestamp: OsuTimestamp) -> chrono::DateTime<chrono::Utc> {
+        use chrono::{Duration, TimeZone};
+        let ticks = timestamp.0;
+        let epoch = chrono::Utc.ymd(1, 1, 1).and_hms(0, 0, 0);
+        epoch
+            + Duration::microseconds((ticks / 10) as i64)
+            + Duration::nanoseconds((ticks % 10 * 100) as i64)
+    }
+}
+#[cfg(feature = "time")]
+impl From<time::OffsetDateTime> for OsuTimestamp {
+    /// Times before `0001-01-01` are clamped to it.
+    fn from(datetime: time::OffsetDateTime) -> OsuTimestamp {
+        let ticks = UNIX_EPOCH_TICKS as i128 + datetime.unix_timestamp_nanos().div_euclid(100);
+        OsuTimestamp(ticks.clamp(0, u64::MAX as i128) as u64)
+    }
+}
+impl fmt::Display for OsuTimestamp {
+    /// Formats the timestamp as `YYYY-MM-DD hh:mm:ss UTC`, with fractional seconds if there are
+    /// any.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_parts(f, ' ', " UTC")
+    }
+}
+impl FromStr for OsuTimestamp {
+    type Err = InvalidTimestamp;
+    /// Parses an RFC 3339 date, see `OsuTimestamp::parse_rfc3339`.
+    fn from_str(date: &str) -> Result<OsuTimestamp, InvalidTimestamp> {
+        OsuTimestamp::parse_rfc3339(date)
+    }
+}
+
+/// The error returned when parsing a string that is not a valid RFC 3339 date, or that falls
+/// outside of the range of timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTimestamp;
+impl fmt::Display for InvalidTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid timestamp, expected an RFC 3339 date")
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidTimestamp {}
+
+/// Get the proleptic gregorian date of a day, given as an amount of days since `1970-01-01`.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month as u32, day as u32)
+}
+
+/// Get the amount of days since `1970-01-01` of a proleptic gregorian date.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+writer!(OsuTimestamp [this,out] this.0.wr(out)?);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rfc3339() {
+        let timestamp = OsuTimestamp::from_unix_millis(1_577_836_800_123);
+        assert_eq!(timestamp.to_rfc3339(), "2020-01-01T00:00:00.1230000Z");
+        assert_eq!(timestamp.to_rfc3339().parse(), Ok(timestamp));
+        assert_eq!(
+            "2020-01-01 01:30:00.123+01:30".parse(),
+            Ok(OsuTimestamp::from_unix_millis(1_577_836_800_123))
+        );
+        assert_eq!(
+            OsuTimestamp::parse_rfc3339("2019-12-31T19:00:00-05:00"),
+            Ok(OsuTimestamp::from_unix_secs(1_577_836_800))
+        );
+        assert_eq!(
+            OsuTimestamp::parse_rfc3339("0001-01-01T00:00:00Z"),
+            Ok(OsuTimestamp(0))
+        );
+        for invalid in &[
+            "2020-02-30T00:00:00Z",
+            "2020-01-01T24:00:00Z",
+            "2020-01-01T00:00:00",
+            "2020-01-01T00:00:00.Z",
+            "2020-1-01T00:00:00Z",
+            "0001-01-01T00:00:00+01:00",
+        ] {
+            assert_eq!(OsuTimestamp::parse_rfc3339(invalid), Err(InvalidTimestamp));
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        let timestamp = OsuTimestamp::from_unix_millis(1_577_836_800_123);
+        assert_eq!(timestamp.unix_millis(), 1_577_836_800_123);
+        assert_eq!(timestamp.to_string(), "2020-01-01 00:00:00.1230000 UTC");
+        assert_eq!(OsuTimestamp(0).to_string(), "0001-01-01 00:00:00 UTC");
+        assert_eq!(OsuTimestamp::from_unix_millis(i64::MIN), OsuTimestamp(0));
+
+        #[cfg(feature = "std")]
+        assert_eq!(OsuTimestamp::from(timestamp.to_system_time()), timestamp);
+        assert_eq!(OsuTimestamp::from_unix_secs(-1).unix_secs(), -1);
+        assert_eq!(timestamp.unix_secs(), 1_577_836_800);
+
+        #[cfg(feature = "chrono")]
+        {
+            let date = "2019-07-29T12:34:56.7654321+02:00";
+            let parsed = OsuTimestamp::parse_rfc3339(date).unwrap();
+            let datetime = chrono::DateTime::parse_from_rfc3339(date).unwrap();
+            assert_eq!(parsed, datetime.with_timezone(&chrono::Utc).into());
+            let datetime =
+                chrono::DateTime::<chrono::Utc>::from(OsuTimestamp(637_000_000_000_000_123));
+            assert_eq!(
+                OsuTimestamp::from(datetime),
+                OsuTimestamp(637_000_000_000_000_123)
+            );
+            assert_eq!(datetime.timestamp(), 1_564_403_200);
+        }
+        #[cfg(feature = "time")]
+        {
+            let datetime = timestamp.to_offset_date_time().unwrap();
+            assert_eq!(datetime.unix_timestamp(), 1_577_836_800);
+            assert_eq!(OsuTimestamp::from(datetime), timestamp);
+            assert_eq!(OsuTimestamp(u64::MAX).to_offset_date_time(), None);
+        }
+    }
+}
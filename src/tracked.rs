@@ -0,0 +1,368 @@
+//! Change tracking over an `osu!.db` listing.
+
+use crate::{
+    listing::{write_listing, Beatmap},
+    prelude::*,
+    Listing,
+};
+
+/// A single change made to a `TrackedListing` since it was loaded.
+///
+/// Also used to describe the changes between two reloads of a watched database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Change {
+    /// Any of the non-beatmap listing fields (version, folder count, player name, etc.) changed.
+    Header,
+    /// A beatmap that was present at load time was modified.
+    Modified {
+        /// The current index of the beatmap.
+        index: usize,
+        /// The index of the beatmap at load time.
+        original_index: usize,
+    },
+    /// A new beatmap was added.
+    Added {
+        /// The current index of the beatmap.
+        index: usize,
+    },
+    /// A beatmap that was present at load time was removed.
+    Removed {
+        /// The index of the beatmap at load time.
+        original_index: usize,
+    },
+}
+
+/// Mutable references to the header fields of a tracked listing.
+///
+/// See the fields of `Listing` for their meaning.
+#[derive(Debug)]
+pub struct HeaderMut<'a> {
+    pub version: &'a mut u32,
+    pub folder_count: &'a mut u32,
+    pub unban_date: &'a mut Option<OsuTimestamp>,
+    pub player_name: &'a mut Option<String>,
+    pub user_permissions: &'a mut u32,
+}
+
+/// Tracking information for a single beatmap.
+#[derive(Debug, Clone)]
+struct Entry {
+    /// The index of the beatmap at load time, if it was present back then.
+    original_index: Option<usize>,
+    /// Whether the beatmap might have changed since load.
+    dirty: bool,
+}
+
+/// A listing wrapper that keeps track of which beatmaps were modified since it was loaded.
+///
+/// All mutation goes through the wrapper, so that it knows what it is about to persist.
+/// When writing, beatmaps that were not touched are copied straight from the original bytes
+/// instead of being serialized again.
+///
+/// Note that the original database bytes are kept in memory for this purpose.
+#[derive(Debug, Clone)]
+pub struct TrackedListing {
+    listing: Listing,
+    entries: Vec<Entry>,
+    removed: Vec<usize>,
+    header_dirty: bool,
+    original_version: u32,
+    original: Vec<u8>,
+    spans: Vec<ops::Range<usize>>,
+}
+impl TrackedListing {
+    /// Parse a listing from its raw bytes, and start tracking changes from there.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TrackedListing, Error> {
+        let (listing, spans) = Listing::from_bytes_with_spans(bytes)?;
+        let entries = (0..listing.beatmaps.len())
+            .map(|idx| Entry {
+                original_index: Some(idx),
+                dirty: false,
+            })
+            .collect();
+        Ok(TrackedListing {
+            original_version: listing.version,
+            listing,
+            entries,
+            removed: Vec::new(),
+            header_dirty: false,
+            original: bytes.to_vec(),
+            spans,
+        })
+    }
+
+    /// Parse a listing from the `osu!.db` database file, and start tracking changes from there.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<TrackedListing, Error> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Read-only access to the underlying listing.
+    pub fn listing(&self) -> &Listing {
+        &self.listing
+    }
+
+    /// Stop tracking changes and get the underlying listing.
+    pub fn into_listing(self) -> Listing {
+        self.listing
+    }
+
+    /// Mutable access to a single beatmap, which is marked as modified.
+    pub fn beatmap_mut(&mut self, index: usize) -> Option<&mut Beatmap> {
+        let beatmap = self.listing.beatmaps.get_mut(index)?;
+        self.entries[index].dirty = true;
+        Some(beatmap)
+    }
+
+    /// Run an edit on all beatmaps matching a filter, marking only those as modified.
+    /// Returns the amount of beatmaps edited.
+    pub fn modify_where<F, E>(&mut self, mut filter: F, mut edit: E) -> usize
+    where
+        F: FnMut(&Beatmap) -> bool,
+        E: FnMut(&mut Beatmap),
+    {
+        let mut count = 0;
+        for (beatmap, entry) in self.listing.beatmaps.iter_mut().zip(&mut self.entries) {
+            if filter(beatmap) {
+                edit(beatmap);
+                entry.dirty = true;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Add a new beatmap at the end of the listing.
+    pub fn push(&mut self, beatmap: Beatmap) {
+        self.listing.beatmaps.push(beatmap);
+        self.entries.push(Entry {
+            original_index: None,
+            dirty: true,
+        });
+    }
+
+    /// Remove the beatmap at the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Beatmap {
+        let entry = self.entries.remove(index);
+        self.removed.extend(entry.original_index);
+        self.listing.beatmaps.remove(index)
+    }
+
+    /// Mutable access to the whole listing.
+    ///
+    /// As there is no way to know what will be changed through it, the header and all beatmaps
+    /// are conservatively marked as modified.
+    pub fn listing_mut(&mut self) -> &mut Listing {
+        self.header_dirty = true;
+        for entry in self.entries.iter_mut() {
+            entry.dirty = true;
+        }
+        &mut self.listing
+    }
+
+    /// Mutable access to the header fields of the listing (the fields that are not part of any
+    /// beatmap), which are marked as modified.
+    pub fn header_mut(&mut self) -> HeaderMut<'_> {
+        self.header_dirty = true;
+        let listing = &mut self.listing;
+        HeaderMut {
+            version: &mut listing.version,
+            folder_count: &mut listing.folder_count,
+            unban_date: &mut listing.unban_date,
+            player_name: &mut listing.player_name,
+            user_permissions: &mut listing.user_permissions,
+        }
+    }
+
+    /// Whether anything was changed since load.
+    pub fn is_dirty(&self) -> bool {
+        !self.changes().is_empty()
+    }
+
+    /// List all changes made since load.
+    ///
+    /// Header changes come first, followed by removals (in original order), and then
+    /// modifications and additions (in current order).
+    pub fn changes(&self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if self.header_dirty {
+            changes.push(Change::Header);
+        }
+        let mut removed = self.removed.clone();
+        removed.sort_unstable();
+        changes.extend(
+            removed
+                .into_iter()
+                .map(|original_index| Change::Removed { original_index }),
+        );
+        for (index, entry) in self.entries.iter().enumerate() {
+            match entry.original_index {
+                None => changes.push(Change::Added { index }),
+                Some(original_index) if entry.dirty => changes.push(Change::Modified {
+                    index,
+                    original_index,
+                }),
+                Some(_) => {}
+            }
+        }
+        changes
+    }
+
+    /// Write the listing to an arbitrary writer, returning the list of changes persisted.
+    ///
+    /// Untouched beatmaps are copied from the original bytes as long as the listing version did
+    /// not change.
+    /// Once written, the written listing becomes the new baseline: there are no changes left, and
+    /// writing again copies every beatmap as written this time.
+    pub fn to_writer<W: Write>(&mut self, mut out: W) -> io::Result<Vec<Change>> {
+        let version = self.listing.version;
+        let reuse = version == self.original_version;
+        let mut written = Vec::with_capacity(self.original.len());
+        let mut spans = Vec::with_capacity(self.entries.len());
+        write_listing(
+            &self.listing,
+            self.listing.folder_count,
+            &mut written,
+            |idx, beatmap, out| {
+                let start = out.len();
+                let entry = &self.entries[idx];
+                match entry.original_index {
+                    Some(orig) if reuse && !entry.dirty => {
+                        out.write_all(&self.original[self.spans[orig].clone()])?
+                    }
+                    _ => beatmap.wr_args(out, version)?,
+                }
+                spans.push(start..out.len());
+                Ok(())
+            },
+        )?;
+        out.write_all(&written)?;
+        out.flush()?;
+
+        let changes = self.changes();
+        self.entries = (0..self.listing.beatmaps.len())
+            .map(|idx| Entry {
+                original_index: Some(idx),
+                dirty: false,
+            })
+            .collect();
+        self.removed.clear();
+        self.header_dirty = false;
+        self.original_version = version;
+        self.original = written;
+        self.spans = spans;
+        Ok(changes)
+    }
+
+    /// Similar to `to_writer` but writes the listing to a file (ie. `osu!.db`).
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Vec<Change>> {
+        self.to_writer(BufWriter::new(File::create(path)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::listing::test::beatmap;
+
+    fn listing() -> Listing {
+        let beatmaps = (0..4)
+            .map(|n| {
+                let mut bm = beatmap();
+                bm.file_name = Some(format!("{}.osu", n));
+                bm
+            })
+            .collect();
+        Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: Some("player".to_string()),
+            beatmaps,
+            user_permissions: 1,
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let mut raw = Vec::new();
+        listing().to_writer(&mut raw).unwrap();
+        let mut tracked = TrackedListing::from_bytes(&raw).unwrap();
+        assert!(!tracked.is_dirty());
+
+        tracked.beatmap_mut(0).unwrap().local_beatmap_offset = 10;
+        let edited = tracked.modify_where(
+            |bm| bm.file_name.as_deref() == Some("2.osu"),
+            |bm| bm.tags = Some("edited".to_string()),
+        );
+        assert_eq!(edited, 1);
+        assert_eq!(tracked.remove(1).file_name.as_deref(), Some("1.osu"));
+        let mut added = beatmap();
+        added.file_name = Some("new.osu".to_string());
+        tracked.push(added);
+        assert_eq!(
+            tracked.changes(),
+            vec![
+                Change::Removed { original_index: 1 },
+                Change::Modified {
+                    index: 0,
+                    original_index: 0
+                },
+                Change::Modified {
+                    index: 1,
+                    original_index: 2
+                },
+                Change::Added { index: 3 },
+            ]
+        );
+        *tracked.header_mut().player_name = Some("other".to_string());
+        assert_eq!(tracked.changes()[0], Change::Header);
+
+        //The untouched beatmap is copied as-is, and the result reads back as edited
+        let mut saved = Vec::new();
+        let changes = tracked.changes();
+        let persisted = tracked.to_writer(&mut saved).unwrap();
+        assert_eq!(persisted, changes);
+        let reloaded = TrackedListing::from_bytes(&saved).unwrap();
+        assert_eq!(reloaded.listing(), tracked.listing());
+        assert!(!reloaded.is_dirty());
+        let (_, spans) = Listing::from_bytes_with_spans(&raw).unwrap();
+        let (_, saved_spans) = Listing::from_bytes_with_spans(&saved).unwrap();
+        assert_eq!(saved[saved_spans[2].clone()], raw[spans[3].clone()]);
+
+        //Saving again has nothing left to persist, and tracks changes from what was saved
+        assert!(!tracked.is_dirty());
+        let mut saved_again = Vec::new();
+        assert_eq!(tracked.to_writer(&mut saved_again).unwrap(), Vec::new());
+        assert_eq!(saved_again, saved);
+        tracked.beatmap_mut(2).unwrap().local_beatmap_offset = 20;
+        assert_eq!(
+            tracked.changes(),
+            vec![Change::Modified {
+                index: 2,
+                original_index: 2
+            }]
+        );
+        let mut saved_again = Vec::new();
+        tracked.to_writer(&mut saved_again).unwrap();
+        assert_eq!(
+            Listing::from_bytes(&saved_again).unwrap(),
+            *tracked.listing()
+        );
+        assert_eq!(
+            saved_again[saved_spans[0].clone()],
+            saved[saved_spans[0].clone()]
+        );
+
+        //Changing the version rewrites every beatmap
+        let mut migrated = TrackedListing::from_bytes(&raw).unwrap();
+        migrated.listing_mut().migrate_to(20140608);
+        assert_eq!(migrated.changes().len(), 5);
+        let mut saved = Vec::new();
+        migrated.to_writer(&mut saved).unwrap();
+        assert_eq!(&Listing::from_bytes(&saved).unwrap(), migrated.listing());
+    }
+}
@@ -0,0 +1,1074 @@
+//! Validation of parsed databases, catching corrupt or inconsistent values before osu! does.
+
+use crate::{
+    listing::{Beatmap, Grade, CHANGE_20140609},
+    prelude::*,
+    replay::{header_len, LifeGraphIssue},
+    score::ScoreList,
+    CollectionList, Listing, Mod, Replay,
+};
+
+/// Where an issue was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    /// The header of a listing.
+    Listing,
+    /// A beatmap within a listing, by index.
+    Beatmap(usize),
+    /// The scores of a beatmap within a score list, by index.
+    /// If the issue is specific to a single score, its index is also included.
+    Scores {
+        beatmap: usize,
+        score: Option<usize>,
+    },
+    /// A standalone replay.
+    Replay,
+    /// A collection within a collection list, by index.
+    Collection(usize),
+}
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Listing => f.write_str("listing header"),
+            Location::Beatmap(idx) => write!(f, "beatmap #{}", idx),
+            Location::Scores {
+                beatmap,
+                score: None,
+            } => write!(f, "scores of beatmap #{}", beatmap),
+            Location::Scores {
+                beatmap,
+                score: Some(score),
+            } => write!(f, "score #{} of beatmap #{}", score, beatmap),
+            Location::Replay => f.write_str("replay"),
+            Location::Collection(idx) => write!(f, "collection #{}", idx),
+        }
+    }
+}
+
+/// The different kinds of issues that can be found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IssueKind {
+    /// A difficulty value (AR, CS, HP or OD) is outside of its valid range.
+    DifficultyOutOfRange { field: &'static str, value: f32 },
+    /// A grade is recorded for a beatmap that was never played.
+    GradeWithoutPlay { mode: Mode, grade: Grade },
+    /// A timestamp lies in the future.
+    FutureTimestamp {
+        field: &'static str,
+        timestamp: OsuTimestamp,
+    },
+    /// A beatmap hash is not a 32-character hexadecimal MD5 string.
+    MalformedHash { field: &'static str, hash: String },
+    /// An uninherited timing point has a non-positive beat length.
+    NonPositiveBeatLength { timing_point: usize, value: f64 },
+    /// An inherited timing point has a non-negative slider velocity multiplier.
+    NonNegativeInheritedValue { timing_point: usize, value: f64 },
+    /// An inherited timing point appears before any uninherited timing point, so there is no bpm
+    /// for it to inherit from.
+    OrphanInheritedPoint { timing_point: usize },
+    /// Scores refer to a beatmap hash that is not present in the listing.
+    UnknownBeatmapHash { hash: Md5Hash },
+    /// A score refers to a different beatmap hash than the one it is grouped under.
+    ScoreHashMismatch {
+        expected: StoredHash,
+        found: StoredHash,
+    },
+    /// A replay action (outside the header and seed frames) goes back in time.
+    NegativeActionDelta { action: usize, delta: i64 },
+    /// A string contains a NUL character, which osu! does not expect.
+    InteriorNul { field: &'static str },
+    /// A list has more entries than its 32-bit length prefix can hold.
+    TooManyEntries { field: &'static str, count: usize },
+    /// A difficulty value cannot be stored as a single byte, as versions older than `20140609`
+    /// do.
+    UnrepresentableDifficulty { field: &'static str, value: f32 },
+    /// `mysterious_short` is present although the version does not store it, or missing although
+    /// the version requires it.
+    MysteriousShortMismatch { version: u32, present: bool },
+    /// A beatmap entry no longer takes up the same amount of bytes, so it cannot be patched in
+    /// place.
+    EntrySizeChanged { original: usize, new: usize },
+    /// A score is marked as a perfect combo although it has misses.
+    PerfectComboWithMisses { misses: u16 },
+    /// The max combo of a score is higher than the amount of hits that can give combo.
+    ComboExceedsHits { max_combo: u16, hits: u32 },
+    /// A ScoreV2 score is higher than the maximum possible with its mods.
+    ScoreAboveCap { score: u32, cap: u32 },
+    /// A score was set with Autoplay or Cinema, so it was not played by a human.
+    AutomatedMods { mods: ModSet },
+    /// The amount of judgements of a score does not match the amount of objects in its beatmap.
+    JudgementCountMismatch { judgements: u32, objects: u32 },
+    /// The replay data ends well before the beatmap does, or has too few frames to cover it.
+    ReplayLengthMismatch {
+        frames: usize,
+        duration: i64,
+        map_length: u32,
+    },
+    /// The life graph is malformed or contradicts the rest of the replay.
+    LifeGraph(LifeGraphIssue),
+}
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::IssueKind::*;
+        match self {
+            DifficultyOutOfRange { field, value } => {
+                write!(f, "{} value {} is out of range", field, value)
+            }
+            GradeWithoutPlay { mode, grade } => write!(
+                f,
+                "{:?} grade is {:?} but the beatmap was never played",
+                mode, grade
+            ),
+            FutureTimestamp { field, timestamp } => {
+                write!(f, "{} ({}) is in the future", field, timestamp)
+            }
+            MalformedHash { field, hash } => write!(f, "{} {:?} is not an MD5 hash", field, hash),
+            NonPositiveBeatLength {
+                timing_point,
+                value,
+            } => write!(
+                f,
+                "uninherited timing point #{} has non-positive beat length {}",
+                timing_point, value
+            ),
+            NonNegativeInheritedValue {
+                timing_point,
+                value,
+            } => write!(
+                f,
+                "inherited timing point #{} has non-negative value {}",
+                timing_point, value
+            ),
+            OrphanInheritedPoint { timing_point } => write!(
+                f,
+                "inherited timing point #{} has no uninherited timing point before it",
+                timing_point
+            ),
+            UnknownBeatmapHash { hash } => {
+                write!(f, "beatmap hash {} is not present in the listing", hash)
+            }
+            ScoreHashMismatch { expected, found } => write!(
+                f,
+                "score beatmap hash {:?} does not match the expected {:?}",
+                found, expected
+            ),
+            NegativeActionDelta { action, delta } => {
+                write!(f, "action #{} has negative delta {}", action, delta)
+            }
+            InteriorNul { field } => write!(f, "{} contains a NUL character", field),
+            TooManyEntries { field, count } => write!(
+                f,
+                "{} has {} entries, more than can be stored",
+                field, count
+            ),
+            UnrepresentableDifficulty { field, value } => write!(
+                f,
+                "{} value {} is not a whole number between 0 and 255",
+                field, value
+            ),
+            MysteriousShortMismatch {
+                version,
+                present: true,
+            } => write!(
+                f,
+                "mysterious_short is set but version {} does not store it",
+                version
+            ),
+            MysteriousShortMismatch {
+                version,
+                present: false,
+            } => write!(
+                f,
+                "mysterious_short is missing but version {} requires it",
+                version
+            ),
+            EntrySizeChanged { original, new } => write!(
+                f,
+                "entry takes up {} bytes instead of {}, so it cannot be patched in place",
+                new, original
+            ),
+            PerfectComboWithMisses { misses } => {
+                write!(f, "marked as a perfect combo but has {} misses", misses)
+            }
+            ComboExceedsHits { max_combo, hits } => write!(
+                f,
+                "max combo {} is higher than the {} hits that give combo",
+                max_combo, hits
+            ),
+            ScoreAboveCap { score, cap } => write!(
+                f,
+                "score {} is higher than the maximum {} for its mods",
+                score, cap
+            ),
+            AutomatedMods { mods } => {
+                f.write_str("mods ")?;
+                for acronym in mods.acronyms() {
+                    f.write_str(acronym)?;
+                }
+                f.write_str(" include Autoplay or Cinema")
+            }
+            JudgementCountMismatch {
+                judgements,
+                objects,
+            } => write!(
+                f,
+                "{} judgements but the beatmap has {} objects",
+                judgements, objects
+            ),
+            ReplayLengthMismatch {
+                frames,
+                duration,
+                map_length,
+            } => write!(
+                f,
+                "replay data has {} frames over {}ms but the beatmap is {}ms long",
+                frames, duration, map_length
+            ),
+            LifeGraph(issue) => write!(f, "{}", issue),
+        }
+    }
+}
+
+/// A single issue found while validating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub location: Location,
+    pub kind: IssueKind,
+}
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.kind)
+    }
+}
+
+impl Listing {
+    /// Check the listing for corrupt or inconsistent values.
+    ///
+    /// An empty list means no issues were found.
+    pub fn validate(&self) -> Vec<Issue> {
+        let now = OsuTimestamp::now();
+        let mut issues = Vec::new();
+        for (idx, beatmap) in self.beatmaps.iter().enumerate() {
+            validate_beatmap(beatmap, now, &mut |kind| {
+                issues.push(Issue {
+                    location: Location::Beatmap(idx),
+                    kind,
+                })
+            });
+        }
+        issues
+    }
+}
+
+fn validate_beatmap(beatmap: &Beatmap, now: OsuTimestamp, issue: &mut dyn FnMut(IssueKind)) {
+    let max_cs = if beatmap.mode == Mode::Mania {
+        //Key count in mania, up to 18 keys in co-op
+        18.0
+    } else {
+        10.0
+    };
+    for &(field, value, max) in &[
+        ("approach_rate", beatmap.approach_rate, 10.0),
+        ("circle_size", beatmap.circle_size, max_cs),
+        ("hp_drain", beatmap.hp_drain, 10.0),
+        ("overall_difficulty", beatmap.overall_difficulty, 10.0),
+    ] {
+        if !(0.0..=max).contains(&value) {
+            issue(IssueKind::DifficultyOutOfRange { field, value });
+        }
+    }
+
+    if beatmap.last_played.is_none() {
+        for &(mode, grade) in &[
+            (Mode::Standard, beatmap.std_grade),
+            (Mode::Taiko, beatmap.taiko_grade),
+            (Mode::CatchTheBeat, beatmap.ctb_grade),
+            (Mode::Mania, beatmap.mania_grade),
+        ] {
+            if grade != Grade::Unplayed {
+                issue(IssueKind::GradeWithoutPlay { mode, grade });
+            }
+        }
+    }
+
+    let timestamps = [
+        ("last_modified", Some(beatmap.last_modified)),
+        ("last_played", beatmap.last_played),
+        ("last_online_check", Some(beatmap.last_online_check)),
+    ];
+    for &(field, timestamp) in &timestamps {
+        if let Some(timestamp) = timestamp {
+            if timestamp > now {
+                issue(IssueKind::FutureTimestamp { field, timestamp });
+            }
+        }
+    }
+
+    check_hash("hash", &beatmap.hash, issue);
+
+    let mut has_uninherited = false;
+    for (idx, point) in beatmap.timing_points.iter().enumerate() {
+        //`inherits` is set on uninherited timing points, which define an absolute beat length
+        if point.inherits {
+            has_uninherited = true;
+            if point.bpm <= 0.0 {
+                issue(IssueKind::NonPositiveBeatLength {
+                    timing_point: idx,
+                    value: point.bpm,
+                });
+            }
+        } else {
+            if !has_uninherited {
+                issue(IssueKind::OrphanInheritedPoint { timing_point: idx });
+            }
+            if point.bpm >= 0.0 {
+                issue(IssueKind::NonNegativeInheritedValue {
+                    timing_point: idx,
+                    value: point.bpm,
+                });
+            }
+        }
+    }
+}
+
+fn check_hash(field: &'static str, hash: &StoredHash, issue: &mut dyn FnMut(IssueKind)) {
+    if let StoredHash::Raw(raw) = hash {
+        if hash.is_malformed() {
+            issue(IssueKind::MalformedHash {
+                field,
+                hash: raw.clone(),
+            });
+        }
+    }
+}
+
+impl ScoreList {
+    /// Check the score list for corrupt or inconsistent values, including scores on beatmaps that
+    /// are not present in the given listing.
+    ///
+    /// An empty list means no issues were found.
+    pub fn validate(&self, listing: &Listing) -> Vec<Issue> {
+        let known = listing.hash_index();
+        let mut issues = Vec::new();
+        for (bm_idx, beatmap) in self.beatmaps.iter().enumerate() {
+            if let Some(hash) = beatmap.hash.md5() {
+                if !known.contains(&hash) {
+                    issues.push(Issue {
+                        location: Location::Scores {
+                            beatmap: bm_idx,
+                            score: None,
+                        },
+                        kind: IssueKind::UnknownBeatmapHash { hash },
+                    });
+                }
+            }
+            for (score_idx, score) in beatmap.scores.iter().enumerate() {
+                let location = Location::Scores {
+                    beatmap: bm_idx,
+                    score: Some(score_idx),
+                };
+                if score.beatmap_hash != beatmap.hash {
+                    issues.push(Issue {
+                        location,
+                        kind: IssueKind::ScoreHashMismatch {
+                            expected: beatmap.hash.clone(),
+                            found: score.beatmap_hash.clone(),
+                        },
+                    });
+                }
+                issues.extend(
+                    score
+                        .validate()
+                        .into_iter()
+                        .map(|issue| Issue { location, ..issue }),
+                );
+            }
+        }
+        issues
+    }
+}
+
+impl Replay {
+    /// Check the replay for corrupt or inconsistent values.
+    ///
+    /// An empty list means no issues were found.
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let mut issue = |kind| {
+            issues.push(Issue {
+                location: Location::Replay,
+                kind,
+            })
+        };
+        if self.timestamp > OsuTimestamp::now() {
+            issue(IssueKind::FutureTimestamp {
+                field: "timestamp",
+                timestamp: self.timestamp,
+            });
+        }
+        check_hash("beatmap_hash", &self.beatmap_hash, &mut issue);
+        check_hash("replay_hash", &self.replay_hash, &mut issue);
+        if let Some(actions) = &self.replay_data {
+            for (idx, action) in actions.iter().enumerate().skip(header_len(actions)) {
+                if action.delta < 0 && !action.is_seed() {
+                    issue(IssueKind::NegativeActionDelta {
+                        action: idx,
+                        delta: action.delta,
+                    });
+                }
+            }
+        }
+        issues
+    }
+}
+
+/// How far before the end of its beatmap a replay may end, in milliseconds, before it is
+/// considered too short.
+const REPLAY_LENGTH_TOLERANCE: i64 = 1000;
+
+/// The ScoreV2 score multiplier of each mod that changes it.
+///
+/// Nightcore always goes with DoubleTime, which carries its multiplier.
+const SCORE_V2_MULTIPLIERS: [(Mod, f64); 8] = [
+    (Mod::Easy, 0.5),
+    (Mod::HalfTime, 0.3),
+    (Mod::SpunOut, 0.9),
+    (Mod::HardRock, 1.1),
+    (Mod::DoubleTime, 1.2),
+    (Mod::Hidden, 1.06),
+    (Mod::FadeIn, 1.06),
+    (Mod::Flashlight, 1.12),
+];
+
+impl Replay {
+    /// Check the score for data that cannot come from a legitimate play, such as a perfect combo
+    /// with misses, Autoplay or Cinema mods, a max combo higher than the hits that give combo (in
+    /// taiko and catch), a ScoreV2 score above the maximum for its mods or a malformed life graph
+    /// (see `validate_life_graph`).
+    ///
+    /// The ScoreV2 maximum does not account for spinner bonus, so plays on beatmaps with spinners
+    /// may be flagged falsely.
+    /// NoFail does not lower the maximum, as it has no score penalty under ScoreV2.
+    /// Use `sanity_check_with` to also check the score against its beatmap.
+    /// An empty list means no issues were found.
+    pub fn sanity_check(&self) -> Vec<Issue> {
+        self.sanity_issues(None)
+    }
+
+    /// Similar to `sanity_check`, but also checks the score against the beatmap it was played on.
+    ///
+    /// On osu!standard beatmaps the amount of judgements must match the amount of objects, and
+    /// the replay data, if present, must cover the whole beatmap with at least a frame per
+    /// second.
+    /// Failed plays, whose replays end early, are flagged as well.
+    /// The ScoreV2 maximum is not checked on beatmaps with spinners.
+    pub fn sanity_check_with(&self, beatmap: &Beatmap) -> Vec<Issue> {
+        self.sanity_issues(Some(beatmap))
+    }
+
+    fn sanity_issues(&self, beatmap: Option<&Beatmap>) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let mut issue = |kind| {
+            issues.push(Issue {
+                location: Location::Replay,
+                kind,
+            })
+        };
+        if self.perfect_combo && self.count_miss > 0 {
+            issue(IssueKind::PerfectComboWithMisses {
+                misses: self.count_miss,
+            });
+        }
+        if self.mods.contains(Mod::Autoplay) || self.mods.contains(Mod::LastMod) {
+            issue(IssueKind::AutomatedMods { mods: self.mods });
+        }
+        for life_graph_issue in self.validate_life_graph() {
+            issue(IssueKind::LifeGraph(life_graph_issue));
+        }
+        //Slider ticks in osu!standard and hold ticks in osu!mania give combo too, so there is no
+        //upper bound for them without the beatmap contents
+        if let Mode::Taiko | Mode::CatchTheBeat = self.mode {
+            let hits = self.count_300 as u32 + self.count_100 as u32;
+            if self.max_combo as u32 > hits {
+                issue(IssueKind::ComboExceedsHits {
+                    max_combo: self.max_combo,
+                    hits,
+                });
+            }
+        }
+        let has_spinners = beatmap.is_some_and(|bm| bm.spinner_count > 0);
+        if self.mods.contains(Mod::ScoreV2) && !has_spinners {
+            let multiplier: f64 = SCORE_V2_MULTIPLIERS
+                .iter()
+                .filter(|(m, _)| self.mods.contains(*m))
+                .map(|&(_, multiplier)| multiplier)
+                .product();
+            let cap = (1_000_000.0 * multiplier).round() as u32;
+            if self.score > cap {
+                issue(IssueKind::ScoreAboveCap {
+                    score: self.score,
+                    cap,
+                });
+            }
+        }
+
+        let beatmap = match beatmap {
+            Some(beatmap) => beatmap,
+            None => return issues,
+        };
+        if self.mode == Mode::Standard && beatmap.mode == Mode::Standard {
+            let judgements = [
+                self.count_300,
+                self.count_100,
+                self.count_50,
+                self.count_miss,
+            ]
+            .iter()
+            .map(|&count| count as u32)
+            .sum();
+            let objects = beatmap.hitcircle_count as u32
+                + beatmap.slider_count as u32
+                + beatmap.spinner_count as u32;
+            if judgements != objects {
+                issue(IssueKind::JudgementCountMismatch {
+                    judgements,
+                    objects,
+                });
+            }
+        }
+        if self.replay_data.is_some() {
+            let frames = self.timed_actions().count();
+            let duration = self.timed_actions().last().map_or(0, |(time, _)| time);
+            let map_length = beatmap.total_time;
+            if duration + REPLAY_LENGTH_TOLERANCE < map_length as i64
+                || frames < (map_length / 1000) as usize
+            {
+                issue(IssueKind::ReplayLengthMismatch {
+                    frames,
+                    duration,
+                    map_length,
+                });
+            }
+        }
+        issues
+    }
+}
+
+impl Listing {
+    /// Check that the listing can be written in the layout of its version and read back by osu!.
+    ///
+    /// Unlike `validate`, this only looks for values that cannot be represented in the binary
+    /// format, which `migrate_to` fixes for version-dependent data.
+    /// An empty list means the listing can be written as-is.
+    pub fn check_writable(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let mut issue = |location, kind| issues.push(Issue { location, kind });
+        check_str("player_name", &self.player_name, &mut |kind| {
+            issue(Location::Listing, kind)
+        });
+        check_len("beatmaps", self.beatmaps.len(), &mut |kind| {
+            issue(Location::Listing, kind)
+        });
+        for (idx, beatmap) in self.beatmaps.iter().enumerate() {
+            check_beatmap(beatmap, self.version, &mut |kind| {
+                issue(Location::Beatmap(idx), kind)
+            });
+        }
+        issues
+    }
+
+    /// Similar to `to_writer`, but first checks that the listing can be written with
+    /// `check_writable`, and fails with `Error::Unwritable` instead of writing anything if it
+    /// cannot.
+    pub fn write_checked<W: Write>(&self, out: W) -> Result<(), Error> {
+        unwritable(self.check_writable())?;
+        Ok(self.to_writer(out)?)
+    }
+}
+
+fn check_beatmap(beatmap: &Beatmap, version: u32, issue: &mut dyn FnMut(IssueKind)) {
+    for &(field, value) in &[
+        ("artist_ascii", &beatmap.artist_ascii),
+        ("artist_unicode", &beatmap.artist_unicode),
+        ("title_ascii", &beatmap.title_ascii),
+        ("title_unicode", &beatmap.title_unicode),
+        ("creator", &beatmap.creator),
+        ("difficulty_name", &beatmap.difficulty_name),
+        ("audio", &beatmap.audio),
+        ("file_name", &beatmap.file_name),
+        ("song_source", &beatmap.song_source),
+        ("tags", &beatmap.tags),
+        ("title_font", &beatmap.title_font),
+        ("folder_name", &beatmap.folder_name),
+    ] {
+        check_str(field, value, issue);
+    }
+    for &(field, len) in &[
+        ("std_ratings", beatmap.std_ratings.len()),
+        ("taiko_ratings", beatmap.taiko_ratings.len()),
+        ("ctb_ratings", beatmap.ctb_ratings.len()),
+        ("mania_ratings", beatmap.mania_ratings.len()),
+        ("timing_points", beatmap.timing_points.len()),
+    ] {
+        check_len(field, len, issue);
+    }
+    //Old versions store difficulty values as bytes, so anything else would be silently mangled
+    if version < CHANGE_20140609 {
+        for &(field, value) in &[
+            ("approach_rate", beatmap.approach_rate),
+            ("circle_size", beatmap.circle_size),
+            ("hp_drain", beatmap.hp_drain),
+            ("overall_difficulty", beatmap.overall_difficulty),
+        ] {
+            if value.fract() != 0.0 || !(0.0..=u8::MAX as f32).contains(&value) {
+                issue(IssueKind::UnrepresentableDifficulty { field, value });
+            }
+        }
+    }
+    let present = beatmap.mysterious_short.is_some();
+    if present != (version < CHANGE_20140609) {
+        issue(IssueKind::MysteriousShortMismatch { version, present });
+    }
+}
+
+impl ScoreList {
+    /// Check that the score list can be written and read back by osu!.
+    ///
+    /// An empty list means the score list can be written as-is.
+    pub fn check_writable(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        //There is no location for the score list as a whole, so blame its first beatmap
+        check_len("beatmaps", self.beatmaps.len(), &mut |kind| {
+            issues.push(Issue {
+                location: Location::Scores {
+                    beatmap: 0,
+                    score: None,
+                },
+                kind,
+            })
+        });
+        for (bm_idx, beatmap) in self.beatmaps.iter().enumerate() {
+            check_len("scores", beatmap.scores.len(), &mut |kind| {
+                issues.push(Issue {
+                    location: Location::Scores {
+                        beatmap: bm_idx,
+                        score: None,
+                    },
+                    kind,
+                })
+            });
+            for (score_idx, score) in beatmap.scores.iter().enumerate() {
+                check_replay(score, &mut |kind| {
+                    issues.push(Issue {
+                        location: Location::Scores {
+                            beatmap: bm_idx,
+                            score: Some(score_idx),
+                        },
+                        kind,
+                    })
+                });
+            }
+        }
+        issues
+    }
+
+    /// Similar to `to_writer`, but first checks that the score list can be written with
+    /// `check_writable`, and fails with `Error::Unwritable` instead of writing anything if it
+    /// cannot.
+    pub fn write_checked<W: Write>(&self, out: W) -> Result<(), Error> {
+        unwritable(self.check_writable())?;
+        Ok(self.to_writer(out)?)
+    }
+}
+
+impl Replay {
+    /// Check that the replay can be written and read back by osu!.
+    ///
+    /// An empty list means the replay can be written as-is.
+    pub fn check_writable(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        check_replay(self, &mut |kind| {
+            issues.push(Issue {
+                location: Location::Replay,
+                kind,
+            })
+        });
+        issues
+    }
+
+    /// Similar to `to_writer`, but first checks that the replay can be written with
+    /// `check_writable`, and fails with `Error::Unwritable` instead of writing anything if it
+    /// cannot.
+    pub fn write_checked<W: Write>(
+        &self,
+        out: W,
+        compression_level: Option<u32>,
+    ) -> Result<(), Error> {
+        unwritable(self.check_writable())?;
+        Ok(self.to_writer(out, compression_level)?)
+    }
+}
+
+fn check_replay(replay: &Replay, issue: &mut dyn FnMut(IssueKind)) {
+    check_str("player_name", &replay.player_name, issue);
+    check_str("life_graph", &replay.life_graph, issue);
+    if let Some(raw) = &replay.raw_replay_data {
+        check_len("raw_replay_data", raw.len(), issue);
+    }
+}
+
+impl CollectionList {
+    /// Check that the collection list can be written and read back by osu!.
+    ///
+    /// An empty list means the collection list can be written as-is.
+    pub fn check_writable(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        //There is no location for the collection list as a whole, so blame its first collection
+        check_len("collections", self.collections.len(), &mut |kind| {
+            issues.push(Issue {
+                location: Location::Collection(0),
+                kind,
+            })
+        });
+        for (idx, collection) in self.collections.iter().enumerate() {
+            let mut issue = |kind| {
+                issues.push(Issue {
+                    location: Location::Collection(idx),
+                    kind,
+                })
+            };
+            check_str("name", &collection.name, &mut issue);
+            check_len(
+                "beatmap_hashes",
+                collection.beatmap_hashes.len(),
+                &mut issue,
+            );
+        }
+        issues
+    }
+
+    /// Similar to `to_writer`, but first checks that the collection list can be written with
+    /// `check_writable`, and fails with `Error::Unwritable` instead of writing anything if it
+    /// cannot.
+    pub fn write_checked<W: Write>(&self, out: W) -> Result<(), Error> {
+        unwritable(self.check_writable())?;
+        Ok(self.to_writer(out)?)
+    }
+}
+
+fn check_str(field: &'static str, value: &Option<String>, issue: &mut dyn FnMut(IssueKind)) {
+    if value.as_deref().is_some_and(|s| s.contains('\0')) {
+        issue(IssueKind::InteriorNul { field });
+    }
+}
+
+fn check_len(field: &'static str, count: usize, issue: &mut dyn FnMut(IssueKind)) {
+    if count > u32::MAX as usize {
+        issue(IssueKind::TooManyEntries { field, count });
+    }
+}
+
+fn unwritable(issues: Vec<Issue>) -> Result<(), Error> {
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Unwritable(issues))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ids::test::hash,
+        listing::{test::beatmap, TimingPoint},
+        replay::{
+            test::{action, replay_with},
+            SEED_FRAME_DELTA,
+        },
+        score::BeatmapScores,
+    };
+
+    #[test]
+    fn malformed_hash() {
+        let mut listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap(), beatmap()],
+            user_permissions: 0,
+        };
+        listing.beatmaps[0].hash = StoredHash::Raw("not a hash".to_string());
+        listing.beatmaps[1].hash = StoredHash::Raw("0123456789ABCDEF0123456789ABCDEF".to_string());
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+        let parsed = Listing::from_bytes(&raw).unwrap();
+        assert_eq!(parsed, listing);
+        let issues: Vec<_> = parsed
+            .validate()
+            .into_iter()
+            .filter(|issue| matches!(issue.kind, IssueKind::MalformedHash { .. }))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![Issue {
+                location: Location::Beatmap(0),
+                kind: IssueKind::MalformedHash {
+                    field: "hash",
+                    hash: "not a hash".to_string()
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn write_checked() {
+        let mut listing = Listing {
+            version: 20140101,
+            folder_count: 1,
+            unban_date: None,
+            player_name: Some("pla\0yer".to_string()),
+            beatmaps: vec![beatmap()],
+            user_permissions: 0,
+        };
+        listing.beatmaps[0].approach_rate = 9.5;
+        listing.beatmaps[0].hp_drain = 6.0;
+        let mut out = Vec::new();
+        match listing.write_checked(&mut out) {
+            Err(Error::Unwritable(issues)) => assert_eq!(
+                issues,
+                vec![
+                    Issue {
+                        location: Location::Listing,
+                        kind: IssueKind::InteriorNul {
+                            field: "player_name"
+                        },
+                    },
+                    Issue {
+                        location: Location::Beatmap(0),
+                        kind: IssueKind::UnrepresentableDifficulty {
+                            field: "approach_rate",
+                            value: 9.5
+                        },
+                    },
+                    Issue {
+                        location: Location::Beatmap(0),
+                        kind: IssueKind::MysteriousShortMismatch {
+                            version: 20140101,
+                            present: false
+                        },
+                    },
+                ]
+            ),
+            other => panic!("expected unwritable listing, got {:?}", other),
+        }
+        assert!(out.is_empty());
+
+        listing.player_name = Some("player".to_string());
+        listing.migrate_to(20140101);
+        listing.write_checked(&mut out).unwrap();
+        assert!(!out.is_empty());
+    }
+
+    #[test]
+    fn sanity_check() {
+        let kinds = |issues: Vec<Issue>| -> Vec<IssueKind> {
+            issues.into_iter().map(|issue| issue.kind).collect()
+        };
+        let mut bm = beatmap();
+        bm.hitcircle_count = 8;
+        bm.slider_count = 2;
+        bm.spinner_count = 0;
+        bm.total_time = 3000;
+        let mut replay = replay_with((0..4).map(|_| action(1000, 0.0)).collect());
+        replay.count_300 = 9;
+        replay.count_miss = 1;
+        replay.max_combo = 12;
+        replay.score = 900_000;
+        replay.mods = ModSet::empty().with(Mod::ScoreV2).with(Mod::NoFail);
+        assert_eq!(replay.sanity_check_with(&bm), Vec::new());
+
+        replay.perfect_combo = true;
+        replay.count_300 = 10;
+        replay.mods = replay.mods.with(Mod::HalfTime).with(Mod::Autoplay);
+        replay.life_graph = Some("0|1,500|2,".to_string());
+        assert_eq!(
+            kinds(replay.sanity_check()),
+            vec![
+                IssueKind::PerfectComboWithMisses { misses: 1 },
+                IssueKind::AutomatedMods { mods: replay.mods },
+                IssueKind::LifeGraph(LifeGraphIssue::LifeOutOfRange {
+                    entry: 1,
+                    life: 2.0,
+                }),
+                IssueKind::ScoreAboveCap {
+                    score: 900_000,
+                    cap: 300_000,
+                },
+            ]
+        );
+
+        let mut replay = replay_with(vec![action(1000, 0.0)]);
+        replay.mode = Mode::Taiko;
+        replay.count_300 = 5;
+        replay.max_combo = 6;
+        assert_eq!(
+            kinds(replay.sanity_check_with(&bm)),
+            vec![
+                IssueKind::ComboExceedsHits {
+                    max_combo: 6,
+                    hits: 5,
+                },
+                IssueKind::ReplayLengthMismatch {
+                    frames: 1,
+                    duration: 1000,
+                    map_length: 3000,
+                },
+            ]
+        );
+        replay.mode = Mode::Standard;
+        replay.max_combo = 0;
+        replay.replay_data = None;
+        assert_eq!(
+            kinds(replay.sanity_check_with(&bm)),
+            vec![IssueKind::JudgementCountMismatch {
+                judgements: 5,
+                objects: 10,
+            }]
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let kinds = |issues: Vec<Issue>| -> Vec<(Location, IssueKind)> {
+            issues
+                .into_iter()
+                .map(|issue| (issue.location, issue.kind))
+                .collect()
+        };
+        //2200-01-01
+        let future = OsuTimestamp::from_unix_millis(7_258_118_400_000);
+        let mut bm = beatmap();
+        bm.approach_rate = 11.0;
+        bm.mode = Mode::Mania;
+        bm.circle_size = 18.0;
+        bm.last_played = None;
+        bm.last_modified = future;
+        let point = |bpm, inherits| TimingPoint {
+            bpm,
+            offset: 0.0,
+            inherits,
+        };
+        bm.timing_points = vec![point(-100.0, false), point(0.0, true), point(50.0, false)];
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap(), bm],
+            user_permissions: 0,
+        };
+        let at = Location::Beatmap(1);
+        assert_eq!(
+            kinds(listing.validate()),
+            vec![
+                (
+                    at,
+                    IssueKind::DifficultyOutOfRange {
+                        field: "approach_rate",
+                        value: 11.0,
+                    }
+                ),
+                (
+                    at,
+                    IssueKind::GradeWithoutPlay {
+                        mode: Mode::Standard,
+                        grade: Grade::A,
+                    }
+                ),
+                (
+                    at,
+                    IssueKind::FutureTimestamp {
+                        field: "last_modified",
+                        timestamp: future,
+                    }
+                ),
+                (at, IssueKind::OrphanInheritedPoint { timing_point: 0 }),
+                (
+                    at,
+                    IssueKind::NonPositiveBeatLength {
+                        timing_point: 1,
+                        value: 0.0,
+                    }
+                ),
+                (
+                    at,
+                    IssueKind::NonNegativeInheritedValue {
+                        timing_point: 2,
+                        value: 50.0,
+                    }
+                ),
+            ]
+        );
+
+        //Only the negative delta after the header frames counts, the seed frame is expected
+        let mut replay = replay_with(vec![
+            action(0, 0.0),
+            action(-1, 0.0),
+            action(10, 0.0),
+            action(-5, 0.0),
+            action(SEED_FRAME_DELTA, 0.0),
+        ]);
+        replay.beatmap_hash = hash(1).into();
+        let negative = IssueKind::NegativeActionDelta {
+            action: 3,
+            delta: -5,
+        };
+        assert_eq!(
+            kinds(replay.validate()),
+            vec![(Location::Replay, negative.clone())]
+        );
+
+        let known = listing.beatmaps[0].hash.clone();
+        let mut valid = replay_with(Vec::new());
+        valid.beatmap_hash = known.clone();
+        let scores = ScoreList {
+            version: 20211103,
+            beatmaps: vec![
+                BeatmapScores {
+                    hash: known,
+                    scores: vec![valid],
+                },
+                BeatmapScores {
+                    hash: hash(2).into(),
+                    scores: vec![replay],
+                },
+            ],
+        };
+        let score = Location::Scores {
+            beatmap: 1,
+            score: Some(0),
+        };
+        assert_eq!(
+            kinds(scores.validate(&listing)),
+            vec![
+                (
+                    Location::Scores {
+                        beatmap: 1,
+                        score: None,
+                    },
+                    IssueKind::UnknownBeatmapHash { hash: hash(2) }
+                ),
+                (
+                    score,
+                    IssueKind::ScoreHashMismatch {
+                        expected: hash(2).into(),
+                        found: hash(1).into(),
+                    }
+                ),
+                (score, negative),
+            ]
+        );
+    }
+}
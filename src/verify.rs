@@ -0,0 +1,166 @@
+//! Verification of beatmap hashes against the `.osu` files on disk.
+//!
+//! Only available with the `hash` feature enabled.
+
+use crate::{listing::Beatmap, prelude::*, Listing};
+use std::{path::PathBuf, thread};
+
+/// The result of checking a beatmap hash against its `.osu` file.
+#[derive(Debug)]
+pub enum HashCheck {
+    /// The file hash matches the recorded hash.
+    Match,
+    /// The file hash does not match the recorded hash, which usually means the `.osu` file was
+    /// edited locally or is corrupt.
+    Mismatch {
+        expected: Option<Md5Hash>,
+        actual: Md5Hash,
+    },
+    /// The beatmap does not record a folder name or a file name, so there is no file to check.
+    NoPath,
+    /// The `.osu` file could not be read.
+    Unreadable(io::Error),
+}
+impl HashCheck {
+    /// Whether the hash matches.
+    pub fn is_match(&self) -> bool {
+        matches!(self, HashCheck::Match)
+    }
+}
+
+impl Md5Hash {
+    /// Compute the MD5 hash of some bytes.
+    ///
+    /// Only available with the `hash` feature enabled.
+    pub fn compute(bytes: &[u8]) -> Md5Hash {
+        Md5Hash(md5::compute(bytes).0)
+    }
+}
+
+impl Beatmap {
+    /// Get the path of the `.osu` file for this beatmap, given the path to the "Songs" folder.
+    ///
+    /// Returns `None` if the beatmap has no folder or file name.
+    pub fn osu_path<P: AsRef<Path>>(&self, songs_dir: P) -> Option<PathBuf> {
+        let folder = self.folder_name.as_deref()?;
+        let file = self.file_name.as_deref()?;
+        Some(songs_dir.as_ref().join(folder).join(file))
+    }
+
+    /// Recompute the MD5 hash of the `.osu` file for this beatmap, and compare it against the
+    /// recorded hash.
+    ///
+    /// Only available with the `hash` feature enabled.
+    pub fn verify_hash<P: AsRef<Path>>(&self, songs_dir: P) -> HashCheck {
+        let path = match self.osu_path(songs_dir) {
+            Some(path) => path,
+            None => return HashCheck::NoPath,
+        };
+        let actual = match fs::read(path) {
+            Ok(bytes) => Md5Hash::compute(&bytes),
+            Err(err) => return HashCheck::Unreadable(err),
+        };
+        if self.hash.md5() == Some(actual) {
+            HashCheck::Match
+        } else {
+            HashCheck::Mismatch {
+                expected: self.hash.md5(),
+                actual,
+            }
+        }
+    }
+}
+
+impl Listing {
+    /// Verify the hashes of all beatmaps in the listing against their `.osu` files, in parallel.
+    ///
+    /// Returns the index and check result of every beatmap that does not match.
+    ///
+    /// Only available with the `hash` feature enabled.
+    pub fn verify_all_hashes<P: AsRef<Path>>(&self, songs_dir: P) -> Vec<(usize, HashCheck)> {
+        let songs_dir = songs_dir.as_ref();
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_len = self.beatmaps.len().div_ceil(threads).max(1);
+        thread::scope(|scope| {
+            let workers: Vec<_> = self
+                .beatmaps
+                .chunks(chunk_len)
+                .enumerate()
+                .map(|(chunk_idx, chunk)| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .enumerate()
+                            .map(|(idx, beatmap)| {
+                                (chunk_idx * chunk_len + idx, beatmap.verify_hash(songs_dir))
+                            })
+                            .filter(|(_idx, check)| !check.is_match())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("hash verification thread panicked"))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::listing::test::beatmap;
+
+    #[test]
+    fn verify_all_hashes() {
+        let songs = std::env::temp_dir().join(format!("osu-db-verify-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&songs);
+        fs::create_dir_all(songs.join("set")).unwrap();
+        let contents = b"osu file format v14\n";
+        fs::write(songs.join("set").join("good.osu"), contents).unwrap();
+        fs::write(
+            songs.join("set").join("edited.osu"),
+            b"osu file format v14\n\n",
+        )
+        .unwrap();
+        let entry = |file: Option<&str>| {
+            let mut bm = beatmap();
+            bm.folder_name = Some("set".to_string());
+            bm.file_name = file.map(str::to_string);
+            bm.hash = Md5Hash::compute(contents).into();
+            bm
+        };
+        let listing = Listing {
+            version: 20211103,
+            folder_count: 1,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![
+                entry(Some("good.osu")),
+                entry(Some("edited.osu")),
+                entry(Some("missing.osu")),
+                entry(None),
+            ],
+            user_permissions: 0,
+        };
+        assert!(listing.beatmaps[0].verify_hash(&songs).is_match());
+
+        let checks = listing.verify_all_hashes(&songs);
+        let indices: Vec<usize> = checks.iter().map(|(idx, _)| *idx).collect();
+        assert_eq!(indices, vec![1, 2, 3]);
+        match &checks[0].1 {
+            HashCheck::Mismatch { expected, actual } => {
+                assert_eq!(*expected, Some(Md5Hash::compute(contents)));
+                assert_ne!(Some(*actual), *expected);
+            }
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+        match &checks[1].1 {
+            HashCheck::Unreadable(err) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+            other => panic!("expected an unreadable file, got {:?}", other),
+        }
+        assert!(matches!(checks[2].1, HashCheck::NoPath));
+        fs::remove_dir_all(&songs).unwrap();
+    }
+}
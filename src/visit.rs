@@ -0,0 +1,488 @@
+//! Event-based parsing of `osu!.db` listings, for consumers that want to build their own data
+//! structures in a single pass instead of going through `Listing`.
+//!
+//! Parsing reports every field to a `ListingVisitor` as it is read, in file order, with strings
+//! borrowed from the input.
+//! Nothing is allocated per beatmap, so counting or collecting a handful of fields is much cheaper
+//! than parsing a full `Listing`.
+//! `Listing` is itself parsed by a visitor, so both always agree on the layout of a listing.
+
+use crate::{
+    listing::{
+        build_option, difficulty_value, grade, ranked_status, star_rating, timing_point, Grade,
+        RankedStatus, SizePrefixes, TimingPoint, CHANGE_20140609, CHANGE_20191106,
+        LATEST_KNOWN_VERSION,
+    },
+    prelude::*,
+};
+
+/// A header field of a listing, reported through `ListingVisitor::header`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderField<'a> {
+    Version(u32),
+    FolderCount(u32),
+    UnbanDate(Option<OsuTimestamp>),
+    PlayerName(Option<&'a str>),
+    /// The amount of beatmaps that follow.
+    BeatmapCount(u32),
+    /// Reported last, after every beatmap.
+    UserPermissions(u32),
+}
+
+/// A single field of a beatmap entry, reported through `ListingVisitor::beatmap_field`.
+///
+/// Fields match those of `listing::Beatmap`, except for star ratings and timing points, which are
+/// reported one by one through their own callbacks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BeatmapField<'a> {
+    ArtistAscii(Option<&'a str>),
+    ArtistUnicode(Option<&'a str>),
+    TitleAscii(Option<&'a str>),
+    TitleUnicode(Option<&'a str>),
+    Creator(Option<&'a str>),
+    DifficultyName(Option<&'a str>),
+    Audio(Option<&'a str>),
+    /// The hash as stored, which `StoredHash::from_stored` interprets.
+    Hash(Option<&'a str>),
+    FileName(Option<&'a str>),
+    Status(RankedStatus),
+    HitcircleCount(u16),
+    SliderCount(u16),
+    SpinnerCount(u16),
+    LastModified(OsuTimestamp),
+    ApproachRate(f32),
+    CircleSize(f32),
+    HpDrain(f32),
+    OverallDifficulty(f32),
+    SliderVelocity(f64),
+    DrainTime(u32),
+    TotalTime(u32),
+    PreviewTime(u32),
+    BeatmapId(BeatmapId),
+    BeatmapsetId(BeatmapsetId),
+    ThreadId(u32),
+    Grade(Mode, Grade),
+    LocalBeatmapOffset(u16),
+    StackLeniency(f32),
+    Mode(Mode),
+    SongSource(Option<&'a str>),
+    Tags(Option<&'a str>),
+    OnlineOffset(u16),
+    TitleFont(Option<&'a str>),
+    LastPlayed(Option<OsuTimestamp>),
+    IsOsz2(bool),
+    FolderName(Option<&'a str>),
+    LastOnlineCheck(OsuTimestamp),
+    IgnoreSounds(bool),
+    IgnoreSkin(bool),
+    DisableStoryboard(bool),
+    DisableVideo(bool),
+    VisualOverride(bool),
+    MysteriousShort(Option<u16>),
+    MysteriousLastModified(u32),
+    ManiaScrollSpeed(u8),
+}
+
+/// A receiver of listing parse events.
+///
+/// Every callback does nothing by default, so implementors only need to override the ones they
+/// care about.
+pub trait ListingVisitor {
+    /// Called for each header field, and once more after the beatmaps for the user permissions.
+    fn header(&mut self, _field: HeaderField) {}
+    /// Called before the fields of a beatmap are reported.
+    fn begin_beatmap(&mut self, _index: usize) {}
+    /// Called for each field of the current beatmap.
+    fn beatmap_field(&mut self, _field: BeatmapField) {}
+    /// Called for each precomputed star rating of the current beatmap.
+    fn star_rating(&mut self, _mode: Mode, _mods: ModSet, _stars: f64) {}
+    /// Called for each timing point of the current beatmap.
+    fn timing_point(&mut self, _point: TimingPoint) {}
+    /// Called after every field of a beatmap was reported.
+    fn end_beatmap(&mut self, _index: usize) {}
+    /// Called instead of `end_beatmap` when a beatmap is skipped, with `SizePrefixes::Recover`.
+    ///
+    /// The fields reported since `begin_beatmap` belong to a broken entry, and should be
+    /// discarded.
+    fn skip_beatmap(&mut self, _index: usize) {}
+}
+
+/// Parse a listing from its raw bytes, reporting each field to the visitor as it is read.
+///
+/// If parsing fails partway, the events reported so far are not undone.
+pub fn parse_listing_events<V: ListingVisitor>(bytes: &[u8], visitor: &mut V) -> Result<(), Error> {
+    parse_listing_events_with(bytes, visitor, ParseOptions::new())
+}
+
+/// Parse a listing from its raw bytes, reporting each field to the visitor as it is read, with the
+/// given parsing options.
+///
+/// Options apply as they do to `Listing::from_bytes_with`, except for the arena, which is not
+/// used.
+/// Strings decoded by a string fallback only live for the duration of the callback they are
+/// reported to.
+pub fn parse_listing_events_with<V: ListingVisitor>(
+    bytes: &[u8],
+    visitor: &mut V,
+    mut opts: ParseOptions,
+) -> Result<(), Error> {
+    listing_events(bytes, &mut opts, visitor)?;
+    Ok(())
+}
+
+pub(crate) fn listing_events<'a, V: ListingVisitor>(
+    bytes: &'a [u8],
+    opts: &mut ParseOptions,
+    visitor: &mut V,
+) -> IResult<&'a [u8], ()> {
+    let (rem, version) = int(bytes)?;
+    let parsed = listing_body(bytes, rem, version, opts, visitor);
+    if !matches!(parsed, Ok((_, false))) {
+        opts.unknown_version(version, LATEST_KNOWN_VERSION);
+    }
+    parsed.map(|(rem, _skipped)| (rem, ()))
+}
+
+/// Report everything after the version.
+///
+/// Returns whether any beatmap was skipped.
+fn listing_body<'a, V: ListingVisitor>(
+    bytes: &'a [u8],
+    rem: &'a [u8],
+    version: u32,
+    opts: &mut ParseOptions,
+    visitor: &mut V,
+) -> IResult<&'a [u8], bool> {
+    visitor.header(HeaderField::Version(version));
+    let (rem, folder_count) = int(rem)?;
+    visitor.header(HeaderField::FolderCount(folder_count));
+    let (rem, account_unlocked) = boolean(rem)?;
+    let (rem, unlock_date) = datetime(rem)?;
+    visitor.header(HeaderField::UnbanDate(build_option(
+        account_unlocked,
+        unlock_date,
+    )));
+    let (rem, player_name) = opt_str_with(rem, opts.string_fallback)?;
+    visitor.header(HeaderField::PlayerName(player_name.as_deref()));
+    let (mut rem, len) = int(rem)?;
+    visitor.header(HeaderField::BeatmapCount(len));
+    let len = len as usize;
+    let mut skipped = false;
+    for index in 0..len {
+        let (rem_, kept) = sized_beatmap_events(rem, version, index, opts, visitor)?;
+        if kept {
+            opts.record_span(bytes, rem, rem_);
+        } else {
+            skipped = true;
+        }
+        rem = rem_;
+        opts.report(index + 1, len, bytes, rem);
+    }
+    let (rem, user_permissions) = int(rem)?;
+    visitor.header(HeaderField::UserPermissions(user_permissions));
+    Ok((rem, skipped))
+}
+
+/// Report a beatmap along with its size prefix, if the version has one, checking the prefix as
+/// requested by the parsing options.
+///
+/// Returns whether the beatmap was kept, as opposed to skipped.
+fn sized_beatmap_events<'a, V: ListingVisitor>(
+    bytes: &'a [u8],
+    version: u32,
+    index: usize,
+    opts: &mut ParseOptions,
+    visitor: &mut V,
+) -> IResult<&'a [u8], bool> {
+    let (rem, declared) = cond(version < CHANGE_20191106, int)(bytes)?;
+    visitor.begin_beatmap(index);
+    let parsed = beatmap_events(rem, version, opts.string_fallback, visitor);
+    let declared = match declared {
+        Some(declared) if opts.size_prefixes != SizePrefixes::Ignore => declared,
+        _ => {
+            let (rem, ()) = parsed?;
+            visitor.end_beatmap(index);
+            return Ok((rem, true));
+        }
+    };
+    let actual = match &parsed {
+        Ok((rem_, ())) => Some(rem.len() - rem_.len()),
+        Err(_) => None,
+    };
+    if actual == Some(declared as usize) {
+        visitor.end_beatmap(index);
+        return parsed.map(|(rem, ())| (rem, true));
+    }
+    if let Some(actual) = actual {
+        opts.warn(Warning::BeatmapSizeMismatch {
+            index,
+            declared,
+            actual,
+        });
+    }
+    match (opts.size_prefixes, parsed) {
+        (SizePrefixes::Recover, _) => {
+            visitor.skip_beatmap(index);
+            let (rem, _entry) = take(declared)(rem)?;
+            opts.warn(Warning::SkippedBeatmap { index });
+            Ok((rem, false))
+        }
+        (_, Err(err)) => Err(err),
+        (_, Ok(_)) => Err(NomErr::Error(NomError::new(rem, NomErrorKind::Verify))),
+    }
+}
+
+fn beatmap_events<'a, V: ListingVisitor>(
+    bytes: &'a [u8],
+    version: u32,
+    strings: StringFallback,
+    visitor: &mut V,
+) -> IResult<&'a [u8], ()> {
+    use self::BeatmapField as F;
+    let mut rem = bytes;
+    macro_rules! field {
+        ($variant:ident, $parser:expr) => {{
+            let (rem_, value) = $parser(rem)?;
+            rem = rem_;
+            visitor.beatmap_field(F::$variant(value));
+        }};
+    }
+    macro_rules! string {
+        ($variant:ident) => {{
+            let (rem_, value) = opt_str_with(rem, strings)?;
+            rem = rem_;
+            visitor.beatmap_field(F::$variant(value.as_deref()));
+        }};
+    }
+    string!(ArtistAscii);
+    string!(ArtistUnicode);
+    string!(TitleAscii);
+    string!(TitleUnicode);
+    string!(Creator);
+    string!(DifficultyName);
+    string!(Audio);
+    field!(Hash, opt_str);
+    string!(FileName);
+    field!(Status, ranked_status);
+    field!(HitcircleCount, short);
+    field!(SliderCount, short);
+    field!(SpinnerCount, short);
+    field!(LastModified, datetime);
+    field!(ApproachRate, |rem| difficulty_value(rem, version));
+    field!(CircleSize, |rem| difficulty_value(rem, version));
+    field!(HpDrain, |rem| difficulty_value(rem, version));
+    field!(OverallDifficulty, |rem| difficulty_value(rem, version));
+    field!(SliderVelocity, double);
+    if version >= CHANGE_20140609 {
+        for &mode in &[Mode::Standard, Mode::Taiko, Mode::CatchTheBeat, Mode::Mania] {
+            let (rem_, len) = int(rem)?;
+            rem = rem_;
+            for _ in 0..len {
+                let (rem_, (mods, stars)) = star_rating(rem, version)?;
+                rem = rem_;
+                visitor.star_rating(mode, mods, stars);
+            }
+        }
+    }
+    field!(DrainTime, int);
+    field!(TotalTime, int);
+    field!(PreviewTime, int);
+    let (rem_, len) = int(rem)?;
+    rem = rem_;
+    for _ in 0..len {
+        let (rem_, point) = timing_point(rem)?;
+        rem = rem_;
+        visitor.timing_point(point);
+    }
+    field!(BeatmapId, map(signed_int, BeatmapId));
+    field!(BeatmapsetId, map(signed_int, BeatmapsetId));
+    field!(ThreadId, int);
+    for &mode in &[Mode::Standard, Mode::Taiko, Mode::CatchTheBeat, Mode::Mania] {
+        let (rem_, grade) = grade(rem)?;
+        rem = rem_;
+        visitor.beatmap_field(F::Grade(mode, grade));
+    }
+    field!(LocalBeatmapOffset, short);
+    field!(StackLeniency, single);
+    field!(Mode, map_opt(byte, Mode::from_raw));
+    string!(SongSource);
+    string!(Tags);
+    field!(OnlineOffset, short);
+    string!(TitleFont);
+    let (rem_, unplayed) = boolean(rem)?;
+    let (rem_, last_played) = datetime(rem_)?;
+    rem = rem_;
+    visitor.beatmap_field(F::LastPlayed(build_option(unplayed, last_played)));
+    field!(IsOsz2, boolean);
+    string!(FolderName);
+    field!(LastOnlineCheck, datetime);
+    field!(IgnoreSounds, boolean);
+    field!(IgnoreSkin, boolean);
+    field!(DisableStoryboard, boolean);
+    field!(DisableVideo, boolean);
+    field!(VisualOverride, boolean);
+    field!(MysteriousShort, cond(version < CHANGE_20140609, short));
+    field!(MysteriousLastModified, int);
+    field!(ManiaScrollSpeed, byte);
+    Ok((rem, ()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        listing::{test::beatmap, Beatmap},
+        Listing,
+    };
+
+    #[derive(Default)]
+    struct Summary {
+        events: Vec<String>,
+        star_ratings: usize,
+        timing_points: usize,
+    }
+    impl ListingVisitor for Summary {
+        fn header(&mut self, field: HeaderField) {
+            self.events.push(format!("{:?}", field));
+        }
+        fn begin_beatmap(&mut self, index: usize) {
+            self.events.push(format!("begin {}", index));
+        }
+        fn beatmap_field(&mut self, field: BeatmapField) {
+            match field {
+                BeatmapField::TitleAscii(title) => self.events.push(format!("{:?}", title)),
+                BeatmapField::LastPlayed(None) => self.events.push("unplayed".to_string()),
+                _ => {}
+            }
+        }
+        fn star_rating(&mut self, _mode: Mode, _mods: ModSet, _stars: f64) {
+            self.star_ratings += 1;
+        }
+        fn timing_point(&mut self, _point: TimingPoint) {
+            self.timing_points += 1;
+        }
+        fn end_beatmap(&mut self, index: usize) {
+            self.events.push(format!("end {}", index));
+        }
+        fn skip_beatmap(&mut self, index: usize) {
+            self.events.push(format!("skip {}", index));
+        }
+    }
+
+    #[test]
+    fn parse_listing_events() {
+        let mut unplayed = beatmap();
+        unplayed.title_ascii = None;
+        unplayed.last_played = None;
+        for &version in &[20211103, 20140608] {
+            let mut listing = Listing {
+                version,
+                folder_count: 2,
+                unban_date: None,
+                player_name: Some("player".to_string()),
+                beatmaps: vec![beatmap(), unplayed.clone()],
+                user_permissions: 4,
+            };
+            listing.migrate_to(version);
+            let mut raw = Vec::new();
+            listing.to_writer(&mut raw).unwrap();
+
+            let mut summary = Summary::default();
+            super::parse_listing_events(&raw, &mut summary).unwrap();
+            assert_eq!(
+                summary.events,
+                vec![
+                    format!("Version({})", version),
+                    "FolderCount(2)".to_string(),
+                    "UnbanDate(None)".to_string(),
+                    "PlayerName(Some(\"player\"))".to_string(),
+                    "BeatmapCount(2)".to_string(),
+                    "begin 0".to_string(),
+                    "Some(\"Title\")".to_string(),
+                    "end 0".to_string(),
+                    "begin 1".to_string(),
+                    "None".to_string(),
+                    "unplayed".to_string(),
+                    "end 1".to_string(),
+                    "UserPermissions(4)".to_string(),
+                ]
+            );
+            let count = |f: fn(&Beatmap) -> usize| listing.beatmaps.iter().map(f).sum::<usize>();
+            assert_eq!(
+                summary.star_ratings,
+                count(|bm| bm.std_ratings.len()
+                    + bm.taiko_ratings.len()
+                    + bm.ctb_ratings.len()
+                    + bm.mania_ratings.len())
+            );
+            assert_eq!(summary.timing_points, count(|bm| bm.timing_points.len()));
+        }
+        assert!(super::parse_listing_events(&[1, 2, 3], &mut Summary::default()).is_err());
+    }
+
+    #[test]
+    fn parse_listing_events_with() {
+        let mut unplayed = beatmap();
+        unplayed.title_ascii = None;
+        unplayed.last_played = None;
+        let mut listing = Listing {
+            version: 20140608,
+            folder_count: 2,
+            unban_date: None,
+            player_name: None,
+            beatmaps: vec![beatmap(), unplayed],
+            user_permissions: 4,
+        };
+        listing.migrate_to(20140608);
+        let mut raw = Vec::new();
+        listing.to_writer(&mut raw).unwrap();
+        let title = raw.windows(5).position(|w| w == b"Title").unwrap();
+        raw[title + 1] = 0xff;
+        let events = |raw: &[u8], opts: ParseOptions| {
+            let mut summary = Summary::default();
+            super::parse_listing_events_with(raw, &mut summary, opts).map(|()| summary.events)
+        };
+
+        assert!(super::parse_listing_events(&raw, &mut Summary::default()).is_err());
+        let lossy = events(
+            &raw,
+            ParseOptions::new().string_fallback(StringFallback::Lossy),
+        );
+        assert!(lossy
+            .unwrap()
+            .contains(&"Some(\"T\u{fffd}tle\")".to_string()));
+
+        //Claim one more byte for the last beatmap than it actually takes
+        let prefix = 4 + 4 + 1 + 8 + 1 + 4;
+        let size = |raw: &[u8], at: usize| {
+            let mut size = [0; 4];
+            size.copy_from_slice(&raw[at..at + 4]);
+            u32::from_le_bytes(size)
+        };
+        let last = prefix + 4 + size(&raw, prefix) as usize;
+        let declared = size(&raw, last) + 1;
+        raw[last..last + 4].copy_from_slice(&declared.to_le_bytes());
+        let end = raw.len() - 4;
+        raw.insert(end, 0);
+
+        let mut warnings = Vec::new();
+        let opts = ParseOptions::new()
+            .string_fallback(StringFallback::Lossy)
+            .size_prefixes(SizePrefixes::Recover)
+            .warnings(&mut warnings);
+        let recovered = events(&raw, opts).unwrap();
+        assert_eq!(
+            recovered[recovered.len() - 3..],
+            [
+                "unplayed".to_string(),
+                "skip 1".to_string(),
+                "UserPermissions(4)".to_string()
+            ]
+        );
+        assert_eq!(warnings.last(), Some(&Warning::SkippedBeatmap { index: 1 }));
+        let strict = ParseOptions::new()
+            .string_fallback(StringFallback::Lossy)
+            .size_prefixes(SizePrefixes::Strict);
+        assert!(events(&raw, strict).is_err());
+    }
+}
@@ -0,0 +1,540 @@
+//! Watching the osu! databases for changes, reloading them as the client rewrites them.
+//!
+//! Only available with the `watch` feature enabled.
+
+pub use crate::Database;
+use crate::{prelude::*, tracked::Change, CollectionList, Listing, ScoreList};
+use alloc::collections::BTreeMap;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A freshly loaded version of a database, along with how it differs from the previous one.
+#[derive(Debug, Clone)]
+pub struct Reload<T> {
+    /// The database as it was before this reload, or `None` if this is the first time it is
+    /// loaded.
+    pub previous: Option<Arc<T>>,
+    /// The database as it is now.
+    pub current: Arc<T>,
+    /// The changes from `previous` to `current`.
+    ///
+    /// Entries are matched up by beatmap hash (or collection name), so `Modified` entries may
+    /// have moved around.
+    /// On the first load, every entry is reported as `Added`.
+    pub changes: Vec<Change>,
+}
+
+/// An update delivered by a `ListingWatcher`.
+#[derive(Debug)]
+pub enum Update {
+    /// The `osu!.db` listing changed.
+    /// Changes refer to beatmaps.
+    Listing(Reload<Listing>),
+    /// The `scores.db` score database changed.
+    /// Changes refer to the per-beatmap score lists.
+    Scores(Reload<ScoreList>),
+    /// The `collection.db` collection list changed.
+    /// Changes refer to collections.
+    Collections(Reload<CollectionList>),
+    /// A database changed but could not be reloaded, even after retrying.
+    /// The previous version is kept, and is used as the base for the next reload.
+    ///
+    /// `database` is `None` if the error comes from watching the filesystem itself.
+    Failed {
+        database: Option<Database>,
+        error: Error,
+    },
+}
+
+/// Options controlling how a `ListingWatcher` behaves.
+pub struct WatchOptions {
+    debounce: Duration,
+    retries: u32,
+    callback: Option<Box<dyn FnMut(Update) + Send>>,
+}
+impl Default for WatchOptions {
+    fn default() -> WatchOptions {
+        WatchOptions {
+            debounce: Duration::from_millis(500),
+            retries: 3,
+            callback: None,
+        }
+    }
+}
+impl WatchOptions {
+    /// Create the default watching options.
+    pub fn new() -> WatchOptions {
+        WatchOptions::default()
+    }
+
+    /// How long a database must go without being touched before it is reloaded.
+    ///
+    /// osu! rewrites its databases in several bursts of writes, and reading in between would
+    /// see a half-written file.
+    /// Defaults to half a second.
+    pub fn debounce(mut self, debounce: Duration) -> WatchOptions {
+        self.debounce = debounce;
+        self
+    }
+
+    /// How many more times to try reloading a database that fails to parse, waiting for another
+    /// debounce period in between, before giving up and reporting `Update::Failed`.
+    ///
+    /// Defaults to 3.
+    pub fn retries(mut self, retries: u32) -> WatchOptions {
+        self.retries = retries;
+        self
+    }
+
+    /// Deliver updates to the given callback, instead of queueing them to be received from the
+    /// watcher.
+    ///
+    /// The callback runs on the background watching thread.
+    pub fn callback<F>(mut self, callback: F) -> WatchOptions
+    where
+        F: FnMut(Update) + Send + 'static,
+    {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+}
+impl fmt::Debug for WatchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WatchOptions")
+            .field("debounce", &self.debounce)
+            .field("retries", &self.retries)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+/// Watches the `osu!.db`, `scores.db` and `collection.db` databases within an osu! directory,
+/// reloading them whenever they change.
+///
+/// Each database is loaded once when the watcher starts, delivering an update with no `previous`
+/// version.
+/// From then on, a database is reloaded once it has not been touched for the debounce period,
+/// and an update is delivered if its contents actually changed.
+/// Databases that do not exist are skipped until they are created.
+///
+/// Watching runs on a background thread, which stops when the watcher is dropped.
+#[derive(Debug)]
+pub struct ListingWatcher {
+    _watcher: RecommendedWatcher,
+    updates: Receiver<Update>,
+}
+impl ListingWatcher {
+    /// Start watching the databases within the given osu! directory, with the default options.
+    ///
+    /// Updates are queued, to be received with `recv` and friends.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<ListingWatcher, Error> {
+        Self::new_with(path, WatchOptions::new())
+    }
+
+    /// Start watching the databases within the given osu! directory, with the given options.
+    pub fn new_with<P: AsRef<Path>>(path: P, opts: WatchOptions) -> Result<ListingWatcher, Error> {
+        let dir = path.as_ref().to_path_buf();
+        let (event_tx, event_rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(event_tx)?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        let (update_tx, updates) = mpsc::channel();
+        let deliver: Box<dyn FnMut(Update) + Send> = match opts.callback {
+            Some(callback) => callback,
+            None => Box::new(move |update| {
+                //The receiving end is only gone once the watcher is dropped
+                let _ = update_tx.send(update);
+            }),
+        };
+        let state = WatchState {
+            dir,
+            debounce: opts.debounce,
+            retries: opts.retries,
+            deliver,
+            listing: None,
+            scores: None,
+            collections: None,
+        };
+        thread::spawn(move || state.run(event_rx));
+        Ok(ListingWatcher {
+            _watcher: watcher,
+            updates,
+        })
+    }
+
+    /// Block until the next update is available.
+    ///
+    /// Returns `None` if updates are delivered to a callback instead, or if the watching thread
+    /// stopped.
+    pub fn recv(&self) -> Option<Update> {
+        self.updates.recv().ok()
+    }
+
+    /// Get the next update if one is available, without blocking.
+    pub fn try_recv(&self) -> Option<Update> {
+        match self.updates.try_recv() {
+            Ok(update) => Some(update),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+
+    /// Block until the next update is available, for at most the given time.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Update> {
+        self.updates.recv_timeout(timeout).ok()
+    }
+}
+
+/// The state of the background watching thread.
+struct WatchState {
+    dir: PathBuf,
+    debounce: Duration,
+    retries: u32,
+    deliver: Box<dyn FnMut(Update) + Send>,
+    listing: Option<Arc<Listing>>,
+    scores: Option<Arc<ScoreList>>,
+    collections: Option<Arc<CollectionList>>,
+}
+impl WatchState {
+    fn run(mut self, events: Receiver<notify::Result<Event>>) {
+        let mut schedule = Schedule::new(self.debounce, Instant::now());
+        loop {
+            let event = match schedule.next_deadline() {
+                Some(deadline) => {
+                    events.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match event {
+                Ok(Ok(event)) => {
+                    if let EventKind::Access(_) = event.kind {
+                        //Our own reads show up as accesses
+                        continue;
+                    }
+                    for &db in Database::ALL.iter() {
+                        let touched = event.paths.iter().any(|path| {
+                            path.file_name().is_some_and(|name| {
+                                name.to_string_lossy().eq_ignore_ascii_case(db.file_name())
+                            })
+                        });
+                        if touched {
+                            schedule.touch(db, Instant::now());
+                        }
+                    }
+                }
+                Ok(Err(err)) => (self.deliver)(Update::Failed {
+                    database: None,
+                    error: err.into(),
+                }),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            for (db, failed) in schedule.take_due(Instant::now()) {
+                match self.reload(db) {
+                    Ok(()) => {}
+                    //The client might be replacing the file, wait for it to show up again
+                    Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(_) if failed < self.retries => schedule.retry(db, failed, Instant::now()),
+                    Err(error) => (self.deliver)(Update::Failed {
+                        database: Some(db),
+                        error,
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Reload a database, delivering an update if it changed.
+    fn reload(&mut self, db: Database) -> Result<(), Error> {
+        let bytes = fs::read(self.dir.join(db.file_name()))?;
+        let update = match db {
+            Database::Listing => {
+                let current = Listing::from_bytes(&bytes)?;
+                reload(&mut self.listing, current, listing_changes).map(Update::Listing)
+            }
+            Database::Scores => {
+                let current = ScoreList::from_bytes(&bytes)?;
+                reload(&mut self.scores, current, score_changes).map(Update::Scores)
+            }
+            Database::Collections => {
+                let current = CollectionList::from_bytes(&bytes)?;
+                reload(&mut self.collections, current, collection_changes).map(Update::Collections)
+            }
+        };
+        if let Some(update) = update {
+            (self.deliver)(update);
+        }
+        Ok(())
+    }
+}
+
+/// A database waiting to be reloaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pending {
+    /// The last time the database was touched.
+    touched: Instant,
+    /// The amount of failed reload attempts so far.
+    failed: u32,
+}
+
+/// Keeps track of when each database is due for a reload, one debounce period after it was last
+/// touched.
+#[derive(Debug)]
+struct Schedule {
+    debounce: Duration,
+    pending: [Option<Pending>; 3],
+}
+impl Schedule {
+    /// Start with every database pending, so that they are all loaded once.
+    fn new(debounce: Duration, now: Instant) -> Schedule {
+        let pending = Pending {
+            touched: now,
+            failed: 0,
+        };
+        Schedule {
+            debounce,
+            pending: [Some(pending); 3],
+        }
+    }
+
+    fn slot(&mut self, db: Database) -> &mut Option<Pending> {
+        let idx = Database::ALL.iter().position(|&d| d == db).unwrap();
+        &mut self.pending[idx]
+    }
+
+    /// Mark a database as touched, pushing back its reload.
+    ///
+    /// A database that is already retrying keeps its count of failed attempts.
+    fn touch(&mut self, db: Database, now: Instant) {
+        let slot = self.slot(db);
+        let failed = slot.map_or(0, |pending| pending.failed);
+        *slot = Some(Pending {
+            touched: now,
+            failed,
+        });
+    }
+
+    /// Schedule another reload attempt after a failed one.
+    fn retry(&mut self, db: Database, failed: u32, now: Instant) {
+        *self.slot(db) = Some(Pending {
+            touched: now,
+            failed: failed + 1,
+        });
+    }
+
+    /// The earliest time a database is due for a reload, if any is pending.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .iter()
+            .flatten()
+            .map(|pending| pending.touched + self.debounce)
+            .min()
+    }
+
+    /// Take the databases due for a reload, along with their count of failed attempts.
+    fn take_due(&mut self, now: Instant) -> Vec<(Database, u32)> {
+        let debounce = self.debounce;
+        Database::ALL
+            .iter()
+            .zip(&mut self.pending)
+            .filter_map(|(&db, slot)| match *slot {
+                Some(pending) if pending.touched + debounce <= now => {
+                    *slot = None;
+                    Some((db, pending.failed))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Replace the latest version of a database, returning the reload if anything changed.
+fn reload<T>(
+    latest: &mut Option<Arc<T>>,
+    current: T,
+    changes: fn(Option<&T>, &T) -> Vec<Change>,
+) -> Option<Reload<T>> {
+    let changes = changes(latest.as_deref(), &current);
+    if latest.is_some() && changes.is_empty() {
+        return None;
+    }
+    let current = Arc::new(current);
+    Some(Reload {
+        previous: latest.replace(current.clone()),
+        current,
+        changes,
+    })
+}
+
+fn listing_changes(previous: Option<&Listing>, current: &Listing) -> Vec<Change> {
+    let header = |l: &Listing| {
+        (
+            l.version,
+            l.folder_count,
+            l.unban_date,
+            l.player_name.clone(),
+            l.user_permissions,
+        )
+    };
+    diff(
+        previous.map(|prev| (header(prev), &prev.beatmaps[..])),
+        (header(current), &current.beatmaps[..]),
+        |bm| bm.hash.clone(),
+    )
+}
+
+fn score_changes(previous: Option<&ScoreList>, current: &ScoreList) -> Vec<Change> {
+    diff(
+        previous.map(|prev| (prev.version, &prev.beatmaps[..])),
+        (current.version, &current.beatmaps[..]),
+        |scores| scores.hash.clone(),
+    )
+}
+
+fn collection_changes(previous: Option<&CollectionList>, current: &CollectionList) -> Vec<Change> {
+    diff(
+        previous.map(|prev| (prev.version, &prev.collections[..])),
+        (current.version, &current.collections[..]),
+        |collection| collection.name.clone(),
+    )
+}
+
+/// Compute the changes between two versions of a database, given as their header and entries.
+///
+/// Entries are matched up by key, and entries sharing a key are matched up in order.
+/// Changes are ordered as in `TrackedListing::changes`.
+fn diff<H, T, K, F>(previous: Option<(H, &[T])>, current: (H, &[T]), key: F) -> Vec<Change>
+where
+    H: PartialEq,
+    T: PartialEq,
+    K: Ord,
+    F: Fn(&T) -> K,
+{
+    let (header, entries) = current;
+    let (prev_header, prev_entries) = match previous {
+        Some((prev_header, prev_entries)) => (Some(prev_header), prev_entries),
+        None => (None, &[][..]),
+    };
+    let mut changes = Vec::new();
+    if prev_header.as_ref() != Some(&header) {
+        changes.push(Change::Header);
+    }
+    let mut by_key: BTreeMap<K, Vec<usize>> = BTreeMap::new();
+    for (idx, entry) in prev_entries.iter().enumerate().rev() {
+        by_key.entry(key(entry)).or_default().push(idx);
+    }
+    let mut matched = vec![None; entries.len()];
+    for (idx, entry) in entries.iter().enumerate() {
+        matched[idx] = by_key.get_mut(&key(entry)).and_then(Vec::pop);
+    }
+    let mut removed: Vec<usize> = by_key.into_values().flatten().collect();
+    removed.sort_unstable();
+    changes.extend(
+        removed
+            .into_iter()
+            .map(|original_index| Change::Removed { original_index }),
+    );
+    for (index, (entry, original_index)) in entries.iter().zip(matched).enumerate() {
+        match original_index {
+            None => changes.push(Change::Added { index }),
+            Some(original_index) if prev_entries[original_index] != *entry => {
+                changes.push(Change::Modified {
+                    index,
+                    original_index,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{collection::Collection, ids::test::hash};
+
+    fn collections(entries: &[(&str, u8)]) -> CollectionList {
+        CollectionList {
+            version: 20211103,
+            collections: entries
+                .iter()
+                .map(|&(name, n)| Collection {
+                    name: Some(name.to_string()),
+                    beatmap_hashes: vec![hash(n).into()],
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let old = collections(&[("a", 1), ("b", 2), ("c", 3), ("c", 4)]);
+        let new = collections(&[("c", 3), ("d", 5), ("a", 9)]);
+        assert_eq!(
+            collection_changes(Some(&old), &new),
+            vec![
+                Change::Removed { original_index: 1 },
+                Change::Removed { original_index: 3 },
+                Change::Added { index: 1 },
+                Change::Modified {
+                    index: 2,
+                    original_index: 0
+                },
+            ]
+        );
+        assert_eq!(
+            collection_changes(None, &new)[..2],
+            [Change::Header, Change::Added { index: 0 }]
+        );
+
+        let mut latest = None;
+        let first = reload(&mut latest, old.clone(), collection_changes).unwrap();
+        assert!(first.previous.is_none());
+        assert!(reload(&mut latest, old, collection_changes).is_none());
+        let second = reload(&mut latest, new.clone(), collection_changes).unwrap();
+        assert_eq!(second.previous.as_deref(), Some(&*first.current));
+        assert_eq!(latest.as_deref(), Some(&new));
+    }
+
+    #[test]
+    fn schedule() {
+        let ms = Duration::from_millis;
+        let start = Instant::now();
+        let mut schedule = Schedule::new(ms(100), start);
+        assert_eq!(schedule.next_deadline(), Some(start + ms(100)));
+
+        //Each database waits for its own quiet period
+        schedule.touch(Database::Listing, start + ms(50));
+        assert!(schedule.take_due(start + ms(99)).is_empty());
+        assert_eq!(
+            schedule.take_due(start + ms(100)),
+            vec![(Database::Scores, 0), (Database::Collections, 0)]
+        );
+        assert_eq!(schedule.next_deadline(), Some(start + ms(150)));
+        schedule.touch(Database::Collections, start + ms(120));
+        assert_eq!(
+            schedule.take_due(start + ms(150)),
+            vec![(Database::Listing, 0)]
+        );
+
+        //Failed attempts are retried one debounce period later, and counted across touches
+        schedule.retry(Database::Listing, 0, start + ms(150));
+        schedule.touch(Database::Listing, start + ms(200));
+        assert_eq!(
+            schedule.take_due(start + ms(250)),
+            vec![(Database::Collections, 0)]
+        );
+        assert_eq!(
+            schedule.take_due(start + ms(300)),
+            vec![(Database::Listing, 1)]
+        );
+        assert_eq!(schedule.next_deadline(), None);
+    }
+}